@@ -0,0 +1,3159 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use buffrs::config::{self, Config};
+use buffrs::error::ErrorCode;
+use buffrs::generator::Language;
+use buffrs::manifest::MANIFEST_FILE;
+use buffrs::ops::{InstallOptions, Lag};
+use buffrs::package::{PackageId, PackageStore};
+use buffrs::registry::Archival;
+use buffrs::registry::Authentication;
+use buffrs::registry::FileRegistryConfig;
+use buffrs::registry::{Artifactory, ArtifactoryConfig, AuditedRegistry};
+use buffrs::resolver::VersionRequirement;
+use buffrs::template::TemplateSource;
+use clap::{Parser, Subcommand, ValueEnum};
+use color_eyre::Section;
+use eyre::{Context, ContextCompat};
+use std::{io::IsTerminal, net::SocketAddr, path::PathBuf, time::Instant, time::SystemTime};
+use tokio_util::sync::CancellationToken;
+use tracing_subscriber::{filter::Targets, fmt::format::FmtSpan, prelude::*};
+use usage::UsageReport;
+
+mod markdown;
+mod progress;
+mod self_update;
+mod sso;
+mod timings;
+mod usage;
+
+#[derive(Parser)]
+#[command(name = "buffrs", author, version, about, long_about)]
+#[command(propagate_version = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    /// Uses this configuration file instead of the default one (see `buffrs env`)
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+    /// Overrides the registry url of the configuration, `file://` urls
+    /// select a directory instead of a remote registry
+    #[clap(long, global = true)]
+    registry_url: Option<url::Url>,
+    /// Overrides the directory dependencies are installed into
+    #[clap(long, global = true)]
+    vendor_dir: Option<PathBuf>,
+    /// Overrides how often registry requests that failed transiently are
+    /// retried, 0 disables retries
+    #[clap(long, global = true, value_name = "COUNT")]
+    retries: Option<u32>,
+    /// Prints debug output, including the timings of registry operations
+    #[clap(short, long, global = true)]
+    verbose: bool,
+}
+
+#[derive(Clone, Subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    /// Initializes a buffrs setup
+    Init {
+        /// Sets up the repository as api package
+        #[clap(long)]
+        api: Option<PackageId>,
+        /// Creates the project from a template: `gh:<owner>/<repo>[@<ref>]`,
+        /// a git url (`<url>.git[#<ref>]`), a `.tar.gz` url or a directory
+        #[clap(long)]
+        template: Option<TemplateSource>,
+    },
+
+    /// Adds dependencies to a manifest file
+    Add {
+        /// Dependency to add (Format <repository>/<package>@<version>
+        dependency: String,
+    },
+    /// Removes dependencies from a manifest file
+    #[clap(alias = "rm")]
+    Remove {
+        /// Package to remove from the dependencies
+        package: PackageId,
+    },
+
+    /// Shows details of a published package
+    Info {
+        /// Package to show (Format <repository>/<package>[@<version>]),
+        /// defaults to the latest version
+        package: String,
+        /// Renders the readme of the package instead
+        #[clap(long)]
+        readme: bool,
+    },
+    /// Searches the registry for packages
+    Search {
+        /// Part of the package name to search for
+        term: String,
+    },
+
+    /// Packages and uploads this api to the registry
+    #[clap(alias = "pub")]
+    Publish {
+        /// Destination repository for the release
+        #[clap(long)]
+        repository: String,
+        /// Publishes only this package, either a `[[package]]` of the
+        /// project or the member of the workspace publishing it
+        #[clap(short, long)]
+        package: Option<PackageId>,
+        /// Writes the url, digest and size of every published archive along
+        /// with the git commit to a JSON file, e.g. for release automation
+        #[clap(long)]
+        receipt: Option<PathBuf>,
+    },
+    /// Checks the api package for breaking changes to a published release
+    Breaking {
+        /// Repository the package is published to
+        #[clap(long)]
+        repository: String,
+        /// Release to compare against, defaults to the highest version
+        /// published before the local one
+        #[clap(long)]
+        against: Option<semver::Version>,
+    },
+
+    /// Marks a package as superseded, installs keep working but warn
+    Archive {
+        /// Package to archive (format <repository>/<package>)
+        package: String,
+        /// Package users should migrate to
+        #[clap(long)]
+        replacement: Option<PackageId>,
+        /// Explanation shown to users of the package
+        #[clap(long)]
+        message: Option<String>,
+        /// Restores an archived package instead
+        #[clap(long, conflicts_with_all = ["replacement", "message"])]
+        undo: bool,
+    },
+    /// Yanks a published version, so it is only installed where it is locked
+    Yank {
+        /// Version to yank (format <repository>/<package>@<version>)
+        package: String,
+        /// Restores a yanked version instead
+        #[clap(long)]
+        undo: bool,
+        /// Skips the confirmation prompt
+        #[clap(short, long)]
+        yes: bool,
+    },
+
+    /// Installs dependencies
+    Install {
+        /// Installs the package even if it was published within the
+        /// quarantine period of the policy
+        #[clap(long = "allow-quarantined", value_name = "PACKAGE")]
+        allow_quarantined: Vec<PackageId>,
+        /// Installs every project found below the current directory
+        #[clap(long)]
+        recursive: bool,
+        /// Installs only this member of the workspace instead of all
+        #[clap(short, long, conflicts_with = "recursive")]
+        package: Option<PackageId>,
+        /// Installs as many dependencies as possible and reports all failures
+        /// at the end, instead of stopping at the first one
+        #[clap(long)]
+        keep_going: bool,
+        /// Fails if Proto.lock is missing or out of date instead of updating
+        /// it, e.g. for reproducible CI builds
+        #[clap(long)]
+        locked: bool,
+        /// Writes a report of when each package was downloaded and extracted
+        /// to buffrs-timings.html
+        #[clap(long, value_enum, num_args = 0..=1, default_missing_value = "html")]
+        timings: Option<Timings>,
+        /// Only installs the dependencies needed to generate code for this
+        /// language, skipping those declared for other languages
+        #[clap(long = "for", value_name = "LANGUAGE")]
+        language: Option<Language>,
+        /// Installs the dependencies of this optional group as well
+        #[clap(long = "group", value_name = "GROUP")]
+        groups: Vec<String>,
+        /// Skips the dependencies of this group
+        #[clap(long = "exclude-group", value_name = "GROUP")]
+        exclude_groups: Vec<String>,
+        /// Maximum number of packages downloaded and extracted at the same
+        /// time (defaults to 8)
+        #[clap(long, short = 'j', value_name = "N")]
+        jobs: Option<usize>,
+    },
+    /// Updates dependencies to the newest versions allowed by the manifest
+    /// and records them in Proto.lock
+    Update {
+        /// Packages to update, defaults to all dependencies
+        packages: Vec<PackageId>,
+        /// Updates only this package, to the version given by --to
+        #[clap(long, requires = "to", conflicts_with = "packages")]
+        package: Option<PackageId>,
+        /// Version to update --package to, it has to satisfy the version
+        /// requirement of the manifest
+        #[clap(long, requires = "package", value_name = "VERSION")]
+        to: Option<semver::Version>,
+        /// Maximum number of packages downloaded and extracted at the same
+        /// time (defaults to 8)
+        #[clap(long, short = 'j', value_name = "N")]
+        jobs: Option<usize>,
+        /// Takes major releases and versions conflicting with other
+        /// workspace members without asking
+        #[clap(short, long, conflicts_with = "no_interactive")]
+        yes: bool,
+        /// Keeps such packages at their current major version instead of
+        /// asking, e.g. in CI
+        #[clap(long)]
+        no_interactive: bool,
+        /// Writes a summary of the changed versions to buffrs-update.md, e.g.
+        /// as description of an automated pull request
+        #[clap(long, value_enum, value_name = "FORMAT")]
+        write_summary: Option<Summary>,
+    },
+    /// Lists dependencies with newer versions in the registry
+    Outdated {
+        /// Output format of the report
+        #[clap(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+        /// Fails if a dependency lags behind its latest release by at least
+        /// the given kind of release, e.g. to enforce fresh schemas in CI
+        #[clap(long, value_enum, num_args = 0..=1, default_missing_value = "any")]
+        exit_code: Option<Threshold>,
+    },
+    /// Uninstalls dependencies
+    Uninstall,
+    /// Removes installed packages that are no longer declared as dependency
+    Prune,
+    /// Shows the disk space used by installed packages and caches
+    Du,
+
+    /// Logs you in for a registry
+    Login {
+        /// Artifactory url (e.g. https://<domain>/artifactory)
+        #[clap(long)]
+        url: url::Url,
+        /// Artifactory username
+        #[clap(long, required_unless_present_any = ["token", "sso"])]
+        username: Option<String>,
+        /// Authenticates with an access token or api key instead of username
+        /// and password
+        #[clap(long, value_enum, num_args = 0..=1, default_missing_value = "bearer")]
+        token: Option<Token>,
+        /// Log in through an identity provider using the device flow, the
+        /// token is sent as bearer token unless --token says otherwise
+        #[clap(long, requires_all = ["issuer", "client_id"])]
+        sso: bool,
+        /// OpenID Connect issuer url of the identity provider
+        #[clap(long)]
+        issuer: Option<url::Url>,
+        /// OAuth client id registered for buffrs at the identity provider
+        #[clap(long)]
+        client_id: Option<String>,
+    },
+    /// Logs you out from a registry
+    Logout,
+    /// Writes an initial configuration without credentials, e.g. for
+    /// provisioning machines
+    Setup {
+        /// Artifactory url (e.g. https://<domain>/artifactory)
+        #[clap(long)]
+        registry: Option<url::Url>,
+        /// Artifactory username
+        #[clap(long)]
+        username: Option<String>,
+        /// Configuration file to start from, settings passed as flags take
+        /// precedence
+        #[clap(long)]
+        from: Option<PathBuf>,
+        /// Fails instead of prompting for missing settings
+        #[clap(long)]
+        non_interactive: bool,
+    },
+
+    /// Updates buffrs to the latest release
+    SelfUpdate,
+
+    /// Copies packages from one registry to another
+    Mirror {
+        /// Url of the registry to copy from
+        #[clap(long)]
+        from: url::Url,
+        /// Url of the registry to copy to
+        #[clap(long)]
+        to: url::Url,
+        /// Username for the source registry, defaults to the logged in user
+        #[clap(long)]
+        from_username: Option<String>,
+        /// Username for the target registry, defaults to the logged in user
+        #[clap(long)]
+        to_username: Option<String>,
+        /// Packages to copy (Format <repository>/<package>@<version>, with an
+        /// exact version), defaults to the dependencies of the current project
+        /// at their locked versions
+        packages: Vec<String>,
+    },
+
+    /// Shows the configuration file and directories used by buffrs
+    Env,
+
+    /// Generates code for the api and installed dependencies using protoc
+    Generate {
+        /// Language to generate code for (cpp, csharp, go, java, kotlin,
+        /// python, ruby or rust)
+        #[clap(long)]
+        lang: Language,
+        /// Directory the generated code is written to
+        #[clap(long, default_value = "generated")]
+        out_dir: PathBuf,
+    },
+
+    /// Decodes a binary message to JSON using the installed protos
+    Decode {
+        /// Message type, e.g. `units.Temperature` or `Temperature`
+        message: String,
+        /// File to decode instead of stdin
+        #[clap(long)]
+        input: Option<PathBuf>,
+    },
+
+    /// Encodes a JSON message in the binary format using the installed protos
+    Encode {
+        /// Message type, e.g. `units.Temperature` or `Temperature`
+        message: String,
+        /// File to encode instead of stdin
+        #[clap(long)]
+        input: Option<PathBuf>,
+        /// File to write instead of stdout
+        #[clap(long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Prints an example message with every field set, e.g. for
+    /// documentation or test fixtures
+    Example {
+        /// Message type, e.g. `units.Temperature` or `Temperature`
+        message: String,
+        /// Prints the protobuf text format instead of JSON
+        #[clap(long)]
+        text: bool,
+        /// Randomizes the values, the same seed always produces the same
+        /// message
+        #[clap(long)]
+        seed: Option<u64>,
+    },
+
+    /// Checks the protos of the project and its dependencies for problems
+    Lint,
+
+    /// Formats the protos of the api package
+    Fmt {
+        /// Fails if protos are not formatted instead of rewriting them
+        #[clap(long)]
+        check: bool,
+    },
+
+    /// Checks that fixtures are valid messages of their declared types
+    Test {
+        /// Directory containing `.txtpb` and `.json` fixtures
+        #[clap(default_value = "fixtures")]
+        fixtures: PathBuf,
+        /// Checks all fixtures against this message type instead of the
+        /// declared ones
+        #[clap(long)]
+        message: Option<String>,
+    },
+
+    /// Exports the installed protos for use by other tools
+    Export {
+        /// Writes a descriptor set including all imports along with an index
+        /// of the gRPC services, as used by grpcurl and ghz
+        #[clap(long)]
+        reflection_bundle: bool,
+        /// Copies the archives of all locked packages into a directory laid
+        /// out like the registry, to install offline with `--registry-url
+        /// file://<dir>`
+        #[clap(long)]
+        registry: bool,
+        /// Directory to write the export to
+        #[clap(long, default_value = "bundle")]
+        output: PathBuf,
+    },
+
+    /// Explains an error code (e.g. E0004) in detail
+    Explain {
+        /// Error code to explain, lists all codes if omitted
+        code: Option<ErrorCode>,
+    },
+
+    /// Inspects the manifest
+    Manifest {
+        #[command(subcommand)]
+        command: ManifestCommand,
+    },
+
+    /// Serves the api package and installed dependencies of this project
+    /// as read-only registry
+    Serve {
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1:4873")]
+        listen: SocketAddr,
+    },
+
+    /// Runs a gRPC server responding to every call with an example message
+    Mock {
+        /// Services to mock, e.g. `weather.Forecasts` or `Forecasts`,
+        /// defaults to all services of the installed protos
+        services: Vec<String>,
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1:50051")]
+        listen: SocketAddr,
+    },
+
+    /// Runs a caching proxy in front of the registry
+    Proxy {
+        #[command(subcommand)]
+        command: ProxyCommand,
+    },
+
+    /// Manages the package cache shared by all projects
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+
+    /// Signs and verifies Proto.lock
+    Lock {
+        #[command(subcommand)]
+        command: LockCommand,
+    },
+
+    /// Integrates the project with editors
+    Ide {
+        #[command(subcommand)]
+        command: IdeCommand,
+    },
+}
+
+#[derive(Clone, Subcommand)]
+enum ProxyCommand {
+    /// Serves the registry of the configuration, caching every package
+    Serve {
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1:4873")]
+        listen: SocketAddr,
+        /// Directory packages are cached in, defaults to `proxy` in the cache
+        /// directory (see `buffrs env`)
+        #[clap(long)]
+        cache_dir: Option<PathBuf>,
+    },
+}
+
+/// Kinds of tokens accepted by `buffrs login --token`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Token {
+    /// Access token sent as bearer token
+    Bearer,
+    /// Api key sent in the `X-JFrog-Art-Api` header
+    ApiKey,
+}
+
+impl From<Token> for Authentication {
+    fn from(token: Token) -> Self {
+        match token {
+            Token::Bearer => Self::Bearer,
+            Token::ApiKey => Self::ApiKey,
+        }
+    }
+}
+
+/// Formats of the timing report of `buffrs install --timings`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Timings {
+    /// A page with a table and timeline of all packages
+    Html,
+}
+
+/// Formats of the summary of `buffrs update --write-summary`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Summary {
+    /// A Markdown table of the updated packages
+    Md,
+}
+
+/// Releases a dependency may lag behind before `buffrs outdated --exit-code`
+/// fails
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Threshold {
+    /// Any newer release, including patches and pre-releases
+    Any,
+    /// A newer minor or major release
+    Minor,
+    /// A newer major release
+    Major,
+}
+
+impl From<Threshold> for Lag {
+    fn from(threshold: Threshold) -> Self {
+        match threshold {
+            Threshold::Any => Self::Patch,
+            Threshold::Minor => Self::Minor,
+            Threshold::Major => Self::Major,
+        }
+    }
+}
+
+/// Output formats of reports
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Human readable text
+    Text,
+    /// JSON, e.g. for dashboards
+    Json,
+}
+
+#[derive(Clone, Subcommand)]
+enum CacheCommand {
+    /// Removes all cached packages
+    Clean,
+    /// Prints the number and size of cached packages
+    Stats,
+}
+
+#[derive(Clone, Subcommand)]
+enum LockCommand {
+    /// Signs Proto.lock with an SSH key, writing Proto.lock.sig
+    Sign {
+        /// Private key to sign with, or the public key of a key held by an
+        /// ssh agent
+        #[clap(long)]
+        key: PathBuf,
+    },
+    /// Checks that Proto.lock is signed by an allowed signer
+    Verify {
+        /// File of the SSH keys trusted to sign, defaults to
+        /// `allowed_signers` of the configuration
+        #[clap(long)]
+        allowed_signers: Option<PathBuf>,
+    },
+    /// Merges diverged lockfiles, for use as git merge driver
+    Merge {
+        /// Lockfile of the common ancestor (%O)
+        base: PathBuf,
+        /// Our lockfile (%A), replaced by the merged one
+        ours: PathBuf,
+        /// Their lockfile (%B)
+        theirs: PathBuf,
+        /// Path of the lockfile in the repository (%P), the manifest next to
+        /// it decides which versions are compatible
+        path: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Subcommand)]
+enum IdeCommand {
+    /// Writes the include paths of the project to the VS Code settings
+    Sync,
+}
+
+#[derive(Clone, Subcommand)]
+enum ManifestCommand {
+    /// Prints the JSON Schema of the manifest format
+    Schema,
+    /// Checks a manifest for errors without installing anything
+    Validate {
+        /// Manifest to validate, defaults to the one of the current project
+        path: Option<PathBuf>,
+    },
+    /// Prints a field of the manifest (e.g. `api.version`)
+    Get {
+        /// Dotted key of the field
+        key: String,
+    },
+    /// Sets a field of the manifest, keeping its formatting intact
+    Set {
+        /// Dotted key of the field
+        key: String,
+        /// New value of the field
+        value: String,
+        /// Parses the value as toml instead of storing it as string
+        #[clap(long)]
+        toml: bool,
+    },
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    color_eyre::install()?;
+
+    let cli = Cli::parse();
+
+    // Registry operations report their package, size and duration in spans,
+    // which are printed when they close
+    let (level, span_events) = match cli.verbose {
+        true => (tracing::Level::DEBUG, FmtSpan::CLOSE),
+        false => (tracing::Level::INFO, FmtSpan::NONE),
+    };
+
+    // Debug output of dependencies, e.g. every connection, is just noise
+    let targets = Targets::new()
+        .with_target("buffrs", level)
+        .with_default(tracing::Level::INFO);
+
+    tracing_subscriber::fmt()
+        .compact()
+        .without_time()
+        .with_max_level(level)
+        .with_span_events(span_events)
+        .with_level(false)
+        .with_file(false)
+        .with_target(false)
+        .with_line_number(false)
+        .with_writer(progress::LogWriter)
+        .finish()
+        .with(targets)
+        .try_init()
+        .unwrap();
+
+    if let Err(error) = config::migrate().await {
+        tracing::warn!("failed to migrate files of previous releases: {error:#}");
+    }
+
+    let config_path = match cli.config {
+        Some(ref path) => path.clone(),
+        None => Config::location()?,
+    };
+
+    // Setup edits the configuration as written, which may refer to
+    // environment variables only set where buffrs is used later on
+    let mut config = match cli.command {
+        Command::Setup { .. } => Config::default(),
+        // Only the default configuration is created on first use, an explicit
+        // path that does not exist is most likely a typo
+        Command::Login { .. } => Config::load_at(&config_path).await?,
+        _ if cli.config.is_some() && !Config::exists_at(&config_path).await? => {
+            eyre::bail!("Configuration {} does not exist", config_path.display())
+        }
+        _ => Config::load_at(&config_path).await?,
+    };
+
+    // The url is applied to the registry that is used, following the
+    // precedence of `Config::registry`
+    if let Some(ref url) = cli.registry_url {
+        match (&mut config.filesystem, &mut config.http, &mut config.artifactory) {
+            _ if url.scheme() == "file" => {
+                config.filesystem = Some(FileRegistryConfig::from_url(url)?)
+            }
+            (Some(filesystem), _, _) => eyre::bail!(
+                "--registry-url {url} can not override the directory {} of the configuration, use a file:// url",
+                filesystem.path.display()
+            ),
+            (None, Some(http), _) => http.url = http.url.with_base(url)?,
+            (None, None, Some(artifactory)) => {
+                artifactory.url = ArtifactoryConfig::canonicalize(url.clone())?
+            }
+            (None, None, None) => tracing::warn!("--registry-url has no effect until you login"),
+        }
+    }
+
+    if let Some(ref vendor_dir) = cli.vendor_dir {
+        config.vendor_dir = Some(vendor_dir.clone());
+    }
+
+    if let Some(retries) = cli.retries {
+        config.retry.get_or_insert_with(Default::default).retries = Some(retries);
+    }
+
+    // The hint is left out of output read by other tools
+    let check_for_updates = config.check_for_updates.unwrap_or(true)
+        && std::env::var_os(self_update::NO_UPDATE_CHECK_ENV).is_none()
+        && !matches!(cli.command, Command::SelfUpdate)
+        && !matches!(
+            cli.command,
+            Command::Outdated {
+                format: Format::Json,
+                ..
+            }
+        )
+        && std::io::stdout().is_terminal();
+
+    // Created up front, commands take ownership of the configuration. The
+    // check is skipped if the network settings are invalid.
+    let update_client = match check_for_updates {
+        true => config.client().ok(),
+        false => None,
+    };
+
+    let root = std::env::current_dir().wrap_err("Failed to locate project root")?;
+
+    let store = match config.vendor_dir {
+        Some(ref vendor_dir) => PackageStore::new(&root).with_dep_path(vendor_dir),
+        None => PackageStore::new(&root),
+    };
+
+    let usage_report = std::env::var_os(usage::USAGE_REPORT_ENV)
+        .map(PathBuf::from)
+        .or(config.usage_report.clone());
+
+    let packages = match usage_report {
+        Some(_) => usage::packages(&cli.command, &root).await,
+        None => vec![],
+    };
+
+    let cancel = CancellationToken::new();
+
+    tokio::spawn({
+        let cancel = cancel.clone();
+
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                cancel.cancel();
+            }
+        }
+    });
+
+    let started_at = SystemTime::now();
+    let started = Instant::now();
+
+    let result = match cli.command.clone() {
+        Command::Init { api, template } => match template {
+            Some(template) => cmd::init_from_template(&root, config, api, template).await,
+            None => cmd::init(&root, api).await,
+        },
+        Command::Add { dependency } => cmd::add(&root, config, dependency).await,
+        Command::Remove { package } => cmd::remove(&store, package).await,
+        Command::Info { package, readme } => cmd::info(config, package, readme).await,
+        Command::Search { term } => cmd::search(config, term).await,
+        Command::Publish {
+            repository,
+            package,
+            receipt,
+        } => cmd::publish(&store, config, repository, package, receipt, &cancel).await,
+        Command::Breaking {
+            repository,
+            against,
+        } => cmd::breaking(&store, config, repository, against).await,
+        Command::Archive {
+            package,
+            replacement,
+            message,
+            undo,
+        } => {
+            let archival = (!undo).then_some(Archival {
+                replacement,
+                message,
+            });
+
+            cmd::archive(config, package, archival).await
+        }
+        Command::Yank { package, undo, yes } => cmd::yank(config, package, !undo, yes).await,
+        Command::Install {
+            allow_quarantined,
+            recursive,
+            package,
+            keep_going,
+            locked,
+            timings,
+            language,
+            groups,
+            exclude_groups,
+            jobs,
+        } => {
+            let options = InstallOptions {
+                allow_quarantined,
+                keep_going,
+                locked,
+                language,
+                groups,
+                exclude_groups,
+                jobs,
+                ..Default::default()
+            };
+
+            cmd::install(
+                &store, config, options, recursive, package, timings, &cancel,
+            )
+            .await
+        }
+        Command::Update {
+            packages,
+            package,
+            to,
+            jobs,
+            yes,
+            no_interactive,
+            write_summary,
+        } => {
+            let mut options = InstallOptions {
+                update: Some(packages),
+                jobs,
+                ..Default::default()
+            };
+
+            if let Some((package, version)) = package.zip(to) {
+                options.update = Some(vec![package.clone()]);
+                options
+                    .pins
+                    .insert(package, VersionRequirement::Exact(version));
+            }
+
+            cmd::update(
+                &store,
+                config,
+                options,
+                yes,
+                !no_interactive,
+                write_summary,
+                &cancel,
+            )
+            .await
+        }
+        Command::Outdated { format, exit_code } => {
+            cmd::outdated(&store, config, format, exit_code).await
+        }
+        Command::Uninstall => cmd::uninstall(&store).await,
+        Command::Prune => cmd::prune(&store).await,
+        Command::Du => cmd::du(&store).await,
+        Command::Login {
+            url,
+            username,
+            token,
+            sso,
+            issuer,
+            client_id,
+        } => {
+            let authentication = match (token, sso) {
+                (Some(token), _) => Some(Authentication::from(token)),
+                // Identity providers issue access tokens, not passwords
+                (None, true) => Some(Authentication::Bearer),
+                (None, false) => None,
+            };
+            let sso = if sso { issuer.zip(client_id) } else { None };
+            let username = username.unwrap_or_default();
+            cmd::login(&config_path, config, url, username, authentication, sso).await
+        }
+        Command::Logout => cmd::logout(&config_path, config).await,
+        Command::Setup {
+            registry,
+            username,
+            from,
+            non_interactive,
+        } => cmd::setup(&config_path, registry, username, from, !non_interactive).await,
+        Command::SelfUpdate => cmd::self_update(config).await,
+        Command::Mirror {
+            from,
+            to,
+            from_username,
+            to_username,
+            packages,
+        } => {
+            let authentication = config.artifactory.as_ref().and_then(|a| a.authentication);
+            let credential_store = config.artifactory.as_ref().and_then(|a| a.credential_store);
+            let password_env = config
+                .artifactory
+                .as_ref()
+                .and_then(|a| a.password_env.clone());
+            let username = config.artifactory.as_ref().map(|a| a.username.clone());
+
+            let from = ArtifactoryConfig {
+                url: ArtifactoryConfig::canonicalize(from)?,
+                username: from_username
+                    .or(username.clone())
+                    .wrap_err("Pass --from-username or login to mirror from another registry")?,
+                publish_token_ttl: None,
+                sigv4: None,
+                layout: None,
+                authentication,
+                credential_store,
+                password_env: password_env.clone(),
+            };
+
+            let to = ArtifactoryConfig {
+                url: ArtifactoryConfig::canonicalize(to)?,
+                username: to_username
+                    .or(username)
+                    .wrap_err("Pass --to-username or login to mirror to another registry")?,
+                publish_token_ttl: None,
+                sigv4: None,
+                layout: None,
+                authentication,
+                credential_store,
+                password_env,
+            };
+
+            // Both registries share the connection pool
+            let client = config.client()?;
+            let retry = config.retry.unwrap_or_default();
+
+            let user = to.username.clone();
+
+            let from = Artifactory::with_client(from, client.clone()).with_retry(retry.clone());
+            let to = AuditedRegistry::new(
+                Artifactory::with_client(to, client).with_retry(retry),
+                config.audit.unwrap_or_default(),
+                user,
+            );
+
+            cmd::mirror(&store, from, to, packages, &cancel).await
+        }
+        Command::Env => cmd::env(&config_path, &store, &config),
+        Command::Generate { lang, out_dir } => cmd::generate(&store, lang, &out_dir).await,
+        Command::Decode { message, input } => cmd::decode(&store, &message, input).await,
+        Command::Encode {
+            message,
+            input,
+            output,
+        } => cmd::encode(&store, &message, input, output).await,
+        Command::Example {
+            message,
+            text,
+            seed,
+        } => cmd::example(&store, &message, text, seed).await,
+        Command::Lint => cmd::lint(&store).await,
+        Command::Fmt { check } => cmd::fmt(&store, check).await,
+        Command::Test { fixtures, message } => {
+            cmd::test(&store, &fixtures, message.as_deref()).await
+        }
+        Command::Export {
+            reflection_bundle,
+            registry,
+            output,
+        } => {
+            cmd::export(
+                &store,
+                config,
+                reflection_bundle,
+                registry,
+                &output,
+                &cancel,
+            )
+            .await
+        }
+        Command::Explain { code } => cmd::explain(code),
+        Command::Manifest { command } => match command {
+            ManifestCommand::Schema => cmd::manifest_schema(),
+            ManifestCommand::Validate { path } => {
+                let path = path.unwrap_or_else(|| root.join(MANIFEST_FILE));
+                cmd::manifest_validate(&path, &config.policies().await?).await
+            }
+            ManifestCommand::Get { key } => cmd::manifest_get(&root, &key).await,
+            ManifestCommand::Set { key, value, toml } => {
+                cmd::manifest_set(&root, &key, &value, toml).await
+            }
+        },
+        Command::Serve { listen } => cmd::serve(store, listen, cancel.clone()).await,
+        Command::Mock { services, listen } => {
+            cmd::mock(&store, services, listen, cancel.clone()).await
+        }
+        Command::Proxy { command } => match command {
+            ProxyCommand::Serve { listen, cache_dir } => {
+                cmd::proxy_serve(config, listen, cache_dir, cancel.clone()).await
+            }
+        },
+        Command::Lock { command } => match command {
+            LockCommand::Sign { key } => cmd::lock_sign(&store, &key).await,
+            LockCommand::Verify { allowed_signers } => {
+                cmd::lock_verify(&store, allowed_signers.or(config.allowed_signers)).await
+            }
+            LockCommand::Merge {
+                base,
+                ours,
+                theirs,
+                path,
+            } => cmd::lock_merge(config, &base, &ours, &theirs, path.as_deref()).await,
+        },
+        Command::Cache { command } => match command {
+            CacheCommand::Clean => cmd::cache_clean().await,
+            CacheCommand::Stats => cmd::cache_stats().await,
+        },
+        Command::Ide { command } => match command {
+            IdeCommand::Sync => cmd::ide_sync(&store, config).await,
+        },
+    };
+
+    let result = result.map_err(|report| match ErrorCode::of(&report) {
+        Some(code) => report.suggestion(format!(
+            "Run `buffrs explain {}` for details on this error",
+            code.code()
+        )),
+        None => report,
+    });
+
+    if let Some(path) = usage_report {
+        let report = UsageReport::new(
+            &cli.command,
+            started_at,
+            started.elapsed(),
+            result.is_ok(),
+            packages,
+        );
+
+        if let Err(error) = report.append(&path).await {
+            tracing::debug!("{error:#}");
+        }
+    }
+
+    if let Some(ref client) = update_client {
+        self_update::notify(client).await;
+    }
+
+    result
+}
+
+mod cmd {
+    use buffrs::{
+        config::{self, Config, ConfigDocument},
+        error::ErrorCode,
+        format,
+        generator::{self, Language},
+        ide::{self, EditorConfig},
+        lint,
+        lock::{self, Lockfile, LOCKFILE, SIGNATURE_FILE},
+        manifest::{self, ApiManifest, Dependency, Manifest, ManifestDocument, MANIFEST_FILE},
+        mock,
+        ops::{self, InstallOptions, InstallReporter, Lag},
+        package::{PackageCache, PackageId, PackageStore},
+        policy::Policy,
+        reflect::{self, Schema, Service},
+        registry::{
+            Archival, Artifactory, ArtifactoryConfig, Authentication, CachedRegistry,
+            CredentialStore, EncryptedRegistry, FileRegistry, FileRegistryConfig, LocalRegistry,
+            Receipt, Registry,
+        },
+        resolver::{self, VersionRequirement},
+        server,
+        spec::{PackageSpec, Versioned},
+        template::{Template, TemplateSource},
+        workspace::Workspace,
+    };
+    use eyre::{ensure, Context, ContextCompat};
+    use std::{
+        io::IsTerminal,
+        net::SocketAddr,
+        path::{Path, PathBuf},
+    };
+
+    use crate::progress::InstallProgress;
+    use crate::self_update::{self, Release};
+    use crate::sso;
+    use crate::timings::{InstallTimings, TIMINGS_FILE};
+    use crate::{Format, Summary, Threshold, Timings};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_util::sync::CancellationToken;
+
+    /// Summary written by `buffrs update --write-summary`
+    const UPDATE_SUMMARY_FILE: &str = "buffrs-update.md";
+    /// Descriptor set of a reflection bundle
+    const BUNDLE_PROTOSET: &str = "descriptors.protoset";
+    /// Service index of a reflection bundle
+    const BUNDLE_INDEX: &str = "services.json";
+
+    /// Initializes the project
+    pub async fn init(root: &Path, api: Option<PackageId>) -> eyre::Result<()> {
+        let mut manifest = Manifest::default();
+
+        if let Some(name) = api {
+            manifest.api = Some(ApiManifest {
+                name,
+                version: semver::Version::new(0, 0, 1),
+                description: None,
+                namespace: None,
+            });
+        }
+
+        ensure!(
+            !Manifest::exists(root).await?,
+            "Cant initialize existing project"
+        );
+
+        manifest.write(root).await
+    }
+
+    /// Initializes a project from a template
+    ///
+    /// The package name defaults to the name of the project directory.
+    pub async fn init_from_template(
+        root: &Path,
+        config: Config,
+        api: Option<PackageId>,
+        source: TemplateSource,
+    ) -> eyre::Result<()> {
+        ensure!(
+            !Manifest::exists(root).await?,
+            "Cant initialize existing project"
+        );
+
+        let name = match api {
+            Some(name) => name,
+            None => root
+                .file_name()
+                .wrap_err("Failed to determine the project name, pass --api")?
+                .to_string_lossy()
+                .to_lowercase()
+                .replace('_', "-")
+                .parse::<PackageId>()
+                .wrap_err("Failed to derive the package name from the directory, pass --api")?,
+        };
+
+        let version = semver::Version::new(0, 0, 1);
+
+        tracing::info!(":: fetching template {source}");
+
+        let template = Template::fetch(&source, &config.client()?).await?;
+
+        for file in template.render(root, &name, &version.to_string()).await? {
+            tracing::info!(
+                "+ created {}",
+                file.strip_prefix(root).unwrap_or(&file).display()
+            );
+        }
+
+        if !Manifest::exists(root).await? {
+            let manifest = Manifest {
+                api: Some(ApiManifest {
+                    name,
+                    version,
+                    description: None,
+                    namespace: None,
+                }),
+                ..Default::default()
+            };
+
+            manifest.write(root).await?;
+        }
+
+        Manifest::read(root)
+            .await
+            .wrap_err("Template produced an invalid manifest")?;
+
+        Ok(())
+    }
+
+    /// Parses a package specification given on the command line
+    fn parse_spec(spec: &str, versioned: Versioned) -> eyre::Result<PackageSpec> {
+        PackageSpec::parse(spec, versioned).map_err(|error| {
+            let code = error.code();
+            eyre::Report::new(error).wrap_err(code)
+        })
+    }
+
+    /// Parses a dependency specification (`<repository>/<package>@<version>`)
+    fn parse_dependency(dependency: &str) -> eyre::Result<Dependency> {
+        Ok(parse_spec(dependency, Versioned::Required)?.dependency())
+    }
+
+    /// Adds a dependency to this project
+    ///
+    /// Warns if the package was archived, provided you are logged in.
+    pub async fn add(root: &Path, config: Config, dependency: String) -> eyre::Result<()> {
+        let dependency = parse_dependency(&dependency)?;
+
+        let mut manifest = Manifest::read(root).await?;
+
+        manifest.dependencies.push(dependency.clone());
+
+        manifest.write(root).await?;
+
+        if let Some(registry) = config.registry()? {
+            let archival = registry
+                .archival(&dependency.manifest.repository, &dependency.package)
+                .await;
+
+            match archival {
+                Ok(Some(archival)) => tracing::warn!("{} is {archival}", dependency.package),
+                Ok(None) => {}
+                Err(error) => tracing::debug!("failed to look up archival: {error:#}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Archives a package, or restores it if `archival` is `None`
+    pub async fn archive(
+        config: Config,
+        package: String,
+        archival: Option<Archival>,
+    ) -> eyre::Result<()> {
+        let PackageSpec {
+            repository,
+            package,
+            ..
+        } = parse_spec(&package, Versioned::Never)?;
+
+        let repository = repository.as_str();
+
+        let registry = config
+            .registry()?
+            .wrap_err("Unable to archive packages, please login using `buffrs login`")
+            .wrap_err(ErrorCode::NotLoggedIn)?;
+
+        ensure!(
+            !registry.versions(repository, &package).await?.is_empty(),
+            "{package} was never published to {repository}"
+        );
+
+        registry
+            .archive(repository, &package, archival.as_ref())
+            .await?;
+
+        match archival {
+            Some(archival) => tracing::info!("+ {repository}/{package} is {archival}"),
+            None => tracing::info!("- {repository}/{package} is no longer archived"),
+        }
+
+        Ok(())
+    }
+
+    /// Reports breaking changes of the api package to a published release
+    ///
+    /// Fails unless the version of the api package announces them by a major
+    /// version bump.
+    pub async fn breaking(
+        store: &PackageStore,
+        config: Config,
+        repository: String,
+        against: Option<semver::Version>,
+    ) -> eyre::Result<()> {
+        let encryption = config.encryption.clone().unwrap_or_default();
+
+        let artifactory = {
+            let Some(registry) = config.registry()? else {
+                return Err(eyre::eyre!(
+                    "Unable to fetch published releases, please login using `buffrs login`"
+                ))
+                .wrap_err(ErrorCode::NotLoggedIn);
+            };
+
+            EncryptedRegistry::new(registry, encryption)
+        };
+
+        let Some(report) = ops::breaking(store, &artifactory, &repository, against).await? else {
+            tracing::info!(":: no earlier release to compare against");
+            return Ok(());
+        };
+
+        let (package, previous, version) = (&report.package, &report.previous, &report.version);
+
+        if report.changes.is_empty() {
+            tracing::info!(":: no breaking changes to {package}@{previous}");
+            return Ok(());
+        }
+
+        for change in &report.changes {
+            match report.is_announced() {
+                true => tracing::warn!("! {change}"),
+                false => tracing::error!("x {change}"),
+            }
+        }
+
+        ensure!(
+            report.is_announced(),
+            "Found {} breaking changes to {package}@{previous}, {version} has to bump the major version",
+            report.changes.len()
+        );
+
+        tracing::info!(
+            ":: {} breaking changes to {package}@{previous} are announced by {version}",
+            report.changes.len()
+        );
+
+        Ok(())
+    }
+
+    /// Yanks a version of a package, or restores it
+    pub async fn yank(
+        config: Config,
+        package: String,
+        yanked: bool,
+        yes: bool,
+    ) -> eyre::Result<()> {
+        let PackageSpec {
+            repository,
+            package,
+            version,
+        } = parse_spec(&package, Versioned::Exact)?;
+
+        let Some(VersionRequirement::Exact(version)) = version else {
+            unreachable!("parsing requires an exact version");
+        };
+
+        let repository = repository.as_str();
+
+        let registry = config
+            .registry()?
+            .wrap_err("Unable to yank packages, please login using `buffrs login`")
+            .wrap_err(ErrorCode::NotLoggedIn)?;
+
+        if yanked && !yes {
+            ensure!(
+                std::io::stdin().is_terminal(),
+                "Refusing to yank without confirmation, pass --yes"
+            );
+
+            tracing::info!(
+                "Yank {repository}/{package}@{version}? Projects that did not lock it can no longer install it [y/N]"
+            );
+
+            let mut answer = String::new();
+
+            std::io::stdin()
+                .read_line(&mut answer)
+                .wrap_err("Failed to read answer")?;
+
+            if !matches!(answer.trim(), "y" | "Y" | "yes") {
+                tracing::info!(":: nothing yanked");
+                return Ok(());
+            }
+        }
+
+        registry
+            .yank(repository, &package, &version, yanked)
+            .await?;
+
+        match yanked {
+            true => tracing::info!("- yanked {repository}/{package}@{version}"),
+            false => tracing::info!("+ restored {repository}/{package}@{version}"),
+        }
+
+        Ok(())
+    }
+
+    /// Removes a dependency from this project
+    pub async fn remove(store: &PackageStore, package: PackageId) -> eyre::Result<()> {
+        let mut manifest = Manifest::read(store.root()).await?;
+
+        let dependency = manifest
+            .dependencies
+            .iter()
+            .find(|d| d.package != package)
+            .wrap_err(eyre::eyre!(
+                "Unable to remove unknown dependency {package:?}"
+            ))?
+            .to_owned();
+
+        manifest.dependencies.retain(|d| *d != dependency);
+
+        store.uninstall(&dependency.package).await?;
+
+        manifest.write(store.root()).await
+    }
+
+    /// Searches the registry for packages
+    pub async fn search(config: Config, term: String) -> eyre::Result<()> {
+        let registry = config
+            .registry()?
+            .wrap_err("Unable to search the registry, please login using `buffrs login`")
+            .wrap_err(ErrorCode::NotLoggedIn)?;
+
+        let results = registry.search(term.trim()).await?;
+
+        if results.is_empty() {
+            tracing::info!(":: no packages found matching {}", term.trim());
+            return Ok(());
+        }
+
+        let mut rows = vec![[
+            "package".to_owned(),
+            "latest".to_owned(),
+            "repository".to_owned(),
+        ]];
+
+        rows.extend(results.iter().map(|r| {
+            [
+                r.package.to_string(),
+                r.latest.to_string(),
+                r.repository.clone(),
+            ]
+        }));
+
+        print_table(&rows);
+
+        Ok(())
+    }
+
+    /// Shows details of a published package
+    ///
+    /// Without a version, or with a version range, the latest matching
+    /// version is shown.
+    pub async fn info(config: Config, package: String, readme: bool) -> eyre::Result<()> {
+        let PackageSpec {
+            repository,
+            package,
+            version,
+        } = parse_spec(&package, Versioned::Optional)?;
+
+        let repository = repository.as_str();
+
+        let requirement = match version {
+            Some(version) => version,
+            None => "*".parse()?,
+        };
+
+        let encryption = config.encryption.clone().unwrap_or_default();
+
+        let artifactory = {
+            let Some(registry) = config.registry()? else {
+                return Err(eyre::eyre!(
+                    "Unable to fetch package information, please login using `buffrs login`"
+                ))
+                .wrap_err(ErrorCode::NotLoggedIn);
+            };
+
+            EncryptedRegistry::new(registry, encryption)
+        };
+
+        let versions = artifactory.versions(repository, &package).await?;
+
+        let version = match requirement {
+            VersionRequirement::Exact(ref version) => version.clone(),
+            VersionRequirement::Range(_) => requirement
+                .select(&versions)
+                .cloned()
+                .wrap_err_with(|| {
+                    format!("No version of {package} in {repository} matches {requirement}")
+                })
+                .wrap_err(ErrorCode::DownloadFailed)?,
+        };
+
+        let dependency = Dependency::new(
+            repository.to_owned(),
+            package,
+            VersionRequirement::Exact(version.clone()),
+        );
+
+        let downloaded = artifactory.download(dependency.clone()).await?;
+
+        if readme {
+            let readme = downloaded
+                .tgz
+                .readme()
+                .await?
+                .wrap_err_with(|| format!("{dependency} was published without a readme"))?;
+
+            match std::io::stdout().is_terminal() {
+                true => println!("{}", crate::markdown::render(&readme)),
+                false => println!("{}", readme.trim_end()),
+            }
+
+            return Ok(());
+        }
+
+        let files = downloaded.tgz.files().await?;
+
+        let description = files
+            .iter()
+            .find(|file| file.path == Path::new(MANIFEST_FILE))
+            .map(|file| String::from_utf8_lossy(&file.contents).parse::<Manifest>())
+            .transpose()?
+            .and_then(|manifest| manifest.api)
+            .and_then(|api| api.description);
+
+        let mut versions = versions;
+        versions.sort();
+
+        println!(":: {}@{} ({repository})", dependency.package, version);
+
+        // Not every registry user may read properties, which is no reason to fail
+        if let Ok(Some(archival)) = artifactory.archival(repository, &dependency.package).await {
+            println!("{archival}");
+        }
+
+        if let Some(description) = description {
+            println!("{description}");
+        }
+
+        println!(
+            "versions: {}",
+            versions
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        println!("files:");
+
+        for file in files {
+            println!("  {}", file.path.display());
+        }
+
+        Ok(())
+    }
+
+    /// Publishs the api package and `[[package]]` entries to the registry
+    ///
+    /// In the root of a workspace every member with packages is published,
+    /// unless a single package is selected. The receipts of all published
+    /// packages are written to `receipt`.
+    pub async fn publish(
+        store: &PackageStore,
+        config: Config,
+        repository: String,
+        package: Option<PackageId>,
+        receipt: Option<PathBuf>,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<()> {
+        let policies = config.policies().await?;
+        let client = config.client()?;
+        let encryption = config.encryption.clone().unwrap_or_default();
+
+        let artifactory = {
+            let Some(registry) = config.registry()? else {
+                return Err(eyre::eyre!(
+                    "Unable to publish package to artifactory, please login using `buffrs login`"
+                ))
+                .wrap_err(ErrorCode::NotLoggedIn);
+            };
+
+            EncryptedRegistry::new(registry, encryption)
+        };
+
+        let Some(workspace) = Workspace::discover(store.root()).await? else {
+            let receipts = publish_project(
+                store,
+                &artifactory,
+                repository,
+                &policies,
+                &client,
+                package.as_ref(),
+                cancel,
+            )
+            .await?;
+
+            return match receipt {
+                Some(path) => write_receipt(store.root(), &path, &receipts).await,
+                None => Ok(()),
+            };
+        };
+
+        workspace.check_namespaces().await?;
+
+        let members: Vec<PathBuf> = match package {
+            Some(ref package) => vec![workspace.member(package).await?.to_owned()],
+            None if workspace.root() == store.root() => {
+                let mut members = vec![];
+
+                for member in workspace.members() {
+                    let manifest = Manifest::read(member).await?;
+
+                    if manifest.api.is_some() || !manifest.packages.is_empty() {
+                        members.push(member.clone());
+                    }
+                }
+
+                ensure!(
+                    !members.is_empty(),
+                    "No member of the workspace has a package to publish"
+                );
+
+                members
+            }
+            None => vec![store.root().to_owned()],
+        };
+
+        let mut receipts = vec![];
+
+        for member in members {
+            tracing::info!(":: publishing {}", workspace.relative(&member).display());
+
+            receipts.extend(
+                publish_project(
+                    &PackageStore::new(&member),
+                    &artifactory,
+                    repository.clone(),
+                    &policies,
+                    &client,
+                    package.as_ref(),
+                    cancel,
+                )
+                .await?,
+            );
+        }
+
+        match receipt {
+            Some(path) => write_receipt(workspace.root(), &path, &receipts).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the receipts of published packages to `path`, along with the
+    /// commit checked out in `root`
+    async fn write_receipt(root: &Path, path: &Path, receipts: &[Receipt]) -> eyre::Result<()> {
+        #[derive(serde::Serialize)]
+        struct ReceiptFile<'a> {
+            git_sha: Option<String>,
+            packages: &'a [Receipt],
+        }
+
+        let git_sha = tokio::process::Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .current_dir(root)
+            .stderr(std::process::Stdio::null())
+            .output()
+            .await
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|sha| sha.trim().to_owned());
+
+        let receipt = ReceiptFile {
+            git_sha,
+            packages: receipts,
+        };
+
+        let json =
+            serde_json::to_string_pretty(&receipt).wrap_err("Failed to serialize receipt")?;
+
+        tokio::fs::write(path, json)
+            .await
+            .wrap_err_with(|| format!("Failed to write receipt {}", path.display()))?;
+
+        tracing::info!("+ wrote {}", path.display());
+
+        Ok(())
+    }
+
+    /// Publishes the api package and `[[package]]` entries of a project, or
+    /// only the one named `package`
+    async fn publish_project(
+        store: &PackageStore,
+        registry: &(impl Registry + Sync),
+        repository: String,
+        policies: &[Policy],
+        client: &reqwest::Client,
+        package: Option<&PackageId>,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<Vec<Receipt>> {
+        let manifest = Manifest::read(store.root()).await?;
+
+        let api = match package {
+            Some(package) => manifest.api.as_ref().is_some_and(|a| &a.name == package),
+            // Without any packages, publishing reports the missing api
+            None => manifest.api.is_some() || manifest.packages.is_empty(),
+        };
+
+        let packages: Vec<&PackageId> = manifest
+            .packages
+            .iter()
+            .map(|p| &p.api.name)
+            .filter(|name| package.is_none_or(|package| package == *name))
+            .collect();
+
+        if let Some(package) = package {
+            ensure!(
+                api || !packages.is_empty(),
+                "Unable to select {package}, the project does not publish it"
+            );
+        }
+
+        let mut receipts = vec![];
+
+        if api {
+            receipts.push(
+                ops::publish(
+                    store,
+                    registry,
+                    repository.clone(),
+                    policies,
+                    client,
+                    cancel,
+                )
+                .await?,
+            );
+        }
+
+        for package in packages {
+            receipts.push(
+                ops::publish_package(
+                    store,
+                    package,
+                    registry,
+                    repository.clone(),
+                    policies,
+                    client,
+                    cancel,
+                )
+                .await?,
+            );
+        }
+
+        Ok(receipts)
+    }
+
+    /// Installs dependencies
+    ///
+    /// The policies of the configuration are added to `options`.
+    pub async fn install(
+        store: &PackageStore,
+        config: Config,
+        options: InstallOptions,
+        recursive: bool,
+        package: Option<PackageId>,
+        timings: Option<Timings>,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<()> {
+        let reporter = InstallTimings::new();
+        let progress = InstallProgress::new();
+
+        let result = install_with(
+            store,
+            config,
+            options,
+            recursive,
+            package,
+            &(&reporter, &progress),
+            cancel,
+        )
+        .await;
+
+        progress.finish();
+        result?;
+
+        tracing::info!(":: {}", reporter.summary());
+
+        if let Some(Timings::Html) = timings {
+            let path = store.root().join(TIMINGS_FILE);
+
+            reporter.write_html(&path).await?;
+
+            tracing::info!("+ wrote {}", path.display());
+        }
+
+        Ok(())
+    }
+
+    async fn install_with(
+        store: &PackageStore,
+        config: Config,
+        options: InstallOptions,
+        recursive: bool,
+        package: Option<PackageId>,
+        reporter: &dyn InstallReporter,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<()> {
+        let options = InstallOptions {
+            policies: config.policies().await?,
+            cache: Some(PackageCache::open()?),
+            allowed_signers: config.allowed_signers.clone(),
+            client: config.client()?,
+            ..options
+        };
+
+        let dep_path = config
+            .vendor_dir
+            .clone()
+            .unwrap_or_else(|| PackageStore::PROTO_DEP_PATH.into());
+
+        let encryption = config.encryption.clone().unwrap_or_default();
+
+        let artifactory = {
+            let Some(registry) = config.registry()? else {
+                return Err(eyre::eyre!(
+                    "Unable to install artifactory dependencies, please login using `buffrs login`"
+                ))
+                .wrap_err(ErrorCode::NotLoggedIn);
+            };
+
+            EncryptedRegistry::new(registry, encryption)
+        };
+
+        if let (false, Some(workspace)) = (recursive, Workspace::discover(store.root()).await?) {
+            let members: Vec<&Path> = match package {
+                Some(ref package) => vec![workspace.member(package).await?],
+                None if workspace.root() == store.root() => {
+                    workspace.members().iter().map(PathBuf::as_path).collect()
+                }
+                None => vec![store.root()],
+            };
+
+            let stores: Vec<PackageStore> = members
+                .into_iter()
+                .map(|member| PackageStore::new(member).with_dep_path(&dep_path))
+                .collect();
+
+            ops::install_workspace(
+                &workspace,
+                &stores,
+                &artifactory,
+                &options,
+                reporter,
+                cancel,
+            )
+            .await?;
+
+            for store in &stores {
+                sync_editor_config(store).await?;
+            }
+
+            return Ok(());
+        }
+
+        if let Some(package) = package {
+            eyre::bail!("Unable to select {package}, not in a workspace");
+        }
+
+        if !recursive {
+            let manifest = Manifest::read(store.root()).await?;
+
+            ops::install(store, &manifest, &artifactory, &options, reporter, cancel).await?;
+
+            return sync_editor_config(store).await;
+        }
+
+        let stores: Vec<PackageStore> = ops::discover(store.root(), &dep_path)?
+            .into_iter()
+            .map(|project| PackageStore::new(project).with_dep_path(&dep_path))
+            .collect();
+
+        ensure!(
+            !stores.is_empty(),
+            "No projects found below {}",
+            store.root().display()
+        );
+
+        let results = ops::install_all(&stores, &artifactory, &options, reporter, cancel).await;
+
+        let mut failed = 0;
+
+        for (project, result) in stores.iter().zip(results) {
+            let path = project
+                .root()
+                .strip_prefix(store.root())
+                .unwrap_or(project.root());
+
+            let path = match path.as_os_str().is_empty() {
+                true => Path::new("."),
+                false => path,
+            };
+
+            match result.and(sync_editor_config(project).await) {
+                Ok(()) => tracing::info!("+ installed {}", path.display()),
+                Err(error) => {
+                    failed += 1;
+                    tracing::error!("x failed to install {}: {error:#}", path.display());
+                }
+            }
+        }
+
+        ensure!(
+            failed == 0,
+            "Failed to install {failed} of {} projects",
+            stores.len()
+        );
+
+        Ok(())
+    }
+
+    /// Updates dependencies, asking before a package is updated to a major
+    /// release or a version conflicting with other workspace members
+    ///
+    /// With `yes` every update is taken. Without `interactive` or a terminal
+    /// to ask on, such packages keep their current major version, or their
+    /// current version if it would conflict.
+    ///
+    /// The changed versions are written to a `summary` next to the lockfile
+    /// if requested.
+    pub async fn update(
+        store: &PackageStore,
+        config: Config,
+        mut options: InstallOptions,
+        yes: bool,
+        interactive: bool,
+        summary: Option<Summary>,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<()> {
+        let lock_root = lock_root(store).await?;
+
+        let previous = match Lockfile::exists(&lock_root).await? {
+            true => Some(Lockfile::read(&lock_root).await?),
+            false => None,
+        };
+
+        if !yes {
+            let registry = config
+                .registry()?
+                .wrap_err("Unable to update dependencies, please login using `buffrs login`")
+                .wrap_err(ErrorCode::NotLoggedIn)?;
+
+            let dep_path = config
+                .vendor_dir
+                .clone()
+                .unwrap_or_else(|| PackageStore::PROTO_DEP_PATH.into());
+
+            let stores: Vec<PackageStore> = match Workspace::discover(store.root()).await? {
+                Some(workspace) if workspace.root() == store.root() => workspace
+                    .members()
+                    .iter()
+                    .map(|member| PackageStore::new(member).with_dep_path(&dep_path))
+                    .collect(),
+                _ => vec![PackageStore::new(store.root()).with_dep_path(&dep_path)],
+            };
+
+            let interactive = interactive && std::io::stdin().is_terminal();
+
+            for update in ops::plan_update(&stores, &lock_root, &registry, &options).await? {
+                // Versions requested explicitly are taken as they are
+                if options.pins.contains_key(&update.package) {
+                    continue;
+                }
+
+                let reason = match (update.conflicts.is_empty(), update.is_breaking()) {
+                    (true, false) => continue,
+                    (true, true) => "a major release".to_owned(),
+                    (false, _) => format!("in conflict with {}", update.conflicts.join(", ")),
+                };
+
+                let question = format!(
+                    "Update {} {} -> {}? It is {reason} [y/N]",
+                    update.package, update.current, update.version
+                );
+
+                if interactive && confirm(&question)? {
+                    continue;
+                }
+
+                tracing::warn!(
+                    "! kept {} at {}, {} is {reason} (pass --yes to take it)",
+                    update.package,
+                    update.current,
+                    update.version
+                );
+
+                let pin = match update.conflicts.is_empty() {
+                    true => VersionRequirement::Range(ops::compatible(&update.current)),
+                    false => VersionRequirement::Exact(update.current),
+                };
+
+                options.pins.insert(update.package, pin);
+            }
+        }
+
+        install(store, config.clone(), options, false, None, None, cancel).await?;
+
+        let Some(Summary::Md) = summary else {
+            return Ok(());
+        };
+
+        let updated = Lockfile::read(&lock_root).await?;
+        let path = lock_root.join(UPDATE_SUMMARY_FILE);
+
+        tokio::fs::write(
+            &path,
+            update_summary(&config, &ops::updated_packages(previous.as_ref(), &updated)),
+        )
+        .await
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+
+        tracing::info!("+ wrote {}", path.display());
+
+        Ok(())
+    }
+
+    /// Renders the changed versions of an update as Markdown table
+    ///
+    /// Both versions link to their archives in the configured registry, so
+    /// reviewers can compare them.
+    fn update_summary(config: &Config, updates: &[ops::UpdatedPackage]) -> String {
+        let mut summary = "# Dependency updates\n\n".to_owned();
+
+        if updates.is_empty() {
+            summary.push_str("All dependencies are up to date.\n");
+            return summary;
+        }
+
+        summary.push_str("| Package | Repository | From | To | Change | Links |\n");
+        summary.push_str("| --- | --- | --- | --- | --- | --- |\n");
+
+        for update in updates {
+            let previous = update
+                .previous
+                .as_ref()
+                .map_or_else(|| "-".to_owned(), ToString::to_string);
+
+            let change = match update.change() {
+                Some(Lag::Major) => "major",
+                Some(Lag::Minor) => "minor",
+                Some(Lag::Patch) => "patch",
+                None => "added",
+            };
+
+            let link = |version: &semver::Version| {
+                config
+                    .archive_url(&update.repository, &update.package, version)
+                    .ok()
+                    .flatten()
+                    .map(|url| format!("[{version}]({url})"))
+            };
+
+            let links = update
+                .previous
+                .iter()
+                .chain([&update.version])
+                .filter_map(link)
+                .collect::<Vec<_>>();
+
+            let links = match links.is_empty() {
+                true => "-".to_owned(),
+                false => links.join(" → "),
+            };
+
+            summary.push_str(&format!(
+                "| {} | {} | {previous} | {} | {change} | {links} |\n",
+                update.package, update.repository, update.version
+            ));
+        }
+
+        summary
+    }
+
+    /// Asks a yes or no question on the terminal, defaulting to no
+    fn confirm(question: &str) -> eyre::Result<bool> {
+        tracing::info!("{question}");
+
+        let mut answer = String::new();
+
+        std::io::stdin()
+            .read_line(&mut answer)
+            .wrap_err("Failed to read answer")?;
+
+        Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+    }
+
+    /// Reports dependencies with newer versions in the registry
+    ///
+    /// Fails if a dependency lags behind by at least `threshold`.
+    pub async fn outdated(
+        store: &PackageStore,
+        config: Config,
+        format: Format,
+        threshold: Option<Threshold>,
+    ) -> eyre::Result<()> {
+        let registry = config
+            .registry()?
+            .wrap_err("Unable to query the registry, please login using `buffrs login`")
+            .wrap_err(ErrorCode::NotLoggedIn)?;
+
+        let manifest = Manifest::read(store.root()).await?;
+
+        let lock_root = lock_root(store).await?;
+
+        let outdated: Vec<_> = ops::outdated(store, &manifest, &lock_root, &registry)
+            .await?
+            .into_iter()
+            .filter(|d| d.is_outdated())
+            .collect();
+
+        let lagging = match threshold {
+            Some(threshold) => outdated
+                .iter()
+                .filter(|d| d.lag() >= Some(threshold.into()))
+                .count(),
+            None => 0,
+        };
+
+        if format == Format::Json {
+            println!("{}", serde_json::to_string_pretty(&outdated)?);
+            return ensure_fresh(lagging, threshold);
+        }
+
+        if outdated.is_empty() {
+            tracing::info!(":: all dependencies are up to date");
+            return Ok(());
+        }
+
+        let version = |version: &Option<semver::Version>| {
+            version
+                .as_ref()
+                .map_or_else(|| "-".to_owned(), ToString::to_string)
+        };
+
+        let mut rows = vec![[
+            "package".to_owned(),
+            "current".to_owned(),
+            "compatible".to_owned(),
+            "latest".to_owned(),
+            "requirement".to_owned(),
+        ]];
+
+        rows.extend(outdated.iter().map(|d| {
+            [
+                d.package.to_string(),
+                version(&d.current),
+                version(&d.compatible),
+                version(&d.latest),
+                d.requirement.to_string(),
+            ]
+        }));
+
+        print_table(&rows);
+
+        ensure_fresh(lagging, threshold)
+    }
+
+    /// Fails if `lagging` dependencies exceed the threshold of `buffrs
+    /// outdated --exit-code`
+    fn ensure_fresh(lagging: usize, threshold: Option<Threshold>) -> eyre::Result<()> {
+        let behind = match threshold {
+            Some(Threshold::Any) => "behind its latest release",
+            Some(Threshold::Minor) => "a minor release or more behind",
+            Some(Threshold::Major) => "a major release or more behind",
+            None => return Ok(()),
+        };
+
+        ensure!(
+            lagging == 0,
+            "{lagging} {} {behind}",
+            match lagging {
+                1 => "dependency lags",
+                _ => "dependencies lag",
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Writes the descriptor set and `buffrs.json` of a project for editors
+    ///
+    /// Protos that do not compile, e.g. because they are being edited, only
+    /// leave out the descriptor set instead of failing the installation.
+    async fn sync_editor_config(store: &PackageStore) -> eyre::Result<()> {
+        let descriptor_set = match Schema::compile(store).await {
+            Ok(schema) => {
+                let path = store.root().join(ide::DESCRIPTOR_SET);
+
+                tokio::fs::write(&path, schema.descriptor_set())
+                    .await
+                    .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+
+                true
+            }
+            Err(error) => {
+                tracing::debug!("not writing descriptor set: {error:#}");
+
+                // Removes a stale descriptor set of a previous installation
+                let _ = tokio::fs::remove_file(store.root().join(ide::DESCRIPTOR_SET)).await;
+
+                false
+            }
+        };
+
+        EditorConfig::new(store, descriptor_set)
+            .write(store.root())
+            .await
+    }
+
+    /// Writes the include paths of the project to the VS Code settings
+    pub async fn ide_sync(store: &PackageStore, config: Config) -> eyre::Result<()> {
+        let store = match config.vendor_dir {
+            Some(ref dir) => store.clone().with_dep_path(dir),
+            None => store.clone(),
+        };
+
+        match ide::sync_vscode(&store).await? {
+            true => tracing::info!("+ updated {}", ide::VSCODE_SETTINGS),
+            false => tracing::info!(":: {} is up to date", ide::VSCODE_SETTINGS),
+        }
+
+        Ok(())
+    }
+
+    /// Logs rows of cells as left aligned columns
+    fn print_table<const N: usize>(rows: &[[String; N]]) {
+        let widths: Vec<usize> = (0..N)
+            .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+            .collect();
+
+        for row in rows {
+            let line: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect();
+
+            println!("{}", line.join("  ").trim_end());
+        }
+    }
+
+    /// Uninstalls dependencies
+    pub async fn uninstall(store: &PackageStore) -> eyre::Result<()> {
+        store.clear().await
+    }
+
+    /// Removes installed packages that are no longer declared as dependency
+    pub async fn prune(store: &PackageStore) -> eyre::Result<()> {
+        let manifest = Manifest::read(store.root()).await?;
+
+        let reclaimed = ops::prune(store, &manifest).await?;
+
+        tracing::info!(":: reclaimed {}", format_size(reclaimed));
+
+        Ok(())
+    }
+
+    /// Logs you in for a registry
+    ///
+    /// With `sso` set to an issuer and client id the token is obtained from the
+    /// identity provider instead of being prompted for.
+    pub async fn login(
+        config_path: &Path,
+        config: Config,
+        url: url::Url,
+        username: String,
+        authentication: Option<Authentication>,
+        sso: Option<(url::Url, String)>,
+    ) -> eyre::Result<()> {
+        // Edit the configuration as written, so environment variables it
+        // refers to are not persisted. It is read up front, so a broken
+        // configuration fails before asking for credentials.
+        let mut document = ConfigDocument::read_at(config_path).await?;
+
+        let password = match sso {
+            Some((issuer, client_id)) => {
+                sso::device_login(&config.client()?, &issuer, &client_id).await?
+            }
+            None => {
+                tracing::info!("Please enter your artifactory token:");
+
+                let mut password = String::new();
+
+                std::io::stdin()
+                    .read_line(&mut password)
+                    .wrap_err("Failed to read token")?;
+
+                password.trim().to_owned()
+            }
+        };
+
+        let credential_store = config.artifactory.and_then(|a| a.credential_store);
+
+        let mut artifactory = ArtifactoryConfig::new(url, username, password, credential_store)?;
+
+        artifactory.authentication = authentication;
+
+        document.login(&artifactory)?;
+        document.write_at(config_path).await
+    }
+
+    /// Logs you out from a registry
+    pub async fn logout(config_path: &Path, config: Config) -> eyre::Result<()> {
+        if let Some(cfg) = config.artifactory {
+            cfg.clear()?;
+        }
+
+        let mut document = ConfigDocument::read_at(config_path).await?;
+
+        document.logout();
+        document.write_at(config_path).await
+    }
+
+    /// Writes an initial configuration without credentials
+    ///
+    /// Settings of an existing configuration are kept unless they are
+    /// overridden by the provisioning file or flags.
+    pub async fn setup(
+        config_path: &Path,
+        registry: Option<url::Url>,
+        username: Option<String>,
+        from: Option<PathBuf>,
+        interactive: bool,
+    ) -> eyre::Result<()> {
+        ensure!(
+            interactive || registry.is_some() || from.is_some(),
+            "Nothing to set up, pass --registry or --from"
+        );
+
+        let mut document = ConfigDocument::read_at(config_path).await?;
+
+        if let Some(ref from) = from {
+            ensure!(
+                Config::exists_at(from).await?,
+                "Failed to read config {}, it does not exist",
+                from.display()
+            );
+
+            document.merge(ConfigDocument::read_at(from).await?);
+        }
+
+        let prompt = |question: &str| -> eyre::Result<String> {
+            tracing::info!("{question}");
+
+            let mut answer = String::new();
+
+            std::io::stdin()
+                .read_line(&mut answer)
+                .wrap_err("Failed to read answer")?;
+
+            Ok(answer.trim().to_owned())
+        };
+
+        // Configured values may refer to environment variables, they are kept
+        // as written unless overridden
+        let url = match (registry, document.get("artifactory.url")) {
+            (Some(url), _) => Some(url),
+            (None, Some(_)) => None,
+            (None, None) if interactive => Some(
+                prompt("Please enter the artifactory url:")?
+                    .parse()
+                    .wrap_err("Invalid artifactory url")?,
+            ),
+            (None, None) => eyre::bail!("No registry configured, pass --registry"),
+        };
+
+        if let Some(url) = url {
+            document.set(
+                "artifactory.url",
+                ArtifactoryConfig::canonicalize(url)?.as_str(),
+            )?;
+        }
+
+        let username = match (username, document.get("artifactory.username")) {
+            (Some(username), _) => Some(username),
+            (None, Some(username)) if !username.is_empty() => None,
+            (None, _) if interactive => Some(prompt("Please enter your artifactory username:")?),
+            (None, _) => Some(String::new()),
+        };
+
+        if let Some(username) = username {
+            document.set("artifactory.username", &username)?;
+        }
+
+        document.write_at(config_path).await?;
+
+        tracing::info!("+ wrote {}", config_path.display());
+
+        Ok(())
+    }
+
+    /// Updates buffrs to the latest release
+    pub async fn self_update(config: Config) -> eyre::Result<()> {
+        let client = config.client()?;
+
+        let release = Release::latest(&client).await?;
+
+        let current = self_update::current_version();
+        let latest = release.version()?;
+
+        if latest <= current {
+            tracing::info!("buffrs {current} is up to date");
+            return Ok(());
+        }
+
+        release.install(&client).await?;
+
+        tracing::info!("+ updated buffrs {current} -> {latest}");
+
+        Ok(())
+    }
+
+    /// Reads a file, or stdin if none is given
+    async fn read_input(input: Option<PathBuf>) -> eyre::Result<Vec<u8>> {
+        match input {
+            Some(path) => tokio::fs::read(&path)
+                .await
+                .wrap_err_with(|| format!("Failed to read {}", path.display())),
+            None => {
+                let mut bytes = vec![];
+
+                tokio::io::stdin()
+                    .read_to_end(&mut bytes)
+                    .await
+                    .wrap_err("Failed to read stdin")?;
+
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Generates code for the api and installed dependencies
+    pub async fn generate(
+        store: &PackageStore,
+        language: Language,
+        out_dir: &Path,
+    ) -> eyre::Result<()> {
+        generator::generate(store, language, &store.root().join(out_dir)).await
+    }
+
+    /// Decodes a binary message to JSON
+    pub async fn decode(
+        store: &PackageStore,
+        message: &str,
+        input: Option<PathBuf>,
+    ) -> eyre::Result<()> {
+        let schema = Schema::compile(store).await?;
+
+        let json = schema.decode(message, &read_input(input).await?)?;
+
+        println!("{json}");
+
+        Ok(())
+    }
+
+    /// Encodes a JSON message in the binary format
+    pub async fn encode(
+        store: &PackageStore,
+        message: &str,
+        input: Option<PathBuf>,
+        output: Option<PathBuf>,
+    ) -> eyre::Result<()> {
+        let schema = Schema::compile(store).await?;
+
+        let json = String::from_utf8(read_input(input).await?).wrap_err("Input is not UTF-8")?;
+
+        let bytes = schema.encode(message, &json)?;
+
+        match output {
+            Some(path) => tokio::fs::write(&path, bytes)
+                .await
+                .wrap_err_with(|| format!("Failed to write {}", path.display())),
+            None => {
+                let mut stdout = tokio::io::stdout();
+
+                stdout.write_all(&bytes).await?;
+                stdout.flush().await.wrap_err("Failed to write stdout")
+            }
+        }
+    }
+
+    /// Prints an example message
+    pub async fn example(
+        store: &PackageStore,
+        message: &str,
+        text: bool,
+        seed: Option<u64>,
+    ) -> eyre::Result<()> {
+        let schema = Schema::compile(store).await?;
+
+        let example = schema.example(message, seed)?;
+
+        let example = match text {
+            true => reflect::to_text(&example).trim_end().to_owned(),
+            false => serde_json::to_string_pretty(&example)
+                .wrap_err("Failed to convert example to JSON")?,
+        };
+
+        println!("{example}");
+
+        Ok(())
+    }
+
+    /// Checks the protos of the project and its dependencies
+    pub async fn lint(store: &PackageStore) -> eyre::Result<()> {
+        let manifest = Manifest::read(store.root()).await?;
+
+        let rules =
+            lint::rules(&manifest.lint.unwrap_or_default()).wrap_err(ErrorCode::InvalidManifest)?;
+
+        let schema = Schema::compile(store).await?;
+        let sources = lint::sources(store).await?;
+
+        let violations = lint::lint(&schema, &rules, &sources);
+
+        for violation in &violations {
+            tracing::error!("x {violation}");
+        }
+
+        ensure!(
+            violations.is_empty(),
+            "Found {} violations",
+            violations.len()
+        );
+
+        tracing::info!(":: no violations found");
+
+        Ok(())
+    }
+
+    /// Formats the protos of the api package, or only checks whether they are
+    /// formatted
+    ///
+    /// Installed dependencies are never touched.
+    pub async fn fmt(store: &PackageStore, check: bool) -> eyre::Result<()> {
+        let mut protos: Vec<PathBuf> = walkdir::WalkDir::new(store.api_path())
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|e| e == "proto"))
+            .map(|entry| entry.into_path())
+            .collect();
+
+        protos.sort();
+
+        let mut unformatted = 0;
+
+        for proto in &protos {
+            let name = proto.strip_prefix(store.root()).unwrap_or(proto).display();
+
+            let source = tokio::fs::read_to_string(proto)
+                .await
+                .wrap_err_with(|| format!("Failed to read {name}"))?;
+
+            let formatted =
+                format::format(&source).wrap_err_with(|| format!("Failed to format {name}"))?;
+
+            if formatted == source {
+                continue;
+            }
+
+            unformatted += 1;
+
+            if check {
+                tracing::error!("x {name} is not formatted");
+                continue;
+            }
+
+            tokio::fs::write(proto, formatted)
+                .await
+                .wrap_err_with(|| format!("Failed to write {name}"))?;
+
+            tracing::info!("+ formatted {name}");
+        }
+
+        ensure!(
+            !check || unformatted == 0,
+            "{unformatted} protos are not formatted, run buffrs fmt"
+        );
+
+        if unformatted == 0 {
+            tracing::info!(":: all {} protos are formatted", protos.len());
+        }
+
+        Ok(())
+    }
+
+    /// Checks fixtures against their message types
+    pub async fn test(
+        store: &PackageStore,
+        fixtures: &Path,
+        message: Option<&str>,
+    ) -> eyre::Result<()> {
+        let schema = Schema::compile(store).await?;
+
+        let fixtures = reflect::fixtures(fixtures)?;
+
+        ensure!(!fixtures.is_empty(), "No fixtures found");
+
+        let mut failed = 0;
+
+        for fixture in &fixtures {
+            let result = async {
+                let contents = tokio::fs::read_to_string(fixture)
+                    .await
+                    .wrap_err("Failed to read fixture")?;
+
+                schema.check_fixture(fixture, &contents, message)
+            }
+            .await;
+
+            match result {
+                Ok(message) => tracing::info!("+ {} ({message})", fixture.display()),
+                Err(error) => {
+                    failed += 1;
+                    tracing::error!("x {}: {error:#}", fixture.display());
+                }
+            }
+        }
+
+        ensure!(
+            failed == 0,
+            "{failed} of {} fixtures are invalid",
+            fixtures.len()
+        );
+
+        Ok(())
+    }
+
+    /// Exports the installed protos as reflection bundle, or the locked
+    /// packages as registry directory
+    pub async fn export(
+        store: &PackageStore,
+        config: Config,
+        reflection_bundle: bool,
+        registry: bool,
+        output: &Path,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<()> {
+        ensure!(
+            reflection_bundle || registry,
+            "Nothing to export, pass --reflection-bundle or --registry"
+        );
+
+        if registry {
+            export_registry(store, config, output, cancel).await?;
+        }
+
+        if reflection_bundle {
+            export_bundle(store, output).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Copies the archives of all locked packages from the registry into
+    /// `output`, laid out like the registry
+    async fn export_registry(
+        store: &PackageStore,
+        config: Config,
+        output: &Path,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<()> {
+        let registry = config
+            .registry()?
+            .wrap_err("Unable to export packages, please login using `buffrs login`")
+            .wrap_err(ErrorCode::NotLoggedIn)?;
+
+        let lock_root = lock_root(store).await?;
+
+        ensure!(
+            Lockfile::exists(&lock_root).await?,
+            "Nothing to export, run `buffrs install` to lock the dependencies first"
+        );
+
+        let dependencies: Vec<Dependency> = Lockfile::read(&lock_root)
+            .await?
+            .packages()
+            .iter()
+            .map(|locked| {
+                Dependency::new(
+                    locked.repository.clone(),
+                    locked.name.clone(),
+                    VersionRequirement::Exact(locked.version.clone()),
+                )
+            })
+            .collect();
+
+        let directory = FileRegistry::from(FileRegistryConfig {
+            path: output.to_owned(),
+            layout: None,
+        });
+
+        ops::mirror(&dependencies, &registry, &directory, cancel).await?;
+
+        let output = tokio::fs::canonicalize(output)
+            .await
+            .unwrap_or_else(|_| output.to_owned());
+
+        tracing::info!(
+            ":: exported {} {}, install with --registry-url file://{}",
+            dependencies.len(),
+            match dependencies.len() {
+                1 => "package",
+                _ => "packages",
+            },
+            output.display()
+        );
+
+        Ok(())
+    }
+
+    /// Writes the descriptor set of the installed protos and an index of
+    /// their services
+    async fn export_bundle(store: &PackageStore, output: &Path) -> eyre::Result<()> {
+        let schema = Schema::compile(store).await?;
+
+        let services = schema.services();
+
+        #[derive(serde::Serialize)]
+        struct Index<'a> {
+            protoset: &'a str,
+            services: &'a [Service],
+        }
+
+        let index = Index {
+            protoset: BUNDLE_PROTOSET,
+            services: &services,
+        };
+
+        tokio::fs::create_dir_all(output)
+            .await
+            .wrap_err_with(|| format!("Failed to create {}", output.display()))?;
+
+        let files = [
+            (output.join(BUNDLE_PROTOSET), schema.descriptor_set()),
+            (
+                output.join(BUNDLE_INDEX),
+                serde_json::to_vec_pretty(&index)?,
+            ),
+        ];
+
+        for (path, contents) in files {
+            tokio::fs::write(&path, contents)
+                .await
+                .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+
+            tracing::info!("+ wrote {}", path.display());
+        }
+
+        tracing::info!(
+            ":: exported {} services, e.g. grpcurl -protoset {} <host> list",
+            services.len(),
+            output.join(BUNDLE_PROTOSET).display()
+        );
+
+        Ok(())
+    }
+
+    /// Explains an error code
+    pub fn explain(code: Option<ErrorCode>) -> eyre::Result<()> {
+        match code {
+            Some(code) => println!("{}", code.explanation()),
+            None => {
+                for code in ErrorCode::ALL {
+                    println!("{}  {}", code.code(), code.summary());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Prints the JSON Schema of the manifest format
+    pub fn manifest_schema() -> eyre::Result<()> {
+        let schema = serde_json::to_string_pretty(&manifest::schema())?;
+
+        println!("{schema}");
+
+        Ok(())
+    }
+
+    /// Validates a manifest file
+    ///
+    /// The metadata of the api package is checked against the publish rules
+    /// of the policies, so violations surface before publishing.
+    pub async fn manifest_validate(path: &Path, policies: &[Policy]) -> eyre::Result<()> {
+        let manifest: Manifest = tokio::fs::read_to_string(path)
+            .await
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))
+            .wrap_err(ErrorCode::MissingManifest)?
+            .parse()?;
+
+        resolver::resolve(&manifest).wrap_err(ErrorCode::InvalidManifest)?;
+
+        if let Some(ref api) = manifest.api {
+            for policy in policies {
+                policy.check_publish(api)?;
+            }
+        }
+
+        tracing::info!("{} is valid", path.display());
+
+        Ok(())
+    }
+
+    /// Prints a field of the manifest
+    pub async fn manifest_get(root: &Path, key: &str) -> eyre::Result<()> {
+        let value = ManifestDocument::read(root).await?.get(key)?;
+
+        println!("{value}");
+
+        Ok(())
+    }
+
+    /// Sets a field of the manifest
+    pub async fn manifest_set(root: &Path, key: &str, value: &str, toml: bool) -> eyre::Result<()> {
+        let mut manifest = ManifestDocument::read(root).await?;
+
+        manifest.set(key, value, toml)?;
+
+        manifest.write(root).await
+    }
+
+    /// Copies packages between registries
+    ///
+    /// Packages have to be given with exact versions. Dependencies of the
+    /// project are mirrored at their locked versions, or else the highest
+    /// version matching their requirement.
+    pub async fn mirror(
+        store: &PackageStore,
+        from: Artifactory,
+        to: impl Registry + Send + Sync,
+        packages: Vec<String>,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<()> {
+        let dependencies: Vec<Dependency> = match packages.is_empty() {
+            true => {
+                // Local and git packages are not published anywhere
+                let requested: Vec<Dependency> = Manifest::read(store.root())
+                    .await?
+                    .dependencies
+                    .into_iter()
+                    .filter(|d| d.manifest.is_registry())
+                    .collect();
+
+                let lock_root = lock_root(store).await?;
+
+                ops::resolve_versions(store.root(), &lock_root, &requested, &from).await?
+            }
+            false => packages
+                .iter()
+                .map(|package| Ok(parse_spec(package, Versioned::Exact)?.dependency()))
+                .collect::<eyre::Result<_>>()?,
+        };
+
+        ops::mirror(&dependencies, &from, &to, cancel).await
+    }
+
+    /// Runs a caching proxy in front of the configured registry
+    pub async fn proxy_serve(
+        config: Config,
+        listen: SocketAddr,
+        cache_dir: Option<PathBuf>,
+        cancel: CancellationToken,
+    ) -> eyre::Result<()> {
+        let Some(registry) = config.registry()? else {
+            return Err(eyre::eyre!(
+                "Unable to proxy artifactory, please login using `buffrs login`"
+            ))
+            .wrap_err(ErrorCode::NotLoggedIn);
+        };
+
+        let url = match (config.filesystem, config.http, config.artifactory) {
+            (Some(filesystem), _, _) => filesystem.path.display().to_string(),
+            (None, Some(http), _) => http.url.to_string(),
+            (None, None, artifactory) => artifactory.map(|a| a.url.to_string()).unwrap_or_default(),
+        };
+
+        let cache_dir = match cache_dir {
+            Some(dir) => dir,
+            None => proxy_cache_dir()?,
+        };
+
+        tracing::info!(":: proxying {url} with cache {}", cache_dir.display());
+
+        let registry = CachedRegistry::new(registry).persist_to(cache_dir);
+
+        server::serve(registry, listen, cancel).await
+    }
+
+    /// Serves the packages of the project as read-only registry
+    pub async fn serve(
+        store: PackageStore,
+        listen: SocketAddr,
+        cancel: CancellationToken,
+    ) -> eyre::Result<()> {
+        server::serve(LocalRegistry::new(store), listen, cancel).await
+    }
+
+    /// Mocks gRPC services of the installed protos
+    pub async fn mock(
+        store: &PackageStore,
+        services: Vec<String>,
+        listen: SocketAddr,
+        cancel: CancellationToken,
+    ) -> eyre::Result<()> {
+        let schema = Schema::compile(store).await?;
+
+        let services = match services.is_empty() {
+            true => schema.all_services().collect(),
+            false => services
+                .iter()
+                .map(|service| schema.service(service))
+                .collect::<eyre::Result<Vec<_>>>()?,
+        };
+
+        ensure!(
+            !services.is_empty(),
+            "The installed protos define no services"
+        );
+
+        mock::serve(services, listen, cancel).await
+    }
+
+    /// Default directory of the cache of `buffrs proxy serve`
+    fn proxy_cache_dir() -> eyre::Result<PathBuf> {
+        Ok(config::cache_dir()?.join("proxy"))
+    }
+
+    /// Prints the resolved locations of configuration, caches and credentials
+    pub fn env(config_path: &Path, store: &PackageStore, config: &Config) -> eyre::Result<()> {
+        let path = |path: &Path| toml::Value::String(path.display().to_string());
+
+        println!("config_file = {}", path(config_path));
+        println!("config_dir = {}", path(&config::config_dir()?));
+        println!("cache_dir = {}", path(&config::cache_dir()?));
+        println!("proxy_cache_dir = {}", path(&proxy_cache_dir()?));
+        println!("package_cache_dir = {}", path(PackageCache::open()?.dir()));
+        println!("vendor_dir = {}", path(&store.dep_path()));
+
+        let Some(ref artifactory) = config.artifactory else {
+            println!("credentials = \"none, run `buffrs login`\"");
+            return Ok(());
+        };
+
+        let credentials = match (&artifactory.sigv4, artifactory.credential_store()) {
+            (Some(_), _) => "aws credential chain".to_owned(),
+            (None, CredentialStore::Keyring) => format!(
+                "system keyring (service {}, user {})",
+                artifactory.url, artifactory.username
+            ),
+            (None, CredentialStore::File) => {
+                format!("file {}", config::credentials_file()?.display())
+            }
+        };
+
+        tracing::info!(
+            "registry = {}",
+            toml::Value::String(artifactory.url.to_string())
+        );
+        println!("credentials = {}", toml::Value::String(credentials));
+
+        Ok(())
+    }
+
+    /// Reports the disk space used by installed packages and caches
+    pub async fn du(store: &PackageStore) -> eyre::Result<()> {
+        let mut packages = vec![];
+
+        for package in store.installed().await? {
+            let size = store.disk_usage(&package.name).await?;
+            packages.push((package, size));
+        }
+
+        packages.sort_by(|(a, x), (b, y)| y.cmp(x).then_with(|| a.name.cmp(&b.name)));
+
+        let total: u64 = packages.iter().map(|(_, size)| size).sum();
+
+        for (index, (package, size)) in packages.iter().enumerate() {
+            let share = match total {
+                0 => 0,
+                total => size * 100 / total,
+            };
+
+            // Highlights the few packages that make up most of the store
+            let marker = match index < 3 && share >= 20 {
+                true => "  <- large",
+                false => "",
+            };
+
+            tracing::info!(
+                "{:>10} {share:>3}%  {}@{}{marker}",
+                format_size(*size),
+                package.name,
+                package.version,
+            );
+        }
+
+        tracing::info!(
+            ":: {} installed in {} packages",
+            format_size(total),
+            packages.len()
+        );
+
+        let cache = proxy_cache_dir()?;
+
+        if cache.exists() {
+            let size: u64 = walkdir::WalkDir::new(&cache)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .filter_map(|e| e.metadata().ok())
+                .map(|m| m.len())
+                .sum();
+
+            tracing::info!(":: {} cached in {}", format_size(size), cache.display());
+        }
+
+        let cache = PackageCache::open()?;
+        let stats = cache.stats().await?;
+
+        if stats.packages > 0 {
+            tracing::info!(
+                ":: {} cached in {}",
+                format_size(stats.size),
+                cache.dir().display()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Directory holding the lockfile, the root of the workspace if the
+    /// project is a member of one
+    async fn lock_root(store: &PackageStore) -> eyre::Result<PathBuf> {
+        Ok(match Workspace::discover(store.root()).await? {
+            Some(workspace) => workspace.root().to_owned(),
+            None => store.root().to_owned(),
+        })
+    }
+
+    /// Signs the lockfile with an SSH key
+    pub async fn lock_sign(store: &PackageStore, key: &Path) -> eyre::Result<()> {
+        let root = lock_root(store).await?;
+
+        lock::sign(&root, key).await?;
+
+        tracing::info!("+ wrote {}", root.join(SIGNATURE_FILE).display());
+
+        Ok(())
+    }
+
+    /// Checks the signature of the lockfile
+    pub async fn lock_verify(
+        store: &PackageStore,
+        allowed_signers: Option<PathBuf>,
+    ) -> eyre::Result<()> {
+        let allowed_signers = allowed_signers.wrap_err(
+            "Pass --allowed-signers or configure allowed_signers to verify the lockfile",
+        )?;
+
+        let signer = lock::verify(&lock_root(store).await?, &allowed_signers)
+            .await
+            .wrap_err(ErrorCode::SignatureInvalid)?;
+
+        tracing::info!(":: {LOCKFILE} is signed by {signer}");
+
+        Ok(())
+    }
+
+    /// Merges the lockfiles `ours` and `theirs` into `ours`, for use as git
+    /// merge driver
+    ///
+    /// Packages whose locked entry differs from `ours` are downloaded again
+    /// to verify their digests. Failing leaves the conflict to git.
+    pub async fn lock_merge(
+        config: Config,
+        base: &Path,
+        ours: &Path,
+        theirs: &Path,
+        path: Option<&Path>,
+    ) -> eyre::Result<()> {
+        let read = |path: &Path| {
+            let path = path.to_owned();
+
+            async move {
+                let contents = tokio::fs::read_to_string(&path)
+                    .await
+                    .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+
+                // Git passes an empty base if the branches share no lockfile
+                match contents.trim().is_empty() {
+                    true => Ok(Lockfile::default()),
+                    false => contents.parse::<Lockfile>(),
+                }
+            }
+        };
+
+        let (base, current) = (read(base).await?, read(ours).await?);
+        let theirs = read(theirs).await?;
+
+        let root = match path.and_then(Path::parent) {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+            _ => PathBuf::from("."),
+        };
+
+        let dependencies = merge_requirements(&root).await.unwrap_or_else(|error| {
+            tracing::warn!("! merging without version requirements: {error:#}");
+            vec![]
+        });
+
+        let merged = Lockfile::merge(&base, &current, &theirs, &dependencies)?;
+
+        let changed: Vec<_> = merged
+            .packages()
+            .iter()
+            .filter(|p| current.get(&p.name) != Some(*p))
+            .cloned()
+            .collect();
+
+        if !changed.is_empty() {
+            let registry = config
+                .registry()?
+                .wrap_err("Unable to verify merged packages, please login using `buffrs login`")
+                .wrap_err(ErrorCode::NotLoggedIn)?;
+
+            ops::verify_locked(&changed, &registry).await?;
+        }
+
+        tokio::fs::write(ours, merged.to_toml()?)
+            .await
+            .wrap_err_with(|| format!("Failed to write {}", ours.display()))?;
+
+        for package in &changed {
+            tracing::info!("+ merged {package}");
+        }
+
+        if tokio::fs::try_exists(root.join(SIGNATURE_FILE))
+            .await
+            .unwrap_or(false)
+        {
+            tracing::warn!("! {LOCKFILE} changed, sign it again with buffrs lock sign");
+        }
+
+        Ok(())
+    }
+
+    /// Registry dependencies of the project or workspace at `root`
+    async fn merge_requirements(root: &Path) -> eyre::Result<Vec<Dependency>> {
+        let root = tokio::fs::canonicalize(root)
+            .await
+            .wrap_err_with(|| format!("Failed to resolve {}", root.display()))?;
+
+        let projects = match Workspace::discover(&root).await? {
+            Some(workspace) if workspace.root() == root => workspace.members().to_vec(),
+            _ => vec![root],
+        };
+
+        let mut dependencies = vec![];
+
+        for project in projects {
+            dependencies.extend(
+                resolver::resolve(&Manifest::read(&project).await?)?
+                    .into_iter()
+                    .filter(|d| d.manifest.is_registry()),
+            );
+        }
+
+        Ok(dependencies)
+    }
+
+    /// Removes all packages from the package cache
+    pub async fn cache_clean() -> eyre::Result<()> {
+        let cache = PackageCache::open()?;
+        let stats = cache.clean().await?;
+
+        tracing::info!(
+            "- removed {} packages ({}) from {}",
+            stats.packages,
+            format_size(stats.size),
+            cache.dir().display()
+        );
+
+        Ok(())
+    }
+
+    /// Reports the number and size of packages in the package cache
+    pub async fn cache_stats() -> eyre::Result<()> {
+        let cache = PackageCache::open()?;
+        let stats = cache.stats().await?;
+
+        tracing::info!(
+            ":: {} packages ({}) cached in {}",
+            stats.packages,
+            format_size(stats.size),
+            cache.dir().display()
+        );
+
+        Ok(())
+    }
+
+    /// Formats a number of bytes for humans, e.g. `1.5 MiB`
+    pub fn format_size(bytes: u64) -> String {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+        if bytes < 1024 {
+            return format!("{bytes} B");
+        }
+
+        let mut size = bytes as f64 / 1024.0;
+        let mut unit = 0;
+
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
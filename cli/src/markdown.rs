@@ -0,0 +1,275 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag};
+
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const ITALIC: &str = "\x1b[3m";
+const UNDERLINE: &str = "\x1b[4m";
+const STRIKETHROUGH: &str = "\x1b[9m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders markdown for display in a terminal using ANSI escape codes
+///
+/// This covers the common elements of readmes: headings, emphasis, lists,
+/// block quotes, code and tables. Html is dropped.
+pub fn render(markdown: &str) -> String {
+    let mut renderer = Renderer {
+        line_start: true,
+        ..Default::default()
+    };
+
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+
+    for event in Parser::new_ext(markdown, options) {
+        renderer.event(event);
+    }
+
+    renderer.finish()
+}
+
+#[derive(Default)]
+struct Renderer {
+    out: String,
+    /// Prefixes of the enclosing block quotes and list items
+    prefix: Vec<String>,
+    /// Next number of every enclosing list, `None` for bullet lists
+    lists: Vec<Option<u64>>,
+    /// Targets of the enclosing links
+    links: Vec<String>,
+    /// Active styles, reapplied after nested styles end
+    styles: Vec<&'static str>,
+    line_start: bool,
+    after_marker: bool,
+    table_cell: usize,
+}
+
+impl Renderer {
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => self.text(&text),
+            Event::Code(code) => {
+                self.push_style(CYAN);
+                self.text(&code);
+                self.pop_style();
+            }
+            Event::SoftBreak | Event::HardBreak => self.newline(),
+            Event::Rule => {
+                self.block();
+                self.push_style(DIM);
+                self.text(&"─".repeat(40));
+                self.pop_style();
+                self.newline();
+            }
+            Event::TaskListMarker(done) => self.text(if done { "[x] " } else { "[ ] " }),
+            Event::Html(_) | Event::FootnoteReference(_) => (),
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.block(),
+            Tag::Heading(level, ..) => {
+                self.block();
+                self.push_style(BOLD);
+
+                if level <= HeadingLevel::H2 {
+                    self.push_style(UNDERLINE);
+                }
+            }
+            Tag::BlockQuote => {
+                self.block();
+                self.prefix.push(format!("{DIM}│{RESET} "));
+            }
+            Tag::CodeBlock(_) => {
+                self.block();
+                self.prefix.push("    ".to_owned());
+                self.push_style(CYAN);
+            }
+            Tag::List(start) => {
+                if self.lists.is_empty() {
+                    self.block();
+                } else {
+                    self.ensure_newline();
+                }
+
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.ensure_newline();
+
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "• ".to_owned(),
+                };
+
+                self.text(&marker);
+                self.prefix.push(" ".repeat(marker.chars().count()));
+                self.after_marker = true;
+            }
+            Tag::Table(_) => self.block(),
+            Tag::TableHead => {
+                self.table_cell = 0;
+                self.push_style(BOLD);
+            }
+            Tag::TableRow => self.table_cell = 0,
+            Tag::TableCell => {
+                if self.table_cell > 0 {
+                    self.text(" │ ");
+                }
+
+                self.table_cell += 1;
+            }
+            Tag::Emphasis => self.push_style(ITALIC),
+            Tag::Strong => self.push_style(BOLD),
+            Tag::Strikethrough => self.push_style(STRIKETHROUGH),
+            Tag::Link(_, url, _) => {
+                self.push_style(UNDERLINE);
+                self.links.push(url.to_string());
+            }
+            Tag::Image(_, url, _) => {
+                self.text("[image: ");
+                self.links.push(url.to_string());
+            }
+            Tag::FootnoteDefinition(_) => self.block(),
+        }
+    }
+
+    fn end(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.ensure_newline(),
+            Tag::Heading(level, ..) => {
+                if level <= HeadingLevel::H2 {
+                    self.pop_style();
+                }
+
+                self.pop_style();
+                self.ensure_newline();
+            }
+            Tag::BlockQuote => {
+                self.prefix.pop();
+                self.ensure_newline();
+            }
+            Tag::CodeBlock(_) => {
+                self.pop_style();
+                self.prefix.pop();
+                self.ensure_newline();
+            }
+            Tag::List(_) => {
+                self.lists.pop();
+                self.ensure_newline();
+            }
+            Tag::Item => {
+                self.prefix.pop();
+                self.ensure_newline();
+            }
+            Tag::TableHead => {
+                self.pop_style();
+                self.newline();
+            }
+            Tag::TableRow => self.newline(),
+            Tag::Emphasis | Tag::Strong | Tag::Strikethrough => self.pop_style(),
+            Tag::Link(..) => {
+                self.pop_style();
+                self.link_target();
+            }
+            Tag::Image(..) => {
+                self.text("]");
+                self.link_target();
+            }
+            Tag::Table(_) | Tag::TableCell | Tag::FootnoteDefinition(_) => (),
+        }
+    }
+
+    /// Appends the target of the link that just ended, unless it points into
+    /// the document itself
+    fn link_target(&mut self) {
+        let Some(url) = self.links.pop() else {
+            return;
+        };
+
+        if url.is_empty() || url.starts_with('#') {
+            return;
+        }
+
+        self.push_style(DIM);
+        self.text(&format!(" ({url})"));
+        self.pop_style();
+    }
+
+    /// Separates a new block from the previous one by an empty line
+    fn block(&mut self) {
+        if self.after_marker || self.out.is_empty() {
+            return;
+        }
+
+        self.ensure_newline();
+
+        if !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.newline();
+            }
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if self.line_start {
+                self.line_start = false;
+                self.out.push_str(&self.prefix.concat());
+                self.out.extend(self.styles.iter().copied());
+            }
+
+            self.after_marker = false;
+            self.out.push_str(line);
+        }
+    }
+
+    fn newline(&mut self) {
+        if !self.styles.is_empty() {
+            self.out.push_str(RESET);
+        }
+
+        self.out.push('\n');
+        self.line_start = true;
+    }
+
+    fn ensure_newline(&mut self) {
+        if !self.line_start && !self.out.is_empty() {
+            self.newline();
+        }
+    }
+
+    fn push_style(&mut self, style: &'static str) {
+        self.styles.push(style);
+
+        if !self.line_start {
+            self.out.push_str(style);
+        }
+    }
+
+    fn pop_style(&mut self) {
+        self.styles.pop();
+
+        if !self.line_start {
+            self.out.push_str(RESET);
+            self.out.extend(self.styles.iter().copied());
+        }
+    }
+
+    fn finish(self) -> String {
+        self.out.trim_end().to_owned()
+    }
+}
@@ -0,0 +1,175 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock, PoisonError,
+    },
+    time::Duration,
+};
+
+use buffrs::{manifest::Dependency, ops::InstallReporter};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::cmd::format_size;
+
+/// Interval spinners of running steps advance in
+const TICK: Duration = Duration::from_millis(100);
+
+/// Progress bars drawn below the log output
+///
+/// Bars are drawn to stderr and hidden if it is not a terminal, e.g. in CI.
+fn bars() -> &'static MultiProgress {
+    static BARS: OnceLock<MultiProgress> = OnceLock::new();
+    BARS.get_or_init(MultiProgress::new)
+}
+
+/// Writes log output to stderr without tearing through progress bars
+///
+/// The bars are hidden while a line is written and redrawn below it. Stdout
+/// is left to the output of commands, e.g. JSON read by other tools.
+#[derive(Clone, Copy, Default)]
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        bars().suspend(|| io::stderr().write(buf))
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        bars().suspend(|| io::stderr().write_all(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogWriter {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        *self
+    }
+}
+
+/// Shows a bar of the installation as a whole and a spinner for every package
+/// that is being downloaded or extracted
+pub struct InstallProgress {
+    total: ProgressBar,
+    packages: Mutex<BTreeMap<String, ProgressBar>>,
+    bytes: AtomicU64,
+}
+
+impl InstallProgress {
+    /// Starts showing the progress of an installation
+    pub fn new() -> Self {
+        // A spinner until the number of packages is known
+        let total = ProgressBar::new(0).with_style(
+            ProgressStyle::with_template("{spinner} {wide_msg}").expect("valid template"),
+        );
+
+        total.enable_steady_tick(TICK);
+
+        Self {
+            total: bars().add(total),
+            packages: Mutex::default(),
+            bytes: AtomicU64::default(),
+        }
+    }
+
+    /// Shows `step` as the current step of a package
+    fn step(&self, dependency: &Dependency, step: &'static str) {
+        let mut packages = self.packages.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let bar = packages.entry(name(dependency)).or_insert_with_key(|name| {
+            let bar = ProgressBar::new_spinner()
+                .with_style(
+                    ProgressStyle::with_template("  {spinner} {prefix} {msg} {elapsed}")
+                        .expect("valid template"),
+                )
+                .with_prefix(name.clone());
+
+            bar.enable_steady_tick(TICK);
+
+            bars().insert_before(&self.total, bar)
+        });
+
+        bar.set_message(step);
+    }
+
+    /// Removes the spinner of a package that is done
+    fn done(&self, dependency: &Dependency) {
+        let mut packages = self.packages.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(bar) = packages.remove(&name(dependency)) {
+            bar.finish_and_clear();
+            bars().remove(&bar);
+        }
+
+        self.total.inc(1);
+    }
+
+    /// Removes all bars once the installation is over
+    pub fn finish(&self) {
+        let packages = self.packages.lock().unwrap_or_else(PoisonError::into_inner);
+
+        for bar in packages.values().chain([&self.total]) {
+            bar.finish_and_clear();
+            bars().remove(bar);
+        }
+    }
+}
+
+impl InstallReporter for InstallProgress {
+    fn resolve_started(&self) {
+        self.total.set_message("resolving");
+    }
+
+    fn resolved(&self, dependencies: &[Dependency]) {
+        self.total.set_style(
+            ProgressStyle::with_template("{bar:30} {pos}/{len} packages {wide_msg}")
+                .expect("valid template")
+                .progress_chars("=> "),
+        );
+
+        self.total.inc_length(dependencies.len() as u64);
+        self.total.set_message("");
+    }
+
+    fn download_started(&self, dependency: &Dependency) {
+        self.step(dependency, "downloading");
+    }
+
+    fn fetched(&self, dependency: &Dependency, bytes: u64, cached: bool) {
+        self.step(dependency, if cached { "cached" } else { "downloaded" });
+
+        let total = self.bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+
+        self.total
+            .set_message(format!("{} fetched", format_size(total)));
+    }
+
+    fn extract_started(&self, dependency: &Dependency) {
+        self.step(dependency, "extracting");
+    }
+
+    fn extracted(&self, dependency: &Dependency) {
+        self.done(dependency);
+    }
+
+    fn failed(&self, dependency: &Dependency, _error: &eyre::Report) {
+        self.done(dependency);
+    }
+}
+
+/// Name of a dependency next to its spinner, e.g. `units@1.2.0`
+fn name(dependency: &Dependency) -> String {
+    match dependency.resolved_version() {
+        Ok(version) => format!("{}@{version}", dependency.package),
+        Err(_) => dependency.package.to_string(),
+    }
+}
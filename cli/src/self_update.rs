@@ -0,0 +1,235 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    io::Write,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use buffrs::{config, error::ErrorCode, package::DigestAlgorithm};
+use eyre::{Context, ContextCompat};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use url::Url;
+
+/// Feed announcing the latest release of `buffrs`
+const RELEASE_FEED: &str = "https://api.github.com/repos/helsing-ai/buffrs/releases/latest";
+/// Environment variable disabling the new version notification
+pub const NO_UPDATE_CHECK_ENV: &str = "BUFFRS_NO_UPDATE_CHECK";
+/// File caching the result of the last version check
+const VERSION_CHECK_FILE: &str = "version-check.toml";
+/// Minimum time between two version checks
+const VERSION_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Upper bound for the time spent querying the release feed
+const VERSION_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A published release of `buffrs`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    #[serde(rename = "tag_name")]
+    tag: String,
+    assets: Vec<Asset>,
+}
+
+/// A downloadable file attached to a release
+#[derive(Debug, Clone, Deserialize)]
+struct Asset {
+    name: String,
+    #[serde(rename = "browser_download_url")]
+    url: Url,
+}
+
+impl Release {
+    /// Fetches the latest release from the release feed
+    pub async fn latest(client: &reqwest::Client) -> eyre::Result<Self> {
+        client
+            .get(RELEASE_FEED)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .wrap_err("Failed to query the release feed")?
+            .json()
+            .await
+            .wrap_err("Failed to parse the release feed")
+    }
+
+    /// Version of this release
+    pub fn version(&self) -> eyre::Result<Version> {
+        self.tag
+            .trim_start_matches('v')
+            .parse()
+            .wrap_err(format!("Invalid release version {}", self.tag))
+    }
+
+    fn asset(&self, name: &str) -> eyre::Result<&Asset> {
+        self.assets
+            .iter()
+            .find(|a| a.name == name)
+            .wrap_err(format!("Release {} contains no asset {name}", self.tag))
+    }
+
+    /// Downloads the binary for this platform, verifies its checksum and
+    /// replaces the running executable with it
+    ///
+    /// The checksum is published with the binary, so it only detects
+    /// corrupted downloads. It does not prove that the release is authentic,
+    /// which relies on the TLS connection to the release host.
+    pub async fn install(&self, client: &reqwest::Client) -> eyre::Result<()> {
+        let name = format!(
+            "buffrs-{}-{}{}",
+            std::env::consts::ARCH,
+            std::env::consts::OS,
+            std::env::consts::EXE_SUFFIX
+        );
+
+        let checksum = client
+            .get(self.asset(&format!("{name}.sha256"))?.url.clone())
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .wrap_err("Failed to download checksum")?
+            .text()
+            .await
+            .wrap_err("Failed to download checksum")?;
+
+        // Accepts both a bare digest and the `sha256sum` output format
+        let expected = checksum
+            .split_whitespace()
+            .next()
+            .wrap_err("Release checksum is empty")?;
+
+        let binary = client
+            .get(self.asset(&name)?.url.clone())
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .wrap_err("Failed to download release")?
+            .bytes()
+            .await
+            .wrap_err("Failed to download release")?;
+
+        let actual = DigestAlgorithm::Sha256.digest(&binary).to_hex();
+
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(eyre::eyre!(
+                "Checksum mismatch for {name}: expected {expected}, got {actual}"
+            ))
+            .wrap_err(ErrorCode::IntegrityMismatch);
+        }
+
+        replace_executable(&binary)
+    }
+}
+
+/// Version of the running executable
+pub fn current_version() -> Version {
+    env!("CARGO_PKG_VERSION")
+        .parse()
+        .expect("crate version is valid semver")
+}
+
+/// Prints a one-line hint to stderr if a newer release of `buffrs` is
+/// available
+///
+/// The release feed is queried at most once a day, errors are ignored.
+pub async fn notify(client: &reqwest::Client) {
+    let current = current_version();
+
+    match latest_version(client).await {
+        Ok(latest) if latest > current => tracing::info!(
+            "A new release of buffrs is available: {current} -> {latest}, run `buffrs self-update`"
+        ),
+        Ok(_) => (),
+        Err(error) => tracing::debug!("version check failed: {error:#}"),
+    }
+}
+
+/// Result of the last version check
+#[derive(Debug, Serialize, Deserialize)]
+struct VersionCheck {
+    /// Seconds since the unix epoch
+    checked_at: u64,
+    latest: Version,
+}
+
+/// Determines the latest version, preferring a recent cached result
+async fn latest_version(client: &reqwest::Client) -> eyre::Result<Version> {
+    let location = location()?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    let cached = fs::read_to_string(&location)
+        .await
+        .ok()
+        .and_then(|toml| toml::from_str::<VersionCheck>(&toml).ok());
+
+    if let Some(check) = cached {
+        if now.saturating_sub(check.checked_at) < VERSION_CHECK_INTERVAL.as_secs() {
+            return Ok(check.latest);
+        }
+    }
+
+    let latest = tokio::time::timeout(VERSION_CHECK_TIMEOUT, Release::latest(client))
+        .await
+        .wrap_err("Timed out querying the release feed")??
+        .version()?;
+
+    let check = VersionCheck {
+        checked_at: now,
+        latest: latest.clone(),
+    };
+
+    if let Some(parent) = location.parent() {
+        fs::create_dir_all(parent).await.ok();
+    }
+
+    fs::write(location, toml::to_string(&check)?)
+        .await
+        .wrap_err("Failed to cache version check")?;
+
+    Ok(latest)
+}
+
+/// Location of the cached version check
+fn location() -> eyre::Result<PathBuf> {
+    Ok(config::cache_dir()?.join(VERSION_CHECK_FILE))
+}
+
+/// Atomically swaps the running executable for `binary`
+fn replace_executable(binary: &[u8]) -> eyre::Result<()> {
+    let current = std::env::current_exe().wrap_err("Failed to locate executable")?;
+
+    let dir = current
+        .parent()
+        .wrap_err("Failed to locate executable directory")?;
+
+    let mut staged =
+        tempfile::NamedTempFile::new_in(dir).wrap_err("Failed to stage new executable")?;
+
+    staged
+        .write_all(binary)
+        .wrap_err("Failed to stage new executable")?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::set_permissions(staged.path(), std::fs::Permissions::from_mode(0o755))
+            .wrap_err("Failed to make new executable runnable")?;
+    }
+
+    // Windows does not allow replacing a running executable, but renaming it
+    let backup = current.with_extension("old");
+
+    std::fs::rename(&current, &backup).wrap_err("Failed to move current executable")?;
+
+    if let Err(error) = staged.persist(&current) {
+        std::fs::rename(&backup, &current).ok();
+        return Err(error).wrap_err("Failed to replace executable");
+    }
+
+    std::fs::remove_file(&backup).ok();
+
+    Ok(())
+}
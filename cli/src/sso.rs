@@ -0,0 +1,125 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::time::Duration;
+
+use eyre::Context;
+use serde::Deserialize;
+use url::Url;
+
+/// Grant type of the OAuth 2.0 device authorization flow (RFC 8628)
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+/// Polling interval if the identity provider does not specify one
+const DEFAULT_INTERVAL: u64 = 5;
+
+/// Endpoints advertised by an OpenID Connect identity provider
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    device_authorization_endpoint: Url,
+    token_endpoint: Url,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceAuthorization {
+    device_code: String,
+    user_code: String,
+    verification_uri: Url,
+    verification_uri_complete: Option<Url>,
+    expires_in: u64,
+    interval: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TokenResponse {
+    Token { access_token: String },
+    Error { error: String },
+}
+
+/// Obtains an access token from an identity provider using the device flow
+///
+/// Prints a verification url and code for the user and polls until the
+/// login was completed in the browser.
+///
+/// Requests are sent with `client`, so the configured proxy and certificates
+/// apply.
+pub async fn device_login(
+    client: &reqwest::Client,
+    issuer: &Url,
+    client_id: &str,
+) -> eyre::Result<String> {
+    let discovery: Discovery = client
+        .get(format!(
+            "{}/.well-known/openid-configuration",
+            issuer.as_str().trim_end_matches('/')
+        ))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .wrap_err("Failed to discover identity provider endpoints")?
+        .json()
+        .await
+        .wrap_err("Identity provider does not support the device flow")?;
+
+    let authorization: DeviceAuthorization = client
+        .post(discovery.device_authorization_endpoint)
+        .form(&[("client_id", client_id), ("scope", "openid")])
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .wrap_err("Failed to start device authorization")?
+        .json()
+        .await
+        .wrap_err("Failed to parse device authorization")?;
+
+    match authorization.verification_uri_complete {
+        Some(ref uri) => tracing::info!("Please open {uri} to complete the login"),
+        None => tracing::info!(
+            "Please open {} and enter the code {}",
+            authorization.verification_uri,
+            authorization.user_code
+        ),
+    }
+
+    let mut interval = Duration::from_secs(authorization.interval.unwrap_or(DEFAULT_INTERVAL));
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(authorization.expires_in);
+
+    loop {
+        tokio::time::sleep(interval).await;
+
+        eyre::ensure!(
+            tokio::time::Instant::now() < deadline,
+            "The device code expired before the login was completed"
+        );
+
+        let response: TokenResponse = client
+            .post(discovery.token_endpoint.clone())
+            .form(&[
+                ("grant_type", DEVICE_CODE_GRANT),
+                ("device_code", &authorization.device_code),
+                ("client_id", client_id),
+            ])
+            .send()
+            .await
+            .wrap_err("Failed to poll for the access token")?
+            .json()
+            .await
+            .wrap_err("Failed to parse the token response")?;
+
+        match response {
+            TokenResponse::Token { access_token } => return Ok(access_token),
+            TokenResponse::Error { error } => match error.as_str() {
+                "authorization_pending" => continue,
+                "slow_down" => interval += Duration::from_secs(DEFAULT_INTERVAL),
+                "access_denied" => eyre::bail!("The login was denied"),
+                "expired_token" => {
+                    eyre::bail!("The device code expired before the login was completed")
+                }
+                other => {
+                    return Err(eyre::eyre!("Identity provider returned {other}"))
+                        .wrap_err("Failed to obtain an access token")
+                }
+            },
+        }
+    }
+}
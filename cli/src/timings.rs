@@ -0,0 +1,285 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::Path,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use buffrs::{manifest::Dependency, ops::InstallReporter};
+use eyre::Context;
+
+use crate::cmd::format_size;
+
+/// File the html timing report is written to, relative to the project root
+pub const TIMINGS_FILE: &str = "buffrs-timings.html";
+
+/// Records when each step of an installation started and finished
+pub struct InstallTimings {
+    started: Instant,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    resolve: Option<(Instant, Option<Instant>)>,
+    resolved: usize,
+    packages: BTreeMap<String, PackageTimings>,
+}
+
+/// Steps of installing a single package
+#[derive(Default)]
+struct PackageTimings {
+    download: Option<(Instant, Option<Instant>)>,
+    extract: Option<(Instant, Option<Instant>)>,
+    bytes: u64,
+    cached: bool,
+    failed: bool,
+}
+
+impl InstallTimings {
+    /// Starts recording an installation
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            state: Mutex::default(),
+        }
+    }
+
+    fn update(&self, dependency: &Dependency, update: impl FnOnce(&mut PackageTimings)) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        update(state.packages.entry(name(dependency)).or_default());
+    }
+
+    /// One line summary, e.g. `resolved 12 packages in 0.31s, downloaded 1.2
+    /// MiB in 0.84s, 3 cache hits, extracted in 0.05s`
+    pub fn summary(&self) -> String {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let packages = state.packages.values();
+
+        let downloaded: Vec<&PackageTimings> =
+            packages.clone().filter(|p| p.download.is_some()).collect();
+
+        let bytes: u64 = downloaded.iter().map(|p| p.bytes).sum();
+        let hits = packages.clone().filter(|p| p.cached).count();
+
+        let mut summary = format!(
+            "resolved {} {} in {}",
+            state.resolved,
+            match state.resolved {
+                1 => "package",
+                _ => "packages",
+            },
+            seconds(span(state.resolve.iter().copied()))
+        );
+
+        if !downloaded.is_empty() {
+            write!(
+                summary,
+                ", downloaded {} in {}",
+                format_size(bytes),
+                seconds(span(downloaded.iter().filter_map(|p| p.download)))
+            )
+            .ok();
+        }
+
+        write!(
+            summary,
+            ", {hits} cache {}, extracted in {}",
+            match hits {
+                1 => "hit",
+                _ => "hits",
+            },
+            seconds(span(packages.filter_map(|p| p.extract)))
+        )
+        .ok();
+
+        summary
+    }
+
+    /// Writes a report of when each package was downloaded and extracted,
+    /// relative to the start of the installation
+    pub async fn write_html(&self, path: &Path) -> eyre::Result<()> {
+        let html = self.html();
+
+        tokio::fs::write(path, html)
+            .await
+            .wrap_err_with(|| format!("Failed to write {}", path.display()))
+    }
+
+    fn html(&self) -> String {
+        let summary = self.summary();
+
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let end = state
+            .packages
+            .values()
+            .flat_map(|p| [p.download, p.extract])
+            .chain([state.resolve])
+            .flatten()
+            .filter_map(|(_, end)| end)
+            .max()
+            .unwrap_or(self.started);
+
+        let total = end.duration_since(self.started).as_secs_f64().max(0.001);
+
+        let offset = |at: Instant| at.duration_since(self.started).as_secs_f64();
+
+        let bar = |step: Option<(Instant, Option<Instant>)>, class: &str| match step {
+            Some((start, Some(end))) => format!(
+                r#"<div class="{class}" style="margin-left:{:.2}%;width:{:.2}%"></div>"#,
+                offset(start) / total * 100.0,
+                (offset(end) - offset(start)) / total * 100.0
+            ),
+            _ => String::new(),
+        };
+
+        let duration = |step: Option<(Instant, Option<Instant>)>| match step {
+            Some((start, Some(end))) => seconds(end - start),
+            _ => "-".to_owned(),
+        };
+
+        let mut rows = String::new();
+
+        for (name, package) in &state.packages {
+            let source = match (package.cached, package.download, package.failed) {
+                (_, _, true) => "failed",
+                (true, _, _) => "cache",
+                (false, Some(_), _) => "registry",
+                (false, None, _) => "local",
+            };
+
+            writeln!(
+                rows,
+                "<tr><td>{}</td><td>{source}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"timeline\">{}{}</td></tr>",
+                escape(name),
+                format_size(package.bytes),
+                duration(package.download),
+                duration(package.extract),
+                bar(package.download, "download"),
+                bar(package.extract, "extract"),
+            )
+            .ok();
+        }
+
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>buffrs install timings</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; width: 100%; }}
+th, td {{ text-align: left; padding: 4px 8px; border-bottom: 1px solid #ddd; white-space: nowrap; }}
+td.timeline {{ width: 50%; }}
+.download, .extract {{ height: 8px; min-width: 1px; }}
+.download {{ background: #4e79a7; }}
+.extract {{ background: #f28e2b; }}
+</style>
+</head>
+<body>
+<h1>buffrs install timings</h1>
+<p>{}, {} in total</p>
+<p><span class="download" style="display:inline-block;width:2em"></span> download
+<span class="extract" style="display:inline-block;width:2em"></span> extract</p>
+<table>
+<tr><th>package</th><th>source</th><th>size</th><th>download</th><th>extract</th><th>timeline</th></tr>
+{rows}</table>
+</body>
+</html>
+"#,
+            escape(&summary),
+            seconds(end - self.started)
+        )
+    }
+}
+
+impl InstallReporter for InstallTimings {
+    fn resolve_started(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.resolve.get_or_insert((Instant::now(), None));
+    }
+
+    fn resolved(&self, dependencies: &[Dependency]) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        state.resolved += dependencies.len();
+
+        if let Some((_, ref mut end)) = state.resolve {
+            *end = Some(Instant::now());
+        }
+    }
+
+    fn download_started(&self, dependency: &Dependency) {
+        self.update(dependency, |p| p.download = Some((Instant::now(), None)));
+    }
+
+    fn fetched(&self, dependency: &Dependency, bytes: u64, cached: bool) {
+        self.update(dependency, |p| {
+            p.bytes = bytes;
+            p.cached = cached;
+
+            if let Some((_, ref mut end)) = p.download {
+                *end = Some(Instant::now());
+            }
+        });
+    }
+
+    fn extract_started(&self, dependency: &Dependency) {
+        self.update(dependency, |p| p.extract = Some((Instant::now(), None)));
+    }
+
+    fn extracted(&self, dependency: &Dependency) {
+        self.update(dependency, |p| {
+            if let Some((_, ref mut end)) = p.extract {
+                *end = Some(Instant::now());
+            }
+        });
+    }
+
+    fn failed(&self, dependency: &Dependency, _error: &eyre::Report) {
+        self.update(dependency, |p| p.failed = true);
+    }
+}
+
+/// Name of a dependency in the report, e.g. `units@1.2.0`
+fn name(dependency: &Dependency) -> String {
+    match dependency.resolved_version() {
+        Ok(version) => format!("{}@{version}", dependency.package),
+        Err(_) => dependency.package.to_string(),
+    }
+}
+
+/// Wall clock time from the first start to the last end of `steps`, which
+/// may overlap
+fn span(steps: impl Iterator<Item = (Instant, Option<Instant>)>) -> Duration {
+    let (mut first, mut last) = (None::<Instant>, None::<Instant>);
+
+    for (start, end) in steps {
+        first = Some(first.map_or(start, |first| first.min(start)));
+
+        if let Some(end) = end {
+            last = Some(last.map_or(end, |last| last.max(end)));
+        }
+    }
+
+    match (first, last) {
+        (Some(first), Some(last)) => last.saturating_duration_since(first),
+        _ => Duration::ZERO,
+    }
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f64())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,178 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use buffrs::manifest::Manifest;
+use eyre::Context;
+use serde::Serialize;
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::Command;
+
+/// Environment variable overriding the configured usage report location
+pub const USAGE_REPORT_ENV: &str = "BUFFRS_USAGE_REPORT";
+
+/// A machine readable summary of a single `buffrs` invocation
+///
+/// Reports are only ever appended to a local file, one json object per line,
+/// and never sent anywhere.
+#[derive(Debug, Serialize)]
+pub struct UsageReport {
+    /// Name of the executed command
+    pub command: &'static str,
+    /// Version of `buffrs`
+    pub version: &'static str,
+    /// Start of the invocation in seconds since the unix epoch
+    pub started_at: u64,
+    /// Wall clock duration of the invocation in milliseconds
+    pub duration_ms: u128,
+    /// Whether the command succeeded
+    pub success: bool,
+    /// Packages the command operated on
+    pub packages: Vec<String>,
+}
+
+impl UsageReport {
+    /// Creates a report for a command that started at `started_at`
+    pub fn new(
+        command: &Command,
+        started_at: SystemTime,
+        duration: Duration,
+        success: bool,
+        packages: Vec<String>,
+    ) -> Self {
+        Self {
+            command: name(command),
+            version: env!("CARGO_PKG_VERSION"),
+            started_at: started_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            duration_ms: duration.as_millis(),
+            success,
+            packages,
+        }
+    }
+
+    /// Appends the report to the file at `path`
+    pub async fn append(&self, path: &Path) -> eyre::Result<()> {
+        let mut line = serde_json::to_string(self).wrap_err("Failed to encode usage report")?;
+        line.push('\n');
+
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .wrap_err(format!("Failed to open usage report {}", path.display()))?
+            .write_all(line.as_bytes())
+            .await
+            .wrap_err("Failed to write usage report")
+    }
+}
+
+/// Collects the packages a command is going to operate on
+pub async fn packages(command: &Command, root: &Path) -> Vec<String> {
+    match command {
+        Command::Init { api, .. } => api.iter().map(|a| a.to_string()).collect(),
+        Command::Add { dependency } => vec![dependency.trim().to_owned()],
+        Command::Info { package, .. }
+        | Command::Archive { package, .. }
+        | Command::Yank { package, .. } => {
+            vec![package.trim().to_owned()]
+        }
+        Command::Remove { package } => vec![package.to_string()],
+        Command::Publish { .. } => Manifest::read(root)
+            .await
+            .map(|m| {
+                m.api
+                    .iter()
+                    .chain(m.packages.iter().map(|p| &p.api))
+                    .map(|api| format!("{}@{}", api.name, api.version))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        Command::Mirror { packages, .. } if !packages.is_empty() => packages.clone(),
+        Command::Update { packages, .. } if !packages.is_empty() => {
+            packages.iter().map(ToString::to_string).collect()
+        }
+        Command::Install { .. }
+        | Command::Update { .. }
+        | Command::Outdated { .. }
+        | Command::Uninstall
+        | Command::Prune
+        | Command::Mirror { .. } => Manifest::read(root)
+            .await
+            .map(|m| m.dependencies.iter().map(|d| d.to_string()).collect())
+            .unwrap_or_default(),
+        Command::Login { .. }
+        | Command::Logout
+        | Command::Search { .. }
+        | Command::Setup { .. }
+        | Command::SelfUpdate
+        | Command::Generate { .. }
+        | Command::Decode { .. }
+        | Command::Encode { .. }
+        | Command::Example { .. }
+        | Command::Breaking { .. }
+        | Command::Lint
+        | Command::Fmt { .. }
+        | Command::Test { .. }
+        | Command::Export { .. }
+        | Command::Explain { .. }
+        | Command::Manifest { .. }
+        | Command::Du
+        | Command::Env
+        | Command::Serve { .. }
+        | Command::Mock { .. }
+        | Command::Proxy { .. }
+        | Command::Cache { .. }
+        | Command::Lock { .. }
+        | Command::Ide { .. } => vec![],
+    }
+}
+
+fn name(command: &Command) -> &'static str {
+    match command {
+        Command::Init { .. } => "init",
+        Command::Add { .. } => "add",
+        Command::Remove { .. } => "remove",
+        Command::Info { .. } => "info",
+        Command::Search { .. } => "search",
+        Command::Publish { .. } => "publish",
+        Command::Breaking { .. } => "breaking",
+        Command::Archive { .. } => "archive",
+        Command::Yank { .. } => "yank",
+        Command::Install { .. } => "install",
+        Command::Update { .. } => "update",
+        Command::Outdated { .. } => "outdated",
+        Command::Uninstall => "uninstall",
+        Command::Prune => "prune",
+        Command::Du => "du",
+        Command::Login { .. } => "login",
+        Command::Logout => "logout",
+        Command::Setup { .. } => "setup",
+        Command::SelfUpdate => "self-update",
+        Command::Mirror { .. } => "mirror",
+        Command::Generate { .. } => "generate",
+        Command::Decode { .. } => "decode",
+        Command::Encode { .. } => "encode",
+        Command::Example { .. } => "example",
+        Command::Lint => "lint",
+        Command::Fmt { .. } => "fmt",
+        Command::Test { .. } => "test",
+        Command::Export { .. } => "export",
+        Command::Explain { .. } => "explain",
+        Command::Env => "env",
+        Command::Manifest { .. } => "manifest",
+        Command::Serve { .. } => "serve",
+        Command::Mock { .. } => "mock",
+        Command::Proxy { .. } => "proxy",
+        Command::Cache { .. } => "cache",
+        Command::Lock { .. } => "lock",
+        Command::Ide { .. } => "ide",
+    }
+}
@@ -0,0 +1,74 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{future::Future, path::Path};
+
+use eyre::Context;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    manifest::Manifest,
+    ops::{self, InstallOptions, InstallReporter},
+    package::{Package, PackageStore},
+    policy::Policy,
+    registry::{Receipt, Registry},
+};
+
+/// Runs a future to completion on a dedicated runtime
+///
+/// Note: Must not be called from within an async runtime.
+pub(crate) fn block_on<F: Future>(future: F) -> eyre::Result<F::Output> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .wrap_err("Failed to start async runtime")?;
+
+    Ok(runtime.block_on(future))
+}
+
+/// Reads the manifest of the project located at `root`
+pub fn read_manifest(root: &Path) -> eyre::Result<Manifest> {
+    block_on(Manifest::read(root))?
+}
+
+/// Writes the manifest of the project located at `root`
+pub fn write_manifest(manifest: &Manifest, root: &Path) -> eyre::Result<()> {
+    block_on(manifest.write(root))?
+}
+
+/// Packages a release from the api of a project, see [`PackageStore::release`]
+pub fn release(store: &PackageStore) -> eyre::Result<Package> {
+    block_on(store.release())?
+}
+
+/// Installs the dependencies of a manifest, see [`ops::install`]
+pub fn install(
+    store: &PackageStore,
+    manifest: &Manifest,
+    registry: &(impl Registry + Sync),
+    options: &InstallOptions,
+    reporter: &dyn InstallReporter,
+    cancel: &CancellationToken,
+) -> eyre::Result<()> {
+    block_on(ops::install(
+        store, manifest, registry, options, reporter, cancel,
+    ))?
+}
+
+/// Packages and publishes the api of a project, see [`ops::publish`]
+pub fn publish(
+    store: &PackageStore,
+    registry: &(impl Registry + Sync),
+    repository: String,
+    policies: &[Policy],
+    client: &reqwest::Client,
+    cancel: &CancellationToken,
+) -> eyre::Result<Receipt> {
+    block_on(ops::publish(
+        store, registry, repository, policies, client, cancel,
+    ))?
+}
+
+/// Removes all installed dependencies of a project
+pub fn uninstall(store: &PackageStore) -> eyre::Result<()> {
+    block_on(store.clear())?
+}
@@ -0,0 +1,387 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{collections::BTreeSet, fmt};
+
+use prost_reflect::{DescriptorPool, FieldDescriptor, Kind};
+use serde::Serialize;
+#[cfg(feature = "reflect")]
+use {
+    crate::{
+        package::{Package, PackageStore},
+        reflect::Schema,
+    },
+    eyre::{ensure, Context},
+    std::path::{Component, Path},
+    walkdir::WalkDir,
+};
+
+use crate::package::PackageId;
+
+/// Ways a change can break users of a package
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Breakage {
+    /// Peers built against different versions fail to exchange messages
+    Wire,
+    /// Code generated from the new version no longer compiles against code
+    /// written for the old one
+    Source,
+}
+
+impl fmt::Display for Breakage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wire => write!(f, "wire"),
+            Self::Source => write!(f, "source"),
+        }
+    }
+}
+
+/// A change between two versions of a package that breaks its users
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Change {
+    /// How the change breaks users
+    pub breakage: Breakage,
+    /// What changed, naming the affected definition
+    pub description: String,
+}
+
+impl Change {
+    fn wire(description: String) -> Self {
+        Self {
+            breakage: Breakage::Wire,
+            description,
+        }
+    }
+
+    fn source(description: String) -> Self {
+        Self {
+            breakage: Breakage::Source,
+            description,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.breakage, self.description)
+    }
+}
+
+/// The protos of one version of an api package, compiled together with the
+/// installed dependencies
+#[derive(Debug, Clone)]
+pub struct Api {
+    pool: DescriptorPool,
+    /// Protos belonging to the package, relative to its root
+    files: BTreeSet<String>,
+}
+
+impl Api {
+    /// An api described by the descriptors in `pool`, of which the protos
+    /// in `files` (relative to the package root) belong to the package
+    pub fn new(pool: DescriptorPool, files: BTreeSet<String>) -> Self {
+        Self { pool, files }
+    }
+
+    /// Compiles the api package of a project
+    #[cfg(feature = "reflect")]
+    pub async fn local(store: &PackageStore) -> eyre::Result<Self> {
+        let schema = Schema::compile(store).await?;
+        let files = files(&store.api_path());
+
+        Ok(Self::new(schema.pool().clone(), files))
+    }
+
+    /// Compiles a published release of the api package, resolving its
+    /// imports against the dependencies installed in `store`
+    #[cfg(feature = "reflect")]
+    pub async fn release(store: &PackageStore, package: &Package) -> eyre::Result<Self> {
+        let root = tempfile::tempdir().wrap_err("Failed to create temporary directory")?;
+        let release = PackageStore::new(root.path()).with_dep_path(store.dep_path());
+
+        for file in package.tgz.files().await? {
+            if file.path.extension().is_none_or(|e| e != "proto") {
+                continue;
+            }
+
+            ensure!(
+                file.path
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_))),
+                "Invalid file path {} in package {}",
+                file.path.display(),
+                package.name
+            );
+
+            let path = release.api_path().join(&file.path);
+
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .wrap_err("Failed to unpack release")?;
+            }
+
+            tokio::fs::write(&path, &file.contents)
+                .await
+                .wrap_err("Failed to unpack release")?;
+        }
+
+        let schema = Schema::compile(&release).await.wrap_err_with(|| {
+            format!(
+                "Failed to compile {}@{} against the installed dependencies",
+                package.name, package.version
+            )
+        })?;
+
+        let files = files(&release.api_path());
+
+        Ok(Self::new(schema.pool().clone(), files))
+    }
+}
+
+/// Breaking changes of the local version of a package compared to an
+/// earlier release
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// Name of the package
+    pub package: PackageId,
+    /// Version of the release compared against
+    pub previous: semver::Version,
+    /// Local version of the package
+    pub version: semver::Version,
+    /// Changes breaking users of the previous release
+    pub changes: Vec<Change>,
+}
+
+impl Report {
+    /// Whether the version bump announces the breaking changes, if any
+    pub fn is_announced(&self) -> bool {
+        self.changes.is_empty() || is_breaking_bump(&self.previous, &self.version)
+    }
+}
+
+/// Lists the protos below `root` by their path relative to it
+#[cfg(feature = "reflect")]
+fn files(root: &Path) -> BTreeSet<String> {
+    WalkDir::new(root)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|e| e == "proto"))
+        .filter_map(|entry| {
+            let relative = entry.path().strip_prefix(root).ok()?;
+
+            Some(
+                relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+            )
+        })
+        .collect()
+}
+
+/// Whether going from `previous` to `next` announces breaking changes under
+/// semver, i.e. bumps the major version or the leftmost non-zero one
+pub fn is_breaking_bump(previous: &semver::Version, next: &semver::Version) -> bool {
+    let key = |v: &semver::Version| match (v.major, v.minor) {
+        (0, 0) => (0, 0, v.patch),
+        (0, minor) => (0, minor, 0),
+        (major, _) => (major, 0, 0),
+    };
+
+    key(next) > key(previous)
+}
+
+/// Lists the changes from `old` to `new` that break users of the package
+///
+/// Definitions are matched by their full name, fields and enum values by
+/// their number. Removing a field or enum value whose number is reserved
+/// only breaks the source, otherwise its number may be reused on the wire.
+pub fn compare(old: &Api, new: &Api) -> Vec<Change> {
+    let (before, after) = (&old.pool, &new.pool);
+
+    let mut changes: Vec<Change> = old
+        .files
+        .difference(&new.files)
+        .map(|file| Change::source(format!("file {file} was removed")))
+        .collect();
+
+    for message in before
+        .all_messages()
+        .filter(|m| old.files.contains(m.parent_file().name()) && !m.is_map_entry())
+    {
+        let name = message.full_name();
+
+        let Some(updated) = after.get_message_by_name(name) else {
+            changes.push(Change::source(format!("message {name} was removed")));
+            continue;
+        };
+
+        for field in message.fields() {
+            let number = field.number();
+            let path = format!("{name}.{} ({number})", field.name());
+
+            let Some(replacement) = updated.get_field(number) else {
+                match updated.reserved_ranges().any(|r| r.contains(&number)) {
+                    true => changes.push(Change::source(format!("field {path} was removed"))),
+                    false => changes.push(Change::wire(format!(
+                        "field {path} was removed without reserving its number"
+                    ))),
+                }
+
+                continue;
+            };
+
+            let (kind, replaced_kind) = (kind(&field), kind(&replacement));
+
+            if kind != replaced_kind {
+                changes.push(Change::wire(format!(
+                    "field {path} changed its type from {kind} to {replaced_kind}"
+                )));
+            }
+
+            let (label, replaced_label) = (label(&field), label(&replacement));
+
+            if label != replaced_label {
+                changes.push(Change::wire(format!(
+                    "field {path} changed from {label} to {replaced_label}"
+                )));
+            }
+
+            if field.name() != replacement.name() {
+                changes.push(Change::source(format!(
+                    "field {path} was renamed to {}",
+                    replacement.name()
+                )));
+            }
+        }
+    }
+
+    for enumeration in before
+        .all_enums()
+        .filter(|e| old.files.contains(e.parent_file().name()))
+    {
+        let name = enumeration.full_name();
+
+        let Some(updated) = after.get_enum_by_name(name) else {
+            changes.push(Change::source(format!("enum {name} was removed")));
+            continue;
+        };
+
+        for value in enumeration.values() {
+            let number = value.number();
+            let path = format!("{name}.{} ({number})", value.name());
+
+            match updated.get_value(number) {
+                None if updated.reserved_ranges().any(|r| r.contains(&number)) => {
+                    changes.push(Change::source(format!("enum value {path} was removed")))
+                }
+                None => changes.push(Change::wire(format!(
+                    "enum value {path} was removed without reserving its number"
+                ))),
+                Some(replacement) if replacement.name() != value.name() => {
+                    changes.push(Change::source(format!(
+                        "enum value {path} was renamed to {}",
+                        replacement.name()
+                    )))
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    for service in before
+        .services()
+        .filter(|s| old.files.contains(s.parent_file().name()))
+    {
+        let name = service.full_name();
+
+        let Some(updated) = after.get_service_by_name(name) else {
+            changes.push(Change::wire(format!("service {name} was removed")));
+            continue;
+        };
+
+        for method in service.methods() {
+            let path = format!("{name}.{}", method.name());
+
+            let Some(replacement) = updated.methods().find(|m| m.name() == method.name()) else {
+                changes.push(Change::wire(format!("rpc {path} was removed")));
+                continue;
+            };
+
+            for (what, type_name, replaced) in [
+                (
+                    "request",
+                    method.input().full_name().to_owned(),
+                    replacement.input().full_name().to_owned(),
+                ),
+                (
+                    "response",
+                    method.output().full_name().to_owned(),
+                    replacement.output().full_name().to_owned(),
+                ),
+            ] {
+                if type_name != replaced {
+                    changes.push(Change::wire(format!(
+                        "rpc {path} changed its {what} from {type_name} to {replaced}"
+                    )));
+                }
+            }
+
+            for (what, streaming, replaced) in [
+                (
+                    "requests",
+                    method.is_client_streaming(),
+                    replacement.is_client_streaming(),
+                ),
+                (
+                    "responses",
+                    method.is_server_streaming(),
+                    replacement.is_server_streaming(),
+                ),
+            ] {
+                if streaming != replaced {
+                    let streams = |streaming| match streaming {
+                        true => "streamed",
+                        false => "unary",
+                    };
+
+                    changes.push(Change::wire(format!(
+                        "rpc {path} changed its {what} from {} to {}",
+                        streams(streaming),
+                        streams(replaced)
+                    )));
+                }
+            }
+        }
+    }
+
+    changes
+}
+
+/// Name of the type of a field, e.g. `int32`, `units.Temp` or
+/// `map<string, int32>`
+fn kind(field: &FieldDescriptor) -> String {
+    match field.kind() {
+        // Entries are named after the field, compare what they hold instead
+        Kind::Message(entry) if entry.is_map_entry() => format!(
+            "map<{}, {}>",
+            kind(&entry.map_entry_key_field()),
+            kind(&entry.map_entry_value_field())
+        ),
+        Kind::Message(message) => message.full_name().to_owned(),
+        Kind::Enum(enumeration) => enumeration.full_name().to_owned(),
+        scalar => format!("{scalar:?}").to_lowercase(),
+    }
+}
+
+/// Whether a field holds a single value, a list or a map
+fn label(field: &FieldDescriptor) -> &'static str {
+    match (field.is_map(), field.is_list()) {
+        (true, _) => "map",
+        (false, true) => "repeated",
+        (false, false) => "singular",
+    }
+}
@@ -0,0 +1,141 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::path::{Path, PathBuf};
+
+use eyre::{Context, ContextCompat};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    blocking::block_on,
+    config::Config,
+    error::ErrorCode,
+    generator::{self, Language},
+    lock::LOCKFILE,
+    manifest::{Manifest, MANIFEST_FILE},
+    ops::{self, InstallOptions},
+    package::{PackageCache, PackageStore},
+    registry::EncryptedRegistry,
+    workspace::Workspace,
+};
+
+/// Installs the dependencies of the crate and generates Rust code for its
+/// protos, for use in a Cargo build script
+///
+/// The project is the crate being built (`CARGO_MANIFEST_DIR`) and the code
+/// is written to `OUT_DIR`, one file per proto package:
+///
+/// ```ignore
+/// // build.rs
+/// fn main() {
+///     buffrs::build::install_and_generate().unwrap();
+/// }
+///
+/// // src/lib.rs
+/// include!(concat!(env!("OUT_DIR"), "/units.rs"));
+/// ```
+///
+/// See [`install_and_generate_at`] for details.
+pub fn install_and_generate() -> eyre::Result<()> {
+    let root = env_path("CARGO_MANIFEST_DIR")?;
+    let out_dir = env_path("OUT_DIR")?;
+
+    install_and_generate_at(&root, &out_dir)
+}
+
+/// Installs the dependencies of the project at `root` and generates Rust code
+/// for its protos into `out_dir`
+///
+/// Dependencies are only installed if they are missing or do not match the
+/// lockfile, so builds with an up to date lockfile and store never contact
+/// the registry. Installing uses the regular `buffrs` configuration and skips
+/// dependencies only needed for other languages.
+///
+/// Cargo is told to rerun the build script when the manifest, lockfile or
+/// api protos change. Code is generated by `protoc` with the
+/// `protoc-gen-prost` plugin, see [`generator::generate`].
+pub fn install_and_generate_at(root: &Path, out_dir: &Path) -> eyre::Result<()> {
+    block_on(async {
+        let config = match Config::exists().await? {
+            true => Config::read().await?,
+            false => Config::default(),
+        };
+
+        let store = match config.vendor_dir {
+            Some(ref vendor_dir) => PackageStore::new(root).with_dep_path(vendor_dir),
+            None => PackageStore::new(root),
+        };
+
+        let manifest = Manifest::read(root).await?;
+        let workspace = Workspace::discover(root).await?;
+
+        let lock_root = workspace.as_ref().map_or(root, |w| w.root());
+
+        for path in [
+            root.join(MANIFEST_FILE),
+            lock_root.join(LOCKFILE),
+            store.api_path(),
+        ] {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+
+        println!("cargo:rerun-if-env-changed={}", generator::PROTOC_ENV);
+
+        if !ops::is_installed(&store, &manifest, lock_root, &rust_options()).await? {
+            install(&store, &manifest, workspace.as_ref(), config).await?;
+        }
+
+        generator::generate(&store, Language::Rust, out_dir).await
+    })?
+}
+
+/// Selects the dependencies needed for Rust code, without optional groups
+fn rust_options() -> InstallOptions {
+    InstallOptions {
+        language: Some(Language::Rust),
+        ..Default::default()
+    }
+}
+
+async fn install(
+    store: &PackageStore,
+    manifest: &Manifest,
+    workspace: Option<&Workspace>,
+    config: Config,
+) -> eyre::Result<()> {
+    let options = InstallOptions {
+        policies: config.policies().await?,
+        cache: Some(PackageCache::open()?),
+        client: config.client()?,
+        ..rust_options()
+    };
+
+    let registry = config
+        .registry()?
+        .wrap_err("Unable to install artifactory dependencies, please login using `buffrs login`")
+        .wrap_err(ErrorCode::NotLoggedIn)?;
+
+    let registry = EncryptedRegistry::new(registry, config.encryption.unwrap_or_default());
+
+    let cancel = CancellationToken::new();
+
+    match workspace {
+        Some(workspace) => {
+            ops::install_workspace(
+                workspace,
+                std::slice::from_ref(store),
+                &registry,
+                &options,
+                &(),
+                &cancel,
+            )
+            .await
+        }
+        None => ops::install(store, manifest, &registry, &options, &(), &cancel).await,
+    }
+}
+
+fn env_path(name: &str) -> eyre::Result<PathBuf> {
+    std::env::var_os(name)
+        .map(PathBuf::from)
+        .wrap_err_with(|| format!("{name} is not set, this has to run in a Cargo build script"))
+}
@@ -2,71 +2,625 @@
 
 use eyre::{Context, ContextCompat};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use tokio::fs;
 
-use crate::registry::ArtifactoryConfig;
+use crate::{
+    policy::{self, Policy},
+    registry::{
+        local_user, Artifactory, ArtifactoryConfig, AuditConfig, AuditedRegistry, EncryptionConfig,
+        FileRegistry, FileRegistryConfig, HttpRegistry, HttpRegistryConfig, NetworkConfig,
+        Registry, RetryConfig,
+    },
+};
 
-/// Global configuration directory for `buffrs`
+/// Configuration directory in the home directory used by previous releases
 pub const BUFFRS_HOME: &str = ".buffrs";
 /// Filename of the configration
 pub const CONFIG_FILE: &str = "config.toml";
+/// Filename of the credentials stored outside of the system keyring
+pub const CREDENTIALS_FILE: &str = "credentials.toml";
 
 /// Configuration format for storing authentication and settings
 #[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Config {
     /// Artifactory related configuration
     pub artifactory: Option<ArtifactoryConfig>,
+    /// Plain HTTP file server or S3-compatible bucket used instead of
+    /// artifactory
+    pub http: Option<HttpRegistryConfig>,
+    /// Local or network mounted directory used instead of a remote registry,
+    /// e.g. in air-gapped environments
+    pub filesystem: Option<FileRegistryConfig>,
+    /// Whether to check daily for new `buffrs` releases (enabled by default)
+    pub check_for_updates: Option<bool>,
+    /// File to append a local usage summary of every invocation to
+    pub usage_report: Option<PathBuf>,
+    /// Rules applied to every installation
+    pub policy: Option<Policy>,
+    /// Organization wide policy files applied to every installation
+    pub policy_files: Option<Vec<PathBuf>>,
+    /// Directory dependencies are installed into, relative to the project
+    /// root (defaults to `proto/dep`)
+    pub vendor_dir: Option<PathBuf>,
+    /// Keys of restricted repositories whose packages are encrypted at rest
+    pub encryption: Option<HashMap<String, EncryptionConfig>>,
+    /// Proxy and certificate settings for reaching remote registries
+    pub network: Option<NetworkConfig>,
+    /// Retries of registry requests that failed transiently
+    pub retry: Option<RetryConfig>,
+    /// Records of the packages published, yanked and archived by this client
+    pub audit: Option<AuditConfig>,
+    /// SSH keys trusted to sign lockfiles, in the allowed signers format of
+    /// `ssh-keygen`. Installs with `--locked` require a valid signature.
+    pub allowed_signers: Option<PathBuf>,
 }
 
 impl Config {
-    fn location() -> eyre::Result<PathBuf> {
-        let home = home::home_dir().wrap_err("Failed to locate home directory")?;
-
-        Ok(home.join(BUFFRS_HOME).join(CONFIG_FILE))
+    /// Default location of the configuration (`config.toml` in [`config_dir`])
+    pub fn location() -> eyre::Result<PathBuf> {
+        Ok(config_dir()?.join(CONFIG_FILE))
     }
 
     /// Checks if the configuration exists
     pub async fn exists() -> eyre::Result<bool> {
-        fs::try_exists(Self::location()?)
+        Self::exists_at(&Self::location()?).await
+    }
+
+    /// Checks if a configuration exists at `path`
+    pub async fn exists_at(path: &Path) -> eyre::Result<bool> {
+        fs::try_exists(path)
             .await
             .wrap_err("Failed to detect config")
     }
 
     /// Reads the configuration from the file system
+    ///
+    /// References to environment variables (`${VAR}`) in string values are
+    /// expanded, use `$${` for a literal `${`.
     pub async fn read() -> eyre::Result<Self> {
-        let toml = fs::read_to_string(Self::location()?)
+        Self::read_at(&Self::location()?).await
+    }
+
+    /// Reads the configuration stored at `path`, see [`Config::read`]
+    ///
+    /// Use [`ConfigDocument`] to modify and write back the configuration, so
+    /// references to environment variables are preserved.
+    pub async fn read_at(path: &Path) -> eyre::Result<Self> {
+        let toml = fs::read_to_string(path)
             .await
-            .wrap_err("Failed to read manifest")?;
+            .wrap_err_with(|| format!("Failed to read config {}", path.display()))?;
+
+        let mut value: toml::Value = toml::from_str(&toml).wrap_err("Failed to parse config")?;
+
+        interpolate(&mut value, "")?;
 
-        toml::from_str(&toml).wrap_err("Failed to parse config")
+        let mut config: Self = value.try_into().wrap_err("Failed to parse config")?;
+
+        if let Some(ref mut policy) = config.policy {
+            policy.source = Some(path.to_owned());
+        }
+
+        Ok(config)
     }
 
     /// Writes the configuration to the file system
     pub async fn write(&self) -> eyre::Result<()> {
-        fs::create_dir(
-            Self::location()?
-                .parent()
-                .wrap_err("Invalid config location")?,
-        )
-        .await
-        .ok();
-
-        fs::write(Self::location()?, toml::to_string(&self)?.into_bytes())
+        self.write_at(&Self::location()?).await
+    }
+
+    /// Writes the configuration to `path`
+    pub async fn write_at(&self, path: &Path) -> eyre::Result<()> {
+        fs::create_dir_all(path.parent().wrap_err("Invalid config location")?)
+            .await
+            .ok();
+
+        fs::write(path, toml::to_string(&self)?.into_bytes())
             .await
             .wrap_err("Failed to write config")
     }
 
+    /// Combines two configurations, settings of `other` take precedence
+    pub fn merge(self, other: Config) -> Config {
+        Config {
+            artifactory: other.artifactory.or(self.artifactory),
+            http: other.http.or(self.http),
+            filesystem: other.filesystem.or(self.filesystem),
+            check_for_updates: other.check_for_updates.or(self.check_for_updates),
+            usage_report: other.usage_report.or(self.usage_report),
+            policy: other.policy.or(self.policy),
+            policy_files: other.policy_files.or(self.policy_files),
+            vendor_dir: other.vendor_dir.or(self.vendor_dir),
+            encryption: other.encryption.or(self.encryption),
+            network: other.network.or(self.network),
+            retry: other.retry.or(self.retry),
+            audit: other.audit.or(self.audit),
+            allowed_signers: other.allowed_signers.or(self.allowed_signers),
+        }
+    }
+
+    /// The remote registry packages are installed from and published to
+    ///
+    /// A configured filesystem registry takes precedence over an http
+    /// registry, which takes precedence over artifactory. Remote registries
+    /// are accessed with the [`NetworkConfig`] settings and retry requests as
+    /// configured by [`RetryConfig`].
+    ///
+    /// With an [`AuditConfig`], publishes, yanks and archivals are recorded
+    /// and attributed to the artifactory user or else the local user.
+    pub fn registry(&self) -> eyre::Result<Option<Box<dyn Registry + Send + Sync>>> {
+        let client = || self.client();
+        let retry = self.retry.clone().unwrap_or_default();
+
+        let registry: Box<dyn Registry + Send + Sync> =
+            match (&self.filesystem, &self.http, &self.artifactory) {
+                (Some(filesystem), _, _) => Box::new(FileRegistry::from(filesystem.clone())),
+                (None, Some(http), _) => {
+                    Box::new(HttpRegistry::with_client(http.clone(), client()?).with_retry(retry))
+                }
+                (None, None, Some(artifactory)) => Box::new(
+                    Artifactory::with_client(artifactory.clone(), client()?).with_retry(retry),
+                ),
+                (None, None, None) => return Ok(None),
+            };
+
+        let Some(ref audit) = self.audit else {
+            return Ok(Some(registry));
+        };
+
+        let user = match (&self.filesystem, &self.http, &self.artifactory) {
+            (None, None, Some(artifactory)) => artifactory.username.clone(),
+            _ => local_user(),
+        };
+
+        Ok(Some(Box::new(AuditedRegistry::new(
+            registry,
+            audit.clone(),
+            user,
+        ))))
+    }
+
+    /// Url of the archive of `package@version` in `repository`, following the
+    /// same precedence as [`Config::registry`]
+    pub fn archive_url(
+        &self,
+        repository: &str,
+        package: &str,
+        version: &semver::Version,
+    ) -> eyre::Result<Option<url::Url>> {
+        match (&self.filesystem, &self.http, &self.artifactory) {
+            (Some(filesystem), _, _) => {
+                let path = filesystem.archive(repository, package, version);
+
+                url::Url::from_file_path(&path)
+                    .map(Some)
+                    .ok()
+                    .wrap_err_with(|| format!("Invalid archive path {}", path.display()))
+            }
+            (None, Some(http), _) => http.url.archive(repository, package, version).map(Some),
+            (None, None, Some(artifactory)) => artifactory
+                .artifact_url(repository, package, version)
+                .map(Some),
+            (None, None, None) => Ok(None),
+        }
+    }
+
+    /// The http client remote registries are accessed with, see
+    /// [`NetworkConfig::client`]
+    pub fn client(&self) -> eyre::Result<reqwest::Client> {
+        self.network.clone().unwrap_or_default().client()
+    }
+
+    /// Loads the inline policy and all policy files of the configuration
+    pub async fn policies(&self) -> eyre::Result<Vec<Policy>> {
+        let mut policies: Vec<Policy> = self.policy.iter().cloned().collect();
+
+        for path in self.policy_files.iter().flatten() {
+            policies.push(Policy::load(path).await?);
+        }
+
+        // Surface conflicting requirements early instead of on first use
+        policy::digest_algorithm(&policies)?;
+
+        Ok(policies)
+    }
+
     /// Loads the configuration from the file system
     ///
     /// Note: Initializes the configuration if its not present
     pub async fn load() -> eyre::Result<Self> {
-        let Ok(cfg) = Self::read().await else {
+        Self::load_at(&Self::location()?).await
+    }
+
+    /// Loads the configuration stored at `path`, see [`Config::load`]
+    pub async fn load_at(path: &Path) -> eyre::Result<Self> {
+        if !Self::exists_at(path).await? {
             let cfg = Config::default();
-            cfg.write().await?;
+            cfg.write_at(path).await?;
             return Ok(cfg);
+        }
+
+        Self::read_at(path).await
+    }
+}
+
+/// The configuration as written, edited in place
+///
+/// Settings are kept as they are, so references to environment variables
+/// (`${VAR}`) are neither expanded nor required to be set. Settings are
+/// addressed by dotted keys such as `artifactory.url`.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigDocument(toml_edit::Document);
+
+/// Artifactory settings kept across logins, as they are not credentials
+const LOGIN_KEPT: [&str; 2] = ["publish_token_ttl", "layout"];
+
+impl ConfigDocument {
+    /// Reads the configuration stored at `path`, empty if there is none
+    pub async fn read_at(path: &Path) -> eyre::Result<Self> {
+        if !Config::exists_at(path).await? {
+            return Ok(Self::default());
+        }
+
+        fs::read_to_string(path)
+            .await
+            .wrap_err_with(|| format!("Failed to read config {}", path.display()))?
+            .parse()
+    }
+
+    /// Writes the configuration to `path`
+    pub async fn write_at(&self, path: &Path) -> eyre::Result<()> {
+        fs::create_dir_all(path.parent().wrap_err("Invalid config location")?)
+            .await
+            .ok();
+
+        fs::write(path, self.to_string().into_bytes())
+            .await
+            .wrap_err("Failed to write config")
+    }
+
+    /// Combines two configurations, see [`Config::merge`]
+    pub fn merge(&mut self, other: ConfigDocument) {
+        for (key, item) in other.0.iter() {
+            self.0.insert(key, item.clone());
+        }
+    }
+
+    /// Reads a setting as written, strings are returned without quotes
+    pub fn get(&self, key: &str) -> Option<String> {
+        let mut item = self.0.as_item();
+
+        for segment in key.split('.') {
+            item = item.get(segment)?;
+        }
+
+        Some(match item.as_str() {
+            Some(string) => string.to_owned(),
+            None => item.to_string().trim().to_owned(),
+        })
+    }
+
+    /// Sets a string setting, creating missing tables along the way
+    pub fn set(&mut self, key: &str, value: &str) -> eyre::Result<()> {
+        let (parents, field) = match key.rsplit_once('.') {
+            Some((parents, field)) => (Some(parents), field),
+            None => (None, key),
         };
 
-        Ok(cfg)
+        let mut table: &mut dyn toml_edit::TableLike = self.0.as_table_mut();
+
+        for segment in parents.into_iter().flat_map(|p| p.split('.')) {
+            if !table.contains_key(segment) {
+                table.insert(segment, toml_edit::table());
+            }
+
+            table = table
+                .get_mut(segment)
+                .and_then(toml_edit::Item::as_table_like_mut)
+                .wrap_err_with(|| format!("{segment} in {key} is not a table"))?;
+        }
+
+        table.insert(field, toml_edit::value(value));
+
+        Ok(())
+    }
+
+    /// Replaces the artifactory credentials by those of `artifactory`
+    ///
+    /// Settings that are not part of the credentials, such as the layout,
+    /// are kept as written.
+    pub fn login(&mut self, artifactory: &ArtifactoryConfig) -> eyre::Result<()> {
+        let credentials: toml_edit::Document = toml::to_string(artifactory)
+            .wrap_err("Failed to encode artifactory config")?
+            .parse()
+            .wrap_err("Failed to encode artifactory config")?;
+
+        if !self
+            .0
+            .get("artifactory")
+            .is_some_and(toml_edit::Item::is_table)
+        {
+            self.0.insert("artifactory", toml_edit::table());
+        }
+
+        let table = self.0["artifactory"]
+            .as_table_mut()
+            .wrap_err("artifactory is not a table")?;
+
+        let previous: Vec<String> = table
+            .iter()
+            .map(|(key, _)| key.to_owned())
+            .filter(|key| !LOGIN_KEPT.contains(&key.as_str()))
+            .collect();
+
+        for key in previous {
+            table.remove(&key);
+        }
+
+        for (key, item) in credentials.iter() {
+            if !LOGIN_KEPT.contains(&key) {
+                table.insert(key, item.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the artifactory settings
+    pub fn logout(&mut self) {
+        self.0.remove("artifactory");
+    }
+}
+
+impl FromStr for ConfigDocument {
+    type Err = eyre::Error;
+
+    fn from_str(toml: &str) -> eyre::Result<Self> {
+        toml.parse().map(Self).wrap_err("Failed to parse config")
+    }
+}
+
+impl fmt::Display for ConfigDocument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Platform specific directories of `buffrs`
+fn project_dirs() -> eyre::Result<directories::ProjectDirs> {
+    directories::ProjectDirs::from("", "", "buffrs").wrap_err("Failed to locate home directory")
+}
+
+/// Directory holding the configuration
+///
+/// This is `$XDG_CONFIG_HOME/buffrs` (`~/.config/buffrs`) on Linux,
+/// `~/Library/Application Support/buffrs` on macOS and
+/// `%APPDATA%\buffrs\config` on Windows.
+pub fn config_dir() -> eyre::Result<PathBuf> {
+    Ok(project_dirs()?.config_dir().to_owned())
+}
+
+/// Location of the passwords of registries using the file credential store
+pub fn credentials_file() -> eyre::Result<PathBuf> {
+    Ok(config_dir()?.join(CREDENTIALS_FILE))
+}
+
+/// Directory holding caches, which can be deleted at any time
+///
+/// This is `$XDG_CACHE_HOME/buffrs` (`~/.cache/buffrs`) on Linux,
+/// `~/Library/Caches/buffrs` on macOS and `%LOCALAPPDATA%\buffrs\cache` on
+/// Windows.
+pub fn cache_dir() -> eyre::Result<PathBuf> {
+    Ok(project_dirs()?.cache_dir().to_owned())
+}
+
+/// Directory used by previous releases for all files (`~/.buffrs`)
+pub fn legacy_dir() -> eyre::Result<PathBuf> {
+    let home = home::home_dir().wrap_err("Failed to locate home directory")?;
+
+    Ok(home.join(BUFFRS_HOME))
+}
+
+/// Moves the files of previous releases from [`legacy_dir`] to the platform
+/// specific directories
+///
+/// Files already present at their new location are left untouched. The legacy
+/// directory is removed once it is empty.
+pub async fn migrate() -> eyre::Result<()> {
+    let legacy = legacy_dir()?;
+
+    if !fs::try_exists(&legacy).await.unwrap_or(false) {
+        return Ok(());
+    }
+
+    let moves = [
+        (legacy.join(CONFIG_FILE), config_dir()?.join(CONFIG_FILE)),
+        (
+            legacy.join(CREDENTIALS_FILE),
+            config_dir()?.join(CREDENTIALS_FILE),
+        ),
+        (legacy.join("proxy"), cache_dir()?.join("proxy")),
+        (
+            legacy.join("version-check.toml"),
+            cache_dir()?.join("version-check.toml"),
+        ),
+    ];
+
+    for (from, to) in moves {
+        if !fs::try_exists(&from).await.unwrap_or(false)
+            || fs::try_exists(&to).await.unwrap_or(true)
+        {
+            continue;
+        }
+
+        fs::create_dir_all(to.parent().wrap_err("Invalid migration target")?)
+            .await
+            .wrap_err_with(|| format!("Failed to create {}", to.display()))?;
+
+        if fs::rename(&from, &to).await.is_err() {
+            // Renaming fails across file systems, files can be copied instead
+            fs::copy(&from, &to).await.wrap_err_with(|| {
+                format!("Failed to move {} to {}", from.display(), to.display())
+            })?;
+
+            fs::remove_file(&from).await.ok();
+        }
+
+        tracing::info!(":: moved {} to {}", from.display(), to.display());
+    }
+
+    fs::remove_dir(&legacy).await.ok();
+
+    Ok(())
+}
+
+/// Expands references to environment variables in all strings of `value`
+fn interpolate(value: &mut toml::Value, key: &str) -> eyre::Result<()> {
+    match value {
+        toml::Value::String(string) => {
+            *string =
+                expand(string).wrap_err_with(|| format!("Failed to expand {key} in config"))?;
+        }
+        toml::Value::Array(array) => {
+            for (index, value) in array.iter_mut().enumerate() {
+                interpolate(value, &format!("{key}[{index}]"))?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (name, value) in table.iter_mut() {
+                let key = match key {
+                    "" => name.to_owned(),
+                    key => format!("{key}.{name}"),
+                };
+
+                interpolate(value, &key)?;
+            }
+        }
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// Replaces `${VAR}` with the value of the environment variable `VAR`
+fn expand(string: &str) -> eyre::Result<String> {
+    let mut expanded = String::with_capacity(string.len());
+    let mut rest = string;
+
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            expanded.push_str("${");
+            rest = escaped;
+        } else if let Some(reference) = rest.strip_prefix("${") {
+            let end = reference
+                .find('}')
+                .wrap_err_with(|| format!("Unterminated variable reference in {string:?}"))?;
+
+            let name = &reference[..end];
+
+            let value = std::env::var(name)
+                .wrap_err_with(|| format!("Environment variable {name} is not set"))?;
+
+            expanded.push_str(&value);
+            rest = &reference[end + 1..];
+        } else {
+            expanded.push('$');
+            rest = &rest[1..];
+        }
+    }
+
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::Authentication;
+
+    const INTERPOLATED: &str = r#"# Provisioned by the platform team
+[artifactory]
+url = "${REG_URL}"
+username = "${REG_USER}"
+layout = "${REG_LAYOUT}"
+
+[network]
+ca_bundle = "${CA_BUNDLE}"
+"#;
+
+    #[test]
+    fn login_and_logout_keep_interpolated_settings() {
+        let mut document: ConfigDocument = INTERPOLATED.parse().unwrap();
+
+        document
+            .login(&ArtifactoryConfig {
+                url: "https://example.com/artifactory".parse().unwrap(),
+                username: "me".to_owned(),
+                publish_token_ttl: None,
+                sigv4: None,
+                layout: None,
+                authentication: Some(Authentication::Bearer),
+                credential_store: None,
+                password_env: None,
+            })
+            .unwrap();
+
+        let document: ConfigDocument = document.to_string().parse().unwrap();
+
+        assert_eq!(
+            document.get("artifactory.url").as_deref(),
+            Some("https://example.com/artifactory")
+        );
+        assert_eq!(document.get("artifactory.username").as_deref(), Some("me"));
+        assert_eq!(
+            document.get("artifactory.authentication").as_deref(),
+            Some("bearer")
+        );
+        assert_eq!(
+            document.get("artifactory.layout").as_deref(),
+            Some("${REG_LAYOUT}")
+        );
+        assert_eq!(
+            document.get("network.ca_bundle").as_deref(),
+            Some("${CA_BUNDLE}")
+        );
+        assert!(document
+            .to_string()
+            .starts_with("# Provisioned by the platform team"));
+
+        let mut document = document;
+        document.logout();
+
+        let document: ConfigDocument = document.to_string().parse().unwrap();
+
+        assert_eq!(document.get("artifactory.url"), None);
+        assert_eq!(
+            document.get("network.ca_bundle").as_deref(),
+            Some("${CA_BUNDLE}")
+        );
+    }
+
+    #[test]
+    fn setup_keeps_interpolated_url() {
+        let mut document: ConfigDocument = INTERPOLATED.parse().unwrap();
+
+        document.set("artifactory.username", "me").unwrap();
+
+        let document: ConfigDocument = document.to_string().parse().unwrap();
+
+        assert_eq!(
+            document.get("artifactory.url").as_deref(),
+            Some("${REG_URL}")
+        );
+        assert_eq!(document.get("artifactory.username").as_deref(), Some("me"));
     }
 }
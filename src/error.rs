@@ -0,0 +1,154 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{fmt, str::FromStr};
+
+/// Codes of failures users can act upon
+///
+/// Codes are attached to reports using `wrap_err` and can be recovered with
+/// [`ErrorCode::of`]. `buffrs explain <code>` prints their explanation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// The manifest of the project could not be found
+    MissingManifest,
+    /// The manifest of the project is malformed
+    InvalidManifest,
+    /// No credentials for the registry are available
+    NotLoggedIn,
+    /// A package could not be downloaded from the registry
+    DownloadFailed,
+    /// The registry rejected a published package
+    PublishRejected,
+    /// A dependency is blocked by an allow or deny list
+    PolicyViolation,
+    /// A dependency was published too recently
+    Quarantined,
+    /// A policy hook vetoed an operation
+    PolicyVeto,
+    /// A package name does not follow the naming rules
+    InvalidPackageId,
+    /// A dependency specification could not be parsed
+    InvalidDependency,
+    /// Downloaded contents do not match their expected checksum
+    IntegrityMismatch,
+    /// An encrypted package could not be decrypted
+    DecryptionFailed,
+    /// The lockfile does not match the dependencies of the manifest
+    LockfileOutdated,
+    /// Several packages define the same proto type
+    SymbolCollision,
+    /// A workspace member declares protos outside of its namespace
+    NamespaceViolation,
+    /// The lockfile is not signed by an allowed signer
+    SignatureInvalid,
+}
+
+impl ErrorCode {
+    /// All known error codes
+    pub const ALL: &'static [Self] = &[
+        Self::MissingManifest,
+        Self::InvalidManifest,
+        Self::NotLoggedIn,
+        Self::DownloadFailed,
+        Self::PublishRejected,
+        Self::PolicyViolation,
+        Self::Quarantined,
+        Self::PolicyVeto,
+        Self::InvalidPackageId,
+        Self::InvalidDependency,
+        Self::IntegrityMismatch,
+        Self::DecryptionFailed,
+        Self::LockfileOutdated,
+        Self::SymbolCollision,
+        Self::NamespaceViolation,
+        Self::SignatureInvalid,
+    ];
+
+    /// Finds the first error code attached to a report
+    pub fn of(report: &eyre::Report) -> Option<Self> {
+        report.downcast_ref::<Self>().copied()
+    }
+
+    /// Stable identifier of the error, e.g. `E0001`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::MissingManifest => "E0001",
+            Self::InvalidManifest => "E0002",
+            Self::NotLoggedIn => "E0003",
+            Self::DownloadFailed => "E0004",
+            Self::PublishRejected => "E0005",
+            Self::PolicyViolation => "E0006",
+            Self::Quarantined => "E0007",
+            Self::PolicyVeto => "E0008",
+            Self::InvalidPackageId => "E0009",
+            Self::InvalidDependency => "E0010",
+            Self::IntegrityMismatch => "E0011",
+            Self::DecryptionFailed => "E0012",
+            Self::LockfileOutdated => "E0013",
+            Self::SymbolCollision => "E0014",
+            Self::NamespaceViolation => "E0015",
+            Self::SignatureInvalid => "E0016",
+        }
+    }
+
+    /// One line description of the error
+    pub fn summary(&self) -> &'static str {
+        match self {
+            Self::MissingManifest => "manifest not found",
+            Self::InvalidManifest => "invalid manifest",
+            Self::NotLoggedIn => "not logged in",
+            Self::DownloadFailed => "download failed",
+            Self::PublishRejected => "publish rejected",
+            Self::PolicyViolation => "blocked by policy",
+            Self::Quarantined => "dependency quarantined",
+            Self::PolicyVeto => "vetoed by policy hook",
+            Self::InvalidPackageId => "invalid package id",
+            Self::InvalidDependency => "invalid dependency specification",
+            Self::IntegrityMismatch => "integrity mismatch",
+            Self::DecryptionFailed => "decryption failed",
+            Self::LockfileOutdated => "lockfile out of date",
+            Self::SymbolCollision => "conflicting proto definitions",
+            Self::NamespaceViolation => "proto package outside namespace",
+            Self::SignatureInvalid => "invalid lockfile signature",
+        }
+    }
+
+    /// Detailed causes and fixes of the error
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            Self::MissingManifest => include_str!("../docs/errors/E0001.md"),
+            Self::InvalidManifest => include_str!("../docs/errors/E0002.md"),
+            Self::NotLoggedIn => include_str!("../docs/errors/E0003.md"),
+            Self::DownloadFailed => include_str!("../docs/errors/E0004.md"),
+            Self::PublishRejected => include_str!("../docs/errors/E0005.md"),
+            Self::PolicyViolation => include_str!("../docs/errors/E0006.md"),
+            Self::Quarantined => include_str!("../docs/errors/E0007.md"),
+            Self::PolicyVeto => include_str!("../docs/errors/E0008.md"),
+            Self::InvalidPackageId => include_str!("../docs/errors/E0009.md"),
+            Self::InvalidDependency => include_str!("../docs/errors/E0010.md"),
+            Self::IntegrityMismatch => include_str!("../docs/errors/E0011.md"),
+            Self::DecryptionFailed => include_str!("../docs/errors/E0012.md"),
+            Self::LockfileOutdated => include_str!("../docs/errors/E0013.md"),
+            Self::SymbolCollision => include_str!("../docs/errors/E0014.md"),
+            Self::NamespaceViolation => include_str!("../docs/errors/E0015.md"),
+            Self::SignatureInvalid => include_str!("../docs/errors/E0016.md"),
+        }
+    }
+}
+
+impl FromStr for ErrorCode {
+    type Err = eyre::Report;
+
+    fn from_str(code: &str) -> eyre::Result<Self> {
+        Self::ALL
+            .iter()
+            .find(|c| c.code().eq_ignore_ascii_case(code.trim()))
+            .copied()
+            .ok_or_else(|| eyre::eyre!("Unknown error code {code}"))
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error[{}]: {}", self.code(), self.summary())
+    }
+}
@@ -0,0 +1,594 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::ops::Range;
+
+/// Indentation of nested definitions
+const INDENT: &str = "  ";
+
+/// Statements with field options longer than this are split over several
+/// lines, one option per line
+const MAX_WIDTH: usize = 100;
+
+/// Formats the source of a `.proto` file
+///
+/// Statements are placed on lines of their own and indented by nesting,
+/// tokens are separated by single spaces, consecutive imports are sorted and
+/// long field option lists are split into one option per line. Comments and
+/// single blank lines between statements are kept, aggregate option values
+/// (`option (x) = { ... }`) are copied verbatim.
+pub fn format(source: &str) -> eyre::Result<String> {
+    let tokens = tokenize(source)?;
+
+    let mut parser = Parser {
+        source,
+        tokens,
+        position: 0,
+    };
+
+    let mut file = parser.block(false)?;
+
+    sort_imports(&mut file.statements);
+
+    let mut out = String::new();
+    print_block(&file, 0, &mut out);
+
+    Ok(out)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Kind {
+    /// Identifiers, keywords and numbers
+    Word,
+    /// Quoted strings, including their quotes
+    Str,
+    /// Single punctuation characters
+    Punct,
+    /// `// ...` comments, without the trailing newline
+    LineComment,
+    /// `/* ... */` comments
+    BlockComment,
+    /// Aggregate option values copied from the source
+    Verbatim,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: Kind,
+    text: String,
+    span: Range<usize>,
+    /// Number of line breaks between the previous token and this one
+    newlines: usize,
+}
+
+impl Token {
+    fn is(&self, punct: &str) -> bool {
+        self.kind == Kind::Punct && self.text == punct
+    }
+
+    fn is_comment(&self) -> bool {
+        matches!(self.kind, Kind::LineComment | Kind::BlockComment)
+    }
+}
+
+/// Line of a byte offset, for error messages
+fn line(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+fn tokenize(source: &str) -> eyre::Result<Vec<Token>> {
+    let bytes = source.as_bytes();
+    let mut tokens = vec![];
+    let mut i = 0;
+    let mut newlines = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+
+        let kind = match c {
+            b'\n' => {
+                newlines += 1;
+                i += 1;
+                continue;
+            }
+            c if c.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = source[i..].find('\n').map_or(bytes.len(), |end| i + end);
+                Kind::LineComment
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let end = source[i + 2..].find("*/").ok_or_else(|| {
+                    eyre::eyre!("Unterminated comment on line {}", line(source, start))
+                })?;
+
+                i += end + 4;
+                Kind::BlockComment
+            }
+            b'"' | b'\'' => {
+                i += 1;
+
+                loop {
+                    match bytes.get(i) {
+                        None | Some(b'\n') => {
+                            eyre::bail!("Unterminated string on line {}", line(source, start))
+                        }
+                        Some(b'\\') => i += 2,
+                        Some(q) if *q == c => break,
+                        Some(_) => i += 1,
+                    }
+                }
+
+                i += 1;
+                Kind::Str
+            }
+            c if c.is_ascii_alphanumeric()
+                || c == b'_'
+                || (c == b'-' && bytes.get(i + 1).is_some_and(|n| n.is_ascii_alphanumeric())) =>
+            {
+                i += 1;
+
+                while let Some(&n) = bytes.get(i) {
+                    let number = bytes[start..i]
+                        .iter()
+                        .find(|c| **c != b'-')
+                        .is_some_and(u8::is_ascii_digit);
+
+                    let part_of_number = number
+                        && (n == b'.'
+                            || ((n == b'+' || n == b'-')
+                                && matches!(bytes[i - 1], b'e' | b'E')
+                                && !bytes[start..i].starts_with(b"0x")));
+
+                    if n.is_ascii_alphanumeric() || n == b'_' || part_of_number {
+                        i += 1;
+                    } else {
+                        break;
+                    }
+                }
+
+                Kind::Word
+            }
+            _ => {
+                i += source[i..].chars().next().map_or(1, char::len_utf8);
+                Kind::Punct
+            }
+        };
+
+        tokens.push(Token {
+            kind,
+            text: source[start..i].to_owned(),
+            span: start..i,
+            newlines,
+        });
+
+        newlines = 0;
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+struct Comment {
+    text: String,
+    blank_before: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Statement {
+    blank_before: bool,
+    comments: Vec<Comment>,
+    /// Whether a blank line separates the comments from the statement
+    blank_after_comments: bool,
+    /// Tokens up to the terminating `;` or opening `{`
+    tokens: Vec<Token>,
+    /// Source of the tokens, for statements that can not be reflowed because
+    /// line comments are interspersed
+    verbatim: Option<String>,
+    body: Option<Block>,
+    /// Comment on the same line as the end of the statement
+    trailing: Option<String>,
+}
+
+impl Statement {
+    fn is_import(&self) -> bool {
+        self.body.is_none()
+            && self
+                .tokens
+                .first()
+                .is_some_and(|t| t.kind == Kind::Word && t.text == "import")
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Block {
+    statements: Vec<Statement>,
+    /// Comments after the last statement
+    comments: Vec<Comment>,
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Parses statements until the closing `}` of a block, or the end of
+    /// the file at the top level
+    fn block(&mut self, nested: bool) -> eyre::Result<Block> {
+        let mut block = Block::default();
+        let mut first = true;
+
+        loop {
+            let mut comments = vec![];
+
+            while let Some(token) = self.peek().filter(|t| t.is_comment()) {
+                comments.push(Comment {
+                    text: token.text.clone(),
+                    blank_before: token.newlines > 1 && !first,
+                });
+
+                first = false;
+                self.position += 1;
+            }
+
+            let Some(token) = self.peek().cloned() else {
+                eyre::ensure!(!nested, "Missing closing }} at the end of the file");
+
+                block.comments = comments;
+                return Ok(block);
+            };
+
+            if token.is("}") {
+                eyre::ensure!(
+                    nested,
+                    "Unexpected }} on line {}",
+                    line(self.source, token.span.start)
+                );
+
+                self.position += 1;
+                block.comments = comments;
+                return Ok(block);
+            }
+
+            // Empty statements
+            if token.is(";") {
+                self.position += 1;
+                continue;
+            }
+
+            let blank_before = match comments.first_mut() {
+                Some(comment) => std::mem::take(&mut comment.blank_before),
+                None => token.newlines > 1 && !first,
+            };
+
+            first = false;
+
+            let mut statement = self.statement()?;
+            statement.blank_before = blank_before;
+            // Detached comments, e.g. license headers, stay apart
+            statement.blank_after_comments = !comments.is_empty() && token.newlines > 1;
+            statement.comments = comments;
+
+            statement.trailing = self
+                .peek()
+                .filter(|t| t.is_comment() && t.newlines == 0 && !t.text.contains('\n'))
+                .map(|t| t.text.clone());
+
+            if statement.trailing.is_some() {
+                self.position += 1;
+            }
+
+            block.statements.push(statement);
+        }
+    }
+
+    fn statement(&mut self) -> eyre::Result<Statement> {
+        let start = self.peek().map_or(0, |t| t.span.start);
+        let mut tokens: Vec<Token> = vec![];
+
+        loop {
+            let Some(token) = self.next() else {
+                eyre::bail!(
+                    "Statement on line {} is missing a ; or {{",
+                    line(self.source, start)
+                );
+            };
+
+            if token.is(";") {
+                return Ok(self.with_verbatim(Statement {
+                    tokens,
+                    ..Default::default()
+                }));
+            }
+
+            if token.is("{") {
+                let value = tokens.last().is_some_and(|t| t.is("=") || t.is(":"));
+
+                if value {
+                    tokens.push(self.aggregate(token)?);
+                    continue;
+                }
+
+                let statement = self.with_verbatim(Statement {
+                    tokens,
+                    ..Default::default()
+                });
+
+                return Ok(Statement {
+                    body: Some(self.block(true)?),
+                    ..statement
+                });
+            }
+
+            // Comments within a statement are kept in place
+            tokens.push(token);
+        }
+    }
+
+    /// Keeps the source of statements containing line comments, which would
+    /// swallow the rest of the statement if joined into one line
+    fn with_verbatim(&self, mut statement: Statement) -> Statement {
+        let comments = statement.tokens.iter().any(|t| t.kind == Kind::LineComment);
+
+        if let (true, Some(first), Some(last)) =
+            (comments, statement.tokens.first(), statement.tokens.last())
+        {
+            statement.verbatim = Some(self.source[first.span.start..last.span.end].to_owned());
+        }
+
+        statement
+    }
+
+    /// Captures an aggregate option value, starting at its opening `{`
+    fn aggregate(&mut self, open: Token) -> eyre::Result<Token> {
+        let mut depth = 1;
+        let mut end = open.span.end;
+
+        while depth > 0 {
+            let token = self.next().ok_or_else(|| {
+                eyre::eyre!(
+                    "Unterminated option value on line {}",
+                    line(self.source, open.span.start)
+                )
+            })?;
+
+            if token.is("{") {
+                depth += 1;
+            } else if token.is("}") {
+                depth -= 1;
+            }
+
+            end = token.span.end;
+        }
+
+        Ok(Token {
+            kind: Kind::Verbatim,
+            text: self.source[open.span.start..end].to_owned(),
+            span: open.span.start..end,
+            newlines: open.newlines,
+        })
+    }
+}
+
+/// Sorts runs of consecutive imports by path, removing duplicates
+fn sort_imports(statements: &mut [Statement]) {
+    let mut start = 0;
+
+    while start < statements.len() {
+        if !statements[start].is_import() {
+            start += 1;
+            continue;
+        }
+
+        let end = statements[start..]
+            .iter()
+            .position(|s| !s.is_import())
+            .map_or(statements.len(), |len| start + len);
+
+        let blank_before = statements[start].blank_before;
+
+        let run = &mut statements[start..end];
+
+        run.sort_by_key(|s| {
+            let path = s.tokens.iter().find(|t| t.kind == Kind::Str);
+            path.map(|t| t.text[1..t.text.len() - 1].to_owned())
+        });
+
+        for (i, statement) in run.iter_mut().enumerate() {
+            statement.blank_before = i == 0 && blank_before;
+        }
+
+        start = end;
+    }
+}
+
+fn print_block(block: &Block, depth: usize, out: &mut String) {
+    let indent = INDENT.repeat(depth);
+    let mut previous_import: Option<String> = None;
+
+    for statement in &block.statements {
+        let line = match statement.verbatim {
+            Some(ref verbatim) => verbatim.clone(),
+            None => join(&statement.tokens),
+        };
+
+        // Sorting brought duplicate imports next to each other
+        if statement.is_import() && statement.comments.is_empty() {
+            if previous_import.as_ref() == Some(&line) {
+                continue;
+            }
+
+            previous_import = Some(line.clone());
+        } else {
+            previous_import = None;
+        }
+
+        if statement.blank_before {
+            out.push('\n');
+        }
+
+        for comment in &statement.comments {
+            print_comment(comment, &indent, out);
+        }
+
+        if statement.blank_after_comments {
+            out.push('\n');
+        }
+
+        match statement.body {
+            Some(ref body) if body.statements.is_empty() && body.comments.is_empty() => {
+                out.push_str(&format!("{indent}{line} {{}}"));
+            }
+            Some(ref body) => {
+                out.push_str(&format!("{indent}{line} {{\n"));
+                print_block(body, depth + 1, out);
+                out.push_str(&format!("{indent}}}"));
+            }
+            None if statement.verbatim.is_some() => out.push_str(&format!("{indent}{line};")),
+            None => print_statement(&statement.tokens, &line, &indent, out),
+        }
+
+        if let Some(ref trailing) = statement.trailing {
+            out.push(' ');
+            out.push_str(trailing);
+        }
+
+        out.push('\n');
+    }
+
+    for comment in &block.comments {
+        print_comment(comment, &indent, out);
+    }
+}
+
+/// Prints a statement ending in `;`, splitting long option lists
+fn print_statement(tokens: &[Token], line: &str, indent: &str, out: &mut String) {
+    let options = tokens.iter().position(|t| t.is("["));
+
+    let fits = indent.len() + line.len() < MAX_WIDTH;
+
+    let (Some(open), false) = (options, fits) else {
+        out.push_str(&format!("{indent}{line};"));
+        return;
+    };
+
+    let Some(close) = tokens.iter().rposition(|t| t.is("]")) else {
+        out.push_str(&format!("{indent}{line};"));
+        return;
+    };
+
+    let mut options: Vec<&[Token]> = vec![];
+    let mut nesting = 0;
+    let mut start = open + 1;
+
+    for (i, token) in tokens.iter().enumerate().take(close).skip(open + 1) {
+        match token.text.as_str() {
+            "(" | "[" | "<" if token.kind == Kind::Punct => nesting += 1,
+            ")" | "]" | ">" if token.kind == Kind::Punct => nesting -= 1,
+            "," if token.kind == Kind::Punct && nesting == 0 => {
+                options.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    options.push(&tokens[start..close]);
+
+    out.push_str(&format!("{indent}{} [\n", join(&tokens[..open])));
+
+    for (i, option) in options.iter().enumerate() {
+        let separator = if i + 1 < options.len() { "," } else { "" };
+        out.push_str(&format!("{indent}{INDENT}{}{separator}\n", join(option)));
+    }
+
+    out.push_str(&format!("{indent}]{};", join(&tokens[close + 1..])));
+}
+
+/// Prints a comment on lines of its own, re-indenting block comments
+fn print_comment(comment: &Comment, indent: &str, out: &mut String) {
+    if comment.blank_before {
+        out.push('\n');
+    }
+
+    for (i, line) in comment.text.lines().enumerate() {
+        let line = line.trim_end();
+
+        match i {
+            _ if line.is_empty() => {}
+            0 => out.push_str(&format!("{indent}{line}")),
+            // Aligns the stars of doc comments, other lines are kept as is
+            _ if line.trim_start().starts_with('*') => {
+                out.push_str(&format!("{indent} {}", line.trim_start()))
+            }
+            _ => out.push_str(line),
+        }
+
+        out.push('\n');
+    }
+}
+
+/// Joins the tokens of a statement with the spacing of the protobuf style
+/// guide
+fn join(tokens: &[Token]) -> String {
+    let mut line = String::new();
+    let mut previous: Option<&Token> = None;
+
+    for token in tokens {
+        if let Some(previous) = previous {
+            if space_between(previous, token) {
+                line.push(' ');
+            }
+        }
+
+        line.push_str(&token.text);
+        previous = Some(token);
+    }
+
+    line
+}
+
+fn space_between(previous: &Token, token: &Token) -> bool {
+    let word = |t: &Token| t.kind == Kind::Word;
+
+    if previous.kind == Kind::LineComment {
+        return true;
+    }
+
+    if previous.is("(") || previous.is("[") || previous.is("<") || previous.is(".") {
+        return false;
+    }
+
+    if token.is(";") || token.is(",") || token.is(")") || token.is("]") || token.is(">") {
+        return false;
+    }
+
+    // Leading dots of fully qualified names, e.g. `repeated .acme.Temp`
+    if token.is(".") {
+        return previous.span.end != token.span.start;
+    }
+
+    if token.is("(") {
+        return !word(previous) || matches!(previous.text.as_str(), "returns" | "option");
+    }
+
+    if token.is("<") {
+        return !(word(previous) && previous.text == "map");
+    }
+
+    true
+}
@@ -0,0 +1,152 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{fmt, path::Path, str::FromStr};
+
+use eyre::{ensure, Context};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, process::Command};
+
+use crate::package::PackageStore;
+
+/// Environment variable overriding the protoc executable, as used by
+/// `prost-build`
+pub const PROTOC_ENV: &str = "PROTOC";
+
+/// Languages code can be generated for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    /// C++, built into protoc
+    Cpp,
+    /// C#, built into protoc
+    CSharp,
+    /// Go, using the `protoc-gen-go` plugin
+    Go,
+    /// Java, built into protoc
+    Java,
+    /// Kotlin, built into protoc
+    Kotlin,
+    /// Python, built into protoc
+    Python,
+    /// Ruby, built into protoc
+    Ruby,
+    /// Rust, using the `protoc-gen-prost` plugin
+    Rust,
+}
+
+impl Language {
+    /// Name of the language, e.g. `rust`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cpp => "cpp",
+            Self::CSharp => "csharp",
+            Self::Go => "go",
+            Self::Java => "java",
+            Self::Kotlin => "kotlin",
+            Self::Python => "python",
+            Self::Ruby => "ruby",
+            Self::Rust => "rust",
+        }
+    }
+
+    /// Name of the protoc generator, which is invoked by `--<name>_out`
+    fn generator(&self) -> &'static str {
+        match self {
+            Self::Cpp => "cpp",
+            Self::CSharp => "csharp",
+            Self::Go => "go",
+            Self::Java => "java",
+            Self::Kotlin => "kotlin",
+            Self::Python => "python",
+            Self::Ruby => "ruby",
+            Self::Rust => "prost",
+        }
+    }
+}
+
+impl FromStr for Language {
+    type Err = eyre::Report;
+
+    fn from_str(name: &str) -> eyre::Result<Self> {
+        match name {
+            "cpp" => Ok(Self::Cpp),
+            "csharp" => Ok(Self::CSharp),
+            "go" => Ok(Self::Go),
+            "java" => Ok(Self::Java),
+            "kotlin" => Ok(Self::Kotlin),
+            "python" => Ok(Self::Python),
+            "ruby" => Ok(Self::Ruby),
+            "rust" => Ok(Self::Rust),
+            name => eyre::bail!(
+                "Unsupported language {name}, expected one of cpp, csharp, go, java, kotlin, python, ruby or rust"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Generates code for the protos of the api package and installed
+/// dependencies into `out_dir`
+///
+/// This runs `protoc` (or the executable named by [`PROTOC_ENV`]) with the
+/// api and dependency directories as include paths, so imports resolve the
+/// same way as for other tools. Plugins of languages not built into protoc
+/// have to be installed separately.
+pub async fn generate(
+    store: &PackageStore,
+    language: Language,
+    out_dir: &Path,
+) -> eyre::Result<()> {
+    let includes = store.include_paths();
+    let protos = store.proto_files();
+
+    ensure!(
+        !protos.is_empty(),
+        "No protos found, run buffrs install first"
+    );
+
+    fs::create_dir_all(out_dir)
+        .await
+        .wrap_err_with(|| format!("Failed to create {}", out_dir.display()))?;
+
+    let protoc = std::env::var_os(PROTOC_ENV).unwrap_or_else(|| "protoc".into());
+
+    let mut command = Command::new(&protoc);
+
+    for include in &includes {
+        command.arg(format!("--proto_path={}", include.display()));
+    }
+
+    command
+        .arg(format!(
+            "--{}_out={}",
+            language.generator(),
+            out_dir.display()
+        ))
+        .args(&protos);
+
+    let status = command.status().await.wrap_err_with(|| {
+        format!(
+            "Failed to run {}, is it installed? Set {PROTOC_ENV} to use a different executable",
+            protoc.to_string_lossy()
+        )
+    })?;
+
+    ensure!(
+        status.success(),
+        "Failed to generate {language} code, protoc exited with {status}"
+    );
+
+    tracing::info!(
+        "+ generated {language} code for {} protos into {}",
+        protos.len(),
+        out_dir.display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::path::{Path, PathBuf};
+
+use eyre::{Context, ContextCompat};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::fs;
+
+use crate::package::PackageStore;
+
+/// File next to `Proto.toml` describing the store layout to editors
+pub const EDITOR_CONFIG: &str = "buffrs.json";
+
+/// Descriptor set of the api package and its dependencies, relative to the
+/// project root
+pub const DESCRIPTOR_SET: &str = "proto/descriptors.protoset";
+
+/// Workspace settings of VS Code, relative to the project root
+pub const VSCODE_SETTINGS: &str = ".vscode/settings.json";
+
+/// Locations editors and protobuf language servers need to resolve imports
+///
+/// Paths are relative to the project root, unless the store lives outside
+/// of it, e.g. with an absolute `vendor_dir`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EditorConfig {
+    /// Directory containing the protos of the api package
+    pub local: PathBuf,
+    /// Directory containing the installed dependencies
+    pub vendor: PathBuf,
+    /// Directories imports are resolved relative to, in order
+    pub include_paths: Vec<PathBuf>,
+    /// Compiled descriptor set, if the protos compile
+    pub descriptor_set: Option<PathBuf>,
+}
+
+impl EditorConfig {
+    /// Describes the layout of a package store
+    pub fn new(store: &PackageStore, descriptor_set: bool) -> Self {
+        let relative = |path: PathBuf| match path.strip_prefix(store.root()) {
+            Ok(relative) => relative.to_owned(),
+            Err(_) => path,
+        };
+
+        Self {
+            local: relative(store.api_path()),
+            vendor: relative(store.dep_path()),
+            include_paths: [store.api_path(), store.dep_path()]
+                .into_iter()
+                .map(relative)
+                .collect(),
+            descriptor_set: descriptor_set.then(|| DESCRIPTOR_SET.into()),
+        }
+    }
+
+    /// Reads the editor config of the project at `root`, if there is one
+    pub async fn read(root: &Path) -> eyre::Result<Option<Self>> {
+        let path = root.join(EDITOR_CONFIG);
+
+        if !fs::try_exists(&path).await.unwrap_or(false) {
+            return Ok(None);
+        }
+
+        let contents = fs::read(&path)
+            .await
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+
+        serde_json::from_slice(&contents)
+            .map(Some)
+            .wrap_err_with(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Writes the editor config of the project at `root`
+    ///
+    /// The file is left untouched if it is up to date, so editors watching
+    /// it are not needlessly reloaded.
+    pub async fn write(&self, root: &Path) -> eyre::Result<()> {
+        if Self::read(root).await.ok().flatten().as_ref() == Some(self) {
+            return Ok(());
+        }
+
+        let path = root.join(EDITOR_CONFIG);
+
+        let mut contents = serde_json::to_vec_pretty(self)?;
+        contents.push(b'\n');
+
+        fs::write(&path, contents)
+            .await
+            .wrap_err_with(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Points protobuf extensions of VS Code to the store layout
+///
+/// This sets the `--proto_path` options of `protoc` used by vscode-proto3
+/// and the `protobuf.includes` of Protobuf VSC, all other settings are kept.
+/// Returns whether the settings changed.
+pub async fn sync_vscode(store: &PackageStore) -> eyre::Result<bool> {
+    let path = store.root().join(VSCODE_SETTINGS);
+
+    let mut settings: serde_json::Map<String, Value> =
+        match fs::try_exists(&path).await.unwrap_or(false) {
+            true => {
+                let contents = fs::read(&path)
+                    .await
+                    .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+
+                serde_json::from_slice(&contents).wrap_err_with(|| {
+                    format!(
+                        "Failed to parse {}, comments and trailing commas are not supported",
+                        path.display()
+                    )
+                })?
+            }
+            false => Default::default(),
+        };
+
+    let previous = settings.clone();
+
+    let includes: Vec<String> = EditorConfig::new(store, false)
+        .include_paths
+        .iter()
+        .map(|path| match path.is_relative() {
+            true => format!("${{workspaceFolder}}/{}", path.display()),
+            false => path.display().to_string(),
+        })
+        .collect();
+
+    let protoc = settings
+        .entry("protoc")
+        .or_insert_with(|| Value::Object(Default::default()))
+        .as_object_mut()
+        .wrap_err_with(|| format!("Expected protoc in {} to be an object", path.display()))?;
+
+    let options = protoc
+        .entry("options")
+        .or_insert_with(|| Value::Array(vec![]))
+        .as_array_mut()
+        .wrap_err_with(|| {
+            format!(
+                "Expected protoc.options in {} to be an array",
+                path.display()
+            )
+        })?;
+
+    options.retain(|option| {
+        !option
+            .as_str()
+            .is_some_and(|option| option.starts_with("--proto_path="))
+    });
+
+    options.extend(
+        includes
+            .iter()
+            .map(|include| Value::String(format!("--proto_path={include}"))),
+    );
+
+    settings.insert("protobuf.includes".to_owned(), includes.into());
+
+    if settings == previous {
+        return Ok(false);
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .wrap_err_with(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    let mut contents = serde_json::to_vec_pretty(&settings)?;
+    contents.push(b'\n');
+
+    fs::write(&path, contents)
+        .await
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(true)
+}
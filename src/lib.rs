@@ -10,12 +10,62 @@
 //! </svg>
 
 #![doc = include_str!("../README.md")]
+#![deny(missing_docs)]
 
+/// Blocking wrappers around the async api
+#[cfg(feature = "io")]
+pub mod blocking;
+/// Detection of breaking changes between releases
+pub mod breaking;
+/// Installation and code generation for Cargo build scripts
+#[cfg(feature = "io")]
+pub mod build;
 /// Configuration format and IO
+#[cfg(feature = "io")]
 pub mod config;
+/// Error codes and their explanations
+pub mod error;
+/// Formatting of protos
+pub mod format;
+/// Code generation using protoc
+#[cfg(feature = "io")]
+pub mod generator;
+/// Store layout for editors and language servers
+#[cfg(feature = "io")]
+pub mod ide;
+/// Checks of the protos of a project
+#[cfg(feature = "reflect")]
+pub mod lint;
+/// Lockfile format and IO
+pub mod lock;
 /// Manifest format and IO
 pub mod manifest;
+/// Mock gRPC servers
+#[cfg(all(feature = "server", feature = "reflect"))]
+pub mod mock;
+/// High level operations on projects
+#[cfg(feature = "io")]
+pub mod ops;
 /// Packages formats and utilities
 pub mod package;
+/// Installation policies
+pub mod policy;
+/// Decoding and encoding of messages using the protos of a project
+#[cfg(feature = "reflect")]
+pub mod reflect;
 /// Supported registries
+#[cfg(feature = "io")]
 pub mod registry;
+/// Dependency resolution
+pub mod resolver;
+/// HTTP server exposing registries
+#[cfg(feature = "server")]
+pub mod server;
+/// Package specifications given on the command line
+pub mod spec;
+/// Project templates
+#[cfg(feature = "io")]
+pub mod template;
+/// Workspaces of several projects
+#[cfg(feature = "io")]
+pub mod workspace;
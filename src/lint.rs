@@ -0,0 +1,396 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use eyre::Context;
+use prost_reflect::ExtensionDescriptor;
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{manifest::LintManifest, package::PackageStore, reflect::Schema};
+
+/// Rule flagging extensions that share a field number
+pub const OPTION_NUMBER_COLLISION: &str = "option-number-collision";
+/// Rule requiring `lower_snake_case` package names
+pub const PACKAGE_LOWER_SNAKE_CASE: &str = "package-lower-snake-case";
+/// Rule requiring `lower_snake_case` field names
+pub const FIELD_LOWER_SNAKE_CASE: &str = "field-lower-snake-case";
+/// Rule requiring the zero value of enums to end in `_UNSPECIFIED`
+pub const ENUM_ZERO_VALUE_SUFFIX: &str = "enum-zero-value-suffix";
+/// Rule requiring comments above messages, enums, services and rpcs
+pub const LEADING_COMMENTS: &str = "leading-comments";
+
+/// Rules checked unless configured otherwise
+pub const DEFAULT_RULES: &[&str] = &[
+    OPTION_NUMBER_COLLISION,
+    PACKAGE_LOWER_SNAKE_CASE,
+    FIELD_LOWER_SNAKE_CASE,
+    ENUM_ZERO_VALUE_SUFFIX,
+];
+
+/// Rules requiring documentation
+pub const COMMENT_RULES: &[&str] = &[LEADING_COMMENTS];
+
+/// Expands the rules and rule sets configured in the `[lint]` section of a
+/// manifest
+pub fn rules(manifest: &LintManifest) -> eyre::Result<BTreeSet<&'static str>> {
+    let expand = |name: &str| -> eyre::Result<Vec<&'static str>> {
+        let rules: Vec<&'static str> = match name {
+            "default" => DEFAULT_RULES.to_vec(),
+            "comments" => COMMENT_RULES.to_vec(),
+            "all" => DEFAULT_RULES.iter().chain(COMMENT_RULES).copied().collect(),
+            name => DEFAULT_RULES
+                .iter()
+                .chain(COMMENT_RULES)
+                .copied()
+                .filter(|rule| *rule == name)
+                .collect(),
+        };
+
+        eyre::ensure!(
+            !rules.is_empty(),
+            "Unknown lint rule {name}, expected a rule set (default, comments, all) or one of: {}",
+            DEFAULT_RULES
+                .iter()
+                .chain(COMMENT_RULES)
+                .copied()
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        Ok(rules)
+    };
+
+    let mut rules = BTreeSet::new();
+
+    match manifest.rules.is_empty() {
+        true => rules.extend(DEFAULT_RULES),
+        false => {
+            for name in &manifest.rules {
+                rules.extend(expand(name)?);
+            }
+        }
+    }
+
+    for name in &manifest.except {
+        for rule in expand(name)? {
+            rules.remove(rule);
+        }
+    }
+
+    Ok(rules)
+}
+
+/// Reads the protos of the api package, keyed by their import path
+///
+/// Style rules only apply to these, as dependencies are owned by others.
+pub async fn sources(store: &PackageStore) -> eyre::Result<BTreeMap<String, String>> {
+    let root = store.api_path();
+
+    let paths: Vec<_> = WalkDir::new(&root)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|e| e == "proto"))
+        .map(|entry| entry.into_path())
+        .collect();
+
+    let mut sources = BTreeMap::new();
+
+    for path in paths {
+        let contents = tokio::fs::read_to_string(&path)
+            .await
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+
+        let name = path
+            .strip_prefix(&root)
+            .unwrap_or(&path)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        sources.insert(name, contents);
+    }
+
+    Ok(sources)
+}
+
+/// A problem found in the protos of a project
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Violation {
+    /// Proto file the problem was found in
+    pub file: String,
+    /// Name of the violated rule, e.g. `option-number-collision`
+    pub rule: &'static str,
+    /// Description of the problem
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} [{}]", self.file, self.message, self.rule)
+    }
+}
+
+/// Checks the protos of a project along with its installed dependencies
+///
+/// Style rules only apply to the protos in `sources`, see [`sources`].
+/// Violations are ordered by file.
+pub fn lint(
+    schema: &Schema,
+    rules: &BTreeSet<&str>,
+    sources: &BTreeMap<String, String>,
+) -> Vec<Violation> {
+    let mut violations = vec![];
+
+    if rules.contains(OPTION_NUMBER_COLLISION) {
+        violations.extend(option_number_collisions(schema));
+    }
+
+    if rules.contains(PACKAGE_LOWER_SNAKE_CASE) {
+        violations.extend(package_names(schema, sources));
+    }
+
+    if rules.contains(FIELD_LOWER_SNAKE_CASE) {
+        violations.extend(field_names(schema, sources));
+    }
+
+    if rules.contains(ENUM_ZERO_VALUE_SUFFIX) {
+        violations.extend(enum_zero_values(schema, sources));
+    }
+
+    if rules.contains(LEADING_COMMENTS) {
+        for (file, source) in sources {
+            violations.extend(leading_comments(file, source));
+        }
+    }
+
+    violations.sort();
+    violations
+}
+
+/// Finds custom options, or other extensions, that extend the same message
+/// with the same field number
+///
+/// Their values are indistinguishable on the wire, so consumers decode the
+/// option of one package as that of another. Collisions typically arise
+/// between packages that never import each other and only meet in the
+/// dependency graph of a consumer.
+fn option_number_collisions(schema: &Schema) -> Vec<Violation> {
+    let mut extensions: BTreeMap<(String, u32), Vec<ExtensionDescriptor>> = BTreeMap::new();
+
+    for extension in schema.pool().all_extensions() {
+        extensions
+            .entry((
+                extension.containing_message().full_name().to_owned(),
+                extension.number(),
+            ))
+            .or_default()
+            .push(extension);
+    }
+
+    let mut violations = vec![];
+
+    for ((extendee, number), extensions) in extensions {
+        if extensions.len() < 2 {
+            continue;
+        }
+
+        let kind = match extendee.starts_with("google.protobuf.") && extendee.ends_with("Options") {
+            true => "Custom option",
+            false => "Extension",
+        };
+
+        for extension in &extensions {
+            let others: Vec<&str> = extensions
+                .iter()
+                .filter(|other| other.full_name() != extension.full_name())
+                .map(|other| other.full_name())
+                .collect();
+
+            violations.push(Violation {
+                file: extension.parent_file().name().to_owned(),
+                rule: OPTION_NUMBER_COLLISION,
+                message: format!(
+                    "{kind} {} uses number {number} of {extendee}, as does {}",
+                    extension.full_name(),
+                    others.join(", ")
+                ),
+            });
+        }
+    }
+
+    violations
+}
+
+fn is_lower_snake_case(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Converts `camelCase` and `PascalCase` names to `snake_case`
+fn to_snake_case(name: &str) -> String {
+    let mut snake = String::new();
+
+    for (index, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() && index > 0 && !snake.ends_with('_') {
+            snake.push('_');
+        }
+
+        snake.push(c.to_ascii_lowercase());
+    }
+
+    snake
+}
+
+fn package_names(schema: &Schema, sources: &BTreeMap<String, String>) -> Vec<Violation> {
+    schema
+        .pool()
+        .files()
+        .filter(|file| sources.contains_key(file.name()))
+        .filter(|file| !file.package_name().split('.').all(is_lower_snake_case))
+        .map(|file| Violation {
+            file: file.name().to_owned(),
+            rule: PACKAGE_LOWER_SNAKE_CASE,
+            message: format!(
+                "Package {} is not lower_snake_case, e.g. {}",
+                file.package_name(),
+                to_snake_case(file.package_name())
+            ),
+        })
+        .collect()
+}
+
+fn field_names(schema: &Schema, sources: &BTreeMap<String, String>) -> Vec<Violation> {
+    let mut violations = vec![];
+
+    let messages = schema
+        .pool()
+        .all_messages()
+        .filter(|message| sources.contains_key(message.parent_file().name()))
+        .filter(|message| !message.is_map_entry());
+
+    for message in messages {
+        for field in message.fields() {
+            if is_lower_snake_case(field.name()) {
+                continue;
+            }
+
+            violations.push(Violation {
+                file: message.parent_file().name().to_owned(),
+                rule: FIELD_LOWER_SNAKE_CASE,
+                message: format!(
+                    "Field {} is not lower_snake_case, e.g. {}",
+                    field.full_name(),
+                    to_snake_case(field.name())
+                ),
+            });
+        }
+    }
+
+    violations
+}
+
+fn enum_zero_values(schema: &Schema, sources: &BTreeMap<String, String>) -> Vec<Violation> {
+    let mut violations = vec![];
+
+    let enums = schema
+        .pool()
+        .all_enums()
+        .filter(|e| sources.contains_key(e.parent_file().name()));
+
+    for descriptor in enums {
+        let expected = format!(
+            "{}_UNSPECIFIED",
+            to_snake_case(descriptor.name()).to_uppercase()
+        );
+
+        let message = match descriptor.values().find(|value| value.number() == 0) {
+            Some(zero) if zero.name().ends_with("_UNSPECIFIED") => continue,
+            Some(zero) => format!(
+                "Zero value {} of enum {} does not end in _UNSPECIFIED, e.g. {expected}",
+                zero.name(),
+                descriptor.full_name()
+            ),
+            None => format!(
+                "Enum {} has no zero value, e.g. {expected} = 0",
+                descriptor.full_name()
+            ),
+        };
+
+        violations.push(Violation {
+            file: descriptor.parent_file().name().to_owned(),
+            rule: ENUM_ZERO_VALUE_SUFFIX,
+            message,
+        });
+    }
+
+    violations
+}
+
+/// Finds messages, enums, services and rpcs without a comment on the line
+/// above them
+///
+/// Descriptors compiled by the pure parser lack source info, so this scans
+/// the source line by line instead.
+fn leading_comments(file: &str, source: &str) -> Vec<Violation> {
+    let mut violations = vec![];
+    let mut commented = false;
+    let mut in_block = false;
+
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+
+        if in_block {
+            in_block = !line.contains("*/");
+            commented = !in_block;
+            continue;
+        }
+
+        if line.starts_with("/*") {
+            in_block = !line.contains("*/");
+            commented = !in_block;
+            continue;
+        }
+
+        if line.starts_with("//") {
+            commented = true;
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+
+        let kind = match words.next() {
+            Some("message") => "Message",
+            Some("enum") => "Enum",
+            Some("service") => "Service",
+            Some("rpc") => "Rpc",
+            _ => {
+                commented = false;
+                continue;
+            }
+        };
+
+        let name = words
+            .next()
+            .and_then(|name| name.split(['{', '(']).next())
+            .filter(|name| !name.is_empty());
+
+        if let (Some(name), false) = (name, commented) {
+            violations.push(Violation {
+                file: file.to_owned(),
+                rule: LEADING_COMMENTS,
+                message: format!("{kind} {name} on line {} has no leading comment", index + 1),
+            });
+        }
+
+        commented = false;
+    }
+
+    violations
+}
@@ -0,0 +1,504 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use eyre::{ensure, Context};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+#[cfg(feature = "io")]
+use {
+    std::{path::Path, process::Stdio},
+    tokio::{fs, io::AsyncWriteExt, process::Command},
+};
+
+use crate::{
+    error::ErrorCode,
+    manifest::Dependency,
+    package::{Digest, PackageId},
+};
+
+/// Filename of the lockfile
+pub const LOCKFILE: &str = "Proto.lock";
+
+/// Version of the lockfile format written by this release
+const LOCKFILE_VERSION: u16 = 1;
+
+/// Filename of the signature of the lockfile, see [`sign`]
+pub const SIGNATURE_FILE: &str = "Proto.lock.sig";
+
+/// Namespace of lockfile signatures, so signatures made with the same key for
+/// other purposes (e.g. git commits) are not accepted
+#[cfg(feature = "io")]
+const SIGNATURE_NAMESPACE: &str = "buffrs-lockfile";
+
+/// Comment heading every lockfile
+const HEADER: &str = "# This file is generated by buffrs, do not edit it manually.\n\n";
+
+/// The exact packages of an installation, stored in `Proto.lock`
+///
+/// Recording the digest of every package makes installations reproducible:
+/// packages that were republished with different contents are rejected.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Lockfile {
+    /// Version of the lockfile format
+    version: u16,
+    /// Locked packages, ordered by name
+    #[serde(default, rename = "package", skip_serializing_if = "Vec::is_empty")]
+    packages: Vec<LockedPackage>,
+}
+
+/// A single package recorded in the lockfile
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LockedPackage {
+    /// Name of the package
+    pub name: PackageId,
+    /// Exact version that was installed
+    pub version: semver::Version,
+    /// Repository the package was downloaded from
+    pub repository: String,
+    /// Digest of the package archive, prefixed with its algorithm
+    pub digest: Digest,
+}
+
+impl LockedPackage {
+    /// Checks whether this entry was recorded for `dependency`
+    ///
+    /// The locked version has to satisfy the version requirement of the
+    /// dependency, which for exact requirements means it has to be equal.
+    pub fn matches(&self, dependency: &Dependency) -> bool {
+        let version = dependency
+            .manifest
+            .version
+            .as_ref()
+            .is_some_and(|requirement| requirement.matches(&self.version));
+
+        self.name == dependency.package
+            && version
+            && self.repository == dependency.manifest.repository
+    }
+}
+
+impl fmt::Display for LockedPackage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}@{}", self.repository, self.name, self.version)
+    }
+}
+
+impl Default for Lockfile {
+    fn default() -> Self {
+        Self::new(vec![])
+    }
+}
+
+impl Lockfile {
+    /// Creates a lockfile of the given packages
+    pub fn new(mut packages: Vec<LockedPackage>) -> Self {
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Self {
+            version: LOCKFILE_VERSION,
+            packages,
+        }
+    }
+
+    /// Packages recorded in the lockfile, ordered by name
+    pub fn packages(&self) -> &[LockedPackage] {
+        &self.packages
+    }
+
+    /// Finds the entry of a package
+    pub fn get(&self, package: &PackageId) -> Option<&LockedPackage> {
+        self.packages.iter().find(|p| &p.name == package)
+    }
+
+    /// Checks that the lockfile records exactly the given dependencies
+    ///
+    /// Fails with a description of every difference otherwise, which means
+    /// that the manifest was changed since the lockfile was written.
+    pub fn ensure_matches(&self, dependencies: &[Dependency]) -> eyre::Result<()> {
+        let mut drift = vec![];
+
+        // Unpublished packages are never locked, their contents change freely
+        let dependencies: Vec<_> = dependencies
+            .iter()
+            .filter(|d| d.manifest.is_registry())
+            .collect();
+
+        for dependency in &dependencies {
+            match self.get(&dependency.package) {
+                None => drift.push(format!("{dependency} is not locked")),
+                Some(locked) if !locked.matches(dependency) => {
+                    drift.push(format!("{dependency} is locked at {locked}"))
+                }
+                Some(_) => (),
+            }
+        }
+
+        for locked in &self.packages {
+            if !dependencies.iter().any(|d| d.package == locked.name) {
+                drift.push(format!("{locked} is locked, but no longer a dependency"));
+            }
+        }
+
+        if drift.is_empty() {
+            return Ok(());
+        }
+
+        Err(eyre::eyre!(
+            "{LOCKFILE} is out of date:{}",
+            drift
+                .iter()
+                .map(|d| format!("\n  - {d}"))
+                .collect::<String>()
+        ))
+        .wrap_err(ErrorCode::LockfileOutdated)
+    }
+
+    /// Merges two lockfiles that diverged from a common `base`, e.g. on
+    /// different git branches
+    ///
+    /// Packages added, updated or removed on one side only are taken over.
+    /// Packages both sides locked differently are locked at the highest
+    /// version satisfying their requirement in `dependencies`, which are the
+    /// dependencies of the merged manifest. A package removed on one side and
+    /// updated on the other is only kept if it still is a dependency.
+    ///
+    /// Fails if both sides locked the same version with different digests.
+    pub fn merge(
+        base: &Lockfile,
+        ours: &Lockfile,
+        theirs: &Lockfile,
+        dependencies: &[Dependency],
+    ) -> eyre::Result<Self> {
+        let mut names: Vec<&PackageId> = base
+            .packages
+            .iter()
+            .chain(&ours.packages)
+            .chain(&theirs.packages)
+            .map(|p| &p.name)
+            .collect();
+
+        names.sort();
+        names.dedup();
+
+        let mut packages = vec![];
+
+        for name in names {
+            let (base, ours, theirs) = (base.get(name), ours.get(name), theirs.get(name));
+
+            let dependency = dependencies.iter().find(|d| &d.package == name);
+
+            let merged = match (ours, theirs) {
+                _ if ours == theirs || theirs == base => ours,
+                _ if ours == base => theirs,
+                (Some(ours), Some(theirs)) => {
+                    ensure!(
+                        ours.version != theirs.version || ours.repository != theirs.repository,
+                        "{ours} is locked with different digests, {} and {}",
+                        ours.digest,
+                        theirs.digest
+                    );
+
+                    let compatible: Vec<&LockedPackage> = [ours, theirs]
+                        .into_iter()
+                        .filter(|p| dependency.is_some_and(|d| p.matches(d)))
+                        .collect();
+
+                    let candidates = match compatible.is_empty() {
+                        true => vec![ours, theirs],
+                        false => compatible,
+                    };
+
+                    candidates
+                        .into_iter()
+                        .max_by(|a, b| a.version.cmp(&b.version))
+                }
+                (Some(locked), None) | (None, Some(locked)) => {
+                    dependency.is_some().then_some(locked)
+                }
+                (None, None) => None,
+            };
+
+            packages.extend(merged.cloned());
+        }
+
+        Ok(Self::new(packages))
+    }
+
+    /// Checks if the project located at `root` has a lockfile
+    #[cfg(feature = "io")]
+    pub async fn exists(root: &Path) -> eyre::Result<bool> {
+        fs::try_exists(root.join(LOCKFILE))
+            .await
+            .wrap_err("Failed to detect lockfile")
+    }
+
+    /// Reads the lockfile of the project located at `root`
+    #[cfg(feature = "io")]
+    pub async fn read(root: &Path) -> eyre::Result<Self> {
+        fs::read_to_string(root.join(LOCKFILE))
+            .await
+            .wrap_err_with(|| format!("Failed to read {LOCKFILE}"))?
+            .parse()
+    }
+
+    /// Writes the lockfile of the project located at `root`
+    #[cfg(feature = "io")]
+    pub async fn write(&self, root: &Path) -> eyre::Result<()> {
+        fs::write(root.join(LOCKFILE), self.to_toml()?.into_bytes())
+            .await
+            .wrap_err_with(|| format!("Failed to write {LOCKFILE}"))
+    }
+
+    /// Encodes the lockfile in the `Proto.lock` format
+    pub fn to_toml(&self) -> eyre::Result<String> {
+        let toml = toml::to_string(self).wrap_err("Failed to encode lockfile")?;
+
+        Ok(format!("{HEADER}{toml}"))
+    }
+}
+
+/// Signs the lockfile of the project located at `root` with an SSH key
+///
+/// The signature is written to [`SIGNATURE_FILE`] next to the lockfile, see
+/// [`verify`]. Keys held by an ssh agent are used by passing their public
+/// key.
+#[cfg(feature = "io")]
+pub async fn sign(root: &Path, key: &Path) -> eyre::Result<()> {
+    let lockfile = fs::read(root.join(LOCKFILE))
+        .await
+        .wrap_err_with(|| format!("Failed to read {LOCKFILE}"))?;
+
+    let signature = ssh_keygen(
+        Command::new("ssh-keygen")
+            .args(["-Y", "sign", "-n", SIGNATURE_NAMESPACE, "-f"])
+            .arg(key),
+        &lockfile,
+    )
+    .await
+    .wrap_err_with(|| format!("Failed to sign {LOCKFILE} with {}", key.display()))?;
+
+    fs::write(root.join(SIGNATURE_FILE), signature)
+        .await
+        .wrap_err_with(|| format!("Failed to write {SIGNATURE_FILE}"))
+}
+
+/// Verifies that the lockfile of the project located at `root` was signed by
+/// one of the `allowed_signers` and returns the signer
+///
+/// The allowed signers file lists the principal and public key of everyone
+/// trusted to sign lockfiles, in the format of `ssh-keygen`, e.g.
+/// `jane@example.com ssh-ed25519 AAAA...`.
+#[cfg(feature = "io")]
+pub async fn verify(root: &Path, allowed_signers: &Path) -> eyre::Result<String> {
+    let signature = root.join(SIGNATURE_FILE);
+
+    ensure!(
+        fs::try_exists(&signature).await.unwrap_or(false),
+        "{LOCKFILE} is not signed, {SIGNATURE_FILE} is missing"
+    );
+
+    let lockfile = fs::read(root.join(LOCKFILE))
+        .await
+        .wrap_err_with(|| format!("Failed to read {LOCKFILE}"))?;
+
+    let principals = ssh_keygen(
+        Command::new("ssh-keygen")
+            .args(["-Y", "find-principals", "-s"])
+            .arg(&signature)
+            .arg("-f")
+            .arg(allowed_signers),
+        &[],
+    )
+    .await
+    .wrap_err_with(|| {
+        format!(
+            "{SIGNATURE_FILE} was not made by any of the allowed signers in {}",
+            allowed_signers.display()
+        )
+    })?;
+
+    let principal = String::from_utf8_lossy(&principals)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_owned();
+
+    ssh_keygen(
+        Command::new("ssh-keygen")
+            .args(["-Y", "verify", "-n", SIGNATURE_NAMESPACE, "-I", &principal, "-s"])
+            .arg(&signature)
+            .arg("-f")
+            .arg(allowed_signers),
+        &lockfile,
+    )
+    .await
+    .wrap_err_with(|| format!("{SIGNATURE_FILE} does not match {LOCKFILE}, it was changed since it was signed by {principal}"))?;
+
+    Ok(principal)
+}
+
+/// Runs `ssh-keygen` with `input` on stdin and returns its output
+#[cfg(feature = "io")]
+async fn ssh_keygen(command: &mut Command, input: &[u8]) -> eyre::Result<Vec<u8>> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .wrap_err("Failed to run ssh-keygen, is OpenSSH installed?")?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(input)
+            .await
+            .wrap_err("Failed to pass input to ssh-keygen")?;
+    }
+
+    let output = child
+        .wait_with_output()
+        .await
+        .wrap_err("Failed to run ssh-keygen")?;
+
+    ensure!(
+        output.status.success(),
+        "ssh-keygen exited with {}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    Ok(output.stdout)
+}
+
+impl FromStr for Lockfile {
+    type Err = eyre::Error;
+
+    fn from_str(toml: &str) -> eyre::Result<Self> {
+        let lockfile: Self =
+            toml::from_str(toml).wrap_err_with(|| format!("Failed to parse {LOCKFILE}"))?;
+
+        ensure!(
+            lockfile.version <= LOCKFILE_VERSION,
+            "{LOCKFILE} was written by a newer release of buffrs (format version {}), please update",
+            lockfile.version
+        );
+
+        Ok(Self::new(lockfile.packages))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locked(name: &str, version: &str, digest: char) -> LockedPackage {
+        LockedPackage {
+            name: name.parse().unwrap(),
+            version: version.parse().unwrap(),
+            repository: "protos".to_owned(),
+            digest: format!("sha256:{}", digest.to_string().repeat(64))
+                .parse()
+                .unwrap(),
+        }
+    }
+
+    fn dependency(name: &str, requirement: &str) -> Dependency {
+        Dependency::new(
+            "protos".to_owned(),
+            name.parse().unwrap(),
+            requirement.parse().unwrap(),
+        )
+    }
+
+    fn versions(lockfile: &Lockfile) -> Vec<String> {
+        lockfile
+            .packages()
+            .iter()
+            .map(|p| format!("{}@{}", p.name, p.version))
+            .collect()
+    }
+
+    #[test]
+    fn merge_takes_changes_of_one_side() {
+        let base = Lockfile::new(vec![locked("units", "1.0.0", 'a')]);
+        let ours = Lockfile::new(vec![
+            locked("units", "1.0.0", 'a'),
+            locked("geo", "0.1.0", 'c'),
+        ]);
+        let theirs = Lockfile::new(vec![locked("units", "1.1.0", 'b')]);
+
+        let dependencies = [dependency("units", "^1"), dependency("geo", "^0.1")];
+
+        let merged = Lockfile::merge(&base, &ours, &theirs, &dependencies).unwrap();
+
+        assert_eq!(versions(&merged), ["geo@0.1.0", "units@1.1.0"]);
+    }
+
+    #[test]
+    fn merge_takes_highest_compatible_version_changed_on_both_sides() {
+        let base = Lockfile::new(vec![locked("units", "1.0.0", 'a')]);
+        let ours = Lockfile::new(vec![locked("units", "1.1.0", 'b')]);
+        let theirs = Lockfile::new(vec![locked("units", "1.2.0", 'c')]);
+
+        let merged = Lockfile::merge(&base, &ours, &theirs, &[dependency("units", "^1")]).unwrap();
+
+        assert_eq!(versions(&merged), ["units@1.2.0"]);
+
+        let merged =
+            Lockfile::merge(&base, &ours, &theirs, &[dependency("units", ">=1, <1.2")]).unwrap();
+
+        assert_eq!(versions(&merged), ["units@1.1.0"]);
+    }
+
+    #[test]
+    fn merge_keeps_packages_removed_and_updated_while_they_are_dependencies() {
+        let base = Lockfile::new(vec![locked("units", "1.0.0", 'a')]);
+        let ours = Lockfile::new(vec![]);
+        let theirs = Lockfile::new(vec![locked("units", "1.1.0", 'b')]);
+
+        let merged = Lockfile::merge(&base, &ours, &theirs, &[dependency("units", "^1")]).unwrap();
+
+        assert_eq!(versions(&merged), ["units@1.1.0"]);
+
+        let merged = Lockfile::merge(&base, &ours, &theirs, &[]).unwrap();
+
+        assert!(merged.packages().is_empty());
+    }
+
+    #[test]
+    fn merge_rejects_same_version_with_different_digests() {
+        let base = Lockfile::new(vec![locked("units", "1.0.0", 'a')]);
+        let ours = Lockfile::new(vec![locked("units", "1.1.0", 'b')]);
+        let theirs = Lockfile::new(vec![locked("units", "1.1.0", 'c')]);
+
+        let error = Lockfile::merge(&base, &ours, &theirs, &[dependency("units", "^1")])
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains("locked with different digests"), "{error}");
+    }
+
+    #[test]
+    fn merge_with_empty_base() {
+        let ours = Lockfile::new(vec![
+            locked("units", "1.0.0", 'a'),
+            locked("geo", "0.1.0", 'c'),
+        ]);
+        let theirs = Lockfile::new(vec![
+            locked("units", "1.1.0", 'b'),
+            locked("time", "2.0.0", 'd'),
+        ]);
+
+        let dependencies = [
+            dependency("units", "^1"),
+            dependency("geo", "^0.1"),
+            dependency("time", "^2"),
+        ];
+
+        let merged = Lockfile::merge(&Lockfile::default(), &ours, &theirs, &dependencies).unwrap();
+
+        assert_eq!(
+            versions(&merged),
+            ["geo@0.1.0", "time@2.0.0", "units@1.1.0"]
+        );
+    }
+}
@@ -1,22 +1,50 @@
 // (c) Copyright 2023 Helsing GmbH. All rights reserved.
 
-use eyre::Context;
+use eyre::{ensure, Context, ContextCompat};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fmt};
-use tokio::fs;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+};
+#[cfg(feature = "io")]
+use {std::path::Path, tokio::fs};
 
-use crate::package::PackageId;
+use crate::{error::ErrorCode, package::PackageId, resolver::VersionRequirement};
 
+/// Filename of the manifest
 pub const MANIFEST_FILE: &str = "Proto.toml";
 
+/// JSON Schema of the `Proto.toml` format
+#[cfg(feature = "schema")]
+pub fn schema() -> schemars::schema::RootSchema {
+    let mut schema = schemars::schema_for!(RawManifest);
+
+    schema.schema.metadata().title = Some(MANIFEST_FILE.to_owned());
+
+    schema
+}
+
 /// A `buffrs` manifest format used for serialization and deserialization.
 ///
 /// This contains the exact structure of the `Proto.toml` and skips
 /// empty fields.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct RawManifest {
+    /// Metadata of the api package, if this project publishes one
     pub api: Option<ApiManifest>,
+    /// Dependencies of the project
     pub dependencies: Option<DependencyMap>,
+    /// Members of the workspace, if this project is the root of one
+    pub workspace: Option<WorkspaceManifest>,
+    /// Additional packages published from subdirectories of the project
+    pub package: Option<Vec<PackageManifest>>,
+    /// Settings of the groups dependencies are assigned to
+    pub groups: Option<BTreeMap<String, GroupManifest>>,
+    /// Rules checked by `buffrs lint`
+    pub lint: Option<LintManifest>,
 }
 
 impl From<Manifest> for RawManifest {
@@ -32,6 +60,10 @@ impl From<Manifest> for RawManifest {
         Self {
             api: manifest.api,
             dependencies,
+            workspace: manifest.workspace,
+            package: (!manifest.packages.is_empty()).then_some(manifest.packages),
+            groups: (!manifest.groups.is_empty()).then_some(manifest.groups),
+            lint: manifest.lint,
         }
     }
 }
@@ -43,33 +75,104 @@ pub type DependencyMap = HashMap<PackageId, DependencyManifest>;
 /// version of the `RawManifest` for easier use.
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Manifest {
+    /// Metadata of the api package, if this project publishes one
     pub api: Option<ApiManifest>,
+    /// Dependencies of the project
     pub dependencies: Vec<Dependency>,
+    /// Members of the workspace, if this project is the root of one
+    pub workspace: Option<WorkspaceManifest>,
+    /// Additional packages published from subdirectories of the project
+    pub packages: Vec<PackageManifest>,
+    /// Settings of the groups dependencies are assigned to
+    pub groups: BTreeMap<String, GroupManifest>,
+    /// Rules checked by `buffrs lint`
+    pub lint: Option<LintManifest>,
 }
 
 impl Manifest {
-    pub async fn exists() -> eyre::Result<bool> {
-        fs::try_exists(MANIFEST_FILE)
+    /// Checks if the project located at `root` has a manifest
+    #[cfg(feature = "io")]
+    pub async fn exists(root: &Path) -> eyre::Result<bool> {
+        fs::try_exists(root.join(MANIFEST_FILE))
             .await
             .wrap_err("Failed to detect manifest")
     }
 
-    pub async fn read() -> eyre::Result<Self> {
-        let toml = fs::read_to_string(MANIFEST_FILE)
+    /// Reads the manifest of the project located at `root`
+    #[cfg(feature = "io")]
+    pub async fn read(root: &Path) -> eyre::Result<Self> {
+        fs::read_to_string(root.join(MANIFEST_FILE))
             .await
-            .wrap_err("Failed to read manifest")?;
+            .wrap_err("Failed to read manifest")
+            .wrap_err(ErrorCode::MissingManifest)?
+            .parse()
+    }
 
-        let raw: RawManifest = toml::from_str(&toml).wrap_err("Failed to parse manifest")?;
+    /// Writes the manifest of the project located at `root`
+    #[cfg(feature = "io")]
+    pub async fn write(&self, root: &Path) -> eyre::Result<()> {
+        fs::write(root.join(MANIFEST_FILE), self.to_toml()?.into_bytes())
+            .await
+            .wrap_err("Failed to write manifest")
+    }
 
-        Ok(raw.into())
+    /// Encodes the manifest in the `Proto.toml` format
+    pub fn to_toml(&self) -> eyre::Result<String> {
+        toml::to_string(&RawManifest::from(self.to_owned())).wrap_err("Failed to encode manifest")
     }
+}
 
-    pub async fn write(&self) -> eyre::Result<()> {
-        let raw = RawManifest::from(self.to_owned());
+impl FromStr for Manifest {
+    type Err = eyre::Error;
 
-        fs::write(MANIFEST_FILE, toml::to_string(&raw)?.into_bytes())
-            .await
-            .wrap_err("Failed to write manifest")
+    fn from_str(toml: &str) -> eyre::Result<Self> {
+        let raw: RawManifest = toml::from_str(toml)
+            .wrap_err("Failed to parse manifest")
+            .wrap_err(ErrorCode::InvalidManifest)?;
+
+        let manifest: Self = raw.into();
+
+        manifest
+            .validate_packages()
+            .wrap_err(ErrorCode::InvalidManifest)?;
+
+        Ok(manifest)
+    }
+}
+
+impl Manifest {
+    /// Ensures the api package and `[[package]]` entries have distinct names
+    /// and the entries distinct, non-overlapping directories
+    fn validate_packages(&self) -> eyre::Result<()> {
+        let names = self
+            .api
+            .iter()
+            .chain(self.packages.iter().map(|p| &p.api))
+            .map(|api| &api.name);
+
+        let mut seen = HashSet::new();
+
+        for name in names {
+            ensure!(
+                seen.insert(name),
+                "The manifest declares the package {name} more than once"
+            );
+        }
+
+        for (i, a) in self.packages.iter().enumerate() {
+            for b in &self.packages[i + 1..] {
+                ensure!(
+                    !a.path.starts_with(&b.path) && !b.path.starts_with(&a.path),
+                    "The packages {} and {} overlap, {} and {} have to be distinct directories",
+                    a.api.name,
+                    b.api.name,
+                    a.path.display(),
+                    b.path.display()
+                );
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -88,19 +191,80 @@ impl From<RawManifest> for Manifest {
         Self {
             api: raw.api,
             dependencies,
+            workspace: raw.workspace,
+            packages: raw.package.unwrap_or_default(),
+            groups: raw.groups.unwrap_or_default(),
+            lint: raw.lint,
         }
     }
 }
 
+/// Manifest format for the rules checked by `buffrs lint`
+///
+/// Entries are either single rules or the rule sets `default`, `comments` and
+/// `all`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LintManifest {
+    /// Rules and rule sets to check, defaults to the `default` rule set
+    #[serde(rename = "use", default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<String>,
+    /// Rules and rule sets to skip
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub except: Vec<String>,
+}
+
+/// Manifest format for groups of dependencies
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GroupManifest {
+    /// Whether the dependencies of the group are only installed on request
+    /// (`buffrs install --group <name>`)
+    #[serde(default)]
+    pub optional: bool,
+}
+
+/// Manifest format for workspaces
+///
+/// A workspace groups several projects of a repository, which share a single
+/// lockfile at the root of the workspace.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WorkspaceManifest {
+    /// Directories of the member projects relative to the workspace root,
+    /// a trailing `/*` includes every project in the directory
+    pub members: Vec<String>,
+}
+
 /// Manifest format for api packages
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ApiManifest {
     /// Name of the api package
     pub name: PackageId,
     /// Version of the api package
-    pub version: String,
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
+    pub version: semver::Version,
     /// Description of the api package
     pub description: Option<String>,
+    /// Proto package prefix the protos of the api package are declared in,
+    /// e.g. `acme.weather`, enforced for members of a workspace
+    pub namespace: Option<String>,
+}
+
+/// Manifest format for packages published from a subdirectory of a project
+///
+/// This allows splitting a large proto tree into several packages without
+/// moving the protos into separate projects first.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PackageManifest {
+    /// Metadata of the package, as for the api package
+    #[serde(flatten)]
+    pub api: ApiManifest,
+    /// Directory containing the protos of the package, relative to the
+    /// project root (e.g. `proto/legacy/weather`)
+    pub path: PathBuf,
 }
 
 /// Represents a single project dependency
@@ -108,18 +272,38 @@ pub struct ApiManifest {
 pub struct Dependency {
     /// Package name of this dependency
     pub package: PackageId,
-    /// Version requirement in the helsing format, currently only supports pinning
+    /// Version requirement, either an exact version or a semver range
     pub manifest: DependencyManifest,
 }
 
 impl Dependency {
     /// Creates a new dependency
-    pub fn new(repository: String, package: PackageId, version: String) -> Self {
+    pub fn new(repository: String, package: PackageId, version: VersionRequirement) -> Self {
         Self {
             package,
             manifest: DependencyManifest {
                 repository,
-                version,
+                version: Some(version),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// The exact version a dependency was resolved to
+    pub fn resolved_version(&self) -> eyre::Result<&semver::Version> {
+        match self.manifest.version {
+            Some(VersionRequirement::Exact(ref version)) => Ok(version),
+            _ => Err(eyre::eyre!("{self} was not resolved to an exact version")),
+        }
+    }
+
+    /// Creates a dependency on a local package located at `path`
+    pub fn local(package: PackageId, path: PathBuf) -> Self {
+        Self {
+            package,
+            manifest: DependencyManifest {
+                path: Some(path),
+                ..Default::default()
             },
         }
     }
@@ -127,19 +311,191 @@ impl Dependency {
 
 impl fmt::Display for Dependency {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}/{}@{}",
-            self.manifest.repository, self.package, self.manifest.version
-        )
+        let manifest = &self.manifest;
+
+        if manifest.is_registry() {
+            write!(f, "{}/", manifest.repository)?;
+        }
+
+        write!(f, "{}", self.package)?;
+
+        if let Some(ref version) = manifest.version {
+            write!(f, "@{version}")?;
+        }
+
+        if let Some(ref path) = manifest.path {
+            write!(f, " ({})", path.display())?;
+        }
+
+        if let Some(ref git) = manifest.git {
+            write!(f, " ({git}")?;
+
+            if let Some(reference) = manifest.git_reference() {
+                write!(f, "#{reference}")?;
+            }
+
+            write!(f, ")")?;
+        }
+
+        Ok(())
     }
 }
 
 /// Manifest forat for dependencies
-#[derive(Debug, Clone, Hash, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Default, Clone, Hash, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct DependencyManifest {
-    /// Version requirement in the helsing format, currently only supports pinning
-    pub version: String,
+    /// Version requirement, either an exact version or a semver range
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
+    pub version: Option<VersionRequirement>,
     /// Artifactory repository to pull dependency from
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub repository: String,
+    /// Directory of a local package, relative to the project, which is
+    /// installed instead of a published release
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// Url of a git repository containing the package in its root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git: Option<String>,
+    /// Branch of the git repository to use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// Tag of the git repository to use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Commit of the git repository to use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+    /// Languages (e.g. `rust`) the dependency is needed to generate code for,
+    /// all languages if empty
+    #[serde(rename = "for", default, skip_serializing_if = "Vec::is_empty")]
+    pub languages: Vec<String>,
+    /// Group the dependency belongs to, e.g. `integration-tests`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+impl DependencyManifest {
+    /// Checks whether the dependency is published to a registry, as opposed
+    /// to being packaged from a local directory or git repository
+    pub fn is_registry(&self) -> bool {
+        self.path.is_none() && self.git.is_none()
+    }
+
+    /// The branch, tag or commit of a git dependency, if one is specified
+    pub fn git_reference(&self) -> Option<&str> {
+        self.branch
+            .as_deref()
+            .or(self.tag.as_deref())
+            .or(self.rev.as_deref())
+    }
+
+    /// Checks whether the dependency is needed to generate code for the
+    /// language named `language`
+    pub fn is_for(&self, language: &str) -> bool {
+        self.languages.is_empty() || self.languages.iter().any(|l| l == language)
+    }
+}
+
+/// A manifest edited in place, preserving formatting and comments
+///
+/// Fields are addressed by dotted keys such as `api.version` or
+/// `dependencies.<package>.repository`.
+#[derive(Debug, Clone)]
+pub struct ManifestDocument(toml_edit::Document);
+
+impl ManifestDocument {
+    /// Reads the manifest of the project located at `root`
+    #[cfg(feature = "io")]
+    pub async fn read(root: &Path) -> eyre::Result<Self> {
+        fs::read_to_string(root.join(MANIFEST_FILE))
+            .await
+            .wrap_err("Failed to read manifest")
+            .wrap_err(ErrorCode::MissingManifest)?
+            .parse()
+    }
+
+    /// Writes the manifest of the project located at `root`
+    #[cfg(feature = "io")]
+    pub async fn write(&self, root: &Path) -> eyre::Result<()> {
+        fs::write(root.join(MANIFEST_FILE), self.to_string().into_bytes())
+            .await
+            .wrap_err("Failed to write manifest")
+    }
+
+    /// Reads a field, strings are returned without quotes
+    pub fn get(&self, key: &str) -> eyre::Result<String> {
+        let mut item = self.0.as_item();
+
+        for segment in key.split('.') {
+            item = item
+                .get(segment)
+                .wrap_err_with(|| format!("{key} is not set in the manifest"))?;
+        }
+
+        Ok(match item.as_str() {
+            Some(string) => string.to_owned(),
+            None => item.to_string().trim().to_owned(),
+        })
+    }
+
+    /// Sets a field to `value`, creating missing tables along the way
+    ///
+    /// The value is stored as a string unless `literal` is set, in which case
+    /// it is parsed as a toml value (e.g. `true` or `["a", "b"]`).
+    pub fn set(&mut self, key: &str, value: &str, literal: bool) -> eyre::Result<()> {
+        let value = if literal {
+            value
+                .parse::<toml_edit::Value>()
+                .wrap_err_with(|| format!("{value} is not a valid toml value"))?
+        } else {
+            value.into()
+        };
+
+        let (parents, field) = match key.rsplit_once('.') {
+            Some((parents, field)) => (Some(parents), field),
+            None => (None, key),
+        };
+
+        let mut table: &mut dyn toml_edit::TableLike = self.0.as_table_mut();
+
+        for segment in parents.into_iter().flat_map(|p| p.split('.')) {
+            if !table.contains_key(segment) {
+                table.insert(segment, toml_edit::table());
+            }
+
+            table = table
+                .get_mut(segment)
+                .and_then(toml_edit::Item::as_table_like_mut)
+                .wrap_err_with(|| format!("{segment} in {key} is not a table"))?;
+        }
+
+        table.insert(field, toml_edit::value(value));
+
+        // Edits must leave a manifest buffrs can still read
+        self.to_string().parse::<Manifest>()?;
+
+        Ok(())
+    }
+}
+
+impl FromStr for ManifestDocument {
+    type Err = eyre::Error;
+
+    fn from_str(toml: &str) -> eyre::Result<Self> {
+        toml.parse::<Manifest>()?;
+
+        toml.parse()
+            .map(Self)
+            .wrap_err("Failed to parse manifest")
+            .wrap_err(ErrorCode::InvalidManifest)
+    }
+}
+
+impl fmt::Display for ManifestDocument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
 }
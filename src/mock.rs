@@ -0,0 +1,120 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc};
+
+use eyre::Context;
+use hyper::{
+    header::{HeaderValue, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, HeaderMap, Request, Response,
+};
+use prost_reflect::{prost::Message as _, MethodDescriptor, ServiceDescriptor};
+use tokio_util::sync::CancellationToken;
+
+use crate::reflect;
+
+/// `grpc-status` of successful calls
+const GRPC_OK: &str = "0";
+/// `grpc-status` of calls to unknown methods
+const GRPC_UNIMPLEMENTED: &str = "12";
+
+/// Serves mock implementations of gRPC services
+///
+/// Every method responds with an example message in which all fields are set,
+/// regardless of the request. Server streams consist of a single message. The
+/// server speaks plaintext HTTP/2 and runs until `cancel` is triggered.
+pub async fn serve(
+    services: Vec<ServiceDescriptor>,
+    addr: SocketAddr,
+    cancel: CancellationToken,
+) -> eyre::Result<()> {
+    let mut methods = HashMap::new();
+
+    for service in &services {
+        for method in service.methods() {
+            let path = format!("/{}/{}", service.full_name(), method.name());
+            let response = reflect::example(&method.output(), None).encode_to_vec();
+
+            methods.insert(path, (method, response));
+        }
+    }
+
+    let methods = Arc::new(methods);
+
+    let service = make_service_fn(move |_| {
+        let methods = methods.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let methods = methods.clone();
+
+                async move { Ok::<_, Infallible>(handle(&methods, request)) }
+            }))
+        }
+    });
+
+    let server = hyper::Server::try_bind(&addr)
+        .wrap_err_with(|| format!("Failed to listen on {addr}"))?
+        .http2_only(true)
+        .serve(service);
+
+    for service in &services {
+        tracing::info!(":: mocking {}", service.full_name());
+    }
+
+    tracing::info!(":: serving on {}", server.local_addr());
+
+    server
+        .with_graceful_shutdown(cancel.cancelled_owned())
+        .await
+        .wrap_err("Server failed")
+}
+
+fn handle(
+    methods: &HashMap<String, (MethodDescriptor, Vec<u8>)>,
+    request: Request<Body>,
+) -> Response<Body> {
+    let path = request.uri().path();
+
+    let Some((method, message)) = methods.get(path) else {
+        tracing::warn!("unknown method {path}");
+
+        // Trailers-only response, the status is sent with the headers
+        let mut response = Response::new(Body::empty());
+        response.headers_mut().extend(grpc_headers());
+        response
+            .headers_mut()
+            .insert("grpc-status", HeaderValue::from_static(GRPC_UNIMPLEMENTED));
+
+        return response;
+    };
+
+    tracing::info!("{} -> {}", method.full_name(), method.output().full_name());
+
+    // Requests are never read, so bidirectional streams respond right away
+    let mut frame = Vec::with_capacity(message.len() + 5);
+    frame.push(0);
+    frame.extend((message.len() as u32).to_be_bytes());
+    frame.extend(message);
+
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static(GRPC_OK));
+
+        if sender.send_data(frame.into()).await.is_ok() {
+            sender.send_trailers(trailers).await.ok();
+        }
+    });
+
+    let mut response = Response::new(body);
+    response.headers_mut().extend(grpc_headers());
+    response
+}
+
+fn grpc_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+    headers
+}
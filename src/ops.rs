@@ -0,0 +1,1918 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use eyre::{ensure, Context, ContextCompat};
+use futures::{stream, StreamExt, TryStreamExt};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+use walkdir::WalkDir;
+
+#[cfg(feature = "reflect")]
+use crate::breaking;
+use crate::{
+    error::ErrorCode,
+    generator::Language,
+    lock::{self, LockedPackage, Lockfile, LOCKFILE, SIGNATURE_FILE},
+    manifest::{ApiManifest, Dependency, Manifest, RawManifest, MANIFEST_FILE},
+    package::{self, Package, PackageCache, PackageId, PackageStore},
+    policy::{self, Operation, Policy},
+    registry::{CachedRegistry, GitRegistry, Receipt, Registry},
+    resolver::{self, VersionRequirement},
+    workspace::Workspace,
+};
+
+/// Maximum number of packages downloaded and extracted at the same time,
+/// unless configured otherwise
+const INSTALL_CONCURRENCY: usize = 8;
+/// Maximum number of projects installed at the same time
+const PROJECT_CONCURRENCY: usize = 4;
+
+/// Settings of an installation
+#[derive(Debug, Default, Clone)]
+pub struct InstallOptions {
+    /// Policies the installed dependencies have to satisfy
+    pub policies: Vec<Policy>,
+    /// Packages that may be installed even though they are quarantined
+    pub allow_quarantined: Vec<PackageId>,
+    /// Installs as many dependencies as possible instead of stopping at the
+    /// first failure, which is then reported along with all others
+    pub keep_going: bool,
+    /// Fails if the lockfile is missing or does not match the manifest,
+    /// instead of updating it
+    pub locked: bool,
+    /// Cache packages are read from instead of the registry, if their locked
+    /// digest is cached, and stored in after downloading them
+    pub cache: Option<PackageCache>,
+    /// Ignores the locked versions of these packages, so they are updated to
+    /// the newest versions satisfying the manifest, or of all dependencies
+    /// if the list is empty
+    pub update: Option<Vec<PackageId>>,
+    /// Only installs the dependencies needed to generate code for this
+    /// language, the locked versions of the others are kept
+    pub language: Option<Language>,
+    /// Optional groups of dependencies to install as well, groups not marked
+    /// as optional in the manifest are installed anyway
+    pub groups: Vec<String>,
+    /// Groups of dependencies to skip, even if they are not optional
+    pub exclude_groups: Vec<String>,
+    /// Maximum number of packages downloaded and extracted at the same time
+    /// (defaults to 8), installing several projects is limited accordingly
+    pub jobs: Option<usize>,
+    /// File of the SSH keys trusted to sign the lockfile, see
+    /// [`lock::verify`]. If set, installations with
+    /// [`InstallOptions::locked`] require a valid signature.
+    pub allowed_signers: Option<PathBuf>,
+    /// Narrows the version requirements of the manifest for these packages,
+    /// e.g. to keep a package at its current major version while updating.
+    /// Pins outside of the requirement of the manifest are rejected.
+    pub pins: BTreeMap<PackageId, VersionRequirement>,
+    /// Http client policy hooks are queried with, see [`Policy::evaluate`]
+    pub client: reqwest::Client,
+}
+
+impl InstallOptions {
+    /// Number of packages installed at the same time, at least one
+    fn jobs(&self) -> usize {
+        self.jobs.unwrap_or(INSTALL_CONCURRENCY).max(1)
+    }
+}
+
+/// Observer receiving progress events of an installation
+///
+/// All methods default to doing nothing, so implementors only need to handle
+/// the events they are interested in. Events of different packages may be
+/// reported concurrently and in any order.
+pub trait InstallReporter: Send + Sync {
+    /// Called before the dependencies of the manifest are resolved
+    fn resolve_started(&self) {}
+    /// Called with the dependencies that are going to be installed
+    fn resolved(&self, _dependencies: &[Dependency]) {}
+    /// Called before a dependency is downloaded from the registry
+    fn download_started(&self, _dependency: &Dependency) {}
+    /// Called after a dependency was downloaded from the registry
+    fn downloaded(&self, _dependency: &Dependency) {}
+    /// Called with the size of the archive of a registry dependency, after it
+    /// was downloaded or read from the package cache
+    fn fetched(&self, _dependency: &Dependency, _bytes: u64, _cached: bool) {}
+    /// Called before a dependency is extracted into the package store
+    fn extract_started(&self, _dependency: &Dependency) {}
+    /// Called after a dependency was extracted into the package store
+    fn extracted(&self, _dependency: &Dependency) {}
+    /// Called when downloading or extracting a dependency failed
+    fn failed(&self, _dependency: &Dependency, _error: &eyre::Report) {}
+}
+
+/// Ignores all events
+impl InstallReporter for () {}
+
+impl<T: InstallReporter + ?Sized> InstallReporter for &T {
+    fn resolve_started(&self) {
+        (**self).resolve_started()
+    }
+
+    fn resolved(&self, dependencies: &[Dependency]) {
+        (**self).resolved(dependencies)
+    }
+
+    fn download_started(&self, dependency: &Dependency) {
+        (**self).download_started(dependency)
+    }
+
+    fn downloaded(&self, dependency: &Dependency) {
+        (**self).downloaded(dependency)
+    }
+
+    fn fetched(&self, dependency: &Dependency, bytes: u64, cached: bool) {
+        (**self).fetched(dependency, bytes, cached)
+    }
+
+    fn extract_started(&self, dependency: &Dependency) {
+        (**self).extract_started(dependency)
+    }
+
+    fn extracted(&self, dependency: &Dependency) {
+        (**self).extracted(dependency)
+    }
+
+    fn failed(&self, dependency: &Dependency, error: &eyre::Report) {
+        (**self).failed(dependency, error)
+    }
+}
+
+/// Reports every event to both reporters
+impl<A: InstallReporter, B: InstallReporter> InstallReporter for (A, B) {
+    fn resolve_started(&self) {
+        self.0.resolve_started();
+        self.1.resolve_started();
+    }
+
+    fn resolved(&self, dependencies: &[Dependency]) {
+        self.0.resolved(dependencies);
+        self.1.resolved(dependencies);
+    }
+
+    fn download_started(&self, dependency: &Dependency) {
+        self.0.download_started(dependency);
+        self.1.download_started(dependency);
+    }
+
+    fn downloaded(&self, dependency: &Dependency) {
+        self.0.downloaded(dependency);
+        self.1.downloaded(dependency);
+    }
+
+    fn fetched(&self, dependency: &Dependency, bytes: u64, cached: bool) {
+        self.0.fetched(dependency, bytes, cached);
+        self.1.fetched(dependency, bytes, cached);
+    }
+
+    fn extract_started(&self, dependency: &Dependency) {
+        self.0.extract_started(dependency);
+        self.1.extract_started(dependency);
+    }
+
+    fn extracted(&self, dependency: &Dependency) {
+        self.0.extracted(dependency);
+        self.1.extracted(dependency);
+    }
+
+    fn failed(&self, dependency: &Dependency, error: &eyre::Report) {
+        self.0.failed(dependency, error);
+        self.1.failed(dependency, error);
+    }
+}
+
+/// Installs the dependencies of a manifest into a package store
+///
+/// Cancelling the token stops all pending downloads. Extractions that are
+/// already in progress are finished, so the store only ever contains fully
+/// installed packages.
+///
+/// Dependencies on local packages (declared with a `path`) and git
+/// repositories are packaged from their sources and skip the registry,
+/// policies and lockfile.
+///
+/// Dependencies blocked by any of the policies fail the installation before
+/// anything is downloaded. Dependencies that were published within a
+/// quarantine period are rejected, unless they are explicitly allowed.
+/// Archived packages are installed with a warning.
+///
+/// Afterwards every dependency has to be installed at its resolved version
+/// and packages no longer declared by the manifest are pruned.
+///
+/// Packages recorded in the lockfile of the project keep their locked
+/// version while it satisfies the manifest, unless they are selected by
+/// [`InstallOptions::update`], and have to match their locked digest.
+/// After a successful installation the lockfile is updated, unless
+/// [`InstallOptions::locked`] is set, which requires the lockfile to match
+/// the manifest up front. Locked packages found in
+/// [`InstallOptions::cache`] are not downloaded again.
+///
+/// If a dependency fails, pending downloads are stopped and the store is
+/// rolled back to its state before the installation. With
+/// [`InstallOptions::keep_going`], the remaining dependencies are installed
+/// instead and the returned error lists all failures.
+///
+/// Only dependencies matching [`InstallOptions::language`] and the selected
+/// groups are installed, see [`InstallOptions::groups`]. Selecting a group no
+/// dependency belongs to is an error.
+pub async fn install(
+    store: &PackageStore,
+    manifest: &Manifest,
+    registry: &(impl Registry + Sync),
+    options: &InstallOptions,
+    reporter: &dyn InstallReporter,
+    cancel: &CancellationToken,
+) -> eyre::Result<()> {
+    ensure_groups(&resolver::resolve(manifest)?, options)?;
+
+    install_project(store, manifest, registry, options, reporter, cancel).await
+}
+
+/// Installs the dependencies of a single project, see [`install`]
+async fn install_project(
+    store: &PackageStore,
+    manifest: &Manifest,
+    registry: &(impl Registry + Sync),
+    options: &InstallOptions,
+    reporter: &dyn InstallReporter,
+    cancel: &CancellationToken,
+) -> eyre::Result<()> {
+    let lockfile = read_lockfile(store.root()).await?;
+    let requested = resolver::resolve(manifest)?;
+
+    if options.locked {
+        ensure_locked(lockfile.as_ref(), &dependencies_for(manifest, options)?)?;
+        ensure_signed(store.root(), options).await?;
+    }
+
+    let unlocked = unlock(lockfile.as_ref(), options, &requested)?;
+
+    let locked = install_locked(
+        store,
+        manifest,
+        registry,
+        options,
+        unlocked.as_ref(),
+        reporter,
+        cancel,
+    )
+    .await?;
+
+    // Skipped dependencies stay locked, so installing for another language
+    // does not change their versions
+    let skipped: Vec<LockedPackage> = unlocked
+        .iter()
+        .flat_map(|l| l.packages())
+        .filter(|p| !locked.iter().any(|l| l.name == p.name))
+        .filter(|p| {
+            requested
+                .iter()
+                .any(|d| d.package == p.name && !is_needed(manifest, d, options))
+        })
+        .cloned()
+        .collect();
+
+    let updated = Lockfile::new(locked.into_iter().chain(skipped).collect());
+
+    if options.update.is_some() {
+        report_updates(lockfile.as_ref(), &updated);
+    }
+
+    if !options.locked && lockfile.as_ref() != Some(&updated) {
+        write_lockfile(store.root(), &updated).await?;
+    }
+
+    Ok(())
+}
+
+/// Installs the dependencies of members of a workspace
+///
+/// All members share the lockfile at the root of the workspace, so a package
+/// is locked at the same version for every member. Members are installed in
+/// order and reuse the versions locked by the ones before them. Only the
+/// given `members` are installed, the locked packages of the others are kept.
+///
+/// Fails if members require versions of a package that can not be locked at
+/// a single version, or declare protos outside of their namespace (see
+/// [`Workspace::check_namespaces`]).
+pub async fn install_workspace(
+    workspace: &Workspace,
+    members: &[PackageStore],
+    registry: &(impl Registry + Sync),
+    options: &InstallOptions,
+    reporter: &dyn InstallReporter,
+    cancel: &CancellationToken,
+) -> eyre::Result<()> {
+    workspace.check_namespaces().await?;
+
+    let lockfile = read_lockfile(workspace.root()).await?;
+
+    let mut requested: Vec<(&Path, Dependency)> = vec![];
+
+    for member in workspace.members() {
+        let manifest = Manifest::read(member).await?;
+
+        requested.extend(
+            resolver::resolve(&manifest)?
+                .into_iter()
+                .filter(|d| d.manifest.is_registry())
+                .map(|d| (member.as_path(), d)),
+        );
+    }
+
+    if options.locked {
+        let dependencies: Vec<Dependency> = requested.iter().map(|(_, d)| d.clone()).collect();
+
+        ensure_locked(lockfile.as_ref(), &dependencies)?;
+        ensure_signed(workspace.root(), options).await?;
+    }
+
+    let mut installed = vec![];
+
+    for store in members {
+        installed.extend(resolver::resolve(&Manifest::read(store.root()).await?)?);
+    }
+
+    ensure_groups(&installed, options)?;
+
+    let registry = CachedRegistry::new(registry);
+
+    let mut updated = unlock(lockfile.as_ref(), options, &installed)?.unwrap_or_default();
+
+    for store in members {
+        let manifest = Manifest::read(store.root()).await?;
+
+        let locked = install_locked(
+            store,
+            &manifest,
+            &registry,
+            options,
+            Some(&updated),
+            reporter,
+            cancel,
+        )
+        .await
+        .wrap_err_with(|| {
+            format!(
+                "Failed to install {}",
+                workspace.relative(store.root()).display()
+            )
+        })?;
+
+        let mut packages: Vec<LockedPackage> = updated
+            .packages()
+            .iter()
+            .filter(|p| !locked.iter().any(|l| l.name == p.name))
+            .cloned()
+            .collect();
+
+        packages.extend(locked);
+
+        updated = Lockfile::new(packages);
+    }
+
+    let conflicts: Vec<String> = requested
+        .iter()
+        .filter_map(|(member, dependency)| {
+            let locked = updated.get(&dependency.package)?;
+
+            (!locked.matches(dependency)).then(|| {
+                format!(
+                    "{dependency} of {} conflicts with {locked}",
+                    workspace.relative(member).display()
+                )
+            })
+        })
+        .collect();
+
+    ensure!(
+        conflicts.is_empty(),
+        "Workspace members require incompatible versions:{}",
+        conflicts
+            .iter()
+            .map(|c| format!("\n  - {c}"))
+            .collect::<String>()
+    );
+
+    // Drops packages no member depends on anymore
+    let updated = Lockfile::new(
+        updated
+            .packages()
+            .iter()
+            .filter(|p| requested.iter().any(|(_, d)| d.package == p.name))
+            .cloned()
+            .collect(),
+    );
+
+    if options.update.is_some() {
+        report_updates(lockfile.as_ref(), &updated);
+    }
+
+    if !options.locked && lockfile.as_ref() != Some(&updated) {
+        write_lockfile(workspace.root(), &updated).await?;
+    }
+
+    Ok(())
+}
+
+/// Versions of a registry dependency compared to the ones published
+///
+/// This is the stable JSON format of `buffrs outdated --format json`, fields
+/// must not be renamed or removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OutdatedDependency {
+    /// Name of the package
+    pub package: PackageId,
+    /// Repository the package is installed from
+    pub repository: String,
+    /// Version requirement of the manifest
+    pub requirement: VersionRequirement,
+    /// Locked or, without lockfile entry, installed version
+    pub current: Option<semver::Version>,
+    /// Newest published version satisfying the requirement
+    pub compatible: Option<semver::Version>,
+    /// Newest published version, even if the requirement excludes it
+    pub latest: Option<semver::Version>,
+}
+
+/// How far a dependency lags behind its latest release
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Lag {
+    /// Only patch releases or pre-releases are newer
+    Patch,
+    /// A newer minor release was published
+    Minor,
+    /// A newer major release was published
+    Major,
+}
+
+impl OutdatedDependency {
+    /// Whether a newer version than the current one was published
+    pub fn is_outdated(&self) -> bool {
+        match self.current {
+            Some(ref current) => self.latest.as_ref().is_some_and(|latest| latest > current),
+            None => true,
+        }
+    }
+
+    /// How far the current version lags behind the latest one, `None` if it
+    /// is up to date
+    ///
+    /// Dependencies that are not installed lag a major release.
+    pub fn lag(&self) -> Option<Lag> {
+        if !self.is_outdated() {
+            return None;
+        }
+
+        let (Some(current), Some(latest)) = (&self.current, &self.latest) else {
+            return Some(Lag::Major);
+        };
+
+        Some(match (current.major, current.minor) {
+            (major, _) if major != latest.major => Lag::Major,
+            (_, minor) if minor != latest.minor => Lag::Minor,
+            _ => Lag::Patch,
+        })
+    }
+}
+
+/// Compares the registry dependencies of a manifest with the versions
+/// published to the registry, ordered by package name
+///
+/// The current version is taken from the lockfile at `lock_root` (the
+/// project or workspace root). Yanked versions are ignored and pre-releases
+/// are only reported as latest version if nothing else was published.
+pub async fn outdated(
+    store: &PackageStore,
+    manifest: &Manifest,
+    lock_root: &Path,
+    registry: &(impl Registry + Sync),
+) -> eyre::Result<Vec<OutdatedDependency>> {
+    let lockfile = read_lockfile(lock_root).await?;
+    let installed = store.installed().await?;
+
+    let dependencies: Vec<Dependency> = resolver::resolve(manifest)?
+        .into_iter()
+        .filter(|d| d.manifest.is_registry())
+        .collect();
+
+    let mut outdated: Vec<OutdatedDependency> = stream::iter(dependencies)
+        .map(|dependency| {
+            let (lockfile, installed) = (&lockfile, &installed);
+
+            async move {
+                let requirement = dependency
+                    .manifest
+                    .version
+                    .clone()
+                    .wrap_err_with(|| format!("{dependency} has no version requirement"))?;
+
+                let yanked = registry
+                    .yanked(&dependency.manifest.repository, &dependency.package)
+                    .await
+                    .wrap_err_with(|| {
+                        format!("Failed to look up yanked versions of {dependency}")
+                    })?;
+
+                let versions: Vec<semver::Version> = registry
+                    .versions(&dependency.manifest.repository, &dependency.package)
+                    .await
+                    .wrap_err_with(|| format!("Failed to list versions of {dependency}"))?
+                    .into_iter()
+                    .filter(|version| !yanked.contains(version))
+                    .collect();
+
+                let current = lockfile
+                    .as_ref()
+                    .and_then(|l| l.get(&dependency.package))
+                    .filter(|l| l.matches(&dependency))
+                    .map(|l| l.version.clone())
+                    .or_else(|| {
+                        installed
+                            .iter()
+                            .find(|api| api.name == dependency.package)
+                            .map(|api| api.version.clone())
+                    });
+
+                let latest = versions
+                    .iter()
+                    .filter(|v| v.pre.is_empty())
+                    .max()
+                    .or_else(|| versions.iter().max());
+
+                Ok::<_, eyre::Report>(OutdatedDependency {
+                    compatible: requirement.select(&versions).cloned(),
+                    latest: latest.cloned(),
+                    current,
+                    requirement,
+                    repository: dependency.manifest.repository,
+                    package: dependency.package,
+                })
+            }
+        })
+        .buffer_unordered(INSTALL_CONCURRENCY)
+        .try_collect()
+        .await?;
+
+    outdated.sort_by(|a, b| a.package.cmp(&b.package));
+
+    Ok(outdated)
+}
+
+/// A locked version an update is going to replace
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedUpdate {
+    /// Name of the package
+    pub package: PackageId,
+    /// Repository the package is installed from
+    pub repository: String,
+    /// Currently locked version
+    pub current: semver::Version,
+    /// Version the package is updated to
+    pub version: semver::Version,
+    /// Requirements of workspace members the new version does not satisfy,
+    /// e.g. `^1.2 of members/client`
+    pub conflicts: Vec<String>,
+}
+
+impl PlannedUpdate {
+    /// Whether the new version is not semver compatible with the current
+    /// one, e.g. a new major release
+    pub fn is_breaking(&self) -> bool {
+        !compatible(&self.current).matches(&self.version)
+    }
+}
+
+/// Range of the versions semver compatible with `version`, e.g. `^1.2.3`
+pub fn compatible(version: &semver::Version) -> semver::VersionReq {
+    semver::VersionReq {
+        comparators: vec![semver::Comparator {
+            op: semver::Op::Caret,
+            major: version.major,
+            minor: Some(version.minor),
+            patch: Some(version.patch),
+            pre: version.pre.clone(),
+        }],
+    }
+}
+
+/// Lists the locked versions [`install`] or [`install_workspace`] would
+/// update with the given options, ordered by package name
+///
+/// `stores` are the project or the members of a workspace that are going to
+/// be installed, `lock_root` the root of their lockfile. Packages that are
+/// not locked yet are not reported.
+pub async fn plan_update(
+    stores: &[PackageStore],
+    lock_root: &Path,
+    registry: &(impl Registry + Sync),
+    options: &InstallOptions,
+) -> eyre::Result<Vec<PlannedUpdate>> {
+    let Some(lockfile) = read_lockfile(lock_root).await? else {
+        return Ok(vec![]);
+    };
+
+    let mut manifests = vec![];
+    let mut requested: Vec<(PathBuf, Dependency)> = vec![];
+
+    for store in stores {
+        let manifest = Manifest::read(store.root()).await?;
+
+        let member = store
+            .root()
+            .strip_prefix(lock_root)
+            .unwrap_or(store.root())
+            .to_owned();
+
+        requested.extend(
+            resolver::resolve(&manifest)?
+                .into_iter()
+                .filter(|d| d.manifest.is_registry())
+                .map(|d| (member.clone(), d)),
+        );
+
+        manifests.push((store, manifest));
+    }
+
+    let dependencies: Vec<Dependency> = requested.iter().map(|(_, d)| d.clone()).collect();
+    let unlocked = unlock(Some(&lockfile), options, &dependencies)?;
+
+    let git = GitRegistry::new();
+    let mut planned: Vec<PlannedUpdate> = vec![];
+
+    for (store, manifest) in manifests {
+        let needed: Vec<Dependency> = dependencies_for(&manifest, options)?
+            .into_iter()
+            .filter(|d| d.manifest.is_registry())
+            .collect();
+
+        let selected = select_versions(
+            store.root(),
+            &needed,
+            registry,
+            &git,
+            unlocked.as_ref(),
+            options.jobs(),
+        )
+        .await?;
+
+        for dependency in selected {
+            let version = dependency.resolved_version()?;
+
+            let Some(current) = lockfile
+                .get(&dependency.package)
+                .filter(|l| &l.version != version)
+            else {
+                continue;
+            };
+
+            if planned.iter().any(|p| p.package == dependency.package) {
+                continue;
+            }
+
+            planned.push(PlannedUpdate {
+                package: dependency.package.clone(),
+                repository: dependency.manifest.repository.clone(),
+                current: current.version.clone(),
+                version: version.clone(),
+                conflicts: vec![],
+            });
+        }
+    }
+
+    for update in &mut planned {
+        update.conflicts = requested
+            .iter()
+            .filter(|(_, d)| d.package == update.package)
+            .filter_map(|(member, d)| {
+                let requirement = d.manifest.version.as_ref()?;
+
+                (!requirement.matches(&update.version))
+                    .then(|| format!("{requirement} of {}", member.display()))
+            })
+            .collect();
+    }
+
+    planned.sort_by(|a, b| a.package.cmp(&b.package));
+
+    Ok(planned)
+}
+
+/// Checks whether the dependencies of a manifest are installed at their
+/// locked versions, without contacting any registry
+///
+/// This requires the lockfile at `lock_root` (the project or workspace root)
+/// to match the manifest. Dependencies on local packages and git repositories
+/// are not locked and only have to be present.
+pub async fn is_installed(
+    store: &PackageStore,
+    manifest: &Manifest,
+    lock_root: &Path,
+    options: &InstallOptions,
+) -> eyre::Result<bool> {
+    let requested = dependencies_for(manifest, options)?;
+
+    let Some(lockfile) = read_lockfile(lock_root).await? else {
+        return Ok(false);
+    };
+
+    let locked: Vec<Dependency> = requested
+        .iter()
+        .filter(|d| d.manifest.is_registry())
+        .cloned()
+        .collect();
+
+    // Workspace lockfiles also lock the dependencies of other members
+    if locked
+        .iter()
+        .any(|d| !lockfile.get(&d.package).is_some_and(|l| l.matches(d)))
+    {
+        return Ok(false);
+    }
+
+    let installed = store.installed().await?;
+
+    Ok(requested.iter().all(|dependency| {
+        installed.iter().any(|api| {
+            api.name == dependency.package
+                && lockfile
+                    .get(&dependency.package)
+                    .filter(|_| dependency.manifest.is_registry())
+                    .is_none_or(|locked| locked.version == api.version)
+        })
+    }))
+}
+
+/// Resolves the dependencies of `manifest` selected by the language and
+/// groups of `options`
+fn dependencies_for(
+    manifest: &Manifest,
+    options: &InstallOptions,
+) -> eyre::Result<Vec<Dependency>> {
+    let requested = resolver::resolve(manifest)?;
+
+    for dependency in &requested {
+        for name in &dependency.manifest.languages {
+            name.parse::<Language>()
+                .wrap_err_with(|| format!("Invalid language of dependency {}", dependency.package))
+                .wrap_err(ErrorCode::InvalidManifest)?;
+        }
+    }
+
+    requested
+        .into_iter()
+        .filter(|d| is_needed(manifest, d, options))
+        .map(|d| pin(d, options))
+        .collect()
+}
+
+/// Narrows the version requirement of a registry dependency to its
+/// [`InstallOptions::pins`]
+fn pin(mut dependency: Dependency, options: &InstallOptions) -> eyre::Result<Dependency> {
+    let (true, Some(pin), Some(requirement)) = (
+        dependency.manifest.is_registry(),
+        options.pins.get(&dependency.package),
+        dependency.manifest.version.as_ref(),
+    ) else {
+        return Ok(dependency);
+    };
+
+    let pinned = match (requirement, pin) {
+        (_, VersionRequirement::Exact(version)) => {
+            ensure!(
+                requirement.matches(version),
+                "Unable to update {} to {version}, {MANIFEST_FILE} requires {requirement}",
+                dependency.package
+            );
+
+            pin.clone()
+        }
+        (VersionRequirement::Exact(_), VersionRequirement::Range(_)) => requirement.clone(),
+        (VersionRequirement::Range(range), VersionRequirement::Range(pin)) => {
+            VersionRequirement::Range(semver::VersionReq {
+                comparators: range
+                    .comparators
+                    .iter()
+                    .chain(&pin.comparators)
+                    .cloned()
+                    .collect(),
+            })
+        }
+    };
+
+    dependency.manifest.version = Some(pinned);
+
+    Ok(dependency)
+}
+
+/// Ensures the groups selected by `options` contain some of `dependencies`,
+/// to catch misspelled group names
+fn ensure_groups(dependencies: &[Dependency], options: &InstallOptions) -> eyre::Result<()> {
+    for group in options.groups.iter().chain(&options.exclude_groups) {
+        ensure!(
+            dependencies
+                .iter()
+                .any(|d| d.manifest.group.as_ref() == Some(group)),
+            "Unable to select group {group}, no dependency belongs to it"
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks whether `dependency` is selected by the language and groups of
+/// `options`
+///
+/// Dependencies of optional groups are only selected on request, those of
+/// other groups unless excluded.
+fn is_needed(manifest: &Manifest, dependency: &Dependency, options: &InstallOptions) -> bool {
+    let language = options
+        .language
+        .is_none_or(|language| dependency.manifest.is_for(language.name()));
+
+    let group = match dependency.manifest.group {
+        Some(ref group) if options.exclude_groups.contains(group) => false,
+        Some(ref group) => {
+            options.groups.contains(group)
+                || !manifest.groups.get(group).is_some_and(|g| g.optional)
+        }
+        None => true,
+    };
+
+    language && group
+}
+
+/// Reads the lockfile of the project or workspace at `root`, if it has one
+async fn read_lockfile(root: &Path) -> eyre::Result<Option<Lockfile>> {
+    match Lockfile::exists(root).await? {
+        true => Lockfile::read(root).await.map(Some),
+        false => Ok(None),
+    }
+}
+
+/// Fails unless the lockfile exists and records exactly `dependencies`
+fn ensure_locked(lockfile: Option<&Lockfile>, dependencies: &[Dependency]) -> eyre::Result<()> {
+    match lockfile {
+        Some(lockfile) => lockfile.ensure_matches(dependencies),
+        None => Err(eyre::eyre!(
+            "{LOCKFILE} is missing, run buffrs install to create it"
+        ))
+        .wrap_err(ErrorCode::LockfileOutdated),
+    }
+}
+
+/// Verifies the signature of the lockfile if
+/// [`InstallOptions::allowed_signers`] are configured
+async fn ensure_signed(root: &Path, options: &InstallOptions) -> eyre::Result<()> {
+    let Some(ref signers) = options.allowed_signers else {
+        return Ok(());
+    };
+
+    let signer = lock::verify(root, signers)
+        .await
+        .wrap_err(ErrorCode::SignatureInvalid)?;
+
+    tracing::info!(":: {LOCKFILE} is signed by {signer}");
+
+    Ok(())
+}
+
+/// Writes an updated lockfile, warning if that invalidated its signature
+async fn write_lockfile(root: &Path, lockfile: &Lockfile) -> eyre::Result<()> {
+    lockfile.write(root).await?;
+
+    if tokio::fs::try_exists(root.join(SIGNATURE_FILE))
+        .await
+        .unwrap_or(false)
+    {
+        tracing::warn!("! {LOCKFILE} changed, sign it again with buffrs lock sign");
+    }
+
+    Ok(())
+}
+
+/// Drops the lockfile entries of the `dependencies` selected by
+/// [`InstallOptions::update`], so they are resolved to their newest versions
+fn unlock(
+    lockfile: Option<&Lockfile>,
+    options: &InstallOptions,
+    dependencies: &[Dependency],
+) -> eyre::Result<Option<Lockfile>> {
+    let Some(ref update) = options.update else {
+        return Ok(lockfile.cloned());
+    };
+
+    for package in update {
+        ensure!(
+            dependencies.iter().any(|d| &d.package == package),
+            "Unable to update {package}, it is not a dependency"
+        );
+    }
+
+    let unlocked = |package: &PackageId| {
+        dependencies.iter().any(|d| &d.package == package)
+            && (update.is_empty() || update.contains(package))
+    };
+
+    Ok(lockfile.map(|lockfile| {
+        Lockfile::new(
+            lockfile
+                .packages()
+                .iter()
+                .filter(|p| !unlocked(&p.name))
+                .cloned()
+                .collect(),
+        )
+    }))
+}
+
+/// A package whose locked version changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UpdatedPackage {
+    /// Name of the package
+    pub package: PackageId,
+    /// Repository the package is installed from
+    pub repository: String,
+    /// Previously locked version, `None` if the package was not locked yet
+    pub previous: Option<semver::Version>,
+    /// Newly locked version
+    pub version: semver::Version,
+}
+
+impl UpdatedPackage {
+    /// Kind of release the package was updated by, `None` if it was not
+    /// locked before
+    pub fn change(&self) -> Option<Lag> {
+        let previous = self.previous.as_ref()?;
+
+        Some(match (previous.major, previous.minor) {
+            (major, _) if major != self.version.major => Lag::Major,
+            (_, minor) if minor != self.version.minor => Lag::Minor,
+            _ => Lag::Patch,
+        })
+    }
+}
+
+/// Compares two lockfiles, listing the packages of `updated` whose locked
+/// version differs from `previous`
+pub fn updated_packages(previous: Option<&Lockfile>, updated: &Lockfile) -> Vec<UpdatedPackage> {
+    updated
+        .packages()
+        .iter()
+        .filter_map(|package| {
+            let previous = previous
+                .and_then(|l| l.get(&package.name))
+                .map(|l| l.version.clone());
+
+            (previous.as_ref() != Some(&package.version)).then(|| UpdatedPackage {
+                package: package.name.clone(),
+                repository: package.repository.clone(),
+                previous,
+                version: package.version.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Logs the packages whose locked version changed
+fn report_updates(previous: Option<&Lockfile>, updated: &Lockfile) {
+    let changes = updated_packages(previous, updated);
+
+    for change in &changes {
+        match change.previous {
+            Some(ref previous) => tracing::info!(
+                "+ updated {} {previous} -> {}",
+                change.package,
+                change.version
+            ),
+            None => tracing::info!("+ locked {}@{}", change.package, change.version),
+        }
+    }
+
+    if changes.is_empty() {
+        tracing::info!(":: all dependencies are up to date");
+    }
+}
+
+/// Installs the dependencies of a manifest without updating the lockfile
+///
+/// Downloaded packages have to match their entries in `lockfile`, the
+/// packages to lock are returned.
+async fn install_locked(
+    store: &PackageStore,
+    manifest: &Manifest,
+    registry: &(impl Registry + Sync),
+    options: &InstallOptions,
+    lockfile: Option<&Lockfile>,
+    reporter: &dyn InstallReporter,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<LockedPackage>> {
+    reporter.resolve_started();
+
+    let requested = dependencies_for(manifest, options)?;
+
+    let git = GitRegistry::new();
+
+    let dependencies = select_versions(
+        store.root(),
+        &requested,
+        registry,
+        &git,
+        lockfile,
+        options.jobs(),
+    )
+    .await?;
+
+    for dependency in dependencies.iter().filter(|d| d.manifest.is_registry()) {
+        for policy in &options.policies {
+            policy.check(dependency)?;
+        }
+    }
+
+    if options.policies.iter().any(|p| !p.hooks.is_empty()) {
+        let operation = Operation::Install {
+            manifest: RawManifest::from(manifest.clone()),
+            dependencies: dependencies.clone(),
+            installed: store.installed().await?,
+        };
+
+        for policy in &options.policies {
+            policy.evaluate(&operation, &options.client).await?;
+        }
+    }
+
+    let algorithm = policy::digest_algorithm(&options.policies)?;
+
+    reporter.resolved(&dependencies);
+
+    let total = dependencies.len();
+    let resolved = dependencies.clone();
+
+    let transaction = store.transaction().await?;
+
+    // Stops pending downloads once a dependency failed, unless keeping going
+    let abort = cancel.child_token();
+
+    let results: Vec<(Dependency, eyre::Result<Option<LockedPackage>>)> = stream::iter(dependencies)
+        .map(|dependency| {
+            let (transaction, abort, lockfile, git) = (&transaction, &abort, &lockfile, &git);
+
+            async move {
+                let result = async {
+                    if !dependency.manifest.is_registry() {
+                        let package = match dependency.manifest.path {
+                            Some(ref path) => {
+                                PackageStore::new(store.root().join(path)).release().await?
+                            }
+                            None => git.download(dependency.clone()).await?,
+                        };
+
+                        reporter.extract_started(&dependency);
+
+                        transaction.install(package).await?;
+
+                        reporter.extracted(&dependency);
+
+                        // Unpublished packages change freely and are not locked
+                        return Ok(None);
+                    }
+
+                    let locked = lockfile
+                        .and_then(|l| l.get(&dependency.package))
+                        .filter(|l| l.matches(&dependency));
+
+                    let cached = match (&options.cache, locked) {
+                        (Some(cache), Some(locked)) => {
+                            cache.get(&locked.digest).await.unwrap_or_else(|error| {
+                                tracing::warn!("failed to read {dependency} from the package cache: {error:#}");
+                                None
+                            })
+                        }
+                        _ => None,
+                    };
+
+                    let download = async {
+                        if options.policies.iter().any(|p| p.quarantine.is_some()) {
+                            if let Some(published_at) = registry.published_at(&dependency).await? {
+                                for policy in &options.policies {
+                                    policy.check_quarantine(
+                                        &dependency,
+                                        published_at,
+                                        &options.allow_quarantined,
+                                    )?;
+                                }
+                            }
+                        }
+
+                        registry.download(dependency.clone()).await
+                    };
+
+                    let package = match cached {
+                        Some(tgz) => {
+                            reporter.fetched(&dependency, tgz.size().await.unwrap_or_default(), true);
+
+                            Package::new(
+                                dependency.package.clone(),
+                                dependency.resolved_version()?.clone(),
+                                tgz,
+                            )
+                        }
+                        None => {
+                            reporter.download_started(&dependency);
+
+                            let package = tokio::select! {
+                                package = download => package?,
+                                _ = abort.cancelled() => eyre::bail!("Installation was cancelled"),
+                            };
+
+                            reporter.downloaded(&dependency);
+                            reporter.fetched(&dependency, package.tgz.size().await.unwrap_or_default(), false);
+
+                            package
+                        }
+                    };
+
+                    let digest = package.tgz.digest(algorithm).await?;
+
+                    if let Some(locked) = locked {
+                        let actual = match locked.digest.algorithm() == algorithm {
+                            true => digest.clone(),
+                            false => package.tgz.digest(locked.digest.algorithm()).await?,
+                        };
+
+                        if actual != locked.digest {
+                            return Err(eyre::eyre!(
+                                "Contents of {dependency} do not match {LOCKFILE}, expected {} but got {actual}",
+                                locked.digest
+                            ))
+                            .wrap_err(ErrorCode::IntegrityMismatch);
+                        }
+                    }
+
+                    // Installing verifies the digest published by the registry, so
+                    // only verified packages are cached
+                    let tgz = package.tgz.clone();
+
+                    reporter.extract_started(&dependency);
+
+                    transaction.install(package).await?;
+
+                    if let Some(ref cache) = options.cache {
+                        let mut digests = vec![&digest];
+                        digests.extend(locked.map(|l| &l.digest).filter(|d| *d != &digest));
+
+                        for digest in digests {
+                            if let Err(error) = cache.insert(digest, &tgz).await {
+                                tracing::warn!("failed to cache {dependency}: {error:#}");
+                            }
+                        }
+                    }
+
+                    reporter.extracted(&dependency);
+
+                    // Archived packages still work, users are only asked to migrate
+                    match registry
+                        .archival(&dependency.manifest.repository, &dependency.package)
+                        .await
+                    {
+                        Ok(Some(archival)) => tracing::warn!("{} is {archival}", dependency.package),
+                        Ok(None) => {}
+                        Err(error) => tracing::debug!(
+                            "failed to look up archival of {dependency}: {error:#}"
+                        ),
+                    }
+
+                    Ok(Some(LockedPackage {
+                        name: dependency.package.clone(),
+                        version: dependency.resolved_version()?.clone(),
+                        repository: dependency.manifest.repository.clone(),
+                        digest,
+                    }))
+                }
+                .await;
+
+                if let Err(ref error) = result {
+                    if !abort.is_cancelled() {
+                        reporter.failed(&dependency, error);
+                    }
+
+                    if !options.keep_going {
+                        abort.cancel();
+                    }
+                }
+
+                (dependency, result)
+            }
+        })
+        .buffer_unordered(options.jobs())
+        .collect()
+        .await;
+
+    let (succeeded, failed): (Vec<_>, Vec<_>) =
+        results.into_iter().partition(|(_, result)| result.is_ok());
+
+    let mut failures: Vec<(Dependency, eyre::Report)> = failed
+        .into_iter()
+        .filter_map(|(dependency, result)| Some(dependency).zip(result.err()))
+        .collect();
+
+    let (succeeded, locked): (Vec<Dependency>, Vec<Option<LockedPackage>>) = succeeded
+        .into_iter()
+        .filter_map(|(dependency, result)| Some(dependency).zip(result.ok()))
+        .unzip();
+
+    failures.extend(verify(store, &succeeded).await?);
+
+    if failures.is_empty() {
+        if let Err(error) = check_collisions(store, &resolved).await {
+            transaction
+                .rollback()
+                .await
+                .wrap_err("Failed to roll back installation")?;
+
+            tracing::info!(":: rolled back installation");
+
+            return Err(error);
+        }
+
+        transaction.commit();
+
+        prune_unreachable(store, &resolved).await?;
+
+        return Ok(locked.into_iter().flatten().collect());
+    }
+
+    if !options.keep_going {
+        // All installs were awaited above, so none can race the rollback
+        transaction
+            .rollback()
+            .await
+            .wrap_err("Failed to roll back installation")?;
+
+        tracing::info!(":: rolled back installation");
+
+        let (_, error) = failures.swap_remove(0);
+
+        return Err(error);
+    }
+
+    transaction.commit();
+
+    failures.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    eyre::bail!(
+        "Failed to install {} of {total} dependencies:{}",
+        failures.len(),
+        failures
+            .iter()
+            .map(|(dependency, error)| format!("\n  - {dependency}: {error:#}"))
+            .collect::<String>()
+    )
+}
+
+/// Replaces version ranges of dependencies with concrete versions
+///
+/// A version recorded in the lockfile is kept as long as it satisfies the
+/// requirement, otherwise the highest matching version of the registry that
+/// was not yanked is selected. Local and git packages are installed at the
+/// version of their manifest.
+async fn select_versions(
+    root: &Path,
+    dependencies: &[Dependency],
+    registry: &(impl Registry + Sync),
+    git: &GitRegistry,
+    lockfile: Option<&Lockfile>,
+    jobs: usize,
+) -> eyre::Result<Vec<Dependency>> {
+    let mut selected: Vec<Dependency> = stream::iter(dependencies)
+        .map(|dependency| async move {
+            if !dependency.manifest.is_registry() {
+                let version = match dependency.manifest.path {
+                    Some(ref path) => local_api(&root.join(path), dependency).await?.version,
+                    None => git.download(dependency.clone()).await?.version,
+                };
+
+                if let Some(ref requirement) = dependency.manifest.version {
+                    ensure!(
+                        requirement.matches(&version),
+                        "{dependency} requires {requirement}, but the package has version {version}"
+                    );
+                }
+
+                let mut dependency = dependency.clone();
+                dependency.manifest.version = Some(VersionRequirement::Exact(version));
+
+                return Ok(dependency);
+            }
+
+            let requirement = dependency
+                .manifest
+                .version
+                .clone()
+                .wrap_err_with(|| format!("{dependency} has no version requirement"))?;
+
+            let locked = lockfile
+                .and_then(|l| l.get(&dependency.package))
+                .filter(|l| l.repository == dependency.manifest.repository)
+                .map(|l| l.version.clone())
+                .filter(|version| requirement.matches(version));
+
+            // Yanked versions are only installed if they were locked before
+            let yanked = match locked {
+                Some(_) => vec![],
+                None => registry
+                    .yanked(&dependency.manifest.repository, &dependency.package)
+                    .await
+                    .wrap_err_with(|| {
+                        format!("Failed to look up yanked versions of {dependency}")
+                    })?,
+            };
+
+            if let VersionRequirement::Exact(ref version) = requirement {
+                ensure!(
+                    !yanked.contains(version),
+                    "{} {version} was yanked from {}, depend on another version instead",
+                    dependency.package,
+                    dependency.manifest.repository
+                );
+
+                return Ok(dependency.clone());
+            }
+
+            let version = match locked {
+                Some(version) => version,
+                None => {
+                    let versions: Vec<semver::Version> = registry
+                        .versions(&dependency.manifest.repository, &dependency.package)
+                        .await?
+                        .into_iter()
+                        .filter(|version| !yanked.contains(version))
+                        .collect();
+
+                    match requirement.select(&versions) {
+                        Some(version) => version.clone(),
+                        None if yanked.iter().any(|v| requirement.matches(v)) => {
+                            return Err(eyre::eyre!(
+                                "All versions of {} in {} matching {requirement} were yanked, available: {}",
+                                dependency.package,
+                                dependency.manifest.repository,
+                                display_versions(&versions)
+                            ))
+                            .wrap_err(ErrorCode::DownloadFailed)
+                        }
+                        None => {
+                            return Err(eyre::eyre!(
+                                "No version of {} in {} matches {requirement}, available: {}",
+                                dependency.package,
+                                dependency.manifest.repository,
+                                display_versions(&versions)
+                            ))
+                            .wrap_err(ErrorCode::DownloadFailed)
+                        }
+                    }
+                }
+            };
+
+            let mut dependency = dependency.clone();
+            dependency.manifest.version = Some(VersionRequirement::Exact(version));
+
+            Ok(dependency)
+        })
+        .buffer_unordered(jobs)
+        .try_collect()
+        .await?;
+
+    selected.sort();
+
+    Ok(selected)
+}
+
+/// Reads the api manifest of the local package of `dependency`
+async fn local_api(path: &Path, dependency: &Dependency) -> eyre::Result<ApiManifest> {
+    let manifest = Manifest::read(path)
+        .await
+        .wrap_err_with(|| format!("Failed to read local package {dependency}"))?;
+
+    let api = manifest
+        .api
+        .wrap_err_with(|| format!("{} is not an api package", path.display()))?;
+
+    ensure!(
+        api.name == dependency.package,
+        "{} contains package {}, not {}",
+        path.display(),
+        api.name,
+        dependency.package
+    );
+
+    Ok(api)
+}
+
+fn display_versions(versions: &[semver::Version]) -> String {
+    if versions.is_empty() {
+        return "none".to_owned();
+    }
+
+    let mut versions = versions.to_vec();
+    versions.sort();
+
+    versions
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Checks that every dependency is installed at its resolved version,
+/// returning the dependencies that are not
+async fn verify(
+    store: &PackageStore,
+    dependencies: &[Dependency],
+) -> eyre::Result<Vec<(Dependency, eyre::Report)>> {
+    let installed = store.installed().await?;
+
+    let failures = dependencies
+        .iter()
+        .filter_map(|dependency| {
+            let error = match installed.iter().find(|p| p.name == dependency.package) {
+                None => eyre::eyre!("{dependency} is missing after installation"),
+                Some(package)
+                    if !dependency
+                        .resolved_version()
+                        .is_ok_and(|v| v == &package.version) =>
+                {
+                    eyre::eyre!(
+                        "{dependency} was requested, but the package contains version {}",
+                        package.version
+                    )
+                }
+                Some(_) => return None,
+            };
+
+            Some((dependency.clone(), error))
+        })
+        .collect();
+
+    Ok(failures)
+}
+
+/// Fails if the api package and installed dependencies define the same
+/// fully qualified proto type more than once
+///
+/// Such packages can be installed together, but fail to compile for
+/// consumers, so the conflict is reported before anyone runs protoc. Only
+/// `dependencies` are checked, orphans are pruned after the installation.
+async fn check_collisions(store: &PackageStore, dependencies: &[Dependency]) -> eyre::Result<()> {
+    let mut packages = vec![];
+
+    if let Some(api) = Manifest::read(store.root()).await?.api {
+        packages.push((format!("{}@{}", api.name, api.version), store.api_path()));
+    }
+
+    for package in store.installed().await? {
+        if !dependencies.iter().any(|d| d.package == package.name) {
+            continue;
+        }
+
+        packages.push((
+            format!("{}@{}", package.name, package.version),
+            store.dep_path().join(package.name.as_package_dir()),
+        ));
+    }
+
+    let definitions = tokio::task::spawn_blocking(move || {
+        let mut definitions: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+        for (package, dir) in packages {
+            let protos = WalkDir::new(&dir)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().extension().is_some_and(|e| e == "proto"));
+
+            for proto in protos {
+                let contents = std::fs::read_to_string(proto.path())
+                    .wrap_err_with(|| format!("Failed to read {}", proto.path().display()))?;
+
+                for name in package::definitions(&contents) {
+                    definitions.entry(name).or_default().push(package.clone());
+                }
+            }
+        }
+
+        Ok::<_, eyre::Report>(definitions)
+    })
+    .await
+    .wrap_err("Failed to join scanning task")??;
+
+    let collisions: String = definitions
+        .iter()
+        .filter(|(_, packages)| packages.len() > 1)
+        .map(|(name, packages)| format!("\n  - {name} is defined by {}", packages.join(", ")))
+        .collect();
+
+    if collisions.is_empty() {
+        return Ok(());
+    }
+
+    Err(eyre::eyre!(
+        "Packages define the same proto types:{collisions}"
+    ))
+    .wrap_err(ErrorCode::SymbolCollision)
+}
+
+/// Uninstalls packages that are no longer reachable from the dependencies of
+/// a manifest, returning the number of bytes reclaimed
+pub async fn prune(store: &PackageStore, manifest: &Manifest) -> eyre::Result<u64> {
+    prune_unreachable(store, &resolver::resolve(manifest)?).await
+}
+
+async fn prune_unreachable(store: &PackageStore, dependencies: &[Dependency]) -> eyre::Result<u64> {
+    let mut reclaimed = 0;
+
+    for package in store.installed().await? {
+        if dependencies.iter().any(|d| d.package == package.name) {
+            continue;
+        }
+
+        reclaimed += store.disk_usage(&package.name).await?;
+
+        store.uninstall(&package.name).await?;
+
+        tracing::info!("- pruned {}@{}", package.name, package.version);
+    }
+
+    Ok(reclaimed)
+}
+
+/// Finds all projects in the directory tree below `root`
+///
+/// Hidden directories and dependency stores (any directory ending in
+/// `dep_path`, e.g. `proto/dep`) are skipped.
+pub fn discover(root: &Path, dep_path: &Path) -> eyre::Result<Vec<PathBuf>> {
+    let mut projects = vec![];
+
+    let walker = WalkDir::new(root).into_iter().filter_entry(|entry| {
+        let hidden = entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.');
+
+        !hidden && !entry.path().ends_with(dep_path)
+    });
+
+    for entry in walker {
+        let entry = entry.wrap_err("Failed to search for projects")?;
+
+        if entry.file_type().is_file() && entry.file_name() == MANIFEST_FILE {
+            projects.extend(entry.path().parent().map(Path::to_path_buf));
+        }
+    }
+
+    projects.sort();
+
+    Ok(projects)
+}
+
+/// Installs the dependencies of several projects concurrently
+///
+/// Packages required by more than one project are only downloaded once. All
+/// projects are attempted, the results are returned in the order of `stores`.
+/// Selected groups only apply to the projects having them.
+pub async fn install_all(
+    stores: &[PackageStore],
+    registry: &(impl Registry + Sync),
+    options: &InstallOptions,
+    reporter: &dyn InstallReporter,
+    cancel: &CancellationToken,
+) -> Vec<eyre::Result<()>> {
+    let registry = CachedRegistry::new(registry);
+
+    stream::iter(stores)
+        .map(|store| {
+            let registry = &registry;
+
+            async move {
+                let manifest = Manifest::read(store.root()).await?;
+
+                install_project(store, &manifest, registry, options, reporter, cancel).await
+            }
+        })
+        .buffered(PROJECT_CONCURRENCY.min(options.jobs()))
+        .collect()
+        .await
+}
+
+/// Downloads locked packages and checks that the registry still serves the
+/// archives recorded in the lockfile
+pub async fn verify_locked(
+    packages: &[LockedPackage],
+    registry: &(impl Registry + Sync),
+) -> eyre::Result<()> {
+    stream::iter(packages)
+        .map(|locked| async move {
+            let package = registry
+                .download(Dependency::new(
+                    locked.repository.clone(),
+                    locked.name.clone(),
+                    VersionRequirement::Exact(locked.version.clone()),
+                ))
+                .await
+                .wrap_err_with(|| format!("Failed to download {locked}"))?;
+
+            let actual = package.tgz.digest(locked.digest.algorithm()).await?;
+
+            if actual != locked.digest {
+                return Err(eyre::eyre!(
+                    "Contents of {locked} do not match {LOCKFILE}, expected {} but got {actual}",
+                    locked.digest
+                ))
+                .wrap_err(ErrorCode::IntegrityMismatch);
+            }
+
+            Ok(())
+        })
+        .buffer_unordered(INSTALL_CONCURRENCY)
+        .try_collect()
+        .await
+}
+
+/// Replaces the version ranges of registry dependencies of the project at
+/// `root` with concrete versions, as [`install`] selects them
+///
+/// Versions locked in the lockfile at `lock_root` (the project or workspace
+/// root) are kept while they satisfy the requirement, otherwise the highest
+/// matching version published to `registry` is selected.
+pub async fn resolve_versions(
+    root: &Path,
+    lock_root: &Path,
+    dependencies: &[Dependency],
+    registry: &(impl Registry + Sync),
+) -> eyre::Result<Vec<Dependency>> {
+    let lockfile = read_lockfile(lock_root).await?;
+
+    select_versions(
+        root,
+        dependencies,
+        registry,
+        &GitRegistry::new(),
+        lockfile.as_ref(),
+        INSTALL_CONCURRENCY,
+    )
+    .await
+}
+
+/// Copies packages from one registry to another
+///
+/// Every package is published to the repository it was downloaded from, the
+/// archives are transferred unchanged so versions and digests are preserved.
+/// The dependencies have to require exact versions, see
+/// [`resolve_versions`].
+pub async fn mirror(
+    dependencies: &[Dependency],
+    from: &(impl Registry + Sync),
+    to: &(impl Registry + Sync),
+    cancel: &CancellationToken,
+) -> eyre::Result<()> {
+    for dependency in dependencies {
+        dependency
+            .resolved_version()
+            .wrap_err_with(|| format!("Unable to mirror {dependency}, select a version first"))?;
+    }
+
+    let mirror = stream::iter(dependencies)
+        .map(|dependency| async move {
+            let package = from.download(dependency.clone()).await?;
+
+            to.publish(package, dependency.manifest.repository.clone())
+                .await
+                .map(|_| ())
+                .wrap_err_with(|| format!("Failed to mirror {dependency}"))
+        })
+        .buffer_unordered(INSTALL_CONCURRENCY)
+        .try_collect();
+
+    tokio::select! {
+        result = mirror => result,
+        _ = cancel.cancelled() => eyre::bail!("Mirroring was cancelled"),
+    }
+}
+
+/// Packages the api of a project and publishes it to a registry
+///
+/// The metadata of the package has to satisfy the publish rules of the
+/// policies and their hooks are asked to approve the package before it is
+/// uploaded. Cancelling the token aborts packaging or an in-flight upload.
+///
+/// Policy hooks are queried with `client`, see [`Policy::evaluate`].
+pub async fn publish(
+    store: &PackageStore,
+    registry: &(impl Registry + Sync),
+    repository: String,
+    policies: &[Policy],
+    client: &reqwest::Client,
+    cancel: &CancellationToken,
+) -> eyre::Result<Receipt> {
+    publish_release(store, None, registry, repository, policies, client, cancel).await
+}
+
+/// Packages a `[[package]]` entry of a project and publishes it to a
+/// registry, like [`publish`] does for the api package
+pub async fn publish_package(
+    store: &PackageStore,
+    package: &PackageId,
+    registry: &(impl Registry + Sync),
+    repository: String,
+    policies: &[Policy],
+    client: &reqwest::Client,
+    cancel: &CancellationToken,
+) -> eyre::Result<Receipt> {
+    publish_release(
+        store,
+        Some(package),
+        registry,
+        repository,
+        policies,
+        client,
+        cancel,
+    )
+    .await
+}
+
+/// Publishes the api package, or the `[[package]]` entry named `package`
+async fn publish_release(
+    store: &PackageStore,
+    package: Option<&PackageId>,
+    registry: &(impl Registry + Sync),
+    repository: String,
+    policies: &[Policy],
+    client: &reqwest::Client,
+    cancel: &CancellationToken,
+) -> eyre::Result<Receipt> {
+    let publish = async {
+        let mut manifest = RawManifest::from(Manifest::read(store.root()).await?);
+
+        if let Some(package) = package {
+            manifest.api = manifest
+                .package
+                .iter()
+                .flatten()
+                .find(|p| &p.api.name == package)
+                .map(|p| p.api.clone());
+        }
+
+        manifest.package = None;
+
+        if let Some(ref api) = manifest.api {
+            for policy in policies {
+                policy.check_publish(api)?;
+            }
+        }
+
+        let release = match package {
+            Some(package) => store.release_package(package).await?,
+            None => store.release().await?,
+        };
+
+        if policies.iter().any(|p| !p.hooks.is_empty()) {
+            let operation = Operation::Publish {
+                manifest,
+                repository: repository.clone(),
+                files: release
+                    .tgz
+                    .files()
+                    .await?
+                    .into_iter()
+                    .map(|file| file.path)
+                    .collect(),
+            };
+
+            for policy in policies {
+                policy.evaluate(&operation, client).await?;
+            }
+        }
+
+        if let Some(policy) = policies.iter().find(|p| p.publish.deny_breaking) {
+            check_breaking(store, package, registry, &repository, policy).await?;
+        }
+
+        registry.publish(release, repository).await
+    };
+
+    tokio::select! {
+        result = publish => result,
+        _ = cancel.cancelled() => eyre::bail!("Publishing was cancelled"),
+    }
+}
+
+/// Blocks publishing breaking changes of the api package without a major
+/// version bump, as required by `policy`
+async fn check_breaking(
+    store: &PackageStore,
+    package: Option<&PackageId>,
+    registry: &(impl Registry + Sync),
+    repository: &str,
+    policy: &Policy,
+) -> eyre::Result<()> {
+    if let Some(package) = package {
+        tracing::warn!("! breaking changes are only checked for api packages, not {package}");
+        return Ok(());
+    }
+
+    #[cfg(feature = "reflect")]
+    {
+        let Some(report) = breaking(store, registry, repository, None).await? else {
+            return Ok(());
+        };
+
+        if report.is_announced() {
+            return Ok(());
+        }
+
+        Err(eyre::eyre!(
+            "Publishing {}@{} is blocked by {policy}: it breaks {} without a major version bump:{}",
+            report.package,
+            report.version,
+            report.previous,
+            report
+                .changes
+                .iter()
+                .map(|change| format!("\n  - {change}"))
+                .collect::<String>()
+        ))
+        .wrap_err(ErrorCode::PolicyViolation)
+    }
+
+    #[cfg(not(feature = "reflect"))]
+    {
+        let _ = (store, registry, repository);
+
+        Err(eyre::eyre!(
+            "{policy} denies breaking changes, which can only be detected with the reflect feature"
+        ))
+        .wrap_err(ErrorCode::PolicyViolation)
+    }
+}
+
+/// Compares the api package of a project with a published release
+///
+/// The release is `against` if given, and the highest version published
+/// before the local one otherwise, skipping yanked releases. Returns `None`
+/// if nothing was published before.
+#[cfg(feature = "reflect")]
+pub async fn breaking(
+    store: &PackageStore,
+    registry: &(impl Registry + Sync),
+    repository: &str,
+    against: Option<semver::Version>,
+) -> eyre::Result<Option<breaking::Report>> {
+    let manifest = Manifest::read(store.root()).await?;
+
+    let api = manifest
+        .api
+        .wrap_err("Only projects with an [api] section can be checked for breaking changes")?;
+
+    let version = api.version;
+
+    let previous = match against {
+        Some(against) => against,
+        None => {
+            let yanked = registry.yanked(repository, &api.name).await?;
+
+            let previous = registry
+                .versions(repository, &api.name)
+                .await?
+                .into_iter()
+                .filter(|v| v < &version && !yanked.contains(v))
+                .max();
+
+            match previous {
+                Some(previous) => previous,
+                None => return Ok(None),
+            }
+        }
+    };
+
+    let dependency = Dependency::new(
+        repository.to_owned(),
+        api.name.clone(),
+        VersionRequirement::Exact(previous.clone()),
+    );
+
+    let release = registry
+        .download(dependency)
+        .await
+        .wrap_err_with(|| format!("Failed to download {}@{previous}", api.name))?;
+
+    let old = breaking::Api::release(store, &release).await?;
+    let new = breaking::Api::local(store).await?;
+
+    Ok(Some(breaking::Report {
+        package: api.name,
+        previous,
+        version,
+        changes: breaking::compare(&old, &new),
+    }))
+}
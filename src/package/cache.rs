@@ -0,0 +1,147 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::path::{Path, PathBuf};
+
+use eyre::Context;
+use tokio::fs;
+
+use super::{Digest, PackageArchive};
+
+/// User level cache of package archives, shared by all projects
+///
+/// Archives are addressed by their digest and stored as
+/// `<algorithm>/<hex>.tgz`, so the same contents are only downloaded once no
+/// matter which project, repository or registry requires them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageCache {
+    dir: PathBuf,
+}
+
+/// Number and size of the archives in a [`PackageCache`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of cached archives
+    pub packages: usize,
+    /// Combined size of the cached archives in bytes
+    pub size: u64,
+}
+
+impl PackageCache {
+    /// Directory of the package cache within the buffrs cache directory
+    pub const DIR: &'static str = "packages";
+
+    /// Creates a cache storing archives in `dir`
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Opens the cache of the current user, see [`crate::config::cache_dir`]
+    pub fn open() -> eyre::Result<Self> {
+        Ok(Self::new(crate::config::cache_dir()?.join(Self::DIR)))
+    }
+
+    /// Directory the archives are stored in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, digest: &Digest) -> PathBuf {
+        self.dir
+            .join(digest.algorithm().name())
+            .join(format!("{}.tgz", digest.to_hex()))
+    }
+
+    /// Copies the archive with the given digest out of the cache
+    ///
+    /// The copy is verified against the digest. Entries that were corrupted
+    /// on disk are removed and reported as missing.
+    pub async fn get(&self, digest: &Digest) -> eyre::Result<Option<PackageArchive>> {
+        let path = self.path(digest);
+
+        if !fs::try_exists(&path).await.unwrap_or(false) {
+            return Ok(None);
+        }
+
+        let archive = PackageArchive::new()?;
+
+        fs::copy(&path, archive.path())
+            .await
+            .wrap_err_with(|| format!("Failed to read cached package {digest}"))?;
+
+        if &archive.digest(digest.algorithm()).await? != digest {
+            fs::remove_file(&path)
+                .await
+                .wrap_err_with(|| format!("Failed to remove corrupted package {digest}"))?;
+
+            return Ok(None);
+        }
+
+        Ok(Some(archive))
+    }
+
+    /// Stores an archive under its digest, unless it is already cached
+    pub async fn insert(&self, digest: &Digest, archive: &PackageArchive) -> eyre::Result<()> {
+        let path = self.path(digest);
+
+        if fs::try_exists(&path).await.unwrap_or(false) {
+            return Ok(());
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .wrap_err("Failed to create package cache")?;
+        }
+
+        // Copy to a sibling file first, so readers never see partial archives
+        let partial = path.with_extension("tgz.partial");
+
+        fs::copy(archive.path(), &partial)
+            .await
+            .wrap_err_with(|| format!("Failed to cache package {digest}"))?;
+
+        fs::rename(&partial, &path)
+            .await
+            .wrap_err_with(|| format!("Failed to cache package {digest}"))
+    }
+
+    /// Counts the cached archives and their size
+    pub async fn stats(&self) -> eyre::Result<CacheStats> {
+        let dir = self.dir.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut stats = CacheStats::default();
+
+            let archives = walkdir::WalkDir::new(dir)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .filter(|e| e.path().extension().is_some_and(|e| e == "tgz"));
+
+            for archive in archives {
+                stats.packages += 1;
+                stats.size += archive
+                    .metadata()
+                    .wrap_err("Failed to read package cache")?
+                    .len();
+            }
+
+            Ok(stats)
+        })
+        .await
+        .wrap_err("Failed to join cache task")?
+    }
+
+    /// Removes all cached archives, returning what was removed
+    pub async fn clean(&self) -> eyre::Result<CacheStats> {
+        let stats = self.stats().await?;
+
+        if fs::try_exists(&self.dir).await.unwrap_or(false) {
+            fs::remove_dir_all(&self.dir)
+                .await
+                .wrap_err_with(|| format!("Failed to remove {}", self.dir.display()))?;
+        }
+
+        Ok(stats)
+    }
+}
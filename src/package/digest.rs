@@ -0,0 +1,173 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{fmt, io, str::FromStr};
+
+use eyre::{ensure, Context, ContextCompat};
+use serde::{Deserialize, Serialize};
+use sha2::Digest as _;
+
+/// Hash algorithms used to identify and verify package contents
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestAlgorithm {
+    /// SHA-256, the default
+    #[default]
+    Sha256,
+    /// SHA-512
+    Sha512,
+}
+
+impl DigestAlgorithm {
+    /// Name of the algorithm as used in digests, e.g. `sha256`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+        }
+    }
+
+    /// Creates an incremental hasher for this algorithm
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            Self::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            Self::Sha512 => Hasher::Sha512(sha2::Sha512::new()),
+        }
+    }
+
+    /// Computes the digest of `bytes`
+    pub fn digest(&self, bytes: &[u8]) -> Digest {
+        let mut hasher = self.hasher();
+        hasher.update(bytes);
+        hasher.finalize()
+    }
+}
+
+impl FromStr for DigestAlgorithm {
+    type Err = eyre::Report;
+
+    fn from_str(name: &str) -> eyre::Result<Self> {
+        match name {
+            "sha256" => Ok(Self::Sha256),
+            "sha512" => Ok(Self::Sha512),
+            name => eyre::bail!("Unsupported digest algorithm {name}"),
+        }
+    }
+}
+
+impl fmt::Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Incremental hasher, usable as [`io::Write`] sink
+#[derive(Clone)]
+pub enum Hasher {
+    /// SHA-256 state
+    Sha256(sha2::Sha256),
+    /// SHA-512 state
+    Sha512(sha2::Sha512),
+}
+
+impl Hasher {
+    /// Feeds `bytes` into the hasher
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(bytes),
+            Self::Sha512(hasher) => hasher.update(bytes),
+        }
+    }
+
+    /// Completes the digest
+    pub fn finalize(self) -> Digest {
+        match self {
+            Self::Sha256(hasher) => Digest {
+                algorithm: DigestAlgorithm::Sha256,
+                value: hasher.finalize().to_vec(),
+            },
+            Self::Sha512(hasher) => Digest {
+                algorithm: DigestAlgorithm::Sha512,
+                value: hasher.finalize().to_vec(),
+            },
+        }
+    }
+}
+
+impl io::Write for Hasher {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.update(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A digest tagged with its algorithm, written as `<algorithm>:<hex>`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Digest {
+    algorithm: DigestAlgorithm,
+    value: Vec<u8>,
+}
+
+impl Digest {
+    /// Algorithm the digest was computed with
+    pub fn algorithm(&self) -> DigestAlgorithm {
+        self.algorithm
+    }
+
+    /// Raw digest bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.value
+    }
+
+    /// Hex encoded digest without the algorithm prefix
+    pub fn to_hex(&self) -> String {
+        hex::encode(&self.value)
+    }
+}
+
+impl FromStr for Digest {
+    type Err = eyre::Report;
+
+    fn from_str(digest: &str) -> eyre::Result<Self> {
+        let (algorithm, value) = digest
+            .split_once(':')
+            .wrap_err_with(|| format!("Digest {digest} lacks an algorithm prefix"))?;
+
+        let algorithm: DigestAlgorithm = algorithm.parse()?;
+
+        let value = hex::decode(value).wrap_err_with(|| format!("Invalid digest {digest}"))?;
+
+        let expected = algorithm.hasher().finalize().value.len();
+
+        ensure!(
+            value.len() == expected,
+            "Invalid {algorithm} digest {digest}, expected {expected} bytes"
+        );
+
+        Ok(Self { algorithm, value })
+    }
+}
+
+impl TryFrom<String> for Digest {
+    type Error = eyre::Report;
+
+    fn try_from(digest: String) -> eyre::Result<Self> {
+        digest.parse()
+    }
+}
+
+impl From<Digest> for String {
+    fn from(digest: Digest) -> Self {
+        digest.to_string()
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm, self.to_hex())
+    }
+}
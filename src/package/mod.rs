@@ -0,0 +1,334 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    collections::HashSet,
+    fmt::{self, Formatter},
+    io::{Read, Write},
+    ops::Deref,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use eyre::{ensure, Context, ContextCompat};
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::{RawManifest, MANIFEST_FILE};
+
+#[cfg(feature = "io")]
+mod cache;
+mod digest;
+#[cfg(feature = "io")]
+mod store;
+mod symbols;
+
+#[cfg(feature = "io")]
+pub use cache::{CacheStats, PackageCache};
+pub use digest::{Digest, DigestAlgorithm, Hasher};
+#[cfg(feature = "io")]
+pub use store::{Package, PackageArchive, PackageStore, Transaction};
+pub use symbols::{declared_package, definitions};
+
+/// Readme of a project, shipped at the root of its packages
+pub const README_FILE: &str = "README.md";
+
+/// Encodes a manifest and a set of proto files as a `tar.gz` package
+///
+/// This does not touch the file system, so it can be used with in-memory
+/// inputs, for example in benchmarks or other tools.
+pub fn pack<W: Write>(
+    writer: W,
+    manifest: &RawManifest,
+    protos: impl IntoIterator<Item = eyre::Result<PackageFile>>,
+) -> eyre::Result<W> {
+    let manifest =
+        toml::to_string_pretty(manifest).wrap_err("Failed to encode release manifest")?;
+
+    let encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+
+    let mut archive = tar::Builder::new(encoder);
+
+    let mut append = |path: &Path, contents: &[u8]| -> eyre::Result<()> {
+        let mut header = tar::Header::new_gnu();
+
+        header.set_size(contents.len().try_into().wrap_err("Failed to pack tar")?);
+        header.set_mode(0o644);
+
+        archive
+            .append_data(&mut header, path, contents)
+            .wrap_err(format!("Failed to add {} to release", path.display()))
+    };
+
+    let mut paths = PackagePaths::default();
+
+    for proto in protos {
+        let proto = proto?;
+        paths.insert(&proto.path)?;
+        append(&proto.path, &proto.contents)?;
+    }
+
+    append(Path::new(MANIFEST_FILE), manifest.as_bytes())?;
+
+    archive
+        .into_inner()
+        .wrap_err("Failed to pack tar")?
+        .finish()
+        .wrap_err("Failed to compress release")
+}
+
+/// Decodes all files contained in a `tar.gz` package
+///
+/// Like [`pack`] this operates purely on the given reader.
+pub fn unpack<R: Read>(reader: R) -> eyre::Result<Vec<PackageFile>> {
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(reader));
+    let mut paths = PackagePaths::default();
+
+    archive
+        .entries()
+        .wrap_err("Failed to unpack tar")?
+        .map(|entry| {
+            let mut entry = entry.wrap_err("Failed to unpack tar")?;
+
+            let path = entry
+                .path()
+                .wrap_err("Invalid file path in package")?
+                .into_owned();
+
+            paths.insert(&path)?;
+
+            let mut contents = Vec::new();
+
+            entry
+                .read_to_end(&mut contents)
+                .wrap_err(format!("Failed to read {} from package", path.display()))?;
+
+            Ok(PackageFile { path, contents })
+        })
+        .collect()
+}
+
+/// Names reserved by Windows, regardless of their extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Checks that a file path inside of a package is portable
+///
+/// Packages are installed on every platform, so paths are restricted to what
+/// all of them support: relative paths without `..`, no Windows reserved
+/// device names (`CON`, `NUL`, `COM1`, ...) and none of the characters
+/// `<>:"|?*\`, control characters or trailing dots and spaces.
+pub fn validate_path(path: &Path) -> eyre::Result<()> {
+    let display = path.display();
+
+    ensure!(
+        path.components().next().is_some(),
+        "Empty file path in package"
+    );
+
+    for component in path.components() {
+        let name = match component {
+            Component::Normal(name) => name,
+            Component::CurDir => continue,
+            _ => eyre::bail!(
+                "File path {display} in package must be relative and must not contain `..`"
+            ),
+        };
+
+        let name = name
+            .to_str()
+            .wrap_err_with(|| format!("File path {display} in package is not valid unicode"))?;
+
+        ensure!(
+            !name
+                .chars()
+                .any(|c| c.is_control() || r#"<>:"|?*\"#.contains(c)),
+            "File path {display} in package contains characters not supported on Windows"
+        );
+
+        ensure!(
+            !name.ends_with(['.', ' ']),
+            "File path {display} in package ends with a dot or space, which is not supported on Windows"
+        );
+
+        let stem = name.split('.').next().unwrap_or(name).trim_end();
+
+        ensure!(
+            !RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)),
+            "File path {display} in package uses a name reserved on Windows"
+        );
+    }
+
+    Ok(())
+}
+
+/// Tracks the file paths of a package to detect conflicts
+///
+/// Files whose paths only differ in case would overwrite each other on case
+/// insensitive file systems, as used by Windows and macOS.
+#[derive(Default)]
+pub(crate) struct PackagePaths(HashSet<String>);
+
+impl PackagePaths {
+    /// Validates a path and records it
+    pub(crate) fn insert(&mut self, path: &Path) -> eyre::Result<()> {
+        validate_path(path)?;
+
+        ensure!(
+            self.0.insert(path.to_string_lossy().to_lowercase()),
+            "File path {} in package conflicts with another file on case-insensitive file systems",
+            path.display()
+        );
+
+        Ok(())
+    }
+}
+
+/// A single file contained in a package
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackageFile {
+    /// Path of the file inside of the package
+    pub path: PathBuf,
+    /// Contents of the file
+    pub contents: Vec<u8>,
+}
+
+/// A `buffrs` package id for parsing and type safety
+#[derive(Clone, Hash, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
+pub struct PackageId(String);
+
+impl PackageId {
+    #[cfg(feature = "io")]
+    pub(crate) fn as_package_dir(&self) -> String {
+        self.0.replace('-', "_")
+    }
+}
+
+impl PackageId {
+    /// Minimum length of package ids
+    pub const MIN_LENGTH: usize = 3;
+    /// Maximum length of package ids
+    pub const MAX_LENGTH: usize = 64;
+    /// Names that clash with the import paths of well known protos
+    pub const RESERVED: &'static [&'static str] = &["google", "grpc"];
+}
+
+impl TryFrom<String> for PackageId {
+    type Error = eyre::Error;
+
+    /// Validates a package id, normalizing it to lowercase
+    fn try_from(value: String) -> eyre::Result<Self> {
+        let value = value.trim().to_ascii_lowercase();
+
+        ensure!(
+            (Self::MIN_LENGTH..=Self::MAX_LENGTH).contains(&value.len()),
+            "Invalid package id {value:?}, package ids must be {} to {} chars long",
+            Self::MIN_LENGTH,
+            Self::MAX_LENGTH
+        );
+
+        if let Some(invalid) = value
+            .chars()
+            .find(|c| !(c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '-'))
+        {
+            let hint = match invalid {
+                '_' => ", use dashes instead of underscores",
+                _ => "",
+            };
+
+            eyre::bail!(
+                "Invalid package id {value:?}, package ids can only consist of lowercase letters, digits and dashes, found {invalid:?}{hint}"
+            );
+        }
+
+        ensure!(
+            value.starts_with(|c: char| c.is_ascii_lowercase()),
+            "Invalid package id {value:?}, package ids must begin with a letter"
+        );
+
+        ensure!(
+            !value.ends_with('-') && !value.contains("--"),
+            "Invalid package id {value:?}, dashes have to separate words"
+        );
+
+        ensure!(
+            !Self::RESERVED.contains(&value.as_str()),
+            "Invalid package id {value:?}, the name is reserved as it clashes with the imports of well known protos"
+        );
+
+        Ok(Self(value))
+    }
+}
+
+impl TryFrom<&str> for PackageId {
+    type Error = eyre::Error;
+
+    fn try_from(value: &str) -> eyre::Result<Self> {
+        Self::try_from(value.to_string())
+    }
+}
+
+impl TryFrom<&String> for PackageId {
+    type Error = eyre::Error;
+
+    fn try_from(value: &String) -> eyre::Result<Self> {
+        Self::try_from(value.to_owned())
+    }
+}
+
+impl FromStr for PackageId {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_from(s)
+    }
+}
+
+impl From<PackageId> for String {
+    fn from(s: PackageId) -> Self {
+        s.to_string()
+    }
+}
+
+impl Deref for PackageId {
+    type Target = String;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for PackageId {
+    fn schema_name() -> String {
+        "PackageId".to_owned()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            string: Some(Box::new(schemars::schema::StringValidation {
+                min_length: Some(Self::MIN_LENGTH as u32),
+                max_length: Some(Self::MAX_LENGTH as u32),
+                pattern: Some("^[a-z][a-z0-9]*(-[a-z0-9]+)*$".to_owned()),
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+impl fmt::Display for PackageId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl fmt::Debug for PackageId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PackageId")
+            .field(&format!("{self}"))
+            .finish()
+    }
+}
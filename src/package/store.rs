@@ -0,0 +1,716 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use eyre::{ensure, Context, ContextCompat};
+use tempfile::TempPath;
+use tokio::fs;
+use walkdir::WalkDir;
+
+use super::{
+    pack, unpack, Digest, DigestAlgorithm, PackageFile, PackageId, PackagePaths, README_FILE,
+};
+use crate::{
+    error::ErrorCode,
+    manifest::{ApiManifest, Manifest, RawManifest},
+};
+
+/// IO abstraction layer over the `buffrs` package store of a project
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageStore {
+    root: PathBuf,
+    dep_path: PathBuf,
+}
+
+impl PackageStore {
+    /// Path to the proto directory
+    pub const PROTO_PATH: &str = "proto";
+    /// Path to the api directory
+    pub const PROTO_API_PATH: &str = "proto/api";
+    /// Path to the dependency store
+    pub const PROTO_DEP_PATH: &str = "proto/dep";
+
+    /// Opens the package store of the project located at `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+
+        Self {
+            dep_path: root.join(Self::PROTO_DEP_PATH),
+            root,
+        }
+    }
+
+    /// Installs dependencies into `path`, relative to the project root,
+    /// instead of [`PackageStore::PROTO_DEP_PATH`]
+    pub fn with_dep_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.dep_path = self.root.join(path);
+        self
+    }
+
+    /// Root directory of the project this store belongs to
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Directory containing the api package of the project
+    pub fn api_path(&self) -> PathBuf {
+        self.root.join(Self::PROTO_API_PATH)
+    }
+
+    /// Directory containing the installed dependencies of the project
+    pub fn dep_path(&self) -> PathBuf {
+        self.dep_path.clone()
+    }
+
+    /// Existing directories protos are resolved relative to: the api package
+    /// and the installed dependencies
+    pub fn include_paths(&self) -> Vec<PathBuf> {
+        [self.api_path(), self.dep_path()]
+            .into_iter()
+            .filter(|path| path.is_dir())
+            .collect()
+    }
+
+    /// Paths of all protos in the api package and installed dependencies
+    pub fn proto_files(&self) -> Vec<PathBuf> {
+        self.include_paths()
+            .iter()
+            .flat_map(WalkDir::new)
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().and_then(|e| e.to_str()) == Some("proto"))
+            .map(|entry| entry.into_path())
+            .collect()
+    }
+
+    /// Creates the expected directory structure for `buffrs`
+    pub async fn create(&self, api: bool) -> eyre::Result<()> {
+        if api {
+            fs::create_dir_all(self.api_path())
+                .await
+                .wrap_err(eyre::eyre!(
+                    "Failed to create api folder {}",
+                    self.api_path().display()
+                ))?;
+        }
+
+        fs::create_dir_all(self.dep_path())
+            .await
+            .wrap_err(eyre::eyre!(
+                "Failed to create dependency folder {}",
+                self.dep_path().display()
+            ))
+    }
+
+    /// Clears all packages from the file system
+    pub async fn clear(&self) -> eyre::Result<()> {
+        fs::remove_dir_all(self.dep_path())
+            .await
+            .wrap_err("Failed to uninstall dependencies")
+    }
+
+    /// Installs a package into the local file system
+    ///
+    /// The package is unpacked into a staging directory first and moved into
+    /// place afterwards, so an aborted install never leaves a partially
+    /// extracted package behind.
+    pub async fn install(&self, package: Package) -> eyre::Result<()> {
+        self.swap_in(package, None).await.map(|_| ())
+    }
+
+    /// Starts a transaction whose installations can be rolled back together
+    pub async fn transaction(&self) -> eyre::Result<Transaction<'_>> {
+        fs::create_dir_all(self.dep_path())
+            .await
+            .wrap_err("Failed to install dependencies")?;
+
+        let backup = tempfile::Builder::new()
+            .prefix(".backup-")
+            .tempdir_in(self.dep_path())
+            .wrap_err("Failed to start installation")?;
+
+        Ok(Transaction {
+            store: self,
+            backup: Some(backup),
+            installed: Mutex::default(),
+        })
+    }
+
+    /// Unpacks a package and moves it into place, returning the directory it
+    /// was installed to
+    ///
+    /// A previously installed version is moved into `backup` if given, and
+    /// deleted otherwise.
+    async fn swap_in(&self, package: Package, backup: Option<PathBuf>) -> eyre::Result<PathBuf> {
+        let dep_path = self.dep_path();
+        let pkg_dir = dep_path.join(package.name.as_package_dir());
+
+        fs::create_dir_all(&dep_path)
+            .await
+            .wrap_err("Failed to install dependencies")?;
+
+        package.verify().await?;
+
+        let Package {
+            name, version, tgz, ..
+        } = package;
+
+        // Decompression and unpacking are blocking, run them off the async
+        // executor so they overlap with downloads of other packages. This
+        // also ensures that dropping the install future can not interrupt
+        // the swap of the staging directory into place.
+        tokio::task::spawn_blocking({
+            let name = name.clone();
+            let pkg_dir = pkg_dir.clone();
+
+            move || -> eyre::Result<()> {
+                let dep_path = long_path(dep_path)?;
+
+                let staging = tempfile::Builder::new()
+                    .prefix(".staging-")
+                    .tempdir_in(dep_path)
+                    .wrap_err("Failed to install dependencies")?;
+
+                let tgz = std::fs::File::open(tgz.path()).wrap_err("Failed to open package")?;
+
+                let gz = flate2::read::GzDecoder::new(BufReader::new(tgz));
+
+                // Entries are validated one by one instead of unpacking the
+                // archive at once, so packages built on other platforms can
+                // not silently clobber files or fail halfway on Windows.
+                let mut archive = tar::Archive::new(gz);
+                let mut paths = PackagePaths::default();
+
+                for entry in archive
+                    .entries()
+                    .wrap_err(format!("Failed to unpack tar of {name}"))?
+                {
+                    let mut entry = entry.wrap_err(format!("Failed to unpack tar of {name}"))?;
+
+                    let path = entry
+                        .path()
+                        .wrap_err(format!("Invalid file path in package {name}"))?
+                        .into_owned();
+
+                    paths
+                        .insert(&path)
+                        .wrap_err(format!("Failed to unpack tar of {name}"))?;
+
+                    entry
+                        .unpack_in(staging.path())
+                        .wrap_err(format!("Failed to unpack {} of {name}", path.display()))?;
+                }
+
+                match backup {
+                    Some(backup) if pkg_dir.exists() => std::fs::rename(&pkg_dir, backup)
+                        .wrap_err(format!("Failed to back up {name}"))?,
+                    _ => {
+                        std::fs::remove_dir_all(&pkg_dir).ok();
+                    }
+                }
+
+                std::fs::rename(staging.path(), &pkg_dir)
+                    .wrap_err(format!("Failed to install {name}"))
+            }
+        })
+        .await
+        .wrap_err("Failed to join extraction task")??;
+
+        tracing::info!("+ installed {name}@{version}");
+
+        Ok(pkg_dir)
+    }
+
+    /// Uninstalls a package from the local file system
+    pub async fn uninstall(&self, package: &PackageId) -> eyre::Result<()> {
+        let pkg_dir = self.dep_path().join(package.as_package_dir());
+
+        fs::remove_dir_all(&pkg_dir)
+            .await
+            .wrap_err("Failed to uninstall {dependency}")
+    }
+
+    /// Size in bytes of the files of an installed package
+    pub async fn disk_usage(&self, package: &PackageId) -> eyre::Result<u64> {
+        let pkg_dir = self.dep_path().join(package.as_package_dir());
+
+        tokio::task::spawn_blocking(move || {
+            WalkDir::new(pkg_dir)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .map(|e| e.metadata().map(|m| m.len()))
+                .sum::<Result<u64, _>>()
+                .wrap_err("Failed to measure package size")
+        })
+        .await
+        .wrap_err("Failed to join measuring task")?
+    }
+
+    /// Lists the packages currently installed in the store
+    pub async fn installed(&self) -> eyre::Result<Vec<ApiManifest>> {
+        let mut installed = vec![];
+
+        if !fs::try_exists(self.dep_path()).await.unwrap_or(false) {
+            return Ok(installed);
+        }
+
+        let mut entries = fs::read_dir(self.dep_path())
+            .await
+            .wrap_err("Failed to list installed packages")?;
+
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .wrap_err("Failed to list installed packages")?
+        {
+            let Ok(manifest) = Manifest::read(&entry.path()).await else {
+                continue;
+            };
+
+            installed.extend(manifest.api);
+        }
+
+        installed.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(installed)
+    }
+
+    /// Packages a release from the local file system state
+    ///
+    /// Protos in the directories of `[[package]]` entries belong to those
+    /// packages and are left out.
+    pub async fn release(&self) -> eyre::Result<Package> {
+        let mut manifest = RawManifest::from(Manifest::read(&self.root).await?);
+        manifest.dependencies = None;
+        manifest.groups = None;
+
+        let exclude: Vec<PathBuf> = manifest
+            .package
+            .take()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|p| std::fs::canonicalize(self.root.join(&p.path)).ok())
+            .collect();
+
+        let package = Self::package(manifest, &self.api_path(), &self.root, &exclude).await?;
+
+        tracing::info!("+ packaged {}@{}", package.name, package.version);
+
+        Ok(package)
+    }
+
+    /// Packages a release of a package declared by a `[[package]]` entry of
+    /// the manifest, from the protos in its directory
+    pub async fn release_package(&self, package: &PackageId) -> eyre::Result<Package> {
+        let manifest = Manifest::read(&self.root).await?;
+
+        let entry = manifest
+            .packages
+            .into_iter()
+            .find(|p| &p.api.name == package)
+            .wrap_err_with(|| format!("The manifest declares no [[package]] named {package}"))?;
+
+        ensure!(
+            entry
+                .path
+                .components()
+                .all(|c| matches!(c, Component::Normal(_))),
+            "The path {} of {package} has to be a subdirectory of the project",
+            entry.path.display()
+        );
+
+        let dir = self.root.join(&entry.path);
+
+        let manifest = RawManifest {
+            api: Some(entry.api),
+            dependencies: None,
+            workspace: None,
+            package: None,
+            groups: None,
+            lint: None,
+        };
+
+        let package = Self::package(manifest, &dir, &dir, &[]).await?;
+
+        tracing::info!("+ packaged {}@{}", package.name, package.version);
+
+        Ok(package)
+    }
+
+    /// Packages an installed dependency again, e.g. to share it with others
+    pub async fn repackage(&self, package: &PackageId) -> eyre::Result<Package> {
+        let pkg_dir = self.dep_path().join(package.as_package_dir());
+
+        let manifest = RawManifest::from(
+            Manifest::read(&pkg_dir)
+                .await
+                .wrap_err_with(|| format!("{package} is not installed"))?,
+        );
+
+        Self::package(manifest, &pkg_dir, &pkg_dir, &[]).await
+    }
+
+    /// Packages the protos found in `dir`, outside of the directories in
+    /// `exclude`, along with `manifest` and the readme located in
+    /// `readme_dir`, if there is one
+    async fn package(
+        manifest: RawManifest,
+        dir: &Path,
+        readme_dir: &Path,
+        exclude: &[PathBuf],
+    ) -> eyre::Result<Package> {
+        let api = manifest
+            .api
+            .to_owned()
+            .wrap_err("Releasing a package requires an api manifest")?;
+
+        let dir = fs::canonicalize(dir)
+            .await
+            .wrap_err("Failed to locate api package")?;
+
+        let protos: Vec<PathBuf> = WalkDir::new(dir)
+            .into_iter()
+            .filter_entry(|e| !exclude.iter().any(|path| e.path() == path))
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().extension().and_then(|s| s.to_str()) == Some("proto"))
+            .map(|e| e.into_path())
+            .collect();
+
+        let readme = Some(readme_dir.join(README_FILE)).filter(|path| path.is_file());
+
+        let tgz = PackageArchive::new()?;
+
+        // Protos are read one at a time while the archive is written, so the
+        // package is never fully held in memory
+        tokio::task::spawn_blocking({
+            let tgz = tgz.clone();
+
+            move || -> eyre::Result<()> {
+                let file =
+                    std::fs::File::create(tgz.path()).wrap_err("Failed to create archive")?;
+
+                let protos = protos
+                    .iter()
+                    .chain(readme.iter())
+                    .map(|path| PackageFile::read(path));
+
+                pack(BufWriter::new(file), &manifest, protos)?
+                    .flush()
+                    .wrap_err("Failed to release package")
+            }
+        })
+        .await
+        .wrap_err("Failed to join packaging task")??;
+
+        Ok(Package::new(api.name, api.version, tgz))
+    }
+}
+
+/// Installations into a [`PackageStore`] that are undone together on failure
+///
+/// Packages replaced by the transaction are kept in a backup directory until
+/// it is committed. Dropping the transaction without committing rolls it
+/// back. Backups that could not be restored are kept on disk.
+pub struct Transaction<'a> {
+    store: &'a PackageStore,
+    backup: Option<tempfile::TempDir>,
+    installed: Mutex<Vec<(PathBuf, Option<PathBuf>)>>,
+}
+
+impl Transaction<'_> {
+    /// Installs a package as part of the transaction
+    pub async fn install(&self, package: Package) -> eyre::Result<()> {
+        let backup = self
+            .backup
+            .as_ref()
+            .context("Transaction was already finished")?
+            .path()
+            .join(package.name.as_package_dir());
+
+        let pkg_dir = self.store.swap_in(package, Some(backup.clone())).await?;
+
+        let backup = fs::try_exists(&backup)
+            .await
+            .unwrap_or(false)
+            .then_some(backup);
+
+        self.installed
+            .lock()
+            .expect("transaction poisoned")
+            .push((pkg_dir, backup));
+
+        Ok(())
+    }
+
+    /// Keeps all installed packages
+    pub fn commit(mut self) {
+        self.installed
+            .get_mut()
+            .expect("transaction poisoned")
+            .clear();
+    }
+
+    /// Restores the store to its state before the transaction
+    pub async fn rollback(mut self) -> eyre::Result<()> {
+        let installed = std::mem::take(self.installed.get_mut().expect("transaction poisoned"));
+
+        let Some(backup) = self.backup.take() else {
+            return Ok(());
+        };
+
+        tokio::task::spawn_blocking(move || restore(installed, backup))
+            .await
+            .wrap_err("Failed to join rollback task")?
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        let installed = std::mem::take(self.installed.get_mut().expect("transaction poisoned"));
+
+        let Some(backup) = self.backup.take() else {
+            return;
+        };
+
+        if installed.is_empty() {
+            return;
+        }
+
+        if let Err(error) = restore(installed, backup) {
+            tracing::warn!("! {error:#}");
+        }
+    }
+}
+
+/// Removes packages installed by a transaction and moves their backups back
+///
+/// Every package is attempted, and if any of them fails the backup directory
+/// is kept so that no replaced package is lost.
+fn restore(
+    installed: Vec<(PathBuf, Option<PathBuf>)>,
+    backup: tempfile::TempDir,
+) -> eyre::Result<()> {
+    let mut failures = vec![];
+
+    for (pkg_dir, restored) in installed.into_iter().rev() {
+        if let Err(error) = std::fs::remove_dir_all(&pkg_dir) {
+            failures.push(format!(
+                "\n  - failed to roll back {}: {error}",
+                pkg_dir.display()
+            ));
+            continue;
+        }
+
+        if let Some(restored) = restored {
+            if let Err(error) = std::fs::rename(&restored, &pkg_dir) {
+                failures.push(format!(
+                    "\n  - failed to restore {}: {error}",
+                    pkg_dir.display()
+                ));
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    let backup = backup.into_path();
+
+    eyre::bail!(
+        "Failed to restore {} packages, backups are kept in {}:{}",
+        failures.len(),
+        backup.display(),
+        failures.concat()
+    )
+}
+
+/// Converts an existing directory into its `\\?\` form on Windows
+///
+/// Paths in that form are not limited to 260 characters, which deeply nested
+/// proto packages easily exceed.
+#[cfg(windows)]
+fn long_path(path: PathBuf) -> eyre::Result<PathBuf> {
+    std::fs::canonicalize(&path).wrap_err(format!("Failed to resolve {}", path.display()))
+}
+
+#[cfg(not(windows))]
+fn long_path(path: PathBuf) -> eyre::Result<PathBuf> {
+    Ok(path)
+}
+
+impl PackageFile {
+    /// Reads a file from disk, placing it at the root of the package
+    fn read(path: &Path) -> eyre::Result<Self> {
+        let name = path
+            .file_name()
+            .wrap_err("Failed to add protos to release")?;
+
+        let contents =
+            std::fs::read(path).wrap_err(format!("Failed to read {}", path.display()))?;
+
+        Ok(Self {
+            path: name.into(),
+            contents,
+        })
+    }
+}
+
+/// A `buffrs` package whose contents are buffered on disk
+#[derive(Clone, Debug)]
+pub struct Package {
+    /// The name of the package
+    pub name: PackageId,
+    /// The version of the package
+    pub version: semver::Version,
+    /// The `tar.gz` archive containing the protocol buffers
+    pub tgz: PackageArchive,
+    /// Digest the registry published for the archive, if it provides one
+    pub digest: Option<Digest>,
+}
+
+impl Package {
+    /// Creates a new package
+    pub fn new(name: PackageId, version: semver::Version, tgz: PackageArchive) -> Self {
+        Self {
+            name,
+            version,
+            tgz,
+            digest: None,
+        }
+    }
+
+    /// Sets the digest the archive is expected to have
+    pub fn with_digest(mut self, digest: Digest) -> Self {
+        self.digest = Some(digest);
+        self
+    }
+
+    /// Checks the archive against its expected digest
+    ///
+    /// This is done before installing a package, so corrupted or tampered
+    /// downloads are never unpacked. Packages without digest pass, registries
+    /// warn about downloads they have no digest for.
+    pub async fn verify(&self) -> eyre::Result<()> {
+        let Some(ref expected) = self.digest else {
+            return Ok(());
+        };
+
+        let actual = self.tgz.digest(expected.algorithm()).await?;
+
+        if &actual != expected {
+            return Err(eyre::eyre!(
+                "Contents of {}@{} do not match the digest published by the registry, expected {expected} but got {actual}",
+                self.name,
+                self.version
+            ))
+            .wrap_err(ErrorCode::IntegrityMismatch);
+        }
+
+        Ok(())
+    }
+}
+
+/// A `tar.gz` package archive stored in a temporary file
+///
+/// The file is deleted once the last clone of the archive is dropped.
+#[derive(Clone, Debug)]
+pub struct PackageArchive(Arc<TempPath>);
+
+impl PackageArchive {
+    /// Creates a new, empty archive in the temporary directory
+    pub fn new() -> eyre::Result<Self> {
+        let path = tempfile::Builder::new()
+            .prefix("buffrs-")
+            .suffix(".tgz")
+            .tempfile()
+            .wrap_err("Failed to create temporary archive")?
+            .into_temp_path();
+
+        Ok(Self(Arc::new(path)))
+    }
+
+    /// Location of the archive on disk
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Size of the archive in bytes
+    pub async fn size(&self) -> eyre::Result<u64> {
+        Ok(fs::metadata(self.path())
+            .await
+            .wrap_err("Failed to inspect archive")?
+            .len())
+    }
+
+    /// Computes the digest of the archive
+    pub async fn digest(&self, algorithm: DigestAlgorithm) -> eyre::Result<Digest> {
+        let archive = self.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut file =
+                std::fs::File::open(archive.path()).wrap_err("Failed to open archive")?;
+
+            let mut hasher = algorithm.hasher();
+
+            std::io::copy(&mut file, &mut hasher).wrap_err("Failed to read archive")?;
+
+            Ok(hasher.finalize())
+        })
+        .await
+        .wrap_err("Failed to join hashing task")?
+    }
+
+    /// Decodes all files contained in the archive
+    pub async fn files(&self) -> eyre::Result<Vec<PackageFile>> {
+        let archive = self.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let file = std::fs::File::open(archive.path()).wrap_err("Failed to open archive")?;
+
+            unpack(BufReader::new(file))
+        })
+        .await
+        .wrap_err("Failed to join unpacking task")?
+    }
+
+    /// Extracts the readme of the package, if it was published with one
+    ///
+    /// Entries are read one at a time and only up to the readme.
+    pub async fn readme(&self) -> eyre::Result<Option<String>> {
+        let archive = self.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let file = std::fs::File::open(archive.path()).wrap_err("Failed to open archive")?;
+
+            let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(BufReader::new(file)));
+
+            for entry in archive.entries().wrap_err("Failed to unpack tar")? {
+                let mut entry = entry.wrap_err("Failed to unpack tar")?;
+
+                if entry.path().wrap_err("Invalid file path in package")? != Path::new(README_FILE)
+                {
+                    continue;
+                }
+
+                let mut readme = Vec::new();
+
+                entry
+                    .read_to_end(&mut readme)
+                    .wrap_err(format!("Failed to read {README_FILE} from package"))?;
+
+                return String::from_utf8(readme)
+                    .map(Some)
+                    .wrap_err("Readme of package is not valid UTF-8");
+            }
+
+            Ok(None)
+        })
+        .await
+        .wrap_err("Failed to join unpacking task")?
+    }
+}
@@ -0,0 +1,126 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+/// Fully qualified names of the top level messages, enums and services
+/// defined by a proto file, e.g. `units.Temperature`
+///
+/// This is a lightweight scan instead of a full parse, so it also works for
+/// protos whose imports are not installed. Nested definitions are scoped by
+/// their parent and are not reported.
+pub fn definitions(proto: &str) -> Vec<String> {
+    let tokens = tokenize(proto);
+
+    let mut package = None;
+    let mut names = vec![];
+    let mut depth = 0usize;
+
+    for (index, token) in tokens.iter().enumerate() {
+        let next = tokens.get(index + 1).copied();
+
+        match *token {
+            "{" => depth += 1,
+            "}" => depth = depth.saturating_sub(1),
+            "package" if depth == 0 => package = next.filter(|n| is_identifier(n)),
+            "message" | "enum" | "service" if depth == 0 => {
+                names.extend(next.filter(|n| is_identifier(n) && !n.contains('.')))
+            }
+            _ => {}
+        }
+    }
+
+    names
+        .into_iter()
+        .map(|name| match package {
+            Some(package) => format!("{package}.{name}"),
+            None => name.to_owned(),
+        })
+        .collect()
+}
+
+/// Name of the proto package a proto file declares, e.g. `units`
+pub fn declared_package(proto: &str) -> Option<String> {
+    let tokens = tokenize(proto);
+    let mut depth = 0usize;
+
+    for (index, token) in tokens.iter().enumerate() {
+        match *token {
+            "{" => depth += 1,
+            "}" => depth = depth.saturating_sub(1),
+            "package" if depth == 0 => {
+                return tokens
+                    .get(index + 1)
+                    .filter(|name| is_identifier(name))
+                    .map(|name| name.to_string())
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn is_identifier(token: &str) -> bool {
+    token
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+}
+
+/// Splits a proto file into identifiers and punctuation, dropping comments
+/// and string literals
+fn tokenize(proto: &str) -> Vec<&str> {
+    let bytes = proto.as_bytes();
+    let mut tokens = vec![];
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let start = index;
+
+        match bytes[index] {
+            b'/' if bytes.get(index + 1) == Some(&b'/') => {
+                while index < bytes.len() && bytes[index] != b'\n' {
+                    index += 1;
+                }
+            }
+            b'/' if bytes.get(index + 1) == Some(&b'*') => {
+                index += 2;
+
+                while index < bytes.len() && !bytes[index..].starts_with(b"*/") {
+                    index += 1;
+                }
+
+                index += 2;
+            }
+            quote @ (b'"' | b'\'') => {
+                index += 1;
+
+                while index < bytes.len() && bytes[index] != quote {
+                    // Skips escaped characters, including quotes
+                    index += if bytes[index] == b'\\' { 2 } else { 1 };
+                }
+
+                index += 1;
+            }
+            byte if byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'.' => {
+                while index < bytes.len()
+                    && (bytes[index].is_ascii_alphanumeric()
+                        || bytes[index] == b'_'
+                        || bytes[index] == b'.')
+                {
+                    index += 1;
+                }
+
+                tokens.push(&proto[start..index]);
+            }
+            byte if byte.is_ascii_whitespace() => index += 1,
+            _ => {
+                // Advances by a whole character to stay on char boundaries
+                let len = proto[index..].chars().next().map_or(1, char::len_utf8);
+                index += len;
+
+                tokens.push(&proto[start..index]);
+            }
+        }
+    }
+
+    tokens
+}
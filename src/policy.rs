@@ -0,0 +1,536 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    fmt,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use eyre::{ensure, Context};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "io")]
+use {
+    eyre::ContextCompat,
+    std::{path::Path, process::Stdio},
+    tokio::{fs, io::AsyncWriteExt},
+};
+
+use crate::{
+    error::ErrorCode,
+    manifest::{ApiManifest, Dependency, RawManifest},
+    package::{DigestAlgorithm, PackageId},
+};
+
+/// Rules restricting which packages may be installed
+///
+/// Policies are either configured inline in the `buffrs` config or shared
+/// across an organization as standalone policy files.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Policy {
+    /// File the policy was loaded from, used to point at it in errors
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+    /// Minimum age of a published version before it may be installed (e.g.
+    /// `3d` or `12h`), giving maintainers time to react to malicious releases
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub quarantine: Option<Duration>,
+    /// Digest algorithm package contents have to be verified with, e.g.
+    /// `sha512` in environments restricted to specific algorithms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<DigestAlgorithm>,
+    /// Sources dependencies are restricted to
+    #[serde(default, skip_serializing_if = "AllowList::is_empty")]
+    pub allow: AllowList,
+    /// Dependencies that must not be installed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<DenyRule>,
+    /// External policies asked to approve every install and publish
+    #[serde(default, rename = "hook", skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<Hook>,
+    /// Metadata published packages have to provide
+    #[serde(default, skip_serializing_if = "PublishRules::is_empty")]
+    pub publish: PublishRules,
+}
+
+/// Fields of the `[api]` section publish rules can require
+const API_FIELDS: &[&str] = &["name", "version", "description"];
+
+/// Requirements on the metadata of published packages
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PublishRules {
+    /// Fields of the `[api]` section that have to be set, e.g. `description`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub require: Vec<String>,
+    /// Constraints on the description, if one is set
+    #[serde(default, skip_serializing_if = "DescriptionRules::is_empty")]
+    pub description: DescriptionRules,
+    /// Whether breaking changes to the previous release have to be announced
+    /// by a major version bump
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deny_breaking: bool,
+}
+
+impl PublishRules {
+    /// Checks whether no rules are configured
+    pub fn is_empty(&self) -> bool {
+        self.require.is_empty() && self.description.is_empty() && !self.deny_breaking
+    }
+}
+
+/// Constraints on package descriptions
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DescriptionRules {
+    /// Minimum number of characters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_length: Option<usize>,
+    /// Maximum number of characters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    /// Phrases that must not appear, compared case-insensitively, e.g.
+    /// placeholders such as `TODO`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+impl DescriptionRules {
+    /// Checks whether no constraints are configured
+    pub fn is_empty(&self) -> bool {
+        self.min_length.is_none() && self.max_length.is_none() && self.deny.is_empty()
+    }
+
+    /// Lists the ways `description` violates these constraints
+    fn violations(&self, description: &str) -> Vec<String> {
+        let description = description.trim();
+        let length = description.chars().count();
+
+        let mut violations = vec![];
+
+        if let Some(min) = self.min_length.filter(|min| length < *min) {
+            violations.push(format!(
+                "description has {length} characters, at least {min} are required"
+            ));
+        }
+
+        if let Some(max) = self.max_length.filter(|max| length > *max) {
+            violations.push(format!(
+                "description has {length} characters, at most {max} are allowed"
+            ));
+        }
+
+        let lowercase = description.to_lowercase();
+
+        for phrase in &self.deny {
+            if lowercase.contains(&phrase.to_lowercase()) {
+                violations.push(format!("description must not contain {phrase:?}"));
+            }
+        }
+
+        violations
+    }
+}
+
+/// External policy that can veto operations
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Hook {
+    /// Program receiving the [`Operation`] as json on stdin
+    ///
+    /// The program approves by exiting successfully. It may print a
+    /// [`Decision`] as json to stdout, e.g. using `opa eval --format raw`.
+    Command(Vec<String>),
+    /// Url of an OPA decision endpoint (e.g.
+    /// `http://localhost:8181/v1/data/buffrs/decision`) that is queried with
+    /// the [`Operation`] as input and must return a [`Decision`]
+    Opa(url::Url),
+}
+
+/// Operation submitted to policy hooks for approval
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "operation", rename_all = "lowercase")]
+pub enum Operation {
+    /// Installation of the dependencies of a project
+    Install {
+        /// Manifest of the project
+        manifest: RawManifest,
+        /// Dependencies that are going to be installed
+        dependencies: Vec<Dependency>,
+        /// Packages installed before this operation
+        installed: Vec<ApiManifest>,
+    },
+    /// Publication of the api package of a project
+    Publish {
+        /// Manifest of the project
+        manifest: RawManifest,
+        /// Repository the package is published to
+        repository: String,
+        /// Files contained in the package
+        files: Vec<PathBuf>,
+    },
+}
+
+/// Verdict of a policy hook
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Decision {
+    /// Whether the operation may proceed
+    pub allow: bool,
+    /// Explanations shown when the operation is vetoed
+    #[serde(default)]
+    pub reasons: Vec<String>,
+}
+
+/// Restricts dependencies to a set of repositories and packages
+///
+/// An empty list places no restriction.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AllowList {
+    /// Repositories dependencies may be installed from
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repositories: Vec<String>,
+    /// Packages that may be installed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<PackageId>,
+}
+
+impl AllowList {
+    /// Checks whether the allow list places no restriction at all
+    pub fn is_empty(&self) -> bool {
+        self.repositories.is_empty() && self.packages.is_empty()
+    }
+}
+
+/// Denies every dependency matching all of the given fields
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DenyRule {
+    /// Repository the dependency is installed from
+    pub repository: Option<String>,
+    /// Name of the denied package
+    pub package: Option<PackageId>,
+    /// Denied versions (e.g. `<1.2.0` or `=2.0.0`)
+    pub version: Option<semver::VersionReq>,
+    /// Explanation shown when the rule blocks a dependency
+    pub reason: Option<String>,
+}
+
+impl DenyRule {
+    /// Checks whether this rule applies to a dependency
+    pub fn matches(&self, dependency: &Dependency) -> bool {
+        let repository = self
+            .repository
+            .as_ref()
+            .is_none_or(|r| *r == dependency.manifest.repository);
+
+        let package = self
+            .package
+            .as_ref()
+            .is_none_or(|p| *p == dependency.package);
+
+        let version = self.version.as_ref().is_none_or(|req| {
+            dependency
+                .resolved_version()
+                .is_ok_and(|version| req.matches(version))
+        });
+
+        repository && package && version
+    }
+}
+
+impl Policy {
+    /// Reads a policy file
+    #[cfg(feature = "io")]
+    pub async fn load(path: &Path) -> eyre::Result<Self> {
+        let toml = fs::read_to_string(path)
+            .await
+            .wrap_err_with(|| format!("Failed to read policy {}", path.display()))?;
+
+        let mut policy: Self = toml::from_str(&toml)
+            .wrap_err_with(|| format!("Failed to parse policy {}", path.display()))?;
+
+        policy.source = Some(path.to_owned());
+
+        Ok(policy)
+    }
+
+    /// Checks that a dependency is neither denied nor outside of the allow
+    /// list of this policy
+    pub fn check(&self, dependency: &Dependency) -> eyre::Result<()> {
+        self.check_lists(dependency)
+            .wrap_err(ErrorCode::PolicyViolation)
+    }
+
+    fn check_lists(&self, dependency: &Dependency) -> eyre::Result<()> {
+        let repositories = &self.allow.repositories;
+
+        ensure!(
+            repositories.is_empty() || repositories.contains(&dependency.manifest.repository),
+            "{dependency} is blocked by {self}: repository {} is not allowed",
+            dependency.manifest.repository
+        );
+
+        let packages = &self.allow.packages;
+
+        ensure!(
+            packages.is_empty() || packages.contains(&dependency.package),
+            "{dependency} is blocked by {self}: package {} is not allowed",
+            dependency.package
+        );
+
+        if let Some(rule) = self.deny.iter().find(|rule| rule.matches(dependency)) {
+            match rule.reason {
+                Some(ref reason) => eyre::bail!("{dependency} is denied by {self}: {reason}"),
+                None => eyre::bail!("{dependency} is denied by {self}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the metadata of a package satisfies the publish rules of
+    /// this policy, listing all violations
+    pub fn check_publish(&self, api: &ApiManifest) -> eyre::Result<()> {
+        let rules = &self.publish;
+
+        if rules.is_empty() {
+            return Ok(());
+        }
+
+        let fields = match toml::Value::try_from(api)? {
+            toml::Value::Table(fields) => fields,
+            _ => Default::default(),
+        };
+
+        let mut violations: Vec<String> = rules
+            .require
+            .iter()
+            .filter_map(|field| match fields.get(field.as_str()) {
+                _ if !API_FIELDS.contains(&field.as_str()) => {
+                    Some(format!("{field} is not a field of the [api] section"))
+                }
+                None => Some(format!("{field} is required")),
+                Some(toml::Value::String(value)) if value.trim().is_empty() => {
+                    Some(format!("{field} must not be empty"))
+                }
+                Some(_) => None,
+            })
+            .collect();
+
+        if let Some(ref description) = api.description {
+            violations.extend(rules.description.violations(description));
+        }
+
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        Err(eyre::eyre!(
+            "Publishing {}@{} is blocked by {self}:{}",
+            api.name,
+            api.version,
+            violations
+                .iter()
+                .map(|violation| format!("\n  - {violation}"))
+                .collect::<String>()
+        ))
+        .wrap_err(ErrorCode::PolicyViolation)
+    }
+
+    /// Asks the hooks of this policy to approve an operation
+    ///
+    /// Remote policies are queried with `client`, which should be built from
+    /// the network settings of the configuration (see `Config::client`).
+    #[cfg(feature = "io")]
+    pub async fn evaluate(
+        &self,
+        operation: &Operation,
+        client: &reqwest::Client,
+    ) -> eyre::Result<()> {
+        for hook in &self.hooks {
+            let decision = hook.evaluate(operation, client).await?;
+
+            if !decision.allow {
+                return Err(eyre::eyre!(
+                    "{operation} was vetoed by {self}{}",
+                    decision
+                        .reasons
+                        .iter()
+                        .map(|reason| format!("\n  - {reason}"))
+                        .collect::<String>()
+                ))
+                .wrap_err(ErrorCode::PolicyVeto);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that `dependency`, published at `published_at`, has left the
+    /// quarantine period
+    ///
+    /// Packages listed in `allowed` are exempt from the quarantine.
+    pub fn check_quarantine(
+        &self,
+        dependency: &Dependency,
+        published_at: SystemTime,
+        allowed: &[PackageId],
+    ) -> eyre::Result<()> {
+        let Some(quarantine) = self.quarantine else {
+            return Ok(());
+        };
+
+        if allowed.contains(&dependency.package) {
+            return Ok(());
+        }
+
+        let age = SystemTime::now()
+            .duration_since(published_at)
+            .unwrap_or_default();
+
+        if age < quarantine {
+            return Err(eyre::eyre!(
+                "{dependency} was published {} ago and is quarantined for {} by {self}, use `--allow-quarantined {}` to install it anyway",
+                humantime::format_duration(Duration::from_secs(age.as_secs())),
+                humantime::format_duration(quarantine),
+                dependency.package
+            ))
+            .wrap_err(ErrorCode::Quarantined);
+        }
+
+        Ok(())
+    }
+}
+
+/// Determines the digest algorithm required by a set of policies
+///
+/// Falls back to the default algorithm if no policy requires one, and fails
+/// if policies require different algorithms.
+pub fn digest_algorithm(policies: &[Policy]) -> eyre::Result<DigestAlgorithm> {
+    let mut required: Option<(DigestAlgorithm, &Policy)> = None;
+
+    for policy in policies {
+        let Some(algorithm) = policy.digest else {
+            continue;
+        };
+
+        if let Some((other, source)) = required {
+            ensure!(
+                algorithm == other,
+                "{policy} requires {algorithm} digests, but {source} requires {other}"
+            );
+        }
+
+        required = Some((algorithm, policy));
+    }
+
+    Ok(required.map(|(algorithm, _)| algorithm).unwrap_or_default())
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.source {
+            Some(ref source) => write!(f, "policy {}", source.display()),
+            None => write!(f, "policy"),
+        }
+    }
+}
+
+#[cfg(feature = "io")]
+impl Hook {
+    /// Asks the hook for its decision on an operation
+    pub async fn evaluate(
+        &self,
+        operation: &Operation,
+        client: &reqwest::Client,
+    ) -> eyre::Result<Decision> {
+        match self {
+            Self::Command(command) => {
+                let (program, args) = command
+                    .split_first()
+                    .wrap_err("Policy hook commands must not be empty")?;
+
+                let mut child = tokio::process::Command::new(program)
+                    .args(args)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .wrap_err_with(|| format!("Failed to run policy hook {program}"))?;
+
+                let input = serde_json::to_vec(operation)?;
+
+                if let Some(mut stdin) = child.stdin.take() {
+                    stdin
+                        .write_all(&input)
+                        .await
+                        .wrap_err("Failed to pass operation to policy hook")?;
+                }
+
+                let output = child
+                    .wait_with_output()
+                    .await
+                    .wrap_err_with(|| format!("Failed to run policy hook {program}"))?;
+
+                if output.stdout.iter().all(u8::is_ascii_whitespace) {
+                    return Ok(Decision {
+                        allow: output.status.success(),
+                        reasons: vec![format!("{program} exited with {}", output.status)],
+                    });
+                }
+
+                let decision: Decision = serde_json::from_slice(&output.stdout)
+                    .wrap_err_with(|| format!("Policy hook {program} printed no decision"))?;
+
+                Ok(Decision {
+                    allow: decision.allow && output.status.success(),
+                    ..decision
+                })
+            }
+            Self::Opa(url) => {
+                #[derive(Deserialize)]
+                struct Response {
+                    result: Option<Decision>,
+                }
+
+                let response = client
+                    .post(url.clone())
+                    .json(&serde_json::json!({ "input": operation }))
+                    .send()
+                    .await
+                    .wrap_err_with(|| format!("Failed to query policy {url}"))?;
+
+                ensure!(
+                    response.status().is_success(),
+                    "Failed to query policy {url}: {}",
+                    response.status()
+                );
+
+                let response: Response = response
+                    .json()
+                    .await
+                    .wrap_err_with(|| format!("Failed to parse decision of {url}"))?;
+
+                response
+                    .result
+                    .wrap_err_with(|| format!("Policy {url} returned no decision"))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Install { .. } => write!(f, "Installation"),
+            Self::Publish {
+                manifest,
+                repository,
+                ..
+            } => match manifest.api {
+                Some(ref api) => write!(f, "Publishing {repository}/{}@{}", api.name, api.version),
+                None => write!(f, "Publishing to {repository}"),
+            },
+        }
+    }
+}
@@ -0,0 +1,578 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use eyre::{ensure, Context, ContextCompat};
+use prost_reflect::{
+    prost::Message as _, DescriptorPool, DynamicMessage, Kind, MapKey, MessageDescriptor,
+    ReflectMessage, ServiceDescriptor, Value,
+};
+use protobuf::{descriptor::FileDescriptorSet, Message as _};
+use rand::{rngs::StdRng, seq::IteratorRandom, Rng, SeedableRng};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::package::PackageStore;
+
+/// The message types defined by the protos of a project
+///
+/// This includes the api package and all installed dependencies, so
+/// messages can be inspected without generating code for them.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    pool: DescriptorPool,
+    /// The same protos for the text format parser of the `protobuf` crate
+    files: Vec<protobuf::reflect::FileDescriptor>,
+}
+
+impl Schema {
+    /// Compiles the protos of the api package and installed dependencies
+    ///
+    /// Protos are resolved relative to `proto/api` and the dependency store,
+    /// so imports look like `<package_dir>/<file>.proto` for dependencies.
+    pub async fn compile(store: &PackageStore) -> eyre::Result<Self> {
+        let store = store.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let includes = store.include_paths();
+            let inputs = store.proto_files();
+
+            ensure!(
+                !inputs.is_empty(),
+                "No protos found, run buffrs install first"
+            );
+
+            let parsed = protobuf_parse::Parser::new()
+                .pure()
+                .includes(&includes)
+                .inputs(&inputs)
+                .parse_and_typecheck()
+                .map_err(|error| eyre::eyre!("{error:#}"))
+                .wrap_err("Failed to compile protos")?;
+
+            // Includes imported well known types such as google.protobuf.Timestamp
+            let descriptors = FileDescriptorSet {
+                file: parsed.file_descriptors.clone(),
+                ..Default::default()
+            }
+            .write_to_bytes()
+            .wrap_err("Failed to encode descriptors")?;
+
+            let pool = DescriptorPool::decode(descriptors.as_slice())
+                .wrap_err("Failed to load descriptors")?;
+
+            let files =
+                protobuf::reflect::FileDescriptor::new_dynamic_fds(parsed.file_descriptors, &[])
+                    .wrap_err("Failed to load descriptors")?;
+
+            Ok(Self { pool, files })
+        })
+        .await
+        .wrap_err("Failed to join compilation task")?
+    }
+
+    /// Finds a message by its full name (`<package>.<Message>`) or, if it is
+    /// unambiguous, by its name alone
+    pub fn message(&self, name: &str) -> eyre::Result<MessageDescriptor> {
+        lookup("Message type", name, self.pool.all_messages(), |m| {
+            m.full_name().to_owned()
+        })
+    }
+
+    /// Finds a gRPC service by its full name (`<package>.<Service>`) or, if
+    /// it is unambiguous, by its name alone
+    pub fn service(&self, name: &str) -> eyre::Result<ServiceDescriptor> {
+        lookup("Service", name, self.pool.services(), |s| {
+            s.full_name().to_owned()
+        })
+    }
+
+    /// The compiled descriptors, including custom options and extensions
+    pub fn pool(&self) -> &DescriptorPool {
+        &self.pool
+    }
+
+    /// All gRPC services defined by the protos
+    pub fn all_services(&self) -> impl Iterator<Item = ServiceDescriptor> + '_ {
+        self.pool.services()
+    }
+
+    /// Decodes a binary message into its JSON representation
+    pub fn decode(&self, message: &str, bytes: &[u8]) -> eyre::Result<String> {
+        let descriptor = self.message(message)?;
+
+        let message = DynamicMessage::decode(descriptor.clone(), bytes)
+            .wrap_err_with(|| format!("Failed to decode {}", descriptor.full_name()))?;
+
+        serde_json::to_string_pretty(&message)
+            .wrap_err_with(|| format!("Failed to convert {} to JSON", descriptor.full_name()))
+    }
+
+    /// Encodes the JSON representation of a message in the binary format
+    pub fn encode(&self, message: &str, json: &str) -> eyre::Result<Vec<u8>> {
+        Ok(self.parse_json(message, json)?.encode_to_vec())
+    }
+
+    /// Parses the JSON representation of a message
+    ///
+    /// Unknown fields are rejected.
+    pub fn parse_json(&self, message: &str, json: &str) -> eyre::Result<DynamicMessage> {
+        let descriptor = self.message(message)?;
+
+        let mut deserializer = serde_json::Deserializer::from_str(json);
+
+        DynamicMessage::deserialize(descriptor.clone(), &mut deserializer)
+            .and_then(|message| deserializer.end().map(|_| message))
+            .wrap_err_with(|| format!("Failed to parse JSON as {}", descriptor.full_name()))
+    }
+
+    /// Parses a message in the protobuf text format
+    ///
+    /// Unknown fields are rejected.
+    pub fn parse_text(&self, message: &str, text: &str) -> eyre::Result<DynamicMessage> {
+        let descriptor = self.message(message)?;
+
+        let text_descriptor = self
+            .files
+            .iter()
+            .find_map(|file| file.message_by_full_name(&format!(".{}", descriptor.full_name())))
+            .wrap_err_with(|| format!("Failed to load {}", descriptor.full_name()))?;
+
+        let mut parsed = text_descriptor.new_instance();
+
+        protobuf::text_format::merge_from_str(&mut *parsed, text)
+            .map_err(|error| eyre::eyre!("{error}"))
+            .wrap_err_with(|| format!("Failed to parse text as {}", descriptor.full_name()))?;
+
+        let bytes = parsed
+            .write_to_bytes_dyn()
+            .wrap_err_with(|| format!("Failed to encode {}", descriptor.full_name()))?;
+
+        DynamicMessage::decode(descriptor.clone(), bytes.as_slice())
+            .wrap_err_with(|| format!("Failed to decode {}", descriptor.full_name()))
+    }
+
+    /// Checks that a fixture is a valid message of its declared type and
+    /// returns the full name of that type
+    ///
+    /// Text fixtures declare their type in a `# proto-message: <type>` header
+    /// comment, JSON fixtures in a top-level `"@type"` field, as used for
+    /// `google.protobuf.Any`. A given `message` type takes precedence.
+    pub fn check_fixture(
+        &self,
+        path: &Path,
+        contents: &str,
+        message: Option<&str>,
+    ) -> eyre::Result<String> {
+        let text = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| TEXT_EXTENSIONS.contains(&e));
+
+        if text {
+            let declared = contents
+                .lines()
+                .map_while(|line| line.trim().strip_prefix('#'))
+                .find_map(|comment| comment.trim().strip_prefix(TEXT_MESSAGE_HEADER))
+                .map(str::trim);
+
+            let message = message.or(declared).wrap_err_with(|| {
+                format!("No message type declared, add a `# {TEXT_MESSAGE_HEADER} <type>` header")
+            })?;
+
+            return Ok(self
+                .parse_text(message, contents)?
+                .descriptor()
+                .full_name()
+                .to_owned());
+        }
+
+        let mut json: serde_json::Value =
+            serde_json::from_str(contents).wrap_err("Failed to parse JSON")?;
+
+        let declared = json
+            .as_object_mut()
+            .and_then(|object| object.remove(JSON_TYPE_FIELD))
+            .map(|declared| declared.as_str().map(str::to_owned));
+
+        // Keeps error locations pointing into the fixture, unless it changed
+        let contents = match declared {
+            Some(_) => json.to_string(),
+            None => contents.to_owned(),
+        };
+
+        let declared = declared.flatten();
+
+        // Type urls such as `type.googleapis.com/units.Temperature` are accepted
+        let declared = declared
+            .as_deref()
+            .map(|url| url.rsplit('/').next().unwrap_or(url));
+
+        let message = message.or(declared).wrap_err_with(|| {
+            format!("No message type declared, add a `\"{JSON_TYPE_FIELD}\"` field")
+        })?;
+
+        Ok(self
+            .parse_json(message, &contents)?
+            .descriptor()
+            .full_name()
+            .to_owned())
+    }
+
+    /// Creates an example of a message type with every field set, see
+    /// [`example`]
+    pub fn example(&self, message: &str, seed: Option<u64>) -> eyre::Result<DynamicMessage> {
+        Ok(example(&self.message(message)?, seed))
+    }
+
+    /// Encodes all protos, including imported ones, as `FileDescriptorSet`
+    ///
+    /// This is the format produced by `protoc --include_imports
+    /// --descriptor_set_out`, as accepted by `grpcurl -protoset` and
+    /// `ghz --protoset`.
+    pub fn descriptor_set(&self) -> Vec<u8> {
+        self.pool.encode_to_vec()
+    }
+
+    /// Lists the gRPC services defined by the protos, ordered by name
+    pub fn services(&self) -> Vec<Service> {
+        let mut services: Vec<Service> = self
+            .pool
+            .services()
+            .map(|service| Service {
+                name: service.full_name().to_owned(),
+                file: service.parent_file().name().to_owned(),
+                methods: service
+                    .methods()
+                    .map(|method| Method {
+                        call: format!("{}/{}", service.full_name(), method.name()),
+                        input: method.input().full_name().to_owned(),
+                        output: method.output().full_name().to_owned(),
+                        client_streaming: method.is_client_streaming(),
+                        server_streaming: method.is_server_streaming(),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+
+        services
+    }
+}
+
+/// Extensions of fixtures in the protobuf text format
+const TEXT_EXTENSIONS: &[&str] = &["txtpb", "textproto", "pbtxt"];
+/// Comment declaring the message type of a text fixture
+const TEXT_MESSAGE_HEADER: &str = "proto-message:";
+/// Field declaring the message type of a JSON fixture
+const JSON_TYPE_FIELD: &str = "@type";
+
+/// Finds all fixtures in the directory tree below `dir`, ordered by path
+///
+/// Fixtures are messages in the protobuf text format (`.txtpb`, `.textproto`
+/// or `.pbtxt`) or JSON (`.json`).
+pub fn fixtures(dir: &Path) -> eyre::Result<Vec<PathBuf>> {
+    let mut fixtures = vec![];
+
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry.wrap_err_with(|| format!("Failed to search {}", dir.display()))?;
+
+        let fixture = entry
+            .path()
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e == "json" || TEXT_EXTENSIONS.contains(&e));
+
+        if entry.file_type().is_file() && fixture {
+            fixtures.push(entry.into_path());
+        }
+    }
+
+    Ok(fixtures)
+}
+
+/// Nesting depth up to which example messages are populated, which also
+/// bounds recursive message types
+const EXAMPLE_DEPTH: usize = 3;
+
+/// Creates a message with every field set to an example value
+///
+/// Only one field of every oneof is set, the first unless randomized. Lists
+/// and maps contain a single element. With a `seed`, values are random but
+/// the same seed always produces the same message.
+pub fn example(descriptor: &MessageDescriptor, seed: Option<u64>) -> DynamicMessage {
+    Example {
+        rng: seed.map(StdRng::seed_from_u64),
+    }
+    .message(descriptor, EXAMPLE_DEPTH)
+}
+
+struct Example {
+    rng: Option<StdRng>,
+}
+
+impl Example {
+    fn message(&mut self, descriptor: &MessageDescriptor, depth: usize) -> DynamicMessage {
+        let mut message = DynamicMessage::new(descriptor.clone());
+
+        if depth == 0 {
+            return message;
+        }
+
+        let oneofs: Vec<u32> = descriptor
+            .oneofs()
+            .filter_map(|oneof| match self.rng {
+                Some(ref mut rng) => oneof.fields().choose(rng),
+                None => oneof.fields().next(),
+            })
+            .map(|field| field.number())
+            .collect();
+
+        for field in descriptor.fields() {
+            if field.containing_oneof().is_some() && !oneofs.contains(&field.number()) {
+                continue;
+            }
+
+            let value = if field.is_map() {
+                let Kind::Message(entry) = field.kind() else {
+                    continue;
+                };
+
+                let key = self
+                    .value(&entry.map_entry_key_field().kind(), field.name(), depth)
+                    .into_map_key()
+                    .unwrap_or_else(|| MapKey::String(field.name().to_owned()));
+
+                let value = self.value(&entry.map_entry_value_field().kind(), field.name(), depth);
+
+                Value::Map(HashMap::from([(key, value)]))
+            } else if field.is_list() {
+                Value::List(vec![self.value(&field.kind(), field.name(), depth)])
+            } else {
+                self.value(&field.kind(), field.name(), depth)
+            };
+
+            message.set_field(&field, value);
+        }
+
+        message
+    }
+
+    fn value(&mut self, kind: &Kind, name: &str, depth: usize) -> Value {
+        if let Kind::Message(descriptor) = kind {
+            return Value::Message(self.message(descriptor, depth - 1));
+        }
+
+        let Some(ref mut rng) = self.rng else {
+            return match kind {
+                Kind::Double => Value::F64(1.5),
+                Kind::Float => Value::F32(1.5),
+                Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => Value::I32(1),
+                Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => Value::I64(1),
+                Kind::Uint32 | Kind::Fixed32 => Value::U32(1),
+                Kind::Uint64 | Kind::Fixed64 => Value::U64(1),
+                Kind::Bool => Value::Bool(true),
+                Kind::String => Value::String(name.to_owned()),
+                Kind::Bytes => Value::Bytes(name.as_bytes().to_vec().into()),
+                Kind::Enum(descriptor) => {
+                    let value = descriptor
+                        .values()
+                        .find(|v| v.number() != 0)
+                        .unwrap_or_else(|| descriptor.default_value());
+
+                    Value::EnumNumber(value.number())
+                }
+                Kind::Message(_) => unreachable!(),
+            };
+        };
+
+        match kind {
+            Kind::Double => Value::F64((rng.gen_range(-1000.0..1000.0) * 100.0f64).round() / 100.0),
+            Kind::Float => Value::F32((rng.gen_range(-1000.0..1000.0) * 100.0f32).round() / 100.0),
+            Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => Value::I32(rng.gen_range(-1000..1000)),
+            Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => Value::I64(rng.gen_range(-1000..1000)),
+            Kind::Uint32 | Kind::Fixed32 => Value::U32(rng.gen_range(0..1000)),
+            Kind::Uint64 | Kind::Fixed64 => Value::U64(rng.gen_range(0..1000)),
+            Kind::Bool => Value::Bool(rng.gen()),
+            Kind::String => Value::String(format!("{name}-{}", rng.gen_range(0..10000))),
+            Kind::Bytes => Value::Bytes((0..8).map(|_| rng.gen::<u8>()).collect()),
+            Kind::Enum(descriptor) => Value::EnumNumber(
+                descriptor
+                    .values()
+                    .choose(rng)
+                    .unwrap_or_else(|| descriptor.default_value())
+                    .number(),
+            ),
+            Kind::Message(_) => unreachable!(),
+        }
+    }
+}
+
+/// Encodes a message in the protobuf text format, as used by `.txtpb` files
+pub fn to_text(message: &DynamicMessage) -> String {
+    let mut out = String::new();
+    write_message(&mut out, message, 0);
+    out
+}
+
+fn write_message(out: &mut String, message: &DynamicMessage, indent: usize) {
+    let pad = "  ".repeat(indent);
+
+    for (field, value) in message.fields() {
+        match value {
+            Value::List(values) => {
+                for value in values {
+                    write_field(out, field.name(), &field.kind(), value, indent);
+                }
+            }
+            Value::Map(entries) => {
+                let Kind::Message(entry) = field.kind() else {
+                    continue;
+                };
+
+                let mut entries: Vec<_> = entries.iter().collect();
+                entries.sort_by_key(|(key, _)| *key);
+
+                for (key, value) in entries {
+                    let key = Value::from(key.clone());
+
+                    out.push_str(&format!("{pad}{} {{\n", field.name()));
+                    write_field(
+                        out,
+                        "key",
+                        &entry.map_entry_key_field().kind(),
+                        &key,
+                        indent + 1,
+                    );
+                    write_field(
+                        out,
+                        "value",
+                        &entry.map_entry_value_field().kind(),
+                        value,
+                        indent + 1,
+                    );
+                    out.push_str(&format!("{pad}}}\n"));
+                }
+            }
+            value => write_field(out, field.name(), &field.kind(), value, indent),
+        }
+    }
+}
+
+fn write_field(out: &mut String, name: &str, kind: &Kind, value: &Value, indent: usize) {
+    let pad = "  ".repeat(indent);
+
+    let Value::Message(message) = value else {
+        out.push_str(&format!("{pad}{name}: {}\n", scalar(kind, value)));
+        return;
+    };
+
+    out.push_str(&format!("{pad}{name} {{\n"));
+    write_message(out, message, indent + 1);
+    out.push_str(&format!("{pad}}}\n"));
+}
+
+/// Formats a value that is neither a message, list nor map
+fn scalar(kind: &Kind, value: &Value) -> String {
+    match (value, kind) {
+        (Value::EnumNumber(number), Kind::Enum(descriptor)) => descriptor
+            .get_value(*number)
+            .map(|value| value.name().to_owned())
+            .unwrap_or_else(|| number.to_string()),
+        (Value::String(string), _) => quote(string.as_bytes()),
+        (Value::Bytes(bytes), _) => quote(bytes),
+        (Value::Bool(value), _) => value.to_string(),
+        (Value::I32(value), _) => value.to_string(),
+        (Value::I64(value), _) => value.to_string(),
+        (Value::U32(value), _) => value.to_string(),
+        (Value::U64(value), _) => value.to_string(),
+        (Value::F32(value), _) => value.to_string(),
+        (Value::F64(value), _) => value.to_string(),
+        (Value::EnumNumber(number), _) => number.to_string(),
+        (Value::Message(_) | Value::List(_) | Value::Map(_), _) => String::new(),
+    }
+}
+
+/// Quotes a string or bytes literal, escaping everything but printable ascii
+fn quote(bytes: &[u8]) -> String {
+    let mut quoted = String::from('"');
+
+    for &byte in bytes {
+        match byte {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\n' => quoted.push_str("\\n"),
+            0x20..=0x7e => quoted.push(byte as char),
+            _ => quoted.push_str(&format!("\\x{byte:02x}")),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// Finds a descriptor by its full name or an unambiguous suffix of it
+fn lookup<T>(
+    kind: &str,
+    name: &str,
+    descriptors: impl Iterator<Item = T>,
+    full_name: impl Fn(&T) -> String,
+) -> eyre::Result<T> {
+    let name = name.trim_start_matches('.');
+    let suffix = format!(".{name}");
+
+    let mut candidates: Vec<T> = descriptors
+        .filter(|d| {
+            let full_name = full_name(d);
+            full_name == name || full_name.ends_with(&suffix)
+        })
+        .collect();
+
+    if let Some(index) = candidates.iter().position(|d| full_name(d) == name) {
+        return Ok(candidates.swap_remove(index));
+    }
+
+    match candidates.len() {
+        1 => Ok(candidates.remove(0)),
+        0 => eyre::bail!("Unknown {} {name}", kind.to_lowercase()),
+        _ => eyre::bail!(
+            "{kind} {name} is ambiguous, use one of: {}",
+            candidates
+                .iter()
+                .map(&full_name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// A gRPC service
+#[derive(Debug, Clone, Serialize)]
+pub struct Service {
+    /// Full name of the service, e.g. `weather.Forecasts`
+    pub name: String,
+    /// Proto file defining the service
+    pub file: String,
+    /// Methods of the service in order of definition
+    pub methods: Vec<Method>,
+}
+
+/// A method of a gRPC service
+#[derive(Debug, Clone, Serialize)]
+pub struct Method {
+    /// Symbol passed to `grpcurl` and `ghz --call`, e.g.
+    /// `weather.Forecasts/Get`
+    pub call: String,
+    /// Full name of the request message
+    pub input: String,
+    /// Full name of the response message
+    pub output: String,
+    /// Whether the client sends a stream of requests
+    pub client_streaming: bool,
+    /// Whether the server responds with a stream
+    pub server_streaming: bool,
+}
@@ -1,113 +1,1246 @@
 // (c) Copyright 2023 Helsing GmbH. All rights reserved.
 
-use eyre::{ensure, Context};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::IsTerminal,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Instant, SystemTime},
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use eyre::{ensure, Context, ContextCompat};
+use reqwest::{
+    header::{HeaderName, HeaderValue, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE},
+    Body, Method, Request, Response, StatusCode,
+};
 use serde::{Deserialize, Serialize};
+use tokio::{fs, io::AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use tracing::{field::Empty, Span};
 use url::Url;
 
-use super::Registry;
-use crate::{manifest::Dependency, package::Package};
+use super::{
+    credentials::{self, CredentialStore},
+    Archival, Layout, Receipt, Registry, RetryConfig, RetryTransport, SearchResult, SigV4Config,
+    SigV4Transport, Transport, CHECKSUM_SHA256,
+};
+use crate::{
+    error::ErrorCode,
+    manifest::Dependency,
+    package::{Digest, DigestAlgorithm, Package, PackageArchive, PackageId},
+};
 
 /// The registry implementation for artifactory
-pub struct Artifactory(ArtifactoryConfig);
+pub struct Artifactory {
+    config: ArtifactoryConfig,
+    transport: Arc<dyn Transport>,
+    retry: RetryConfig,
+}
+
+impl Artifactory {
+    /// Creates an artifactory registry that sends its requests via `transport`
+    pub fn with_transport(config: ArtifactoryConfig, transport: impl Transport + 'static) -> Self {
+        Self {
+            config,
+            transport: Arc::new(transport),
+            retry: RetryConfig::disabled(),
+        }
+    }
+
+    /// Retries requests that failed transiently, see [`RetryConfig`]
+    pub fn with_retry(self, retry: RetryConfig) -> Self {
+        Self {
+            transport: Arc::new(RetryTransport::new(self.transport, retry.clone())),
+            retry,
+            ..self
+        }
+    }
+
+    /// Creates an artifactory registry that sends its requests with `client`,
+    /// signing them if configured
+    pub fn with_client(config: ArtifactoryConfig, client: reqwest::Client) -> Self {
+        match config.sigv4.clone() {
+            Some(sigv4) => Self::with_transport(config, SigV4Transport::new(client, sigv4)),
+            None => Self::with_transport(config, client),
+        }
+    }
+
+    /// Sends `request` with the archive of `package` as body
+    ///
+    /// The archive is streamed, so it is opened again for every retry.
+    async fn upload(&self, request: &Request, package: &Package) -> eyre::Result<Response> {
+        self.retry
+            .send(|| async {
+                let mut request = request.try_clone().wrap_err("Failed to copy request")?;
+
+                let tgz = fs::File::open(package.tgz.path())
+                    .await
+                    .wrap_err("Failed to open archive")?;
+
+                *request.body_mut() = Some(Body::wrap_stream(ReaderStream::new(tgz)));
+
+                self.transport.send(request).await
+            })
+            .await
+    }
+
+    /// Creates a request to artifactory carrying the configured credentials
+    ///
+    /// Requests to registries authenticating with SigV4 are signed by the
+    /// transport instead.
+    fn request(&self, method: Method, uri: Url) -> eyre::Result<Request> {
+        if self.config.sigv4.is_some() {
+            return Ok(Request::new(method, uri));
+        }
+
+        let password = self.config.password()?;
+
+        let (header, value) = match self.config.authentication.unwrap_or_default() {
+            Authentication::Basic => {
+                let credentials = BASE64.encode(format!("{}:{password}", self.config.username));
+                (AUTHORIZATION, format!("Basic {credentials}"))
+            }
+            Authentication::Bearer => (AUTHORIZATION, format!("Bearer {password}")),
+            Authentication::ApiKey => (HeaderName::from_static(API_KEY), password),
+        };
+
+        let mut value =
+            HeaderValue::from_str(&value).wrap_err("Invalid artifactory credentials")?;
+
+        value.set_sensitive(true);
+
+        let mut request = Request::new(method, uri);
+
+        request.headers_mut().insert(header, value);
+
+        Ok(request)
+    }
+
+    /// Constructs the uri of a package archive in a repository
+    fn artifact_uri(
+        &self,
+        repository: &str,
+        package: &str,
+        version: &semver::Version,
+    ) -> eyre::Result<Url> {
+        self.config.artifact_url(repository, package, version)
+    }
+
+    /// Exchanges the stored credentials for a short-lived access token that
+    /// is only allowed to deploy to `repository`
+    async fn scoped_token(&self, repository: &str, ttl: u64) -> eyre::Result<String> {
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            access_token: String,
+        }
+
+        let uri = self
+            .config
+            .url
+            .join("/access/api/v1/tokens")
+            .wrap_err("Failed to construct token uri")?;
+
+        let body = serde_json::json!({
+            "scope": format!("artifact:{repository}:w"),
+            "expires_in": ttl,
+            "description": format!("buffrs publish to {repository}"),
+        });
+
+        let mut request = self.request(Method::POST, uri)?;
+
+        request
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        *request.body_mut() = Some(body.to_string().into());
+
+        let response = self.transport.send(request).await?;
+
+        if !response.status().is_success() {
+            return Err(eyre::eyre!(
+                "Failed to obtain a scoped token for {repository}: {}",
+                response.status()
+            ))
+            .wrap_err(ErrorCode::PublishRejected);
+        }
+
+        let token: TokenResponse = response
+            .json()
+            .await
+            .wrap_err("Failed to parse scoped token response")?;
+
+        Ok(token.access_token)
+    }
+
+    /// Looks for near misses of a dependency that could not be found, e.g.
+    /// other versions in its repository or other repositories containing it
+    ///
+    /// This is best effort, lookups that fail are skipped.
+    async fn suggestions(&self, dependency: &Dependency, wanted: &semver::Version) -> Vec<String> {
+        #[derive(Deserialize)]
+        struct Search {
+            results: Vec<StorageChild>,
+        }
+
+        let mut suggestions = vec![];
+
+        let package = &dependency.package;
+        let repository = &dependency.manifest.repository;
+
+        if let Ok(versions) = self.versions(repository, package).await {
+            let closest = versions.iter().filter(|v| *v != wanted).min_by_key(|v| {
+                (
+                    v.major.abs_diff(wanted.major),
+                    v.minor.abs_diff(wanted.minor),
+                    v.patch.abs_diff(wanted.patch),
+                )
+            });
+
+            if let Some(closest) = closest {
+                suggestions.push(format!("did you mean {package}@{closest}?"));
+            }
+
+            if !versions.is_empty() {
+                let available: Vec<String> = versions
+                    .iter()
+                    .rev()
+                    .take(5)
+                    .map(|v| v.to_string())
+                    .collect();
+
+                suggestions.push(format!(
+                    "{repository} contains {package}@{}",
+                    available.join(", ")
+                ));
+            }
+        }
+
+        let search = async {
+            let mut uri = Url::parse(&format!("{}/api/search/artifact", self.config.base()))?;
+
+            uri.query_pairs_mut()
+                .append_pair("name", &self.config.layout().file_name(package, wanted));
+
+            self.get_json::<Search>(uri.as_str()).await
+        };
+
+        if let Ok(search) = search.await {
+            let mut repositories: Vec<&str> = search
+                .results
+                .iter()
+                .filter_map(|r| r.uri.split("/api/storage/").nth(1)?.split('/').next())
+                .filter(|r| r != repository)
+                .collect();
+
+            repositories.sort();
+            repositories.dedup();
+
+            for other in repositories {
+                suggestions.push(format!("{package}@{wanted} exists in repository {other}"));
+            }
+        }
+
+        suggestions
+    }
+
+    /// SHA-256 checksum of the archive stored at `uri`, if there is one
+    async fn stored_checksum(&self, uri: &Url) -> eyre::Result<Option<String>> {
+        let response = self
+            .transport
+            .send(self.request(Method::HEAD, uri.clone())?)
+            .await?;
+
+        if !response.status().is_success() {
+            return Ok(None);
+        }
+
+        Ok(response
+            .headers()
+            .get(CHECKSUM_SHA256)
+            .and_then(|checksum| checksum.to_str().ok())
+            .map(str::to_owned))
+    }
+
+    /// Fetches and parses a json document from artifactory
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, uri: &str) -> eyre::Result<T> {
+        let uri: Url = uri.parse().wrap_err("Failed to construct uri")?;
+
+        let response = self.transport.send(self.request(Method::GET, uri)?).await?;
+
+        ensure!(response.status().is_success(), "{}", response.status());
+
+        response.json().await.wrap_err("Failed to parse response")
+    }
+
+    /// Resolves the repository packages are deployed to when publishing to
+    /// `repository`
+    ///
+    /// Virtual repositories only aggregate other repositories and reject
+    /// uploads, so publishes are routed to their default deployment
+    /// repository instead.
+    async fn deployment_repository(&self, repository: &str) -> eyre::Result<String> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RepositoryInfo {
+            rclass: String,
+            default_deployment_repo: Option<String>,
+        }
+
+        if self.config.sigv4.is_some() {
+            return Ok(repository.to_owned());
+        }
+
+        let uri: Url = format!("{}/api/repositories/{repository}", self.config.base())
+            .parse()
+            .wrap_err("Failed to construct repository uri")?;
+
+        let response = self.transport.send(self.request(Method::GET, uri)?).await?;
+
+        // Not every user may inspect repositories, fall back to the given one
+        if !response.status().is_success() {
+            tracing::debug!(
+                "failed to inspect repository {repository}: {}",
+                response.status()
+            );
+
+            return Ok(repository.to_owned());
+        }
+
+        let Ok(info) = response.json::<RepositoryInfo>().await else {
+            return Ok(repository.to_owned());
+        };
+
+        if info.rclass != "virtual" {
+            return Ok(repository.to_owned());
+        }
+
+        match info.default_deployment_repo {
+            Some(local) if !local.is_empty() => {
+                tracing::info!(
+                    ":: {repository} is a virtual repository, publishing to {local}"
+                );
+
+                Ok(local)
+            }
+            _ => Err(eyre::eyre!(
+                "{repository} is a virtual repository without default deployment repository, publish to one of the repositories it aggregates instead"
+            ))
+            .wrap_err(ErrorCode::PublishRejected),
+        }
+    }
+}
 
 #[async_trait::async_trait]
 impl Registry for Artifactory {
     /// Downloads a package from artifactory
+    #[tracing::instrument(
+        level = "debug",
+        name = "download",
+        skip_all,
+        fields(
+            package = %dependency.package,
+            version = Empty,
+            repository = %dependency.manifest.repository,
+            bytes = Empty,
+            duration_ms = Empty,
+        )
+    )]
     async fn download(&self, dependency: Dependency) -> eyre::Result<Package> {
-        let artifact_uri: Url = format!(
-            "{}/{}/{}/{}-{}.tgz",
-            self.0.url,
+        let started = Instant::now();
+
+        let version = dependency.resolved_version()?.clone();
+
+        Span::current().record("version", tracing::field::display(&version));
+
+        let artifact_uri = self.artifact_uri(
+            &dependency.manifest.repository,
+            &dependency.package,
+            &version,
+        )?;
+
+        let request = self.request(Method::GET, artifact_uri)?;
+
+        let mut response = self.transport.send(request).await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            let suggestions: String = self
+                .suggestions(&dependency, &version)
+                .await
+                .iter()
+                .map(|suggestion| format!("\n  - {suggestion}"))
+                .collect();
+
+            return Err(eyre::eyre!(
+                "Failed to fetch {dependency}: {}{suggestions}",
+                response.status()
+            ))
+            .wrap_err(ErrorCode::DownloadFailed);
+        }
+
+        if !response.status().is_success() {
+            return Err(eyre::eyre!(
+                "Failed to fetch {dependency}: {}",
+                response.status()
+            ))
+            .wrap_err(ErrorCode::DownloadFailed);
+        }
+
+        let checksum = response
+            .headers()
+            .get(CHECKSUM_SHA256)
+            .and_then(|checksum| checksum.to_str().ok())
+            .map(|checksum| format!("{}:{checksum}", DigestAlgorithm::Sha256).parse::<Digest>())
+            .transpose()
+            .wrap_err_with(|| format!("Invalid checksum of {dependency}"))
+            .wrap_err(ErrorCode::IntegrityMismatch)?;
+
+        let tgz = PackageArchive::new()?;
+
+        let mut file = fs::File::create(tgz.path())
+            .await
+            .wrap_err("Failed to create archive")?;
+
+        let mut bytes = 0;
+
+        while let Some(chunk) = response.chunk().await.wrap_err("Failed to download tar")? {
+            bytes += chunk.len();
+
+            file.write_all(&chunk)
+                .await
+                .wrap_err("Failed to write archive")?;
+        }
+
+        file.flush().await.wrap_err("Failed to write archive")?;
+
+        Span::current()
+            .record("bytes", bytes)
+            .record("duration_ms", started.elapsed().as_millis());
+
+        tracing::debug!("downloaded dependency {dependency}");
+
+        let package = Package::new(dependency.package, version, tgz);
+
+        // Artifactory reports the checksum it computed when the package was
+        // deployed, which lets the store verify the download
+        match checksum {
+            Some(checksum) => Ok(package.with_digest(checksum)),
+            None => {
+                tracing::warn!(
+                    "! {}@{} has no checksum and can not be verified",
+                    package.name,
+                    package.version
+                );
+
+                Ok(package)
+            }
+        }
+    }
+
+    /// Publishes a package to artifactory
+    #[tracing::instrument(
+        level = "debug",
+        name = "publish",
+        skip_all,
+        fields(
+            package = %package.name,
+            version = %package.version,
+            repository = Empty,
+            bytes = Empty,
+            duration_ms = Empty,
+        )
+    )]
+    async fn publish(&self, package: Package, repository: String) -> eyre::Result<Receipt> {
+        let started = Instant::now();
+
+        let repository = self.deployment_repository(&repository).await?;
+
+        let artifact_uri = self.artifact_uri(&repository, &package.name, &package.version)?;
+
+        let size = package.tgz.size().await?;
+
+        Span::current()
+            .record("repository", repository.as_str())
+            .record("bytes", size);
+
+        // Artifactory rejects the upload if its contents do not match
+        let digest = package.tgz.digest(DigestAlgorithm::Sha256).await?;
+
+        // Retried publishes succeed without overwriting the archive, releases
+        // are never replaced
+        let stored_checksum = self.stored_checksum(&artifact_uri).await?;
+
+        if stored_checksum
+            .as_ref()
+            .is_some_and(|c| *c != digest.to_hex())
+        {
+            return Err(eyre::eyre!(
+                "{repository}/{}@{} is already published with different contents",
+                package.name,
+                package.version
+            ))
+            .wrap_err(ErrorCode::PublishRejected);
+        }
+
+        if stored_checksum.is_some() {
+            tracing::info!(
+                ":: {repository}/{}@{} is already published",
+                package.name,
+                package.version
+            );
+
+            return Ok(Receipt {
+                package: package.name,
+                version: package.version,
+                repository,
+                url: artifact_uri,
+                digest,
+                size,
+                metadata: serde_json::Value::Null,
+                already_published: true,
+            });
+        }
+
+        let mut request = self.request(Method::PUT, artifact_uri.clone())?;
+
+        if let Some(ttl) = self
+            .config
+            .publish_token_ttl
+            .filter(|_| self.config.sigv4.is_none())
+        {
+            let token = self.scoped_token(&repository, ttl).await?;
+
+            let mut authorization = HeaderValue::from_str(&format!("Bearer {token}"))
+                .wrap_err("Invalid scoped token")?;
+
+            authorization.set_sensitive(true);
+
+            request.headers_mut().remove(API_KEY);
+            request.headers_mut().insert(AUTHORIZATION, authorization);
+        }
+
+        request.headers_mut().insert(CONTENT_LENGTH, size.into());
+
+        let checksum =
+            HeaderValue::from_str(&digest.to_hex()).wrap_err("Invalid package checksum")?;
+
+        request
+            .headers_mut()
+            .insert(CHECKSUM_SHA256, checksum.clone());
+
+        // Lets SigV4 sign the streamed body without reading it twice
+        if self.config.sigv4.is_some() {
+            request
+                .headers_mut()
+                .insert("x-amz-content-sha256", checksum);
+        }
+
+        let response = self.upload(&request, &package).await?;
+
+        if !response.status().is_success() {
+            return Err(eyre::eyre!(
+                "Failed to publish {}: {}",
+                package.name,
+                response.status()
+            ))
+            .wrap_err(ErrorCode::PublishRejected);
+        }
+
+        // Artifactory describes the deployed artifact, e.g. its checksums
+        let metadata = response.json().await.unwrap_or_default();
+
+        Span::current().record("duration_ms", started.elapsed().as_millis());
+
+        tracing::info!(
+            "+ pubished {}/{}@{}",
+            repository,
+            package.name,
+            package.version
+        );
+
+        Ok(Receipt {
+            package: package.name,
+            version: package.version,
+            repository,
+            url: artifact_uri,
+            digest,
+            size,
+            metadata,
+            already_published: false,
+        })
+    }
+
+    /// Lists the versions of a package using the artifactory storage api
+    async fn versions(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        #[derive(Deserialize)]
+        struct Folder {
+            children: Vec<StorageChild>,
+        }
+
+        let layout = self.config.layout();
+
+        let uri: Url = format!(
+            "{}/api/storage/{repository}/{}",
+            self.config.base(),
+            layout.folder(package)
+        )
+        .trim_end_matches('/')
+        .parse()
+        .wrap_err("Failed to construct storage uri")?;
+
+        let response = self.transport.send(self.request(Method::GET, uri)?).await?;
+
+        // Packages that were never published have no folder
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(vec![]);
+        }
+
+        if !response.status().is_success() {
+            return Err(eyre::eyre!(
+                "Failed to list versions of {package} in {repository}: {}",
+                response.status()
+            ))
+            .wrap_err(ErrorCode::DownloadFailed);
+        }
+
+        let folder: Folder = response
+            .json()
+            .await
+            .wrap_err("Failed to parse folder listing")?;
+
+        let mut versions: Vec<semver::Version> = folder
+            .children
+            .iter()
+            .filter_map(|c| layout.entry_version(package, c.uri.trim_start_matches('/')))
+            .collect();
+
+        versions.sort();
+
+        versions.dedup();
+
+        Ok(versions)
+    }
+
+    /// Reads the creation time of a package from the artifactory storage api
+    async fn published_at(&self, dependency: &Dependency) -> eyre::Result<Option<SystemTime>> {
+        #[derive(Deserialize)]
+        struct StorageInfo {
+            created: String,
+        }
+
+        let uri: Url = format!(
+            "{}/api/storage/{}/{}",
+            self.config.base(),
             dependency.manifest.repository,
-            dependency.package,
-            dependency.package,
-            dependency.manifest.version
+            self.config
+                .layout()
+                .path(&dependency.package, dependency.resolved_version()?)
         )
         .parse()
-        .wrap_err("Failed to construct artifact uri")?;
+        .wrap_err("Failed to construct storage uri")?;
 
-        let response = reqwest::Client::new()
-            .get(artifact_uri.clone())
-            .basic_auth(self.0.username.to_owned(), Some(self.0.password()?))
-            .send()
-            .await?;
+        let request = self.request(Method::GET, uri)?;
+
+        let response = self.transport.send(request).await?;
 
         ensure!(
             response.status().is_success(),
-            "Failed to fetch {dependency}"
+            "Failed to look up {dependency}: {}",
+            response.status()
         );
 
-        tracing::debug!("downloaded dependency {dependency}");
+        let info: StorageInfo = response
+            .json()
+            .await
+            .wrap_err("Failed to parse storage info")?;
 
-        let tgz = response.bytes().await.wrap_err("Failed to download tar")?;
+        let created = chrono::DateTime::parse_from_rfc3339(&info.created)
+            .or_else(|_| chrono::DateTime::parse_from_str(&info.created, "%Y-%m-%dT%H:%M:%S%.f%z"))
+            .wrap_err_with(|| format!("Invalid creation time of {dependency}"))?;
 
-        Ok(Package::new(
-            dependency.package,
-            dependency.manifest.version,
-            tgz,
-        ))
+        Ok(Some(created.into()))
     }
 
-    /// Publishes a package to artifactory
-    async fn publish(&self, package: Package, repository: String) -> eyre::Result<()> {
-        let artifact_uri: Url = format!(
-            "{}/{}/{}/{}-{}.tgz",
-            self.0.url, repository, package.name, package.name, package.version
+    /// Reads the archival notice from the properties of the package folder
+    async fn archival(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Option<Archival>> {
+        #[derive(Deserialize)]
+        struct Properties {
+            properties: HashMap<String, Vec<String>>,
+        }
+
+        let mut uri: Url = format!(
+            "{}/api/storage/{repository}/{}",
+            self.config.base(),
+            self.config.layout().folder(package)
         )
+        .trim_end_matches('/')
         .parse()
-        .wrap_err("Failed to construct artifact uri")?;
+        .wrap_err("Failed to construct storage uri")?;
 
-        let response = reqwest::Client::new()
-            .put(artifact_uri.clone())
-            .basic_auth(self.0.username.to_owned(), Some(self.0.password()?))
-            .body(package.tgz)
-            .send()
-            .await?;
+        uri.set_query(Some("properties"));
+
+        let response = self.transport.send(self.request(Method::GET, uri)?).await?;
+
+        // Folders without any properties are reported as missing
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
 
         ensure!(
             response.status().is_success(),
-            "Failed to publish {}",
-            package.name
+            "Failed to look up properties of {package} in {repository}: {}",
+            response.status()
         );
 
-        tracing::info!(
-            "+ pubished {}/{}@{}",
-            repository,
-            package.name,
-            package.version
+        let mut properties = response
+            .json::<Properties>()
+            .await
+            .wrap_err("Failed to parse properties")?
+            .properties;
+
+        let mut property = |name: &str| properties.remove(name).and_then(|v| v.into_iter().next());
+
+        if property(ARCHIVED_PROPERTY).as_deref() != Some("true") {
+            return Ok(None);
+        }
+
+        Ok(Some(Archival {
+            replacement: property(REPLACEMENT_PROPERTY).and_then(|r| r.parse().ok()),
+            message: property(MESSAGE_PROPERTY),
+        }))
+    }
+
+    /// Searches packages in proto repositories using an AQL query
+    ///
+    /// Only repositories following the `<group>-proto-<stability>` naming
+    /// scheme are searched.
+    async fn search(&self, term: &str) -> eyre::Result<Vec<SearchResult>> {
+        #[derive(Deserialize)]
+        struct Aql {
+            results: Vec<AqlItem>,
+        }
+
+        #[derive(Deserialize)]
+        struct AqlItem {
+            repo: String,
+            path: String,
+            name: String,
+        }
+
+        ensure!(
+            !term.is_empty() && term.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'),
+            "Invalid search term {term:?}, package names only contain letters, digits and dashes"
+        );
+
+        let layout = self.config.layout();
+
+        let query = format!(
+            r#"items.find({{"repo":{{"$match":"*-proto-*"}},"name":{{"$match":"{}"}},"depth":{}}}).include("repo","path","name")"#,
+            layout.file_pattern(term),
+            layout.depth()
         );
 
+        let uri: Url = format!("{}/api/search/aql", self.config.base())
+            .parse()
+            .wrap_err("Failed to construct search uri")?;
+
+        let mut request = self.request(Method::POST, uri)?;
+
+        request
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+
+        *request.body_mut() = Some(Body::from(query));
+
+        let response = self.transport.send(request).await?;
+
+        ensure!(
+            response.status().is_success(),
+            "Failed to search for {term}: {}",
+            response.status()
+        );
+
+        let aql: Aql = response
+            .json()
+            .await
+            .wrap_err("Failed to parse search results")?;
+
+        let mut latest: HashMap<(PackageId, String), semver::Version> = HashMap::new();
+
+        for item in aql.results {
+            // Items at the root of a repository have `.` as path
+            let path = match item.path.as_str() {
+                "." => item.name,
+                folder => format!("{folder}/{}", item.name),
+            };
+
+            let Some((package, version)) = layout.parse_path(&path) else {
+                continue;
+            };
+
+            if !package.as_str().contains(term) {
+                continue;
+            }
+
+            latest
+                .entry((package, item.repo))
+                .and_modify(|latest| *latest = version.clone().max(latest.clone()))
+                .or_insert(version);
+        }
+
+        let mut results: Vec<SearchResult> = latest
+            .into_iter()
+            .map(|((package, repository), latest)| SearchResult {
+                package,
+                repository,
+                latest,
+            })
+            .collect();
+
+        results.sort();
+
+        Ok(results)
+    }
+
+    /// Stores the archival notice in properties of the package folder
+    async fn archive(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        archival: Option<&Archival>,
+    ) -> eyre::Result<()> {
+        let mut uri: Url = format!(
+            "{}/api/storage/{repository}/{}",
+            self.config.base(),
+            self.config.layout().folder(package)
+        )
+        .trim_end_matches('/')
+        .parse()
+        .wrap_err("Failed to construct storage uri")?;
+
+        let (method, properties) =
+            match archival {
+                Some(archival) => {
+                    let mut properties = vec![format!("{ARCHIVED_PROPERTY}=true")];
+
+                    properties.extend(archival.replacement.iter().map(|r| {
+                        format!("{REPLACEMENT_PROPERTY}={}", escape_property(r.as_str()))
+                    }));
+
+                    properties.extend(
+                        archival
+                            .message
+                            .iter()
+                            .map(|m| format!("{MESSAGE_PROPERTY}={}", escape_property(m))),
+                    );
+
+                    (Method::PUT, properties.join(";"))
+                }
+                None => (
+                    Method::DELETE,
+                    [ARCHIVED_PROPERTY, REPLACEMENT_PROPERTY, MESSAGE_PROPERTY].join(","),
+                ),
+            };
+
+        uri.query_pairs_mut()
+            .append_pair("properties", &properties)
+            .append_pair("recursive", "0");
+
+        let response = self.transport.send(self.request(method, uri)?).await?;
+
+        if !response.status().is_success() {
+            return Err(eyre::eyre!(
+                "Failed to update {package} in {repository}: {}",
+                response.status()
+            ))
+            .wrap_err(ErrorCode::PublishRejected);
+        }
+
         Ok(())
     }
-}
 
-impl From<ArtifactoryConfig> for Artifactory {
-    fn from(cfg: ArtifactoryConfig) -> Self {
-        Self(cfg)
+    /// Finds yanked versions with a property search, which searches the
+    /// whole repository
+    async fn yanked(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        #[derive(Deserialize)]
+        struct Search {
+            results: Vec<SearchItem>,
+        }
+
+        #[derive(Deserialize)]
+        struct SearchItem {
+            uri: String,
+        }
+
+        let mut uri: Url = format!("{}/api/search/prop", self.config.base())
+            .parse()
+            .wrap_err("Failed to construct search uri")?;
+
+        uri.query_pairs_mut()
+            .append_pair(YANKED_PROPERTY, "true")
+            .append_pair("repos", repository);
+
+        let response = self.transport.send(self.request(Method::GET, uri)?).await?;
+
+        // Searches without results are reported as missing
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(vec![]);
+        }
+
+        ensure!(
+            response.status().is_success(),
+            "Failed to look up yanked versions of {package} in {repository}: {}",
+            response.status()
+        );
+
+        let search: Search = response
+            .json()
+            .await
+            .wrap_err("Failed to parse search results")?;
+
+        let prefix = format!("/api/storage/{repository}/");
+
+        let mut versions: Vec<semver::Version> = search
+            .results
+            .iter()
+            .filter_map(|item| {
+                let (_, path) = item.uri.split_once(&prefix)?;
+                let (yanked, version) = self.config.layout().parse_path(path)?;
+                (&yanked == package).then_some(version)
+            })
+            .collect();
+
+        versions.sort();
+
+        Ok(versions)
     }
+
+    /// Marks the archive of the version with a property, so it stays
+    /// downloadable for projects that locked it
+    async fn yank(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        version: &semver::Version,
+        yanked: bool,
+    ) -> eyre::Result<()> {
+        let mut uri: Url = format!(
+            "{}/api/storage/{repository}/{}",
+            self.config.base(),
+            self.config.layout().path(package, version)
+        )
+        .parse()
+        .wrap_err("Failed to construct storage uri")?;
+
+        let (method, properties) = match yanked {
+            true => (Method::PUT, format!("{YANKED_PROPERTY}=true")),
+            false => (Method::DELETE, YANKED_PROPERTY.to_owned()),
+        };
+
+        uri.query_pairs_mut().append_pair("properties", &properties);
+
+        let response = self.transport.send(self.request(method, uri)?).await?;
+
+        ensure!(
+            response.status() != StatusCode::NOT_FOUND,
+            "{package}@{version} was not published to {repository}"
+        );
+
+        if !response.status().is_success() {
+            return Err(eyre::eyre!(
+                "Failed to update {package}@{version} in {repository}: {}",
+                response.status()
+            ))
+            .wrap_err(ErrorCode::PublishRejected);
+        }
+
+        Ok(())
+    }
+
+    /// Adds the properties to the archive of the version, keeping its other
+    /// properties
+    async fn annotate(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        version: &semver::Version,
+        properties: &BTreeMap<String, String>,
+    ) -> eyre::Result<()> {
+        let mut uri: Url = format!(
+            "{}/api/storage/{repository}/{}",
+            self.config.base(),
+            self.config.layout().path(package, version)
+        )
+        .parse()
+        .wrap_err("Failed to construct storage uri")?;
+
+        let properties: Vec<String> = properties
+            .iter()
+            .map(|(key, value)| format!("{key}={}", escape_property(value)))
+            .collect();
+
+        uri.query_pairs_mut()
+            .append_pair("properties", &properties.join(";"));
+
+        let response = self.transport.send(self.request(Method::PUT, uri)?).await?;
+
+        ensure!(
+            response.status().is_success(),
+            "Failed to set properties of {package}@{version} in {repository}: {}",
+            response.status()
+        );
+
+        Ok(())
+    }
+}
+
+/// Environment variable the password is read from if the keyring can not be
+/// accessed, unless configured otherwise
+pub const PASSWORD_ENV: &str = "BUFFRS_PASSWORD";
+
+/// Header carrying the api key of a user
+const API_KEY: &str = "x-jfrog-art-api";
+
+/// Property marking a yanked version
+const YANKED_PROPERTY: &str = "buffrs.yanked";
+/// Property marking an archived package
+const ARCHIVED_PROPERTY: &str = "buffrs.archived";
+/// Property naming the replacement of an archived package
+const REPLACEMENT_PROPERTY: &str = "buffrs.replacement";
+/// Property explaining why a package was archived
+const MESSAGE_PROPERTY: &str = "buffrs.message";
+
+/// Escapes the characters separating properties in the artifactory api
+fn escape_property(value: &str) -> String {
+    value
+        .chars()
+        .flat_map(|c| match c {
+            '\\' | ',' | '|' | '=' | ';' => vec!['\\', c],
+            c => vec![c],
+        })
+        .collect()
+}
+
+/// Entry of a folder listing or search result of the artifactory storage api
+#[derive(Deserialize)]
+struct StorageChild {
+    uri: String,
 }
 
 /// Authentication data and settings for the artifactory registry
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ArtifactoryConfig {
+    /// Base url of the artifactory instance, see [`ArtifactoryConfig::canonicalize`]
+    #[serde(deserialize_with = "deserialize_url")]
     pub url: Url,
+    /// Username used to authenticate
+    #[serde(default)]
     pub username: String,
+    /// Lifetime in seconds of the repository scoped token minted for each
+    /// publish. If unset, publishing uses the stored credentials directly.
+    pub publish_token_ttl: Option<u64>,
+    /// Signs requests with AWS Signature V4 instead of sending the stored
+    /// credentials, for registries behind S3 or an API gateway
+    pub sigv4: Option<SigV4Config>,
+    /// Path of package archives within repositories, for instances enforcing
+    /// a repository layout (defaults to `{package}/{package}-{version}.tgz`)
+    pub layout: Option<Layout>,
+    /// How the stored credentials are sent (defaults to `basic`)
+    pub authentication: Option<Authentication>,
+    /// Where the password is stored, defaults to the keyring unless the
+    /// machine is headless (see [`CredentialStore::detect`])
+    pub credential_store: Option<CredentialStore>,
+    /// Environment variable the password is read from if the keyring can
+    /// not be accessed (defaults to `BUFFRS_PASSWORD`)
+    pub password_env: Option<String>,
+}
+
+/// Ways of sending the stored credentials to artifactory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Authentication {
+    /// Username and password or identity token using HTTP basic auth
+    #[default]
+    Basic,
+    /// Access token in the `Authorization` header, no username required
+    Bearer,
+    /// Api key in the `X-JFrog-Art-Api` header, no username required
+    ApiKey,
 }
 
 impl ArtifactoryConfig {
-    /// Creates a new artifactory config in the system keyring
-    pub fn new(url: Url, username: String, password: String) -> eyre::Result<Self> {
-        let cfg = Self { url, username };
+    /// Creates a new artifactory config, storing the password in
+    /// `credential_store` or the one detected for this machine
+    pub fn new(
+        url: Url,
+        username: String,
+        password: String,
+        credential_store: Option<CredentialStore>,
+    ) -> eyre::Result<Self> {
+        let cfg = Self {
+            url: Self::canonicalize(url)?,
+            username,
+            publish_token_ttl: None,
+            sigv4: None,
+            layout: None,
+            authentication: None,
+            credential_store,
+            password_env: None,
+        };
+
+        if cfg.credential_store() == CredentialStore::File {
+            credentials::store(cfg.url.as_str(), &cfg.username, password.clone())?;
+            passwords().insert(cfg.keyring_key(), password);
+
+            return Ok(cfg);
+        }
+
+        // Remove chunks of a previous, longer password
+        cfg.clone().clear().ok();
+
+        let chunks: Vec<String> = password
+            .chars()
+            .collect::<Vec<_>>()
+            .chunks(KEYRING_CHUNK_LEN)
+            .map(|chunk| chunk.iter().collect())
+            .collect();
+
+        let stored = if chunks.len() > 1 {
+            for (index, chunk) in chunks.iter().enumerate() {
+                cfg.chunk_entry(index)?
+                    .set_password(chunk)
+                    .wrap_err("Failed to store password in keyring")?;
+            }
+
+            format!("{KEYRING_CHUNKED}{}", chunks.len())
+        } else {
+            password.clone()
+        };
 
         cfg.entry()?
-            .set_password(&password)
+            .set_password(&stored)
             .wrap_err("Failed to store password in keyring")?;
 
+        passwords().insert(cfg.keyring_key(), password);
+
         Ok(cfg)
     }
 
-    /// Clears the artifactory config from the system keyring
+    /// Brings a registry url into the form artifact uris are built from
+    ///
+    /// Only http and https urls without credentials, query or fragment are
+    /// accepted. Trailing slashes are removed, as is anything following an
+    /// `/artifactory` segment, e.g. a repository or api path pasted from the
+    /// web interface. Artifactory cloud instances (`*.jfrog.io`) given by
+    /// their bare host get the `/artifactory` path they serve the api at.
+    pub fn canonicalize(mut url: Url) -> eyre::Result<Url> {
+        ensure!(
+            matches!(url.scheme(), "http" | "https"),
+            "Unsupported registry url {url}, expected an http or https url"
+        );
+
+        ensure!(
+            url.username().is_empty() && url.password().is_none(),
+            "Registry urls must not contain credentials, use buffrs login instead"
+        );
+
+        ensure!(
+            url.query().is_none() && url.fragment().is_none(),
+            "Registry urls must not contain a query or fragment"
+        );
+
+        let mut segments: Vec<String> = url
+            .path_segments()
+            .into_iter()
+            .flatten()
+            .filter(|segment| !segment.is_empty())
+            .map(ToOwned::to_owned)
+            .collect();
+
+        if let Some(index) = segments.iter().position(|s| s == "artifactory") {
+            if index + 1 < segments.len() {
+                tracing::warn!(
+                    "! ignoring /{} following /artifactory in the registry url {url}",
+                    segments[index + 1..].join("/")
+                );
+
+                segments.truncate(index + 1);
+            }
+        }
+
+        let cloud = url
+            .host_str()
+            .is_some_and(|host| host.ends_with(".jfrog.io"));
+
+        if cloud && segments.is_empty() {
+            segments.push("artifactory".to_owned());
+        }
+
+        url.set_path(&segments.join("/"));
+
+        Ok(url)
+    }
+
+    /// Where the password is stored
+    pub fn credential_store(&self) -> CredentialStore {
+        self.credential_store
+            .unwrap_or_else(CredentialStore::detect)
+    }
+
+    /// Url of the archive of `package@version` in `repository`
+    pub fn artifact_url(
+        &self,
+        repository: &str,
+        package: &str,
+        version: &semver::Version,
+    ) -> eyre::Result<Url> {
+        format!(
+            "{}/{repository}/{}",
+            self.base(),
+            self.layout().path(package, version)
+        )
+        .parse()
+        .wrap_err("Failed to construct artifact uri")
+    }
+
+    /// Path of package archives within repositories
+    fn layout(&self) -> &Layout {
+        self.layout.as_ref().unwrap_or(&Layout::DEFAULT)
+    }
+
+    /// The registry url without trailing slash, to append api paths to
+    fn base(&self) -> &str {
+        self.url.as_str().trim_end_matches('/')
+    }
+
+    /// Removes the stored password of this artifactory config
     pub fn clear(self) -> eyre::Result<()> {
-        self.entry()?
+        passwords().remove(&self.keyring_key());
+
+        if self.credential_store() == CredentialStore::File {
+            return credentials::remove(self.url.as_str(), &self.username);
+        }
+
+        let entry = self.entry()?;
+
+        if let Some(chunks) = entry.get_password().ok().as_deref().and_then(chunk_count) {
+            for index in 0..chunks {
+                self.chunk_entry(index)?.delete_password().ok();
+            }
+        }
+
+        entry
             .delete_password()
             .wrap_err("Failed to delete password from keyring")?;
 
@@ -116,9 +1249,103 @@ impl ArtifactoryConfig {
 
     /// Loads the password for this artifactory config
     fn password(&self) -> eyre::Result<String> {
-        self.entry()?
-            .get_password()
+        let load = |cfg: &Self| -> eyre::Result<String> {
+            let password = cfg.entry()?.get_password()?;
+
+            let Some(chunks) = chunk_count(&password) else {
+                return Ok(password);
+            };
+
+            (0..chunks)
+                .map(|index| Ok(cfg.chunk_entry(index)?.get_password()?))
+                .collect()
+        };
+
+        // Logins of previous releases kept the trailing slash of the url
+        let legacy = || {
+            let mut url = self.url.clone();
+            url.set_path(&format!("{}/", url.path().trim_end_matches('/')));
+
+            load(&Self {
+                url,
+                ..self.clone()
+            })
+        };
+
+        // Concurrent requests wait for the first read instead of racing it
+        let mut passwords = passwords();
+
+        if let Some(password) = passwords.get(&self.keyring_key()) {
+            return Ok(password.clone());
+        }
+
+        if self.credential_store() == CredentialStore::File {
+            let password = credentials::load(self.url.as_str(), &self.username)?
+                .wrap_err_with(|| format!("No password for {} in the credentials file", self.url))
+                .wrap_err("Failed to load password, please login")
+                .wrap_err(ErrorCode::NotLoggedIn)?;
+
+            passwords.insert(self.keyring_key(), password.clone());
+
+            return Ok(password);
+        }
+
+        let password = load(self)
+            .or_else(|error| legacy().map_err(|_| error))
+            .or_else(|error| self.fallback_password(error))
             .wrap_err("Failed to load password from keyring, please login")
+            .wrap_err(ErrorCode::NotLoggedIn)?;
+
+        passwords.insert(self.keyring_key(), password.clone());
+
+        Ok(password)
+    }
+
+    /// Obtains the password if the keyring failed with `error`, e.g. because
+    /// it is locked or there is no session bus
+    ///
+    /// The password is read from the configured environment variable, or
+    /// else asked for if running interactively. Missing keyring entries are
+    /// not asked for, as those need a login.
+    fn fallback_password(&self, error: eyre::Report) -> eyre::Result<String> {
+        let var = self.password_env.as_deref().unwrap_or(PASSWORD_ENV);
+
+        if let Ok(password) = std::env::var(var) {
+            tracing::debug!("using the password of {} from {var}: {error:#}", self.url);
+            return Ok(password);
+        }
+
+        let missing = error
+            .chain()
+            .any(|cause| matches!(cause.downcast_ref(), Some(keyring::Error::NoEntry)));
+
+        if missing || !std::io::stdin().is_terminal() {
+            return Err(error);
+        }
+
+        tracing::warn!(
+            "! failed to read the password of {} from the keyring: {error:#}",
+            self.url
+        );
+
+        tracing::info!("Please enter your artifactory token:");
+
+        let mut password = String::new();
+
+        std::io::stdin()
+            .read_line(&mut password)
+            .wrap_err("Failed to read token")?;
+
+        let password = password.trim().to_owned();
+
+        ensure!(!password.is_empty(), "No token entered");
+
+        Ok(password)
+    }
+
+    /// Key of the password in the keyring and in [`passwords`]
+    fn keyring_key(&self) -> (String, String) {
+        (self.url.to_string(), self.username.clone())
     }
 
     /// Accesses the keyring entry associated with this artifactory config
@@ -126,4 +1353,47 @@ impl ArtifactoryConfig {
         keyring::Entry::new(self.url.as_str(), &self.username)
             .wrap_err("Failed to load keyring entry")
     }
+
+    /// Accesses the keyring entry holding a chunk of a long password
+    fn chunk_entry(&self, index: usize) -> eyre::Result<keyring::Entry> {
+        keyring::Entry::new(self.url.as_str(), &format!("{}#{index}", self.username))
+            .wrap_err("Failed to load keyring entry")
+    }
+}
+
+/// Deserializes a registry url into its canonical form
+fn deserialize_url<'de, D>(deserializer: D) -> Result<Url, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let url = Url::deserialize(deserializer)?;
+
+    ArtifactoryConfig::canonicalize(url).map_err(|e| serde::de::Error::custom(format!("{e:#}")))
+}
+
+/// Passwords read from the keyring by this process
+///
+/// Every read may prompt the user or query a slow agent, e.g. on macOS, so
+/// each password is only read once, no matter how many packages are
+/// downloaded in parallel.
+static PASSWORDS: Mutex<BTreeMap<(String, String), String>> = Mutex::new(BTreeMap::new());
+
+/// Locks the passwords read from the keyring
+fn passwords() -> MutexGuard<'static, BTreeMap<(String, String), String>> {
+    PASSWORDS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Maximum length of a secret stored in a single keyring entry
+///
+/// The Windows credential manager limits secrets to 2560 bytes of UTF-16,
+/// which tokens issued by identity providers regularly exceed. Longer
+/// passwords are split across several entries on all platforms.
+const KEYRING_CHUNK_LEN: usize = 1024;
+
+/// Marker stored in place of a password that was split into chunks
+const KEYRING_CHUNKED: &str = "buffrs-chunked:";
+
+/// Number of chunks of a password stored by [`ArtifactoryConfig::new`]
+fn chunk_count(stored: &str) -> Option<usize> {
+    stored.strip_prefix(KEYRING_CHUNKED)?.parse().ok()
 }
@@ -0,0 +1,342 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{collections::BTreeMap, path::PathBuf, time::SystemTime};
+
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io::AsyncWriteExt};
+use url::Url;
+
+use super::{Archival, Receipt, Registry, SearchResult};
+use crate::{
+    manifest::Dependency,
+    package::{Digest, Package, PackageId},
+};
+
+/// Property naming who performed an operation, prefixed by the operation
+const USER_PROPERTY: &str = "user";
+/// Property recording when an operation was performed
+const TIMESTAMP_PROPERTY: &str = "timestamp";
+/// Property recording the digest of a published archive
+const DIGEST_PROPERTY: &str = "digest";
+
+/// Settings for recording the operations changing a registry
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AuditConfig {
+    /// File every publish, yank and archival is appended to, one json object
+    /// per line
+    pub log: Option<PathBuf>,
+    /// Records publishes and yanks in properties of the affected archive as
+    /// well, e.g. `buffrs.publish.user` (only supported by artifactory)
+    pub properties: Option<bool>,
+}
+
+/// An operation recorded by an [`AuditedRegistry`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOperation {
+    /// A package was published
+    Publish,
+    /// A version was yanked
+    Yank,
+    /// A yanked version was restored
+    Unyank,
+    /// A package was archived
+    Archive,
+    /// An archived package was restored
+    Unarchive,
+}
+
+impl AuditOperation {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Publish => "publish",
+            Self::Yank => "yank",
+            Self::Unyank => "unyank",
+            Self::Archive => "archive",
+            Self::Unarchive => "unarchive",
+        }
+    }
+}
+
+/// A line of the audit log
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    /// When the operation finished, in RFC 3339 format
+    pub timestamp: String,
+    /// Who performed the operation
+    pub user: String,
+    /// What was done
+    pub operation: AuditOperation,
+    /// Repository containing the package
+    pub repository: String,
+    /// Name of the package
+    pub package: PackageId,
+    /// Affected version, missing for operations on all versions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<semver::Version>,
+    /// Digest of the published archive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<Digest>,
+    /// Url of the published archive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<Url>,
+}
+
+/// Registry wrapper recording every publish, yank and archival performed by
+/// this client, e.g. for regulated environments
+///
+/// The audit log is opened before the operation is performed, so operations
+/// are not carried out if they could not be recorded. Downloads and lookups
+/// are not recorded.
+pub struct AuditedRegistry<R> {
+    inner: R,
+    config: AuditConfig,
+    user: String,
+}
+
+impl<R> AuditedRegistry<R> {
+    /// Wraps a registry, attributing all operations to `user`
+    pub fn new(inner: R, config: AuditConfig, user: String) -> Self {
+        Self {
+            inner,
+            config,
+            user,
+        }
+    }
+
+    /// Opens the audit log for appending, if one is configured
+    async fn open(&self) -> eyre::Result<Option<fs::File>> {
+        let Some(ref path) = self.config.log else {
+            return Ok(None);
+        };
+
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .await
+                .wrap_err_with(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map(Some)
+            .wrap_err_with(|| format!("Failed to open audit log {}", path.display()))
+    }
+
+    /// Appends an entry to the audit log
+    async fn record(&self, log: Option<fs::File>, entry: &AuditEntry) -> eyre::Result<()> {
+        let Some(mut log) = log else {
+            return Ok(());
+        };
+
+        let mut line = serde_json::to_string(entry).wrap_err("Failed to encode audit entry")?;
+        line.push('\n');
+
+        log.write_all(line.as_bytes())
+            .await
+            .wrap_err("Failed to write audit log")
+    }
+
+    fn entry(
+        &self,
+        operation: AuditOperation,
+        repository: &str,
+        package: &PackageId,
+        version: Option<&semver::Version>,
+    ) -> AuditEntry {
+        AuditEntry {
+            timestamp: chrono::DateTime::<chrono::Utc>::from(SystemTime::now()).to_rfc3339(),
+            user: self.user.clone(),
+            operation,
+            repository: repository.to_owned(),
+            package: package.clone(),
+            version: version.cloned(),
+            digest: None,
+            url: None,
+        }
+    }
+}
+
+impl<R: Registry + Send + Sync> AuditedRegistry<R> {
+    /// Stores the entry in properties of the archive of its version
+    async fn store_properties(&self, entry: &AuditEntry) -> eyre::Result<()> {
+        let (true, Some(version)) = (self.config.properties.unwrap_or(false), &entry.version)
+        else {
+            return Ok(());
+        };
+
+        let prefix = format!("buffrs.{}", entry.operation.name());
+
+        let mut properties = BTreeMap::from([
+            (format!("{prefix}.{USER_PROPERTY}"), entry.user.clone()),
+            (
+                format!("{prefix}.{TIMESTAMP_PROPERTY}"),
+                entry.timestamp.clone(),
+            ),
+        ]);
+
+        if let Some(ref digest) = entry.digest {
+            properties.insert(format!("{prefix}.{DIGEST_PROPERTY}"), digest.to_string());
+        }
+
+        self.inner
+            .annotate(&entry.repository, &entry.package, version, &properties)
+            .await
+            .wrap_err_with(|| {
+                format!(
+                    "Failed to record {} of {}@{version} in its properties",
+                    entry.operation.name(),
+                    entry.package
+                )
+            })
+    }
+}
+
+/// Explains that an operation was carried out, but could not be recorded
+fn unrecorded(entry: &AuditEntry, error: eyre::Report) -> eyre::Report {
+    error.wrap_err(format!(
+        "The {} of {} in {} succeeded, but was not fully recorded",
+        entry.operation.name(),
+        entry.package,
+        entry.repository
+    ))
+}
+
+#[async_trait::async_trait]
+impl<R: Registry + Send + Sync> Registry for AuditedRegistry<R> {
+    async fn download(&self, dependency: Dependency) -> eyre::Result<Package> {
+        self.inner.download(dependency).await
+    }
+
+    async fn publish(&self, package: Package, repository: String) -> eyre::Result<Receipt> {
+        let log = self.open().await?;
+
+        let receipt = self.inner.publish(package, repository).await?;
+
+        let entry = AuditEntry {
+            digest: Some(receipt.digest.clone()),
+            url: Some(receipt.url.clone()),
+            ..self.entry(
+                AuditOperation::Publish,
+                &receipt.repository,
+                &receipt.package,
+                Some(&receipt.version),
+            )
+        };
+
+        self.store_properties(&entry)
+            .await
+            .map_err(|e| unrecorded(&entry, e))?;
+
+        self.record(log, &entry)
+            .await
+            .map_err(|e| unrecorded(&entry, e))?;
+
+        Ok(receipt)
+    }
+
+    async fn published_at(&self, dependency: &Dependency) -> eyre::Result<Option<SystemTime>> {
+        self.inner.published_at(dependency).await
+    }
+
+    async fn versions(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        self.inner.versions(repository, package).await
+    }
+
+    async fn archival(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Option<Archival>> {
+        self.inner.archival(repository, package).await
+    }
+
+    async fn archive(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        archival: Option<&Archival>,
+    ) -> eyre::Result<()> {
+        let log = self.open().await?;
+
+        self.inner.archive(repository, package, archival).await?;
+
+        let operation = match archival {
+            Some(_) => AuditOperation::Archive,
+            None => AuditOperation::Unarchive,
+        };
+
+        let entry = self.entry(operation, repository, package, None);
+
+        self.record(log, &entry)
+            .await
+            .map_err(|e| unrecorded(&entry, e))
+    }
+
+    async fn yanked(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        self.inner.yanked(repository, package).await
+    }
+
+    async fn yank(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        version: &semver::Version,
+        yanked: bool,
+    ) -> eyre::Result<()> {
+        let log = self.open().await?;
+
+        self.inner
+            .yank(repository, package, version, yanked)
+            .await?;
+
+        let operation = match yanked {
+            true => AuditOperation::Yank,
+            false => AuditOperation::Unyank,
+        };
+
+        let entry = self.entry(operation, repository, package, Some(version));
+
+        self.store_properties(&entry)
+            .await
+            .map_err(|e| unrecorded(&entry, e))?;
+
+        self.record(log, &entry)
+            .await
+            .map_err(|e| unrecorded(&entry, e))
+    }
+
+    async fn annotate(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        version: &semver::Version,
+        properties: &BTreeMap<String, String>,
+    ) -> eyre::Result<()> {
+        self.inner
+            .annotate(repository, package, version, properties)
+            .await
+    }
+
+    async fn search(&self, term: &str) -> eyre::Result<Vec<SearchResult>> {
+        self.inner.search(term).await
+    }
+}
+
+/// Name of the user running `buffrs`, used if the registry has no username
+pub fn local_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_owned())
+}
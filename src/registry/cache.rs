@@ -0,0 +1,198 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use eyre::Context;
+use tokio::{fs, sync::OnceCell};
+
+use super::{Archival, Receipt, Registry, SearchResult};
+use crate::{
+    manifest::Dependency,
+    package::{Package, PackageArchive, PackageId},
+};
+
+/// Registry wrapper downloading every dependency at most once
+///
+/// Concurrent downloads of the same dependency wait for the first one to
+/// finish. Failed downloads are not cached and retried by the next caller.
+/// Packages are kept in memory unless they are persisted to a directory, in
+/// which case later downloads read them from there.
+pub struct CachedRegistry<R> {
+    inner: R,
+    downloads: Mutex<HashMap<Dependency, Arc<OnceCell<Package>>>>,
+    dir: Option<PathBuf>,
+}
+
+impl<R> CachedRegistry<R> {
+    /// Wraps a registry
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            downloads: Mutex::default(),
+            dir: None,
+        }
+    }
+
+    /// Persists downloaded packages in `dir`, so they survive restarts
+    ///
+    /// Packages are stored as `<repository>/<package>/<package>-<version>.tgz`.
+    pub fn persist_to(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    /// Downloads a dependency from the cache directory or the inner registry
+    async fn fetch(&self, dependency: Dependency) -> eyre::Result<Package>
+    where
+        R: Registry,
+    {
+        let Some(ref dir) = self.dir else {
+            return self.inner.download(dependency).await;
+        };
+
+        let version = dependency.resolved_version()?.clone();
+
+        let path = dir
+            .join(&dependency.manifest.repository)
+            .join(dependency.package.as_str())
+            .join(format!("{}-{version}.tgz", dependency.package));
+
+        if fs::try_exists(&path).await.unwrap_or(false) {
+            let tgz = PackageArchive::new()?;
+
+            fs::copy(&path, tgz.path())
+                .await
+                .wrap_err_with(|| format!("Failed to read cached {dependency}"))?;
+
+            return Ok(Package::new(dependency.package, version, tgz));
+        }
+
+        let package = self.inner.download(dependency).await?;
+
+        // Packages read back from the directory have no digest to check
+        package.verify().await?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .wrap_err("Failed to create cache directory")?;
+        }
+
+        // Copy to a sibling file first, so readers never see partial archives
+        let partial = path.with_extension("tgz.partial");
+
+        fs::copy(package.tgz.path(), &partial)
+            .await
+            .wrap_err("Failed to cache package")?;
+
+        fs::rename(&partial, &path)
+            .await
+            .wrap_err("Failed to cache package")?;
+
+        Ok(package)
+    }
+}
+
+#[async_trait::async_trait]
+impl<R: Registry + Send + Sync> Registry for CachedRegistry<R> {
+    async fn download(&self, dependency: Dependency) -> eyre::Result<Package> {
+        let cell = self
+            .downloads
+            .lock()
+            .expect("download cache poisoned")
+            .entry(dependency.clone())
+            .or_default()
+            .clone();
+
+        let package = cell
+            .get_or_try_init(|| self.fetch(dependency.clone()))
+            .await
+            .cloned();
+
+        // Persisted packages do not need to hold on to their temporary
+        // archives, which would otherwise pile up in long-running proxies
+        if self.dir.is_some() {
+            let mut downloads = self.downloads.lock().expect("download cache poisoned");
+
+            if downloads
+                .get(&dependency)
+                .is_some_and(|c| Arc::ptr_eq(c, &cell))
+            {
+                downloads.remove(&dependency);
+            }
+        }
+
+        package
+    }
+
+    async fn publish(&self, package: Package, repository: String) -> eyre::Result<Receipt> {
+        self.inner.publish(package, repository).await
+    }
+
+    async fn published_at(&self, dependency: &Dependency) -> eyre::Result<Option<SystemTime>> {
+        self.inner.published_at(dependency).await
+    }
+    async fn versions(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        self.inner.versions(repository, package).await
+    }
+
+    async fn archival(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Option<Archival>> {
+        self.inner.archival(repository, package).await
+    }
+
+    async fn archive(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        archival: Option<&Archival>,
+    ) -> eyre::Result<()> {
+        self.inner.archive(repository, package, archival).await
+    }
+
+    async fn yanked(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        self.inner.yanked(repository, package).await
+    }
+
+    async fn yank(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        version: &semver::Version,
+        yanked: bool,
+    ) -> eyre::Result<()> {
+        self.inner.yank(repository, package, version, yanked).await
+    }
+
+    async fn annotate(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        version: &semver::Version,
+        properties: &BTreeMap<String, String>,
+    ) -> eyre::Result<()> {
+        self.inner
+            .annotate(repository, package, version, properties)
+            .await
+    }
+
+    async fn search(&self, term: &str) -> eyre::Result<Vec<SearchResult>> {
+        self.inner.search(term).await
+    }
+}
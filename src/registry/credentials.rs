@@ -0,0 +1,151 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{fs, io::Write};
+
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::config::credentials_file;
+
+/// Where the password of a registry is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CredentialStore {
+    /// The system keyring, e.g. the macOS keychain or the Secret Service
+    Keyring,
+    /// `credentials.toml` in the configuration directory, only readable by
+    /// the current user
+    File,
+}
+
+impl CredentialStore {
+    /// The keyring on desktops, the credentials file on headless machines
+    ///
+    /// Linux machines without a session bus or display, e.g. CI agents and
+    /// containers, have no keyring to store passwords in.
+    pub fn detect() -> Self {
+        let headless = cfg!(target_os = "linux")
+            && ["DBUS_SESSION_BUS_ADDRESS", "DISPLAY", "WAYLAND_DISPLAY"]
+                .iter()
+                .all(|var| std::env::var_os(var).is_none());
+
+        match headless {
+            true => Self::File,
+            false => Self::Keyring,
+        }
+    }
+}
+
+/// Credentials stored in the credentials file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CredentialsFile {
+    #[serde(default)]
+    credentials: Vec<Credentials>,
+}
+
+/// Password of a user of a registry
+#[derive(Debug, Serialize, Deserialize)]
+struct Credentials {
+    url: String,
+    username: String,
+    password: String,
+}
+
+/// Reads the password of `username` at the registry `url`
+pub fn load(url: &str, username: &str) -> eyre::Result<Option<String>> {
+    Ok(read()?
+        .credentials
+        .into_iter()
+        .find(|c| c.url == url && c.username == username)
+        .map(|c| c.password))
+}
+
+/// Stores the password of `username` at the registry `url`, replacing a
+/// previous one
+pub fn store(url: &str, username: &str, password: String) -> eyre::Result<()> {
+    let mut file = read()?;
+
+    file.credentials
+        .retain(|c| c.url != url || c.username != username);
+
+    file.credentials.push(Credentials {
+        url: url.to_owned(),
+        username: username.to_owned(),
+        password,
+    });
+
+    write(&file)
+}
+
+/// Removes the password of `username` at the registry `url`
+pub fn remove(url: &str, username: &str) -> eyre::Result<()> {
+    let mut file = read()?;
+
+    file.credentials
+        .retain(|c| c.url != url || c.username != username);
+
+    write(&file)
+}
+
+fn read() -> eyre::Result<CredentialsFile> {
+    let path = credentials_file()?;
+
+    if !path.exists() {
+        return Ok(CredentialsFile::default());
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = fs::metadata(&path)
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?
+            .permissions()
+            .mode();
+
+        if mode & 0o077 != 0 {
+            tracing::warn!(
+                "! {} is accessible by other users, restrict it with chmod 600",
+                path.display()
+            );
+        }
+    }
+
+    let contents =
+        fs::read_to_string(&path).wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+
+    toml::from_str(&contents).wrap_err_with(|| format!("Failed to parse {}", path.display()))
+}
+
+fn write(file: &CredentialsFile) -> eyre::Result<()> {
+    let path = credentials_file()?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .wrap_err_with(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        options.mode(0o600);
+
+        // The mode only applies to new files, tighten existing ones as well
+        if path.exists() {
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+                .wrap_err_with(|| format!("Failed to restrict {}", path.display()))?;
+        }
+    }
+
+    let contents = toml::to_string(file).wrap_err("Failed to serialize credentials")?;
+
+    options
+        .open(&path)
+        .and_then(|mut f| f.write_all(contents.as_bytes()))
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))
+}
@@ -0,0 +1,240 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use eyre::{Context, ContextCompat};
+use serde::{Deserialize, Serialize};
+
+use super::{Archival, Receipt, Registry, SearchResult};
+use crate::{
+    error::ErrorCode,
+    manifest::Dependency,
+    package::{Package, PackageArchive, PackageId},
+};
+
+/// Magic bytes every age encrypted file starts with
+const AGE_HEADER: &[u8] = b"age-encryption.org/v1";
+
+/// Keys used to encrypt the packages of a restricted repository
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EncryptionConfig {
+    /// Public age keys (`age1...`) of everyone allowed to install packages
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<String>,
+    /// File containing the private age keys of the current user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<PathBuf>,
+}
+
+/// Registry wrapper encrypting packages of restricted repositories
+///
+/// Packages published to a configured repository are encrypted for its
+/// recipients. Downloaded packages are decrypted transparently if they are
+/// encrypted, using the identity configured for their repository.
+///
+/// Without the `encryption` feature, publishing to a configured repository
+/// and installing encrypted packages fails instead of falling back to
+/// plaintext.
+pub struct EncryptedRegistry<R> {
+    inner: R,
+    repositories: HashMap<String, EncryptionConfig>,
+}
+
+impl<R> EncryptedRegistry<R> {
+    /// Wraps a registry, encrypting the given repositories
+    pub fn new(inner: R, repositories: HashMap<String, EncryptionConfig>) -> Self {
+        Self {
+            inner,
+            repositories,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<R: Registry + Send + Sync> Registry for EncryptedRegistry<R> {
+    async fn download(&self, dependency: Dependency) -> eyre::Result<Package> {
+        let repository = dependency.manifest.repository.clone();
+        let package = self.inner.download(dependency).await?;
+
+        // The registry only knows the digest of the ciphertext
+        package.verify().await?;
+
+        let tgz = package.tgz.clone();
+        let config = self.repositories.get(&repository).cloned();
+
+        let tgz = tokio::task::spawn_blocking(move || -> eyre::Result<Option<PackageArchive>> {
+            let ciphertext = std::fs::read(tgz.path()).wrap_err("Failed to read package")?;
+
+            if !ciphertext.starts_with(AGE_HEADER) {
+                return Ok(None);
+            }
+
+            let identity = config
+                .and_then(|c| c.identity)
+                .wrap_err_with(|| {
+                    format!("Package is encrypted, but no identity is configured for repository {repository}")
+                })
+                .wrap_err(ErrorCode::DecryptionFailed)?;
+
+            decrypt(&ciphertext, &identity)
+                .map(Some)
+                .wrap_err(ErrorCode::DecryptionFailed)
+        })
+        .await??;
+
+        match tgz {
+            Some(tgz) => Ok(Package::new(package.name, package.version, tgz)),
+            None => Ok(package),
+        }
+    }
+
+    async fn publish(&self, package: Package, repository: String) -> eyre::Result<Receipt> {
+        let Some(config) = self.repositories.get(&repository).cloned() else {
+            return self.inner.publish(package, repository).await;
+        };
+
+        let tgz = package.tgz.clone();
+
+        let tgz = tokio::task::spawn_blocking(move || encrypt(&tgz, &config.recipients))
+            .await?
+            .wrap_err_with(|| format!("Failed to encrypt package for repository {repository}"))?;
+
+        self.inner
+            .publish(Package::new(package.name, package.version, tgz), repository)
+            .await
+    }
+
+    async fn published_at(&self, dependency: &Dependency) -> eyre::Result<Option<SystemTime>> {
+        self.inner.published_at(dependency).await
+    }
+    async fn versions(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        self.inner.versions(repository, package).await
+    }
+
+    async fn archival(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Option<Archival>> {
+        self.inner.archival(repository, package).await
+    }
+
+    async fn archive(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        archival: Option<&Archival>,
+    ) -> eyre::Result<()> {
+        self.inner.archive(repository, package, archival).await
+    }
+
+    async fn yanked(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        self.inner.yanked(repository, package).await
+    }
+
+    async fn yank(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        version: &semver::Version,
+        yanked: bool,
+    ) -> eyre::Result<()> {
+        self.inner.yank(repository, package, version, yanked).await
+    }
+
+    async fn annotate(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        version: &semver::Version,
+        properties: &BTreeMap<String, String>,
+    ) -> eyre::Result<()> {
+        self.inner
+            .annotate(repository, package, version, properties)
+            .await
+    }
+
+    async fn search(&self, term: &str) -> eyre::Result<Vec<SearchResult>> {
+        self.inner.search(term).await
+    }
+}
+
+#[cfg(feature = "encryption")]
+fn encrypt(tgz: &PackageArchive, recipients: &[String]) -> eyre::Result<PackageArchive> {
+    use std::io::{self, Write};
+
+    eyre::ensure!(!recipients.is_empty(), "No recipients are configured");
+
+    let recipients = recipients
+        .iter()
+        .map(|r| {
+            r.parse::<age::x25519::Recipient>()
+                .map_err(|e| eyre::eyre!("Invalid recipient {r}: {e}"))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let encryptor =
+        age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))?;
+
+    let encrypted = PackageArchive::new()?;
+
+    let mut input = std::fs::File::open(tgz.path()).wrap_err("Failed to open package")?;
+    let output = std::fs::File::create(encrypted.path()).wrap_err("Failed to create archive")?;
+
+    let mut writer = encryptor.wrap_output(io::BufWriter::new(output))?;
+    io::copy(&mut input, &mut writer)?;
+    writer.finish()?.flush()?;
+
+    Ok(encrypted)
+}
+
+#[cfg(feature = "encryption")]
+fn decrypt(ciphertext: &[u8], identity: &Path) -> eyre::Result<PackageArchive> {
+    use std::io::Read;
+
+    let identities = age::IdentityFile::from_file(identity.display().to_string())
+        .wrap_err_with(|| format!("Failed to read identity {}", identity.display()))?
+        .into_identities()?;
+
+    let decryptor = age::Decryptor::new_buffered(ciphertext)?;
+
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|i| i.as_ref() as &dyn age::Identity))
+        .map_err(|e| match e {
+            age::DecryptError::NoMatchingKeys => eyre::eyre!(
+                "Identity {} is not authorized to decrypt this package",
+                identity.display()
+            ),
+            e => eyre::eyre!("Failed to decrypt package: {e}"),
+        })?;
+
+    let mut plaintext = vec![];
+    reader.read_to_end(&mut plaintext)?;
+
+    let decrypted = PackageArchive::new()?;
+    std::fs::write(decrypted.path(), plaintext).wrap_err("Failed to write archive")?;
+
+    Ok(decrypted)
+}
+
+#[cfg(not(feature = "encryption"))]
+fn encrypt(_tgz: &PackageArchive, _recipients: &[String]) -> eyre::Result<PackageArchive> {
+    eyre::bail!("buffrs was built without the `encryption` feature")
+}
+
+#[cfg(not(feature = "encryption"))]
+fn decrypt(_ciphertext: &[u8], _identity: &Path) -> eyre::Result<PackageArchive> {
+    eyre::bail!("buffrs was built without the `encryption` feature")
+}
@@ -0,0 +1,277 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use eyre::{Context, ContextCompat};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use url::Url;
+
+use super::{Layout, Receipt, Registry};
+use crate::{
+    error::ErrorCode,
+    manifest::Dependency,
+    package::{Digest, DigestAlgorithm, Package, PackageArchive, PackageId},
+};
+
+/// Registry storing packages in a local or network mounted directory
+///
+/// The directory is laid out like a remote repository, archives are stored at
+/// `<directory>/<repository>/<layout>`. This allows installing without network
+/// access, e.g. in air-gapped environments. Each archive is accompanied by a
+/// `.sha256` file in `sha256sum` format, which downloads are verified against.
+pub struct FileRegistry {
+    config: FileRegistryConfig,
+}
+
+/// Settings of a [`FileRegistry`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FileRegistryConfig {
+    /// Directory holding the repositories
+    pub path: PathBuf,
+    /// Path of package archives within repositories (defaults to
+    /// `{package}/{package}-{version}.tgz`)
+    pub layout: Option<Layout>,
+}
+
+impl FileRegistryConfig {
+    /// Registry in the directory of a `file://` url
+    pub fn from_url(url: &Url) -> eyre::Result<Self> {
+        let path = url
+            .to_file_path()
+            .ok()
+            .wrap_err_with(|| format!("{url} is not a local directory"))?;
+
+        Ok(Self { path, layout: None })
+    }
+
+    fn layout(&self) -> &Layout {
+        self.layout.as_ref().unwrap_or(&Layout::DEFAULT)
+    }
+
+    /// Path of the archive of `package@version` in `repository`
+    pub fn archive(&self, repository: &str, package: &str, version: &semver::Version) -> PathBuf {
+        self.path
+            .join(repository)
+            .join(self.layout().path(package, version))
+    }
+}
+
+impl FileRegistry {
+    /// Path of the archive of `package@version` in `repository`
+    fn archive(&self, repository: &str, package: &str, version: &semver::Version) -> PathBuf {
+        self.config.archive(repository, package, version)
+    }
+}
+
+/// Path of the checksum file of an archive
+fn checksum_path(archive: &Path) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(".sha256");
+    PathBuf::from(path)
+}
+
+#[async_trait::async_trait]
+impl Registry for FileRegistry {
+    async fn download(&self, dependency: Dependency) -> eyre::Result<Package> {
+        let version = dependency.resolved_version()?.clone();
+
+        let path = self.archive(
+            &dependency.manifest.repository,
+            &dependency.package,
+            &version,
+        );
+
+        let tgz = PackageArchive::new()?;
+
+        fs::copy(&path, tgz.path())
+            .await
+            .wrap_err_with(|| format!("Failed to fetch {dependency} from {}", path.display()))
+            .wrap_err(ErrorCode::DownloadFailed)?;
+
+        tracing::debug!("copied dependency {dependency} from {}", path.display());
+
+        // Accepts both a bare digest and the `sha256sum` output format
+        let checksum = match fs::read_to_string(checksum_path(&path)).await {
+            Ok(checksum) => Some(
+                format!(
+                    "{}:{}",
+                    DigestAlgorithm::Sha256,
+                    checksum.split_whitespace().next().unwrap_or_default()
+                )
+                .parse::<Digest>()
+                .wrap_err_with(|| format!("Invalid checksum of {dependency}"))
+                .wrap_err(ErrorCode::IntegrityMismatch)?,
+            ),
+            Err(error) if error.kind() == ErrorKind::NotFound => None,
+            Err(error) => {
+                return Err(error)
+                    .wrap_err_with(|| format!("Failed to read the checksum of {dependency}"))
+            }
+        };
+
+        let package = Package::new(dependency.package.clone(), version, tgz);
+
+        match checksum {
+            Some(checksum) => Ok(package.with_digest(checksum)),
+            None => {
+                tracing::warn!("! {dependency} has no checksum and can not be verified");
+
+                Ok(package)
+            }
+        }
+    }
+
+    /// Copies the archive into the directory
+    ///
+    /// The archive is moved into place once it was copied completely, so
+    /// concurrent installs never see partial files.
+    async fn publish(&self, package: Package, repository: String) -> eyre::Result<Receipt> {
+        let path = self.archive(&repository, &package.name, &package.version);
+
+        let digest = package.tgz.digest(DigestAlgorithm::Sha256).await?;
+
+        // Retried publishes succeed without overwriting the archive, releases
+        // are never replaced
+        let already_published = match fs::read(&path).await {
+            Ok(stored) if DigestAlgorithm::Sha256.digest(&stored) != digest => {
+                return Err(eyre::eyre!(
+                    "{repository}/{}@{} is already published with different contents",
+                    package.name,
+                    package.version
+                ))
+                .wrap_err(ErrorCode::PublishRejected);
+            }
+            Ok(_) => true,
+            Err(_) => false,
+        };
+
+        if already_published {
+            tracing::info!(
+                ":: {repository}/{}@{} is already published",
+                package.name,
+                package.version
+            );
+        } else {
+            let parent = path.parent().unwrap_or(Path::new("."));
+
+            fs::create_dir_all(parent)
+                .await
+                .wrap_err_with(|| format!("Failed to create {}", parent.display()))
+                .wrap_err(ErrorCode::PublishRejected)?;
+
+            let partial = path.with_extension("tgz.partial");
+
+            fs::copy(package.tgz.path(), &partial)
+                .await
+                .and(fs::rename(&partial, &path).await)
+                .wrap_err_with(|| format!("Failed to write {}", path.display()))
+                .wrap_err(ErrorCode::PublishRejected)?;
+
+            tracing::info!(
+                "+ published {}/{}@{}",
+                repository,
+                package.name,
+                package.version
+            );
+        }
+
+        // Archives published before checksums were recorded get one as well
+        let checksum = checksum_path(&path);
+
+        if !fs::try_exists(&checksum).await.unwrap_or(false) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+            fs::write(&checksum, format!("{}  {name}\n", digest.to_hex()))
+                .await
+                .wrap_err_with(|| format!("Failed to write {}", checksum.display()))
+                .wrap_err(ErrorCode::PublishRejected)?;
+        }
+
+        let path = fs::canonicalize(&path).await.unwrap_or(path);
+
+        Ok(Receipt {
+            url: Url::from_file_path(&path)
+                .ok()
+                .wrap_err_with(|| format!("Invalid archive path {}", path.display()))?,
+            digest,
+            size: package.tgz.size().await?,
+            metadata: serde_json::Value::Null,
+            already_published,
+            package: package.name,
+            version: package.version,
+            repository,
+        })
+    }
+
+    /// Uses the modification time of the archive
+    async fn published_at(&self, dependency: &Dependency) -> eyre::Result<Option<SystemTime>> {
+        let path = self.archive(
+            &dependency.manifest.repository,
+            &dependency.package,
+            dependency.resolved_version()?,
+        );
+
+        let metadata = fs::metadata(&path)
+            .await
+            .wrap_err_with(|| format!("Failed to look up {dependency}"))?;
+
+        Ok(metadata.modified().ok())
+    }
+
+    /// Lists the versions of a package from the entries of its folder
+    async fn versions(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        let layout = self.config.layout();
+
+        let folder = self
+            .config
+            .path
+            .join(repository)
+            .join(layout.folder(package));
+
+        let mut entries = match fs::read_dir(&folder).await {
+            Ok(entries) => entries,
+            // Packages that were never published have no folder
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(error) => {
+                return Err(error).wrap_err_with(|| format!("Failed to list {}", folder.display()))
+            }
+        };
+
+        let mut versions = vec![];
+
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .wrap_err_with(|| format!("Failed to list {}", folder.display()))?
+        {
+            let name = entry.file_name();
+
+            if let Some(version) = name
+                .to_str()
+                .and_then(|name| layout.entry_version(package, name))
+            {
+                versions.push(version);
+            }
+        }
+
+        versions.sort();
+        versions.dedup();
+
+        Ok(versions)
+    }
+}
+
+impl From<FileRegistryConfig> for FileRegistry {
+    fn from(config: FileRegistryConfig) -> Self {
+        Self { config }
+    }
+}
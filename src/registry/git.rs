@@ -0,0 +1,138 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{collections::HashMap, path::Path};
+
+use eyre::{ensure, Context, ContextCompat};
+use tokio::{process::Command, sync::Mutex};
+
+use super::{Receipt, Registry};
+use crate::{
+    error::ErrorCode,
+    manifest::Dependency,
+    package::{Package, PackageStore},
+};
+
+/// Read-only registry packaging dependencies straight from git repositories
+///
+/// The repository is cloned with the `git` executable, using its configured
+/// credentials, and the api package in its root is packaged on the fly. Every
+/// repository and reference is only fetched once per registry.
+#[derive(Default)]
+pub struct GitRegistry {
+    packages: Mutex<HashMap<String, Package>>,
+}
+
+impl GitRegistry {
+    /// Creates a registry without any fetched repositories
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    async fn package(&self, dependency: &Dependency) -> eyre::Result<Package> {
+        let manifest = &dependency.manifest;
+
+        let url = manifest
+            .git
+            .as_deref()
+            .wrap_err_with(|| format!("{dependency} is not a git dependency"))?;
+
+        let source = match manifest.git_reference() {
+            Some(reference) => format!("{url}#{reference}"),
+            None => url.to_owned(),
+        };
+
+        // Holding the lock while cloning keeps concurrent requests of the same
+        // repository from fetching it twice
+        let mut packages = self.packages.lock().await;
+
+        if let Some(package) = packages.get(&source) {
+            return Ok(package.clone());
+        }
+
+        let checkout = tempfile::tempdir().wrap_err("Failed to create temporary directory")?;
+
+        tracing::info!(":: fetching {source}");
+
+        clone(url, dependency, checkout.path()).await?;
+
+        let package = PackageStore::new(checkout.path())
+            .release()
+            .await
+            .wrap_err_with(|| format!("Failed to package {source}"))?;
+
+        ensure!(
+            package.name == dependency.package,
+            "{source} contains package {}, not {}",
+            package.name,
+            dependency.package
+        );
+
+        packages.insert(source, package.clone());
+
+        Ok(package)
+    }
+}
+
+/// Checks out the referenced revision of a git dependency into `dir`
+async fn clone(url: &str, dependency: &Dependency, dir: &Path) -> eyre::Result<()> {
+    let manifest = &dependency.manifest;
+
+    let mut clone = git_command();
+
+    clone.args(["clone", "--quiet"]);
+
+    // Commits can not be cloned shallowly by name, so they need the history
+    match manifest.branch.as_ref().or(manifest.tag.as_ref()) {
+        Some(reference) => clone.args(["--depth", "1", "--branch", reference]),
+        None if manifest.rev.is_none() => clone.args(["--depth", "1"]),
+        None => &mut clone,
+    };
+
+    git(clone.arg(url).arg(dir))
+        .await
+        .wrap_err_with(|| format!("Failed to clone {url}"))?;
+
+    if let Some(ref rev) = manifest.rev {
+        git(git_command()
+            .current_dir(dir)
+            .args(["checkout", "--quiet", rev]))
+        .await
+        .wrap_err_with(|| format!("Failed to check out {rev} of {url}"))?;
+    }
+
+    Ok(())
+}
+
+/// Creates a git invocation that does not print advice meant for humans
+fn git_command() -> Command {
+    let mut command = Command::new("git");
+    command.args(["-c", "advice.detachedHead=false"]);
+    command
+}
+
+async fn git(command: &mut Command) -> eyre::Result<()> {
+    let status = command
+        .status()
+        .await
+        .wrap_err("Failed to run git, is it installed?")?;
+
+    ensure!(status.success(), "git exited with {status}");
+
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl Registry for GitRegistry {
+    async fn download(&self, dependency: Dependency) -> eyre::Result<Package> {
+        self.package(&dependency)
+            .await
+            .wrap_err(ErrorCode::DownloadFailed)
+    }
+
+    async fn publish(&self, _package: Package, repository: String) -> eyre::Result<Receipt> {
+        Err(eyre::eyre!(
+            "Unable to publish to {repository}, git registries are read-only"
+        ))
+        .wrap_err(ErrorCode::PublishRejected)
+    }
+}
@@ -0,0 +1,683 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    fmt,
+    str::FromStr,
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
+
+use eyre::{ensure, Context, ContextCompat};
+use reqwest::{
+    header::{
+        HeaderValue, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH,
+    },
+    Body, Method, Request, Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io::AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use tracing::{field::Empty, Span};
+use url::Url;
+
+use super::{
+    Archival, Layout, Receipt, Registry, RetryConfig, RetryTransport, SigV4Config, SigV4Transport,
+    Transport, CHECKSUM_SHA256,
+};
+use crate::{
+    error::ErrorCode,
+    manifest::Dependency,
+    package::{Digest, DigestAlgorithm, Package, PackageArchive, PackageId},
+};
+
+/// Placeholder replaced by the repository of a package
+const REPOSITORY: &str = "{repository}";
+/// File next to the archives of a package listing its releases
+const INDEX_FILE: &str = "index.json";
+/// Response headers describing the stored archive, recorded in receipts
+const METADATA_HEADERS: &[&str] = &["etag", "last-modified", "x-amz-version-id"];
+
+/// Registry storing packages as plain files on an HTTP server or in an
+/// S3-compatible bucket
+///
+/// Archives are read and written with `GET` and `PUT` at the configured url
+/// template. As such servers can not list their contents, every package has
+/// an `index.json` recording its releases, which is updated on publish.
+pub struct HttpRegistry {
+    config: HttpRegistryConfig,
+    transport: Arc<dyn Transport>,
+    retry: RetryConfig,
+}
+
+/// Settings of an [`HttpRegistry`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HttpRegistryConfig {
+    /// Url of package archives, see [`UrlTemplate`]
+    pub url: UrlTemplate,
+    /// Token sent as bearer token with every request
+    pub token: Option<String>,
+    /// Signs requests with AWS Signature V4, for S3-compatible buckets
+    pub sigv4: Option<SigV4Config>,
+}
+
+/// Url of package archives with `{repository}`, `{package}` and `{version}`
+/// as placeholders, e.g.
+/// `https://example.com/protos/{repository}/{package}/{package}-{version}.tgz`
+///
+/// `{repository}` has to be a whole path segment, everything following it is
+/// the [`Layout`] of the repository.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct UrlTemplate {
+    base: Url,
+    layout: Layout,
+}
+
+/// Releases of a package, stored in its `index.json`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    #[serde(default)]
+    releases: Vec<Release>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    archived: Option<Archived>,
+}
+
+/// A published version of a package
+#[derive(Debug, Serialize, Deserialize)]
+struct Release {
+    version: semver::Version,
+    /// Publication time in RFC 3339 format
+    published: String,
+    /// Hex encoded SHA-256 checksum of the archive
+    sha256: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    yanked: bool,
+}
+
+/// Archival notice of a package
+#[derive(Debug, Serialize, Deserialize)]
+struct Archived {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replacement: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+impl HttpRegistry {
+    /// Creates an http registry that sends its requests via `transport`
+    pub fn with_transport(config: HttpRegistryConfig, transport: impl Transport + 'static) -> Self {
+        Self {
+            config,
+            transport: Arc::new(transport),
+            retry: RetryConfig::disabled(),
+        }
+    }
+
+    /// Retries requests that failed transiently, see [`RetryConfig`]
+    pub fn with_retry(self, retry: RetryConfig) -> Self {
+        Self {
+            transport: Arc::new(RetryTransport::new(self.transport, retry.clone())),
+            retry,
+            ..self
+        }
+    }
+
+    /// Creates an http registry that sends its requests with `client`,
+    /// signing them if configured
+    pub fn with_client(config: HttpRegistryConfig, client: reqwest::Client) -> Self {
+        match config.sigv4.clone() {
+            Some(sigv4) => Self::with_transport(config, SigV4Transport::new(client, sigv4)),
+            None => Self::with_transport(config, client),
+        }
+    }
+
+    /// Sends `request` with the archive of `package` as body
+    ///
+    /// The archive is streamed, so it is opened again for every retry.
+    async fn upload(&self, request: &Request, package: &Package) -> eyre::Result<Response> {
+        self.retry
+            .send(|| async {
+                let mut request = request.try_clone().wrap_err("Failed to copy request")?;
+
+                let tgz = fs::File::open(package.tgz.path())
+                    .await
+                    .wrap_err("Failed to open archive")?;
+
+                *request.body_mut() = Some(Body::wrap_stream(ReaderStream::new(tgz)));
+
+                self.transport.send(request).await
+            })
+            .await
+    }
+
+    /// Creates a request carrying the configured token
+    fn request(&self, method: Method, uri: Url) -> eyre::Result<Request> {
+        let mut request = Request::new(method, uri);
+
+        if let Some(ref token) = self.config.token {
+            let mut value = HeaderValue::from_str(&format!("Bearer {token}"))
+                .wrap_err("Invalid registry token")?;
+
+            value.set_sensitive(true);
+
+            request.headers_mut().insert(AUTHORIZATION, value);
+        }
+
+        Ok(request)
+    }
+
+    /// Url of the index of `package` in `repository`
+    fn index_uri(&self, repository: &str, package: &PackageId) -> eyre::Result<Url> {
+        format!(
+            "{}/{repository}/{package}/{INDEX_FILE}",
+            self.config.url.base()
+        )
+        .parse()
+        .wrap_err("Failed to construct index uri")
+    }
+
+    /// Reads the index of a package along with its entity tag
+    ///
+    /// Packages that were never published have an empty index.
+    async fn index(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<(Index, Option<HeaderValue>)> {
+        let uri = self.index_uri(repository, package)?;
+
+        let response = self.transport.send(self.request(Method::GET, uri)?).await?;
+
+        let missing = match response.status() {
+            StatusCode::NOT_FOUND => true,
+            // S3 answers forbidden for missing objects unless listing is allowed
+            StatusCode::FORBIDDEN => self.config.sigv4.is_some(),
+            _ => false,
+        };
+
+        if missing {
+            return Ok((Index::default(), None));
+        }
+
+        ensure!(
+            response.status().is_success(),
+            "Failed to read the index of {package} in {repository}: {}",
+            response.status()
+        );
+
+        let etag = response.headers().get(ETAG).cloned();
+
+        let index = response
+            .json()
+            .await
+            .wrap_err_with(|| format!("Failed to parse the index of {package} in {repository}"))?;
+
+        Ok((index, etag))
+    }
+
+    /// Replaces the index of a package, unless it changed since it was read
+    /// with entity tag `etag`
+    async fn write_index(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        index: &Index,
+        etag: Option<HeaderValue>,
+    ) -> eyre::Result<()> {
+        let uri = self.index_uri(repository, package)?;
+
+        let body = serde_json::to_vec_pretty(index).wrap_err("Failed to serialize index")?;
+
+        let mut request = self.request(Method::PUT, uri)?;
+
+        let headers = request.headers_mut();
+
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(CONTENT_LENGTH, body.len().into());
+
+        // Servers supporting conditional requests reject concurrent updates
+        match etag {
+            Some(etag) => headers.insert(IF_MATCH, etag),
+            None => headers.insert(IF_NONE_MATCH, HeaderValue::from_static("*")),
+        };
+
+        *request.body_mut() = Some(body.into());
+
+        let response = self.transport.send(request).await?;
+
+        if response.status() == StatusCode::PRECONDITION_FAILED {
+            return Err(eyre::eyre!(
+                "The index of {package} in {repository} was modified concurrently, try again"
+            ))
+            .wrap_err(ErrorCode::PublishRejected);
+        }
+
+        if !response.status().is_success() {
+            return Err(eyre::eyre!(
+                "Failed to update the index of {package} in {repository}: {}",
+                response.status()
+            ))
+            .wrap_err(ErrorCode::PublishRejected);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Registry for HttpRegistry {
+    /// Downloads a package archive
+    #[tracing::instrument(
+        level = "debug",
+        name = "download",
+        skip_all,
+        fields(
+            package = %dependency.package,
+            version = Empty,
+            repository = %dependency.manifest.repository,
+            bytes = Empty,
+            duration_ms = Empty,
+        )
+    )]
+    async fn download(&self, dependency: Dependency) -> eyre::Result<Package> {
+        let started = Instant::now();
+
+        let version = dependency.resolved_version()?.clone();
+
+        Span::current().record("version", tracing::field::display(&version));
+
+        let uri = self.config.url.archive(
+            &dependency.manifest.repository,
+            &dependency.package,
+            &version,
+        )?;
+
+        let mut response = self.transport.send(self.request(Method::GET, uri)?).await?;
+
+        if !response.status().is_success() {
+            return Err(eyre::eyre!(
+                "Failed to fetch {dependency}: {}",
+                response.status()
+            ))
+            .wrap_err(ErrorCode::DownloadFailed);
+        }
+
+        // The index records the checksum on publish, plain http servers and
+        // s3 do not send one along with the archive
+        let (index, _) = self
+            .index(&dependency.manifest.repository, &dependency.package)
+            .await?;
+
+        let checksum = index
+            .releases
+            .iter()
+            .find(|r| r.version == version)
+            .map(|r| r.sha256.as_str())
+            .or_else(|| {
+                response
+                    .headers()
+                    .get(CHECKSUM_SHA256)
+                    .and_then(|checksum| checksum.to_str().ok())
+            })
+            .map(|checksum| format!("{}:{checksum}", DigestAlgorithm::Sha256).parse::<Digest>())
+            .transpose()
+            .wrap_err_with(|| format!("Invalid checksum of {dependency}"))
+            .wrap_err(ErrorCode::IntegrityMismatch)?;
+
+        let tgz = PackageArchive::new()?;
+
+        let mut file = fs::File::create(tgz.path())
+            .await
+            .wrap_err("Failed to create archive")?;
+
+        let mut bytes = 0;
+
+        while let Some(chunk) = response.chunk().await.wrap_err("Failed to download tar")? {
+            bytes += chunk.len();
+
+            file.write_all(&chunk)
+                .await
+                .wrap_err("Failed to write archive")?;
+        }
+
+        file.flush().await.wrap_err("Failed to write archive")?;
+
+        Span::current()
+            .record("bytes", bytes)
+            .record("duration_ms", started.elapsed().as_millis());
+
+        tracing::debug!("downloaded dependency {dependency}");
+
+        let package = Package::new(dependency.package, version, tgz);
+
+        match checksum {
+            Some(checksum) => Ok(package.with_digest(checksum)),
+            None => {
+                tracing::warn!(
+                    "! {}@{} has no checksum and can not be verified",
+                    package.name,
+                    package.version
+                );
+
+                Ok(package)
+            }
+        }
+    }
+
+    /// Uploads a package archive and records it in the index of the package
+    #[tracing::instrument(
+        level = "debug",
+        name = "publish",
+        skip_all,
+        fields(
+            package = %package.name,
+            version = %package.version,
+            repository = %repository,
+            bytes = Empty,
+            duration_ms = Empty,
+        )
+    )]
+    async fn publish(&self, package: Package, repository: String) -> eyre::Result<Receipt> {
+        let started = Instant::now();
+
+        let uri = self
+            .config
+            .url
+            .archive(&repository, &package.name, &package.version)?;
+
+        let size = package.tgz.size().await?;
+
+        Span::current().record("bytes", size);
+
+        let digest = package.tgz.digest(DigestAlgorithm::Sha256).await?;
+
+        let (mut index, etag) = self.index(&repository, &package.name).await?;
+
+        let published = index.releases.iter().find(|r| r.version == package.version);
+
+        // Retried publishes succeed without overwriting the archive, releases
+        // are never replaced
+        if published.is_some_and(|r| r.sha256 != digest.to_hex()) {
+            return Err(eyre::eyre!(
+                "{repository}/{}@{} is already published with different contents",
+                package.name,
+                package.version
+            ))
+            .wrap_err(ErrorCode::PublishRejected);
+        }
+
+        if published.is_some() {
+            tracing::info!(
+                ":: {repository}/{}@{} is already published",
+                package.name,
+                package.version
+            );
+
+            return Ok(Receipt {
+                package: package.name,
+                version: package.version,
+                repository,
+                url: uri,
+                digest,
+                size,
+                metadata: serde_json::Value::Null,
+                already_published: true,
+            });
+        }
+
+        let checksum =
+            HeaderValue::from_str(&digest.to_hex()).wrap_err("Invalid package checksum")?;
+
+        let mut request = self.request(Method::PUT, uri.clone())?;
+
+        let headers = request.headers_mut();
+
+        headers.insert(CONTENT_LENGTH, size.into());
+        headers.insert(CHECKSUM_SHA256, checksum.clone());
+
+        // Lets SigV4 sign the streamed body without reading it twice
+        if self.config.sigv4.is_some() {
+            headers.insert("x-amz-content-sha256", checksum);
+        }
+
+        let response = self.upload(&request, &package).await?;
+
+        if !response.status().is_success() {
+            return Err(eyre::eyre!(
+                "Failed to publish {}: {}",
+                package.name,
+                response.status()
+            ))
+            .wrap_err(ErrorCode::PublishRejected);
+        }
+
+        let metadata: serde_json::Map<String, serde_json::Value> = METADATA_HEADERS
+            .iter()
+            .filter_map(|header| {
+                let value = response.headers().get(*header)?.to_str().ok()?;
+                Some((header.to_string(), value.into()))
+            })
+            .collect();
+
+        index.releases.retain(|r| r.version != package.version);
+
+        index.releases.push(Release {
+            version: package.version.clone(),
+            published: chrono::DateTime::<chrono::Utc>::from(SystemTime::now()).to_rfc3339(),
+            sha256: digest.to_hex(),
+            yanked: false,
+        });
+
+        index.releases.sort_by(|a, b| a.version.cmp(&b.version));
+
+        self.write_index(&repository, &package.name, &index, etag)
+            .await?;
+
+        Span::current().record("duration_ms", started.elapsed().as_millis());
+
+        tracing::info!(
+            "+ published {}/{}@{}",
+            repository,
+            package.name,
+            package.version
+        );
+
+        Ok(Receipt {
+            package: package.name,
+            version: package.version,
+            repository,
+            url: uri,
+            digest,
+            size,
+            metadata: metadata.into(),
+            already_published: false,
+        })
+    }
+
+    /// Reads the publication time from the index of the package
+    async fn published_at(&self, dependency: &Dependency) -> eyre::Result<Option<SystemTime>> {
+        let version = dependency.resolved_version()?;
+
+        let (index, _) = self
+            .index(&dependency.manifest.repository, &dependency.package)
+            .await?;
+
+        let Some(release) = index.releases.iter().find(|r| &r.version == version) else {
+            return Ok(None);
+        };
+
+        let published = chrono::DateTime::parse_from_rfc3339(&release.published)
+            .wrap_err_with(|| format!("Invalid publication time of {dependency}"))?;
+
+        Ok(Some(published.into()))
+    }
+
+    /// Lists the versions recorded in the index of the package
+    async fn versions(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        let (index, _) = self.index(repository, package).await?;
+
+        Ok(index.releases.into_iter().map(|r| r.version).collect())
+    }
+
+    async fn archival(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Option<Archival>> {
+        let (index, _) = self.index(repository, package).await?;
+
+        Ok(index.archived.map(|archived| Archival {
+            replacement: archived.replacement.and_then(|r| r.parse().ok()),
+            message: archived.message,
+        }))
+    }
+
+    async fn archive(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        archival: Option<&Archival>,
+    ) -> eyre::Result<()> {
+        let (mut index, etag) = self.index(repository, package).await?;
+
+        index.archived = archival.map(|archival| Archived {
+            replacement: archival.replacement.as_ref().map(|r| r.to_string()),
+            message: archival.message.clone(),
+        });
+
+        self.write_index(repository, package, &index, etag).await
+    }
+
+    async fn yanked(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        let (index, _) = self.index(repository, package).await?;
+
+        Ok(index
+            .releases
+            .into_iter()
+            .filter(|r| r.yanked)
+            .map(|r| r.version)
+            .collect())
+    }
+
+    /// Marks the version as yanked in the index, the archive stays
+    /// downloadable for projects that locked it
+    async fn yank(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        version: &semver::Version,
+        yanked: bool,
+    ) -> eyre::Result<()> {
+        let (mut index, etag) = self.index(repository, package).await?;
+
+        let release = index.releases.iter_mut().find(|r| &r.version == version);
+
+        let Some(release) = release else {
+            return Err(eyre::eyre!(
+                "{package}@{version} was not published to {repository}"
+            ));
+        };
+
+        release.yanked = yanked;
+
+        self.write_index(repository, package, &index, etag).await
+    }
+}
+
+impl UrlTemplate {
+    /// Url of the archive of `package@version` in `repository`
+    pub fn archive(
+        &self,
+        repository: &str,
+        package: &str,
+        version: &semver::Version,
+    ) -> eyre::Result<Url> {
+        format!(
+            "{}/{repository}/{}",
+            self.base(),
+            self.layout.path(package, version)
+        )
+        .parse()
+        .wrap_err("Failed to construct artifact uri")
+    }
+
+    /// The same layout of archives below another url
+    pub fn with_base(&self, base: &Url) -> eyre::Result<Self> {
+        format!(
+            "{}/{REPOSITORY}/{}",
+            base.as_str().trim_end_matches('/'),
+            self.layout
+        )
+        .parse()
+    }
+
+    /// The part of the url preceding the repository, without trailing slash
+    fn base(&self) -> &str {
+        self.base.as_str().trim_end_matches('/')
+    }
+}
+
+impl FromStr for UrlTemplate {
+    type Err = eyre::Report;
+
+    fn from_str(template: &str) -> eyre::Result<Self> {
+        let segment = format!("/{REPOSITORY}/");
+
+        let (base, layout) = template.split_once(&segment).ok_or_else(|| {
+            eyre::eyre!("Url template {template} has to contain {REPOSITORY} as path segment")
+        })?;
+
+        ensure!(
+            !layout.contains(REPOSITORY),
+            "Url template {template} may only contain {REPOSITORY} once"
+        );
+
+        let base: Url = base
+            .parse()
+            .wrap_err_with(|| format!("Invalid url template {template}"))?;
+
+        ensure!(
+            matches!(base.scheme(), "http" | "https"),
+            "Url template {template} has to use http or https"
+        );
+
+        ensure!(
+            base.query().is_none() && base.fragment().is_none(),
+            "Url template {template} may not contain a query or fragment"
+        );
+
+        let layout = layout
+            .parse()
+            .wrap_err_with(|| format!("Invalid url template {template}"))?;
+
+        Ok(Self { base, layout })
+    }
+}
+
+impl TryFrom<String> for UrlTemplate {
+    type Error = eyre::Report;
+
+    fn try_from(template: String) -> eyre::Result<Self> {
+        template.parse()
+    }
+}
+
+impl From<UrlTemplate> for String {
+    fn from(template: UrlTemplate) -> Self {
+        template.to_string()
+    }
+}
+
+impl fmt::Display for UrlTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{REPOSITORY}/{}", self.base(), self.layout)
+    }
+}
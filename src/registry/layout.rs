@@ -0,0 +1,239 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{borrow::Cow, fmt, str::FromStr};
+
+use eyre::{ensure, ContextCompat};
+use serde::{Deserialize, Serialize};
+
+use crate::package::PackageId;
+
+/// Placeholder replaced by the name of the package
+const PACKAGE: &str = "{package}";
+/// Placeholder replaced by the version of the package
+const VERSION: &str = "{version}";
+
+/// Path of package archives within a repository, with `{package}` and
+/// `{version}` as placeholders
+///
+/// Artifactory instances enforcing a repository layout only accept archives
+/// at the paths it prescribes, e.g. `{package}/{version}/{package}.tgz`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Layout(Cow<'static, str>);
+
+/// Part of a layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    Literal(&'a str),
+    Package,
+    Version,
+}
+
+impl Layout {
+    /// Layout used unless configured otherwise
+    pub const DEFAULT: Self = Self(Cow::Borrowed("{package}/{package}-{version}.tgz"));
+
+    /// Path of the archive of `package@version`
+    pub fn path(&self, package: &str, version: &semver::Version) -> String {
+        self.0
+            .replace(PACKAGE, package)
+            .replace(VERSION, &version.to_string())
+    }
+
+    /// File name of the archive of `package@version`
+    pub fn file_name(&self, package: &str, version: &semver::Version) -> String {
+        let path = self.path(package, version);
+
+        match path.rsplit_once('/') {
+            Some((_, name)) => name.to_owned(),
+            None => path,
+        }
+    }
+
+    /// Number of path segments of archives
+    pub fn depth(&self) -> usize {
+        self.0.split('/').count()
+    }
+
+    /// Folder of `package` whose entries are named after its versions, empty
+    /// if versions are listed at the root of the repository
+    pub fn folder(&self, package: &PackageId) -> String {
+        self.0
+            .split('/')
+            .take(self.version_segment())
+            .collect::<Vec<_>>()
+            .join("/")
+            .replace(PACKAGE, package.as_str())
+    }
+
+    /// Version named by an entry of the [`Layout::folder`] of `package`
+    pub fn entry_version(&self, package: &PackageId, entry: &str) -> Option<semver::Version> {
+        let segment = self.0.split('/').nth(self.version_segment())?;
+
+        let (_, version) = matches(&tokens(segment), entry, Some(package.as_str()), None)?;
+
+        version?.parse().ok()
+    }
+
+    /// Package and version of the archive at `path`, if it follows the layout
+    pub fn parse_path(&self, path: &str) -> Option<(PackageId, semver::Version)> {
+        let (package, version) = matches(&tokens(&self.0), path, None, None)?;
+
+        Some((package?.parse().ok()?, version?.parse().ok()?))
+    }
+
+    /// Pattern matching the file names of archives of packages whose name
+    /// contains `term`, using `*` as wildcard
+    pub fn file_pattern(&self, term: &str) -> String {
+        let name = self.0.rsplit('/').next().unwrap_or_default();
+
+        name.replace(PACKAGE, &format!("*{term}*"))
+            .replace(VERSION, "*")
+    }
+
+    /// Index of the path segment holding the version
+    fn version_segment(&self) -> usize {
+        self.0
+            .split('/')
+            .position(|segment| segment.contains(VERSION))
+            .unwrap_or_default()
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl FromStr for Layout {
+    type Err = eyre::Report;
+
+    fn from_str(layout: &str) -> eyre::Result<Self> {
+        ensure!(
+            layout.contains(PACKAGE) && layout.contains(VERSION),
+            "Layout {layout} has to contain {PACKAGE} and {VERSION}"
+        );
+
+        ensure!(
+            layout
+                .split('/')
+                .all(|segment| !matches!(segment, "" | "." | "..")),
+            "Layout {layout} has to be a relative path without empty, . or .. segments"
+        );
+
+        let mut rest = layout;
+
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .wrap_err_with(|| format!("Unclosed placeholder in layout {layout}"))?;
+
+            let placeholder = &rest[start..start + end + 1];
+
+            ensure!(
+                placeholder == PACKAGE || placeholder == VERSION,
+                "Unknown placeholder {placeholder} in layout {layout}, expected {PACKAGE} or {VERSION}"
+            );
+
+            rest = &rest[start + end + 1..];
+        }
+
+        ensure!(
+            !rest.contains('}'),
+            "Unopened placeholder in layout {layout}"
+        );
+
+        Ok(Self(Cow::Owned(layout.to_owned())))
+    }
+}
+
+impl TryFrom<String> for Layout {
+    type Error = eyre::Report;
+
+    fn try_from(layout: String) -> eyre::Result<Self> {
+        layout.parse()
+    }
+}
+
+impl From<Layout> for String {
+    fn from(layout: Layout) -> Self {
+        layout.0.into_owned()
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Splits a validated layout into literals and placeholders
+fn tokens(layout: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut rest = layout;
+
+    while let Some(start) = rest.find('{') {
+        let end = start + rest[start..].find('}').unwrap_or_default() + 1;
+
+        if start > 0 {
+            tokens.push(Token::Literal(&rest[..start]));
+        }
+
+        tokens.push(match &rest[start..end] {
+            PACKAGE => Token::Package,
+            _ => Token::Version,
+        });
+
+        rest = &rest[end..];
+    }
+
+    if !rest.is_empty() {
+        tokens.push(Token::Literal(rest));
+    }
+
+    tokens
+}
+
+/// Matches `input` against `tokens`, returning the text of the placeholders
+///
+/// Placeholders occurring several times have to match the same text. As
+/// package names and versions may both contain dashes, all ways to split the
+/// input are tried until the version is valid.
+fn matches<'a>(
+    tokens: &[Token<'_>],
+    input: &'a str,
+    package: Option<&'a str>,
+    version: Option<&'a str>,
+) -> Option<(Option<&'a str>, Option<&'a str>)> {
+    let Some((token, rest)) = tokens.split_first() else {
+        let valid = version.is_none_or(|v| semver::Version::parse(v).is_ok());
+        return (input.is_empty() && valid).then_some((package, version));
+    };
+
+    let bound = match token {
+        Token::Literal(literal) => Some(*literal),
+        Token::Package => package,
+        Token::Version => version,
+    };
+
+    if let Some(text) = bound {
+        return matches(rest, input.strip_prefix(text)?, package, version);
+    }
+
+    let allowed = |c: char| match token {
+        Token::Package => c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-',
+        _ => c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+'),
+    };
+
+    let longest = input.find(|c| !allowed(c)).unwrap_or(input.len());
+
+    (1..=longest).find_map(|len| {
+        let (text, input) = input.split_at(len);
+
+        match token {
+            Token::Package => matches(rest, input, Some(text), version),
+            _ => matches(rest, input, package, Some(text)),
+        }
+    })
+}
@@ -0,0 +1,86 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use eyre::{ensure, Context};
+
+use super::{Receipt, Registry};
+use crate::{
+    error::ErrorCode,
+    manifest::{Dependency, Manifest},
+    package::{Package, PackageId, PackageStore},
+};
+
+/// Read-only registry serving the packages of a local project
+///
+/// Offers the api package of the project, even if it was never published,
+/// along with every installed dependency, regardless of the repository they
+/// are requested from.
+pub struct LocalRegistry {
+    store: PackageStore,
+}
+
+impl LocalRegistry {
+    /// Serves the packages of the project owning `store`
+    pub fn new(store: PackageStore) -> Self {
+        Self { store }
+    }
+
+    async fn package(&self, dependency: &Dependency) -> eyre::Result<Package> {
+        let api = Manifest::read(self.store.root())
+            .await
+            .ok()
+            .and_then(|m| m.api);
+
+        let package = match api {
+            Some(api) if api.name == dependency.package => self.store.release().await?,
+            _ => self.store.repackage(&dependency.package).await?,
+        };
+
+        ensure!(
+            dependency.resolved_version()? == &package.version,
+            "{dependency} is not available, found version {}",
+            package.version
+        );
+
+        Ok(package)
+    }
+}
+
+#[async_trait::async_trait]
+impl Registry for LocalRegistry {
+    async fn download(&self, dependency: Dependency) -> eyre::Result<Package> {
+        self.package(&dependency)
+            .await
+            .wrap_err(ErrorCode::DownloadFailed)
+    }
+
+    async fn publish(&self, _package: Package, repository: String) -> eyre::Result<Receipt> {
+        Err(eyre::eyre!(
+            "Unable to publish to {repository}, local registries are read-only"
+        ))
+        .wrap_err(ErrorCode::PublishRejected)
+    }
+    async fn versions(
+        &self,
+        _repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        let api = Manifest::read(self.store.root())
+            .await
+            .ok()
+            .and_then(|m| m.api)
+            .filter(|api| api.name == *package);
+
+        let version = match api {
+            Some(api) => Some(api.version),
+            None => self
+                .store
+                .installed()
+                .await?
+                .into_iter()
+                .find(|p| p.name == *package)
+                .map(|p| p.version),
+        };
+
+        Ok(version.into_iter().collect())
+    }
+}
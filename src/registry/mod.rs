@@ -1,10 +1,109 @@
 // (c) Copyright 2023 Helsing GmbH. All rights reserved.
 
-use crate::{manifest::Dependency, package::Package};
+use std::{collections::BTreeMap, fmt, time::SystemTime};
+
+use serde::Serialize;
+use url::Url;
+
+use crate::{
+    manifest::Dependency,
+    package::{Digest, Package, PackageId},
+};
 
 mod artifactory;
+mod audit;
+mod cache;
+mod credentials;
+mod encryption;
+mod filesystem;
+mod git;
+mod http;
+mod layout;
+mod local;
+mod network;
+mod retry;
+mod sigv4;
+mod transport;
+
+pub use artifactory::{Artifactory, ArtifactoryConfig, Authentication, PASSWORD_ENV};
+pub use audit::{local_user, AuditConfig, AuditEntry, AuditOperation, AuditedRegistry};
+pub use cache::CachedRegistry;
+pub use credentials::CredentialStore;
+pub use encryption::{EncryptedRegistry, EncryptionConfig};
+pub use filesystem::{FileRegistry, FileRegistryConfig};
+pub use git::GitRegistry;
+pub use http::{HttpRegistry, HttpRegistryConfig, UrlTemplate};
+pub use layout::Layout;
+pub use local::LocalRegistry;
+pub use network::{NetworkConfig, CA_BUNDLE_ENV, NO_PROXY_ENV, PROXY_ENV};
+pub use retry::{RetryConfig, RetryTransport};
+pub use sigv4::{Credentials, SigV4Config, SigV4Transport};
+pub use transport::Transport;
+
+/// Notice that a package was superseded and should no longer be used
+///
+/// Archived packages can still be installed, but users are told to migrate.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Archival {
+    /// Package replacing the archived one
+    pub replacement: Option<PackageId>,
+    /// Explanation for users of the package
+    pub message: Option<String>,
+}
+
+impl fmt::Display for Archival {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("archived")?;
 
-pub use artifactory::{Artifactory, ArtifactoryConfig};
+        if let Some(ref message) = self.message {
+            write!(f, ": {message}")?;
+        }
+
+        if let Some(ref replacement) = self.replacement {
+            write!(f, ", use {replacement} instead")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Header carrying the SHA-256 checksum of uploaded and downloaded packages,
+/// as used by Artifactory
+pub(crate) const CHECKSUM_SHA256: &str = "X-Checksum-Sha256";
+
+/// A package found by [`Registry::search`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SearchResult {
+    /// Name of the package
+    pub package: PackageId,
+    /// Repository containing the package
+    pub repository: String,
+    /// Highest version of the package in the repository
+    pub latest: semver::Version,
+}
+
+/// Record of a package uploaded by [`Registry::publish`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Receipt {
+    /// Name of the package
+    pub package: PackageId,
+    /// Version of the package
+    pub version: semver::Version,
+    /// Repository the package was published to
+    pub repository: String,
+    /// Url of the uploaded archive
+    pub url: Url,
+    /// Digest of the uploaded archive
+    pub digest: Digest,
+    /// Size of the uploaded archive in bytes
+    pub size: u64,
+    /// What the registry reported about the stored archive, e.g. its
+    /// checksums or version id
+    pub metadata: serde_json::Value,
+    /// Whether an identical archive was published before, in which case
+    /// nothing was uploaded
+    pub already_published: bool,
+}
 
 /// A `buffrs` registry used for remote package management
 #[async_trait::async_trait]
@@ -12,10 +111,252 @@ pub trait Registry {
     /// Downloads a package from the registry
     async fn download(&self, dependency: Dependency) -> eyre::Result<Package>;
     /// Publishs a package to the registry
-    async fn publish(&self, package: Package, repository: String) -> eyre::Result<()>;
+    async fn publish(&self, package: Package, repository: String) -> eyre::Result<Receipt>;
+    /// Looks up when a dependency was published
+    ///
+    /// Registries that do not track this return `None`.
+    async fn published_at(&self, _dependency: &Dependency) -> eyre::Result<Option<SystemTime>> {
+        Ok(None)
+    }
+
+    /// Lists the published versions of a package in a repository
+    ///
+    /// This is needed to resolve version ranges. Registries that can not
+    /// enumerate their packages only support exact versions.
+    async fn versions(
+        &self,
+        _repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        Err(eyre::eyre!(
+            "This registry can not list the versions of {package}, depend on an exact version instead"
+        ))
+    }
+
+    /// Looks up whether a package was archived
+    ///
+    /// Registries that do not support archiving return `None`.
+    async fn archival(
+        &self,
+        _repository: &str,
+        _package: &PackageId,
+    ) -> eyre::Result<Option<Archival>> {
+        Ok(None)
+    }
+
+    /// Archives all versions of a package, or restores it if `archival` is
+    /// `None`
+    async fn archive(
+        &self,
+        _repository: &str,
+        package: &PackageId,
+        _archival: Option<&Archival>,
+    ) -> eyre::Result<()> {
+        Err(eyre::eyre!(
+            "Unable to archive {package}, this registry does not support archiving"
+        ))
+    }
+
+    /// Lists the yanked versions of a package in a repository
+    ///
+    /// Yanked versions are still published, but only installed if they are
+    /// locked. Registries that do not support yanking return no versions.
+    async fn yanked(
+        &self,
+        _repository: &str,
+        _package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        Ok(vec![])
+    }
+
+    /// Yanks a version of a package, or restores it if `yanked` is false
+    async fn yank(
+        &self,
+        _repository: &str,
+        package: &PackageId,
+        version: &semver::Version,
+        _yanked: bool,
+    ) -> eyre::Result<()> {
+        Err(eyre::eyre!(
+            "Unable to yank {package}@{version}, this registry does not support yanking"
+        ))
+    }
+
+    /// Sets properties on the archive of a version, e.g. to record who
+    /// published it
+    async fn annotate(
+        &self,
+        _repository: &str,
+        package: &PackageId,
+        version: &semver::Version,
+        _properties: &BTreeMap<String, String>,
+    ) -> eyre::Result<()> {
+        Err(eyre::eyre!(
+            "Unable to annotate {package}@{version}, this registry does not support properties"
+        ))
+    }
+
+    /// Finds packages whose name contains `term` in all repositories,
+    /// ordered by package and repository
+    async fn search(&self, _term: &str) -> eyre::Result<Vec<SearchResult>> {
+        Err(eyre::eyre!("This registry does not support searching"))
+    }
+}
+
+#[async_trait::async_trait]
+impl<R: Registry + Sync + ?Sized> Registry for &R {
+    async fn download(&self, dependency: Dependency) -> eyre::Result<Package> {
+        (**self).download(dependency).await
+    }
+
+    async fn publish(&self, package: Package, repository: String) -> eyre::Result<Receipt> {
+        (**self).publish(package, repository).await
+    }
+
+    async fn published_at(&self, dependency: &Dependency) -> eyre::Result<Option<SystemTime>> {
+        (**self).published_at(dependency).await
+    }
+
+    async fn versions(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        (**self).versions(repository, package).await
+    }
+
+    async fn archival(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Option<Archival>> {
+        (**self).archival(repository, package).await
+    }
+
+    async fn archive(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        archival: Option<&Archival>,
+    ) -> eyre::Result<()> {
+        (**self).archive(repository, package, archival).await
+    }
+
+    async fn yanked(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        (**self).yanked(repository, package).await
+    }
+
+    async fn yank(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        version: &semver::Version,
+        yanked: bool,
+    ) -> eyre::Result<()> {
+        (**self).yank(repository, package, version, yanked).await
+    }
+
+    async fn annotate(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        version: &semver::Version,
+        properties: &BTreeMap<String, String>,
+    ) -> eyre::Result<()> {
+        (**self)
+            .annotate(repository, package, version, properties)
+            .await
+    }
+
+    async fn search(&self, term: &str) -> eyre::Result<Vec<SearchResult>> {
+        (**self).search(term).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<R: Registry + Send + Sync + ?Sized> Registry for Box<R> {
+    async fn download(&self, dependency: Dependency) -> eyre::Result<Package> {
+        (**self).download(dependency).await
+    }
+
+    async fn publish(&self, package: Package, repository: String) -> eyre::Result<Receipt> {
+        (**self).publish(package, repository).await
+    }
+
+    async fn published_at(&self, dependency: &Dependency) -> eyre::Result<Option<SystemTime>> {
+        (**self).published_at(dependency).await
+    }
+
+    async fn versions(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        (**self).versions(repository, package).await
+    }
+
+    async fn archival(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Option<Archival>> {
+        (**self).archival(repository, package).await
+    }
+
+    async fn archive(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        archival: Option<&Archival>,
+    ) -> eyre::Result<()> {
+        (**self).archive(repository, package, archival).await
+    }
+
+    async fn yanked(
+        &self,
+        repository: &str,
+        package: &PackageId,
+    ) -> eyre::Result<Vec<semver::Version>> {
+        (**self).yanked(repository, package).await
+    }
+
+    async fn yank(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        version: &semver::Version,
+        yanked: bool,
+    ) -> eyre::Result<()> {
+        (**self).yank(repository, package, version, yanked).await
+    }
+
+    async fn annotate(
+        &self,
+        repository: &str,
+        package: &PackageId,
+        version: &semver::Version,
+        properties: &BTreeMap<String, String>,
+    ) -> eyre::Result<()> {
+        (**self)
+            .annotate(repository, package, version, properties)
+            .await
+    }
+
+    async fn search(&self, term: &str) -> eyre::Result<Vec<SearchResult>> {
+        (**self).search(term).await
+    }
 }
 
 /// An enum containing all supported registries
 pub enum RegistryType {
+    /// JFrog Artifactory
     Artifactory,
+    /// Plain HTTP file server or S3-compatible bucket
+    Http,
+    /// Local or network mounted directory
+    Filesystem,
 }
@@ -0,0 +1,138 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use eyre::{ensure, Context};
+use reqwest::{Certificate, NoProxy, Proxy};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Time to establish a connection unless configured otherwise
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Identifies buffrs to registries, e.g. in their access logs
+const USER_AGENT: &str = concat!("buffrs/", env!("CARGO_PKG_VERSION"));
+
+/// Environment variable overriding [`NetworkConfig::proxy`]
+pub const PROXY_ENV: &str = "BUFFRS_PROXY";
+/// Environment variable overriding [`NetworkConfig::no_proxy`], a comma
+/// separated list of hosts
+pub const NO_PROXY_ENV: &str = "BUFFRS_NO_PROXY";
+/// Environment variable naming a PEM file of root certificates, trusted in
+/// addition to [`NetworkConfig::ca_certificates`]
+pub const CA_BUNDLE_ENV: &str = "BUFFRS_CA_BUNDLE";
+
+/// Settings of the http client registries are accessed with, e.g. timeouts
+/// or a proxy in corporate networks
+///
+/// Without a configured proxy, the standard `HTTPS_PROXY`, `HTTP_PROXY` and
+/// `NO_PROXY` environment variables are honored.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NetworkConfig {
+    /// Proxy all requests are sent through, e.g. `http://proxy.corp:3128`
+    pub proxy: Option<Url>,
+    /// Hosts, domains (`.corp.internal`) or networks (`10.0.0.0/8`) reached
+    /// without the proxy
+    pub no_proxy: Option<Vec<String>>,
+    /// PEM files of root certificates trusted in addition to the system
+    /// ones, e.g. of a private certificate authority
+    pub ca_certificates: Option<Vec<PathBuf>>,
+    /// Time to establish a connection (defaults to `30s`)
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub connect_timeout: Option<Duration>,
+    /// Time a request may take including transferring the response, e.g.
+    /// `5m` (unlimited by default, as packages may be large)
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timeout: Option<Duration>,
+    /// Idle connections kept open to each registry host for reuse
+    pub pool_max_idle_per_host: Option<usize>,
+}
+
+impl NetworkConfig {
+    /// Builds an http client using these settings and their environment
+    /// variable overrides
+    ///
+    /// The client pools its connections, so it should be built once and
+    /// shared by all requests of an invocation. Clones share the pool.
+    pub fn client(&self) -> eyre::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .connect_timeout(self.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT));
+
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        if let Some(idle) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(idle);
+        }
+
+        let proxy = match std::env::var(PROXY_ENV) {
+            Ok(proxy) => Some(
+                proxy
+                    .parse::<Url>()
+                    .wrap_err_with(|| format!("Invalid proxy url in {PROXY_ENV}"))?,
+            ),
+            Err(_) => self.proxy.clone(),
+        };
+
+        let no_proxy = match std::env::var(NO_PROXY_ENV) {
+            Ok(hosts) => Some(hosts),
+            Err(_) => self.no_proxy.as_ref().map(|hosts| hosts.join(",")),
+        };
+
+        if let Some(proxy) = proxy {
+            let proxy = Proxy::all(proxy.as_str())
+                .wrap_err_with(|| format!("Invalid proxy url {proxy}"))?
+                .no_proxy(no_proxy.as_deref().and_then(NoProxy::from_string));
+
+            builder = builder.proxy(proxy);
+        }
+
+        let bundle = std::env::var_os(CA_BUNDLE_ENV).map(PathBuf::from);
+
+        for path in self.ca_certificates.iter().flatten().chain(bundle.iter()) {
+            for certificate in read_certificates(path)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        builder.build().wrap_err("Failed to create http client")
+    }
+}
+
+/// Reads all certificates of a PEM file, which may contain several
+fn read_certificates(path: &Path) -> eyre::Result<Vec<Certificate>> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let pem = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read certificates {}", path.display()))?;
+
+    let certificates = pem
+        .split_inclusive(END)
+        .filter_map(|block| block.find(BEGIN).map(|start| &block[start..]))
+        .map(|block| {
+            Certificate::from_pem(block.as_bytes())
+                .wrap_err_with(|| format!("Invalid certificate in {}", path.display()))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    ensure!(
+        !certificates.is_empty(),
+        "{} contains no PEM certificates",
+        path.display()
+    );
+
+    Ok(certificates)
+}
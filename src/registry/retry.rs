@@ -0,0 +1,148 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{future::Future, time::Duration};
+
+use eyre::ContextCompat;
+use reqwest::{Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use super::Transport;
+
+/// Retries of a failed request unless configured otherwise
+const DEFAULT_RETRIES: u32 = 2;
+/// Delay before the first retry unless configured otherwise
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(500);
+/// Upper bound of the delay between retries unless configured otherwise
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Response statuses retried unless configured otherwise
+const DEFAULT_STATUSES: [u16; 6] = [408, 429, 500, 502, 503, 504];
+
+/// Settings for retrying registry requests that failed transiently, e.g.
+/// because of an overloaded server or a dropped connection
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RetryConfig {
+    /// How often a failed request is retried (defaults to 2, 0 disables
+    /// retries)
+    pub retries: Option<u32>,
+    /// Delay before the first retry (e.g. `500ms`), doubled for every
+    /// further one
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub backoff: Option<Duration>,
+    /// Upper bound of the delay between retries (defaults to `30s`)
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_backoff: Option<Duration>,
+    /// Response statuses considered transient (defaults to 408, 429, 500,
+    /// 502, 503 and 504)
+    pub statuses: Option<Vec<u16>>,
+}
+
+impl RetryConfig {
+    /// Sends requests only once
+    pub fn disabled() -> Self {
+        Self {
+            retries: Some(0),
+            ..Default::default()
+        }
+    }
+
+    /// Sends the request created by `send` until it succeeds, fails
+    /// permanently or runs out of retries
+    ///
+    /// Requests fail transiently if they could not be sent, timed out or the
+    /// server answered with one of the configured statuses. The response of
+    /// the last attempt is returned.
+    pub async fn send<F, Fut>(&self, mut send: F) -> eyre::Result<Response>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = eyre::Result<Response>>,
+    {
+        let retries = self.retries.unwrap_or(DEFAULT_RETRIES);
+        let mut backoff = self.backoff.unwrap_or(DEFAULT_BACKOFF);
+        let max_backoff = self.max_backoff.unwrap_or(DEFAULT_MAX_BACKOFF);
+
+        let mut attempt = 0;
+
+        loop {
+            let result = send().await;
+
+            let reason = match result {
+                Ok(ref response) if self.is_transient(response.status()) => {
+                    format!("{} responded {}", response.url(), response.status())
+                }
+                Err(ref error) if is_transient(error) => error.to_string(),
+                _ => return result,
+            };
+
+            if attempt == retries {
+                return result;
+            }
+
+            attempt += 1;
+
+            tracing::warn!(
+                "! {reason}, retrying in {} ({attempt}/{retries})",
+                humantime::format_duration(backoff)
+            );
+
+            tokio::time::sleep(backoff).await;
+
+            backoff = (backoff * 2).min(max_backoff);
+        }
+    }
+
+    fn is_transient(&self, status: StatusCode) -> bool {
+        match self.statuses {
+            Some(ref statuses) => statuses.contains(&status.as_u16()),
+            None => DEFAULT_STATUSES.contains(&status.as_u16()),
+        }
+    }
+}
+
+/// Checks whether a request failed to be sent or timed out
+fn is_transient(error: &eyre::Report) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        .any(|error| error.is_connect() || error.is_timeout() || error.is_request())
+}
+
+/// Transport retrying requests that failed transiently
+///
+/// Requests with streamed bodies can not be sent twice and are only sent
+/// once, callers uploading files retry with [`RetryConfig::send`] instead.
+pub struct RetryTransport<T> {
+    inner: T,
+    config: RetryConfig,
+}
+
+impl<T> RetryTransport<T> {
+    /// Retries the requests sent via `inner` as configured
+    pub fn new(inner: T, config: RetryConfig) -> Self {
+        Self { inner, config }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: Transport> Transport for RetryTransport<T> {
+    async fn send(&self, request: Request) -> eyre::Result<Response> {
+        let Some(template) = request.try_clone() else {
+            return self.inner.send(request).await;
+        };
+
+        self.config
+            .send(|| async {
+                let request = template.try_clone().wrap_err("Failed to copy request")?;
+
+                self.inner.send(request).await
+            })
+            .await
+    }
+}
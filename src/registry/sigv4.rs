@@ -0,0 +1,607 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use eyre::{Context, ContextCompat};
+use hmac::{Hmac, Mac};
+use reqwest::{header::HeaderValue, Method, Request, Response};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use super::Transport;
+
+/// Payload hash of requests whose body is streamed
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+/// Header carrying the hash of the payload
+const CONTENT_SHA256: &str = "x-amz-content-sha256";
+/// Ip of the instance metadata service of EC2
+const IMDS: &str = "http://169.254.169.254";
+/// Ip of the credentials endpoint of ECS tasks
+const ECS: &str = "http://169.254.170.2";
+/// Credentials are refreshed once they expire within this period
+const REFRESH_WINDOW: Duration = Duration::from_secs(300);
+
+/// Settings for registries authenticating requests with AWS Signature V4
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SigV4Config {
+    /// AWS region of the registry, e.g. `eu-central-1`
+    pub region: String,
+    /// Signing name of the AWS service fronting the registry, e.g. `s3` or
+    /// `execute-api`
+    #[serde(default = "SigV4Config::default_service")]
+    pub service: String,
+    /// Profile of the shared credentials file to use instead of `AWS_PROFILE`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+}
+
+impl SigV4Config {
+    fn default_service() -> String {
+        "s3".to_owned()
+    }
+
+    /// Signs a request with the given credentials at time `at`
+    ///
+    /// The payload hash is taken from the `x-amz-content-sha256` header if
+    /// present. Otherwise in-memory bodies are hashed and streamed bodies are
+    /// sent unsigned, which only S3 accepts.
+    pub fn sign(
+        &self,
+        request: &mut Request,
+        credentials: &Credentials,
+        at: SystemTime,
+    ) -> eyre::Result<()> {
+        let at = chrono::DateTime::<chrono::Utc>::from(at);
+        let timestamp = at.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = at.format("%Y%m%d").to_string();
+
+        let payload_hash = match request.headers().get(CONTENT_SHA256) {
+            Some(hash) => hash.to_str().wrap_err("Invalid payload hash")?.to_owned(),
+            None => match request.body() {
+                None => hex::encode(Sha256::digest([])),
+                Some(body) => match body.as_bytes() {
+                    Some(bytes) => hex::encode(Sha256::digest(bytes)),
+                    None => UNSIGNED_PAYLOAD.to_owned(),
+                },
+            },
+        };
+
+        let url = request.url();
+
+        let mut host = url
+            .host_str()
+            .wrap_err("Request url lacks a host")?
+            .to_owned();
+
+        if let Some(port) = url.port() {
+            host = format!("{host}:{port}");
+        }
+
+        let mut headers = vec![("host", host), ("x-amz-date", timestamp.clone())];
+
+        // S3 requires the payload hash to be sent along
+        if self.service == "s3" || request.headers().contains_key(CONTENT_SHA256) {
+            headers.push((CONTENT_SHA256, payload_hash.clone()));
+        }
+
+        if let Some(ref token) = credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+
+        headers.sort();
+
+        let mut query: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(k, v)| (uri_encode(k.as_bytes()), uri_encode(v.as_bytes())))
+            .collect();
+
+        query.sort();
+
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical_request = [
+            request.method().as_str().to_owned(),
+            canonical_path(url.path()),
+            query
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<_>>()
+                .join("&"),
+            headers
+                .iter()
+                .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+                .collect(),
+            signed_headers.clone(),
+            payload_hash,
+        ]
+        .join("\n");
+
+        let scope = format!("{date}/{}/{}/aws4_request", self.region, self.service);
+
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request))
+        );
+
+        let key = [date.as_str(), &self.region, &self.service, "aws4_request"]
+            .iter()
+            .try_fold(
+                format!("AWS4{}", credentials.secret_access_key).into_bytes(),
+                |key, part| hmac(&key, part.as_bytes()),
+            )?;
+
+        let signature = hex::encode(hmac(&key, string_to_sign.as_bytes())?);
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            credentials.access_key_id
+        );
+
+        for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+            let mut value = HeaderValue::from_str(&value).wrap_err("Invalid signed header")?;
+            value.set_sensitive(name == "x-amz-security-token");
+            request.headers_mut().insert(name, value);
+        }
+
+        let mut authorization =
+            HeaderValue::from_str(&authorization).wrap_err("Invalid signature")?;
+
+        authorization.set_sensitive(true);
+
+        request
+            .headers_mut()
+            .insert(reqwest::header::AUTHORIZATION, authorization);
+
+        Ok(())
+    }
+}
+
+/// AWS credentials used to sign requests
+#[derive(Clone, Deserialize)]
+pub struct Credentials {
+    /// Access key id, e.g. `AKIA...`
+    #[serde(rename = "AccessKeyId")]
+    pub access_key_id: String,
+    /// Secret access key
+    #[serde(rename = "SecretAccessKey")]
+    pub secret_access_key: String,
+    /// Session token of temporary credentials
+    #[serde(rename = "Token", default)]
+    pub session_token: Option<String>,
+    /// Time the credentials expire at
+    #[serde(skip)]
+    pub expires_at: Option<SystemTime>,
+}
+
+impl Credentials {
+    /// Resolves credentials using the standard AWS credential chain
+    ///
+    /// Tries, in order, the `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`
+    /// environment variables, the shared credentials and config files, the
+    /// ECS task role and the EC2 instance profile.
+    pub async fn load(profile: Option<&str>) -> eyre::Result<Self> {
+        if let Some(credentials) = Self::from_env() {
+            return Ok(credentials);
+        }
+
+        if let Some(credentials) = Self::from_profile(profile).await? {
+            return Ok(credentials);
+        }
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(2))
+            .build()
+            .wrap_err("Failed to create http client")?;
+
+        if let Some(credentials) = Self::from_container(&client).await? {
+            return Ok(credentials);
+        }
+
+        if let Ok(credentials) = Self::from_instance(&client).await {
+            return Ok(credentials);
+        }
+
+        eyre::bail!("No AWS credentials found, set AWS_ACCESS_KEY_ID or configure a profile")
+    }
+
+    fn from_env() -> Option<Self> {
+        Some(Self {
+            access_key_id: std::env::var("AWS_ACCESS_KEY_ID").ok()?,
+            secret_access_key: std::env::var("AWS_SECRET_ACCESS_KEY").ok()?,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+            expires_at: None,
+        })
+    }
+
+    async fn from_profile(profile: Option<&str>) -> eyre::Result<Option<Self>> {
+        let profile = match profile {
+            Some(profile) => profile.to_owned(),
+            None => std::env::var("AWS_PROFILE").unwrap_or_else(|_| "default".to_owned()),
+        };
+
+        let aws_dir = home::home_dir().map(|home| home.join(".aws"));
+
+        let files = [
+            (
+                "AWS_SHARED_CREDENTIALS_FILE",
+                "credentials",
+                profile.clone(),
+            ),
+            ("AWS_CONFIG_FILE", "config", format!("profile {profile}")),
+        ];
+
+        for (env, name, section) in files {
+            let path = match std::env::var_os(env) {
+                Some(path) => PathBuf::from(path),
+                None => match aws_dir {
+                    Some(ref dir) => dir.join(name),
+                    None => continue,
+                },
+            };
+
+            let Ok(contents) = tokio::fs::read_to_string(&path).await else {
+                continue;
+            };
+
+            let mut values = parse_ini_section(&contents, &section);
+
+            // The default profile has no `profile` prefix in the config file
+            if values.is_empty() && profile == "default" {
+                values = parse_ini_section(&contents, "default");
+            }
+
+            let get = |key: &str| {
+                values
+                    .iter()
+                    .find(|(k, _)| k == key)
+                    .map(|(_, v)| v.clone())
+            };
+
+            if let (Some(access_key_id), Some(secret_access_key)) =
+                (get("aws_access_key_id"), get("aws_secret_access_key"))
+            {
+                return Ok(Some(Self {
+                    access_key_id,
+                    secret_access_key,
+                    session_token: get("aws_session_token"),
+                    expires_at: None,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn from_container(client: &reqwest::Client) -> eyre::Result<Option<Self>> {
+        let uri = match (
+            std::env::var("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI"),
+            std::env::var("AWS_CONTAINER_CREDENTIALS_FULL_URI"),
+        ) {
+            (Ok(relative), _) => format!("{ECS}{relative}"),
+            (_, Ok(full)) => full,
+            _ => return Ok(None),
+        };
+
+        let mut request = client.get(&uri);
+
+        if let Ok(token) = std::env::var("AWS_CONTAINER_AUTHORIZATION_TOKEN") {
+            request = request.header(reqwest::header::AUTHORIZATION, token);
+        }
+
+        let response = request
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .wrap_err("Failed to obtain credentials of the ECS task role")?;
+
+        Ok(Some(Self::from_response(response).await?))
+    }
+
+    async fn from_instance(client: &reqwest::Client) -> eyre::Result<Self> {
+        let token = client
+            .request(Method::PUT, format!("{IMDS}/latest/api/token"))
+            .header("X-aws-ec2-metadata-token-ttl-seconds", "21600")
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        let url = format!("{IMDS}/latest/meta-data/iam/security-credentials/");
+
+        let role = client
+            .get(&url)
+            .header("X-aws-ec2-metadata-token", &token)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        let role = role
+            .lines()
+            .next()
+            .wrap_err("No instance profile attached")?;
+
+        let response = client
+            .get(format!("{url}{role}"))
+            .header("X-aws-ec2-metadata-token", &token)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Self::from_response(response).await
+    }
+
+    async fn from_response(response: Response) -> eyre::Result<Self> {
+        #[derive(Deserialize)]
+        struct Temporary {
+            #[serde(flatten)]
+            credentials: Credentials,
+            #[serde(rename = "Expiration")]
+            expiration: Option<String>,
+        }
+
+        let temporary: Temporary = response
+            .json()
+            .await
+            .wrap_err("Failed to parse AWS credentials")?;
+
+        let expires_at = temporary
+            .expiration
+            .and_then(|e| chrono::DateTime::parse_from_rfc3339(&e).ok())
+            .map(SystemTime::from);
+
+        Ok(Self {
+            expires_at,
+            ..temporary.credentials
+        })
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.expires_at
+            .is_none_or(|expires_at| SystemTime::now() + REFRESH_WINDOW < expires_at)
+    }
+}
+
+/// Transport signing every request with AWS Signature V4
+pub struct SigV4Transport<T> {
+    inner: T,
+    config: SigV4Config,
+    credentials: Mutex<Option<Credentials>>,
+}
+
+impl<T> SigV4Transport<T> {
+    /// Signs the requests sent via `inner`
+    pub fn new(inner: T, config: SigV4Config) -> Self {
+        Self {
+            inner,
+            config,
+            credentials: Mutex::default(),
+        }
+    }
+
+    /// Returns cached credentials, resolving them again once they expire
+    async fn credentials(&self) -> eyre::Result<Credentials> {
+        let mut credentials = self.credentials.lock().await;
+
+        match *credentials {
+            Some(ref credentials) if credentials.is_fresh() => Ok(credentials.clone()),
+            _ => {
+                let fresh = Credentials::load(self.config.profile.as_deref()).await?;
+                *credentials = Some(fresh.clone());
+                Ok(fresh)
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: Transport> Transport for SigV4Transport<T> {
+    async fn send(&self, mut request: Request) -> eyre::Result<Response> {
+        let credentials = self.credentials().await?;
+
+        self.config
+            .sign(&mut request, &credentials, SystemTime::now())?;
+
+        self.inner.send(request).await
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> eyre::Result<Vec<u8>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).wrap_err("Invalid signing key")?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Encodes every segment of a url path once, as SigV4 requires
+///
+/// Segments are decoded first so characters the url left alone, like the `+`
+/// of semver build metadata, end up encoded as well.
+fn canonical_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| uri_encode(percent_decode(segment)))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Decodes `%XX` escapes, leaving malformed ones as they are
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    decoded
+}
+
+/// Percent-encodes everything but unreserved characters, as SigV4 requires
+fn uri_encode(value: impl AsRef<[u8]>) -> String {
+    value
+        .as_ref()
+        .iter()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (*b as char).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Reads the `key = value` pairs of a section of an ini file
+fn parse_ini_section(contents: &str, section: &str) -> Vec<(String, String)> {
+    let mut current = None;
+    let mut values = vec![];
+
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = Some(name.trim());
+            continue;
+        }
+
+        if current != Some(section) {
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once('=') {
+            values.push((key.trim().to_owned(), value.trim().to_owned()));
+        }
+    }
+
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Signs a request like the AWS SigV4 test suite does
+    fn sign(method: Method, url: &str, service: &str) -> String {
+        let config = SigV4Config {
+            region: "us-east-1".to_owned(),
+            service: service.to_owned(),
+            profile: None,
+        };
+
+        let credentials = Credentials {
+            access_key_id: "AKIDEXAMPLE".to_owned(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_owned(),
+            session_token: None,
+            expires_at: None,
+        };
+
+        let at = chrono::DateTime::parse_from_rfc3339("2015-08-30T12:36:00Z").unwrap();
+        let mut request = Request::new(method, url.parse().unwrap());
+
+        config.sign(&mut request, &credentials, at.into()).unwrap();
+
+        request.headers()["authorization"]
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    fn suite_authorization(signature: &str) -> String {
+        format!(
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, Signature={signature}"
+        )
+    }
+
+    #[test]
+    fn get_vanilla() {
+        assert_eq!(
+            sign(Method::GET, "https://example.amazonaws.com/", "service"),
+            suite_authorization("5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31")
+        );
+    }
+
+    #[test]
+    fn get_vanilla_query_order_key_case() {
+        assert_eq!(
+            sign(
+                Method::GET,
+                "https://example.amazonaws.com/?Param2=value2&Param1=value1",
+                "service"
+            ),
+            suite_authorization("b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500")
+        );
+    }
+
+    #[test]
+    fn get_unreserved() {
+        assert_eq!(
+            sign(
+                Method::GET,
+                "https://example.amazonaws.com/-._~0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz",
+                "service"
+            ),
+            suite_authorization("07ef7494c76fa4850883e2b006601f940f8a34d404d0cfa977f52a65bbf5f24f")
+        );
+    }
+
+    #[test]
+    fn get_utf8() {
+        assert_eq!(
+            sign(
+                Method::GET,
+                "https://example.amazonaws.com/\u{1234}",
+                "service"
+            ),
+            suite_authorization("8318018e0b0f223aa2bbf98705b62bb787dc9c0e678f255a891fd03141be5d85")
+        );
+    }
+
+    #[test]
+    fn get_space() {
+        assert_eq!(
+            sign(
+                Method::GET,
+                "https://example.amazonaws.com/example space/",
+                "service"
+            ),
+            suite_authorization("652487583200325589f1fba4c7e578f72c47cb61beeca81406b39ddec1366741")
+        );
+    }
+
+    #[test]
+    fn canonical_path_encodes_segments() {
+        assert_eq!(canonical_path("/"), "/");
+        assert_eq!(
+            canonical_path("/bucket/pkg/pkg-1.0.0+build.1.tgz"),
+            "/bucket/pkg/pkg-1.0.0%2Bbuild.1.tgz"
+        );
+        assert_eq!(canonical_path("/a%20b/%2B/100%"), "/a%20b/%2B/100%25");
+    }
+}
@@ -0,0 +1,34 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::sync::Arc;
+
+use eyre::Context;
+use reqwest::{Request, Response};
+
+/// The HTTP layer used by registries to communicate with remote servers
+///
+/// Implement this to inject a custom client into a registry, for example to
+/// add authentication middleware or to replay recorded responses in tests.
+#[async_trait::async_trait]
+pub trait Transport: Send + Sync {
+    /// Sends a request and returns the response of the server
+    async fn send(&self, request: Request) -> eyre::Result<Response>;
+}
+
+#[async_trait::async_trait]
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    async fn send(&self, request: Request) -> eyre::Result<Response> {
+        (**self).send(request).await
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for reqwest::Client {
+    async fn send(&self, request: Request) -> eyre::Result<Response> {
+        let uri = request.url().to_owned();
+
+        self.execute(request)
+            .await
+            .wrap_err(format!("Failed to send request to {uri}"))
+    }
+}
@@ -0,0 +1,155 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use eyre::{ensure, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::{Dependency, Manifest};
+
+/// Resolves the dependencies declared in a manifest into the list of packages
+/// to install
+///
+/// This is a pure function of the manifest, the result is ordered by package
+/// name so that resolutions are deterministic. Version requirements are
+/// validated, but not resolved, see [`VersionRequirement::select`].
+pub fn resolve(manifest: &Manifest) -> eyre::Result<Vec<Dependency>> {
+    let mut dependencies = manifest.dependencies.clone();
+
+    if let Some(ref api) = manifest.api {
+        ensure!(
+            dependencies.iter().all(|d| d.package != api.name),
+            "Package {} can not depend on itself",
+            api.name
+        );
+    }
+
+    for dependency in &dependencies {
+        let manifest = &dependency.manifest;
+
+        let sources = [
+            !manifest.repository.is_empty(),
+            manifest.path.is_some(),
+            manifest.git.is_some(),
+        ];
+
+        ensure!(
+            sources.iter().filter(|s| **s).count() == 1,
+            "Dependency {} needs exactly one of repository, path or git",
+            dependency.package
+        );
+
+        let references = [&manifest.branch, &manifest.tag, &manifest.rev];
+
+        ensure!(
+            manifest.git.is_some() || references.iter().all(|r| r.is_none()),
+            "Dependency {} can only specify a branch, tag or rev along with git",
+            dependency.package
+        );
+
+        ensure!(
+            references.iter().filter(|r| r.is_some()).count() <= 1,
+            "Dependency {} can only specify one of branch, tag or rev",
+            dependency.package
+        );
+
+        // Unpublished packages only optionally constrain their version
+        ensure!(
+            !manifest.is_registry() || manifest.version.is_some(),
+            "Dependency {} needs a version requirement",
+            dependency.package
+        );
+    }
+
+    dependencies.sort();
+
+    Ok(dependencies)
+}
+
+/// Version requirement of a dependency
+///
+/// Plain versions such as `1.2.3` pin exactly that version. Everything else
+/// is a semver range, e.g. `^1.2`, `~0.4` or `>=2, <3`.
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum VersionRequirement {
+    /// Exactly this version
+    Exact(semver::Version),
+    /// Any version matching the range
+    Range(semver::VersionReq),
+}
+
+impl VersionRequirement {
+    /// Checks whether `version` satisfies the requirement
+    pub fn matches(&self, version: &semver::Version) -> bool {
+        match self {
+            Self::Exact(exact) => exact == version,
+            Self::Range(range) => range.matches(version),
+        }
+    }
+
+    /// Picks the highest of the given versions satisfying the requirement
+    pub fn select<'a>(
+        &self,
+        versions: impl IntoIterator<Item = &'a semver::Version>,
+    ) -> Option<&'a semver::Version> {
+        versions.into_iter().filter(|v| self.matches(v)).max()
+    }
+}
+
+impl FromStr for VersionRequirement {
+    type Err = eyre::Report;
+
+    fn from_str(requirement: &str) -> eyre::Result<Self> {
+        if let Ok(version) = semver::Version::parse(requirement.trim()) {
+            return Ok(Self::Exact(version));
+        }
+
+        semver::VersionReq::parse(requirement)
+            .map(Self::Range)
+            .wrap_err_with(|| {
+                format!("{requirement:?} is neither a version (1.2.3) nor a semver range (^1.2, ~0.4, >=2, <3)")
+            })
+    }
+}
+
+impl fmt::Display for VersionRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exact(version) => write!(f, "{version}"),
+            Self::Range(range) => write!(f, "{range}"),
+        }
+    }
+}
+
+impl TryFrom<String> for VersionRequirement {
+    type Error = eyre::Report;
+
+    fn try_from(requirement: String) -> eyre::Result<Self> {
+        requirement.parse()
+    }
+}
+
+impl From<VersionRequirement> for String {
+    fn from(requirement: VersionRequirement) -> Self {
+        requirement.to_string()
+    }
+}
+
+impl PartialOrd for VersionRequirement {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for VersionRequirement {
+    /// Orders exact versions by precedence, ranges by their text
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Exact(a), Self::Exact(b)) => a.cmp(b),
+            (Self::Exact(_), Self::Range(_)) => Ordering::Less,
+            (Self::Range(_), Self::Exact(_)) => Ordering::Greater,
+            (Self::Range(a), Self::Range(b)) => a.to_string().cmp(&b.to_string()),
+        }
+    }
+}
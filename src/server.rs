@@ -0,0 +1,231 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use eyre::Context;
+use futures::StreamExt;
+use hyper::{
+    header::{CONTENT_TYPE, RETRY_AFTER},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, StatusCode,
+};
+use tokio_util::{io::ReaderStream, sync::CancellationToken};
+
+use crate::{
+    error::ErrorCode,
+    manifest::Dependency,
+    package::{DigestAlgorithm, PackageId},
+    registry::{Registry, CHECKSUM_SHA256},
+    resolver::VersionRequirement,
+};
+
+/// Serves a registry over HTTP using the artifactory url layout
+///
+/// Packages are available at `/<repository>/<package>/<package>-<version>.tgz`
+/// and their publication time at `/api/storage/<path>`. Listing the versions of
+/// a package is possible at `/api/storage/<repository>/<package>`, so clients can use
+/// the server as regular artifactory registry. The server is read-only and
+/// ignores credentials sent by clients. It runs until `cancel` is triggered.
+pub async fn serve<R>(registry: R, addr: SocketAddr, cancel: CancellationToken) -> eyre::Result<()>
+where
+    R: Registry + Send + Sync + 'static,
+{
+    let registry = Arc::new(registry);
+
+    let service = make_service_fn(move |_| {
+        let registry = registry.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let registry = registry.clone();
+
+                async move { Ok::<_, Infallible>(handle(&*registry, request).await) }
+            }))
+        }
+    });
+
+    let server = hyper::Server::try_bind(&addr)
+        .wrap_err_with(|| format!("Failed to listen on {addr}"))?
+        .serve(service);
+
+    tracing::info!(":: serving on http://{}", server.local_addr());
+
+    server
+        .with_graceful_shutdown(cancel.cancelled_owned())
+        .await
+        .wrap_err("Server failed")
+}
+
+async fn handle(registry: &(impl Registry + Sync), request: Request<Body>) -> Response<Body> {
+    if request.method() != Method::GET {
+        return status(StatusCode::METHOD_NOT_ALLOWED, "This registry is read-only");
+    }
+
+    let path = request.uri().path().trim_start_matches('/');
+
+    let (storage, path) = match path.strip_prefix("api/storage/") {
+        Some(path) => (true, path),
+        None => (false, path),
+    };
+
+    if storage {
+        if let Some((repository, package)) = parse_folder_path(path) {
+            return match versions(registry, repository, &package).await {
+                Ok(response) => response,
+                Err(error) => {
+                    tracing::warn!("failed to list versions of {package}: {error:#}");
+                    status(StatusCode::BAD_GATEWAY, &format!("{error:#}"))
+                }
+            };
+        }
+    }
+
+    let Some(dependency) = parse_artifact_path(path) else {
+        return status(StatusCode::NOT_FOUND, "Not found");
+    };
+
+    let response = if storage {
+        published_at(registry, &dependency).await
+    } else {
+        download(registry, dependency.clone()).await
+    };
+
+    match response {
+        Ok(response) => {
+            tracing::debug!("{} {}", request.method(), request.uri());
+            response
+        }
+        Err(error) => {
+            tracing::warn!("failed to serve {dependency}: {error:#}");
+
+            match ErrorCode::of(&error) {
+                Some(ErrorCode::DownloadFailed) => {
+                    status(StatusCode::NOT_FOUND, &format!("{error:#}"))
+                }
+                _ => {
+                    let mut response = status(StatusCode::BAD_GATEWAY, &format!("{error:#}"));
+
+                    response
+                        .headers_mut()
+                        .insert(RETRY_AFTER, hyper::header::HeaderValue::from_static("5"));
+
+                    response
+                }
+            }
+        }
+    }
+}
+
+/// Streams the archive of a dependency after checking it against the digest
+/// of the registry, so corrupted downloads are never passed on as valid
+async fn download(
+    registry: &(impl Registry + Sync),
+    dependency: Dependency,
+) -> eyre::Result<Response<Body>> {
+    let package = registry.download(dependency).await?;
+
+    package.verify().await?;
+
+    let checksum = package.tgz.digest(DigestAlgorithm::Sha256).await?;
+
+    let file = tokio::fs::File::open(package.tgz.path())
+        .await
+        .wrap_err("Failed to read package")?;
+
+    // The archive is deleted with the last handle to it, so the stream keeps
+    // one until it is finished
+    let tgz = package.tgz;
+
+    let body = ReaderStream::new(file).map(move |chunk| {
+        let _ = &tgz;
+        chunk
+    });
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/gzip")
+        .header(CHECKSUM_SHA256, checksum.to_hex())
+        .body(Body::wrap_stream(body))?)
+}
+
+async fn published_at(
+    registry: &(impl Registry + Sync),
+    dependency: &Dependency,
+) -> eyre::Result<Response<Body>> {
+    let Some(published_at) = registry.published_at(dependency).await? else {
+        return Ok(status(StatusCode::NOT_FOUND, "Publication time unknown"));
+    };
+
+    let created = chrono::DateTime::<chrono::Utc>::from(published_at).to_rfc3339();
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({ "created": created }).to_string(),
+        ))?)
+}
+
+async fn versions(
+    registry: &(impl Registry + Sync),
+    repository: &str,
+    package: &PackageId,
+) -> eyre::Result<Response<Body>> {
+    let versions = registry.versions(repository, package).await?;
+
+    if versions.is_empty() {
+        return Ok(status(StatusCode::NOT_FOUND, "Package not found"));
+    }
+
+    let children: Vec<_> = versions
+        .iter()
+        .map(|version| serde_json::json!({ "uri": format!("/{package}-{version}.tgz") }))
+        .collect();
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({ "children": children }).to_string(),
+        ))?)
+}
+
+/// Parses `<repository>/<package>`
+fn parse_folder_path(path: &str) -> Option<(&str, PackageId)> {
+    let (repository, package) = path.trim_end_matches('/').split_once('/')?;
+
+    if repository.is_empty() || repository.starts_with('.') {
+        return None;
+    }
+
+    Some((repository, package.parse().ok()?))
+}
+
+/// Parses `<repository>/<package>/<package>-<version>.tgz`
+fn parse_artifact_path(path: &str) -> Option<Dependency> {
+    let mut segments = path.split('/');
+
+    let (repository, package, file) = (segments.next()?, segments.next()?, segments.next()?);
+
+    if segments.next().is_some() || repository.is_empty() || repository.starts_with('.') {
+        return None;
+    }
+
+    let package: PackageId = package.parse().ok()?;
+
+    let version = file
+        .strip_suffix(".tgz")?
+        .strip_prefix(package.as_str())?
+        .strip_prefix('-')?;
+
+    let version = semver::Version::parse(version).ok()?;
+
+    Some(Dependency::new(
+        repository.to_owned(),
+        package,
+        VersionRequirement::Exact(version),
+    ))
+}
+
+fn status(status: StatusCode, message: &str) -> Response<Body> {
+    let mut response = Response::new(Body::from(format!("{message}\n")));
+    *response.status_mut() = status;
+    response
+}
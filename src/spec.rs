@@ -0,0 +1,316 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{fmt, ops::Range};
+
+use crate::{
+    error::ErrorCode, manifest::Dependency, package::PackageId, resolver::VersionRequirement,
+};
+
+/// Separator of group and channel in repository names
+const PROTO_INFIX: &str = "-proto-";
+
+/// A package as named on the command line:
+/// `<group>-proto-<channel>/<package>[@<version>]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageSpec {
+    /// Repository containing the package, e.g. `physics-proto-stable`
+    pub repository: String,
+    /// Name of the package
+    pub package: PackageId,
+    /// Version requirement following the `@`, if any
+    pub version: Option<VersionRequirement>,
+}
+
+/// Versions a [`PackageSpec`] is expected to carry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Versioned {
+    /// A version requirement is optional
+    Optional,
+    /// Any version requirement has to be given
+    Required,
+    /// An exact version has to be given
+    Exact,
+    /// No version may be given
+    Never,
+}
+
+impl PackageSpec {
+    /// Parses a package specification, pointing to the offending part of
+    /// `spec` if it is invalid
+    pub fn parse(spec: &str, versioned: Versioned) -> Result<Self, SpecError> {
+        let start = spec.len() - spec.trim_start().len();
+        let end = spec.trim_end().len();
+
+        let error = |span: Range<usize>, code: ErrorCode, message: String| SpecError {
+            spec: spec.to_owned(),
+            span,
+            code,
+            message,
+        };
+
+        let expected = match versioned {
+            Versioned::Optional => "<repository>/<package>[@<version>]",
+            Versioned::Required | Versioned::Exact => "<repository>/<package>@<version>",
+            Versioned::Never => "<repository>/<package>",
+        };
+
+        if start == end {
+            return Err(error(
+                start..end,
+                ErrorCode::InvalidDependency,
+                format!("Missing package, expected {expected}"),
+            ));
+        }
+
+        let Some(slash) = spec[start..end].find('/').map(|i| start + i) else {
+            return Err(error(
+                start..end,
+                ErrorCode::InvalidDependency,
+                format!("Missing repository, expected {expected}"),
+            ));
+        };
+
+        let repository = &spec[start..slash];
+
+        if let Some(invalid) = repository
+            .char_indices()
+            .find(|(_, c)| !(c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '-'))
+            .map(|(i, c)| start + i..start + i + c.len_utf8())
+        {
+            return Err(error(
+                invalid,
+                ErrorCode::InvalidDependency,
+                "Repositories only contain lowercase letters, digits and dashes".to_owned(),
+            ));
+        }
+
+        let channel = repository
+            .split_once(PROTO_INFIX)
+            .filter(|(group, channel)| !group.is_empty() && !channel.is_empty());
+
+        if channel.is_none() {
+            return Err(error(
+                start..slash,
+                ErrorCode::InvalidDependency,
+                "Only proto repositories are allowed, named <group>-proto-<channel>".to_owned(),
+            ));
+        }
+
+        let at = spec[slash..end].find('@').map(|i| slash + i);
+
+        let name = slash + 1..at.unwrap_or(end);
+
+        let package = spec[name.clone()].parse::<PackageId>().map_err(|e| {
+            error(
+                name.clone(),
+                ErrorCode::InvalidPackageId,
+                format!("{}", e.root_cause()),
+            )
+        })?;
+
+        let version = match at {
+            Some(at) if versioned == Versioned::Never => {
+                return Err(error(
+                    at..end,
+                    ErrorCode::InvalidDependency,
+                    format!("Unexpected version, expected {expected}"),
+                ))
+            }
+            Some(at) if at + 1 == end => {
+                return Err(error(
+                    at..end,
+                    ErrorCode::InvalidDependency,
+                    "Missing version after @, e.g. @1.2.3 or @^1.2".to_owned(),
+                ))
+            }
+            Some(at) => {
+                let span = at + 1..end;
+
+                let version = spec[span.clone()]
+                    .parse::<VersionRequirement>()
+                    .map_err(|_| {
+                        error(
+                            span.clone(),
+                            ErrorCode::InvalidDependency,
+                            "Versions must be an exact version (1.2.3) or a semver range (^1.2)"
+                                .to_owned(),
+                        )
+                    })?;
+
+                if let (Versioned::Exact, VersionRequirement::Range(_)) = (versioned, &version) {
+                    return Err(error(
+                        span,
+                        ErrorCode::InvalidDependency,
+                        "Expected an exact version, e.g. 1.2.3".to_owned(),
+                    ));
+                }
+
+                Some(version)
+            }
+            None if matches!(versioned, Versioned::Required | Versioned::Exact) => {
+                return Err(error(
+                    end..end,
+                    ErrorCode::InvalidDependency,
+                    format!("Missing version, expected {expected}"),
+                ))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            repository: repository.to_owned(),
+            package,
+            version,
+        })
+    }
+
+    /// Group owning the repository, e.g. `physics` in `physics-proto-stable`
+    pub fn group(&self) -> &str {
+        self.repository
+            .split_once(PROTO_INFIX)
+            .map_or(&self.repository, |(group, _)| group)
+    }
+
+    /// Release channel of the repository, e.g. `stable` in
+    /// `physics-proto-stable`
+    pub fn channel(&self) -> &str {
+        self.repository
+            .split_once(PROTO_INFIX)
+            .map_or("", |(_, channel)| channel)
+    }
+
+    /// The dependency to declare in a manifest, on any version if none was
+    /// given
+    pub fn dependency(&self) -> Dependency {
+        Dependency::new(
+            self.repository.clone(),
+            self.package.clone(),
+            self.version
+                .clone()
+                .unwrap_or_else(|| VersionRequirement::Range(semver::VersionReq::STAR)),
+        )
+    }
+}
+
+impl fmt::Display for PackageSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.repository, self.package)?;
+
+        if let Some(ref version) = self.version {
+            write!(f, "@{version}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An invalid [`PackageSpec`], rendered with the offending part underlined
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpecError {
+    spec: String,
+    span: Range<usize>,
+    code: ErrorCode,
+    message: String,
+}
+
+impl SpecError {
+    /// Byte range of the specification the error refers to
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    /// Code of the error, depending on the part of the specification that is
+    /// invalid
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let offset = self.spec[..self.span.start].chars().count();
+        let width = self.spec[self.span.clone()].chars().count().max(1);
+
+        writeln!(f, "{}", self.message)?;
+        writeln!(f)?;
+        writeln!(f, "    {}", self.spec)?;
+        write!(f, "    {}{}", " ".repeat(offset), "^".repeat(width))
+    }
+}
+
+impl std::error::Error for SpecError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_versioned_spec() {
+        let spec =
+            PackageSpec::parse("physics-proto-stable/sensors@1.2.3", Versioned::Exact).unwrap();
+
+        assert_eq!(spec.repository, "physics-proto-stable");
+        assert_eq!(spec.package, "sensors".parse::<PackageId>().unwrap());
+        assert_eq!(spec.version, Some("1.2.3".parse().unwrap()));
+        assert_eq!(spec.group(), "physics");
+        assert_eq!(spec.channel(), "stable");
+    }
+
+    #[test]
+    fn rejects_missing_slash() {
+        let error = PackageSpec::parse("physics-proto-stable", Versioned::Optional).unwrap_err();
+
+        assert_eq!(error.span(), 0..20);
+        assert_eq!(error.code(), ErrorCode::InvalidDependency);
+        assert!(error.to_string().starts_with("Missing repository"));
+    }
+
+    #[test]
+    fn rejects_invalid_repository_character() {
+        let error =
+            PackageSpec::parse("physics-proto-Stable/sensors", Versioned::Optional).unwrap_err();
+
+        assert_eq!(error.span(), 14..15);
+        assert_eq!(error.code(), ErrorCode::InvalidDependency);
+    }
+
+    #[test]
+    fn rejects_empty_version() {
+        let error =
+            PackageSpec::parse("physics-proto-stable/sensors@", Versioned::Optional).unwrap_err();
+
+        assert_eq!(error.span(), 28..29);
+        assert!(error.to_string().starts_with("Missing version after @"));
+    }
+
+    #[test]
+    fn rejects_range_where_exact_is_required() {
+        let spec = "physics-proto-stable/sensors@^1.2";
+
+        assert!(PackageSpec::parse(spec, Versioned::Optional).is_ok());
+
+        let error = PackageSpec::parse(spec, Versioned::Exact).unwrap_err();
+
+        assert_eq!(error.span(), 29..33);
+        assert!(error.to_string().starts_with("Expected an exact version"));
+    }
+
+    #[test]
+    fn underlines_characters_of_non_ascii_input() {
+        let error =
+            PackageSpec::parse("physics-pröto-stable/sensors", Versioned::Optional).unwrap_err();
+
+        assert_eq!(error.span(), 10..12);
+        assert!(error
+            .to_string()
+            .ends_with(&format!("\n    {}^", " ".repeat(10))));
+
+        let error = PackageSpec::parse("physics-proto-stable/sensors@1.ü", Versioned::Optional)
+            .unwrap_err();
+
+        assert_eq!(error.span(), 29..33);
+        assert!(error
+            .to_string()
+            .ends_with(&format!("\n    {}^^^", " ".repeat(29))));
+    }
+}
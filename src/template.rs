@@ -0,0 +1,335 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use eyre::{ensure, Context, ContextCompat};
+use tempfile::TempDir;
+use url::Url;
+use walkdir::WalkDir;
+
+use crate::package::PackageId;
+
+/// Location of a project template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateSource {
+    /// GitHub repository, written as `gh:<owner>/<repo>[@<ref>]`
+    GitHub {
+        /// Owner of the repository
+        owner: String,
+        /// Name of the repository
+        repo: String,
+        /// Branch, tag or commit, defaults to the default branch
+        reference: Option<String>,
+    },
+    /// Git repository, written as `<url>.git[#<ref>]` or `git+<url>[#<ref>]`
+    Git {
+        /// Url passed to `git clone`
+        url: String,
+        /// Branch or tag, defaults to the default branch
+        reference: Option<String>,
+    },
+    /// `.tar.gz` archive served over http(s)
+    Tarball(Url),
+    /// Directory on the local file system
+    Local(PathBuf),
+}
+
+impl FromStr for TemplateSource {
+    type Err = eyre::Report;
+
+    fn from_str(source: &str) -> eyre::Result<Self> {
+        let split_ref = |source: &str, separator: char| match source.split_once(separator) {
+            Some((source, reference)) => (source.to_owned(), Some(reference.to_owned())),
+            None => (source.to_owned(), None),
+        };
+
+        if let Some(repository) = source.strip_prefix("gh:") {
+            let (repository, reference) = split_ref(repository, '@');
+
+            let (owner, repo) = repository
+                .split_once('/')
+                .filter(|(owner, repo)| !owner.is_empty() && !repo.is_empty())
+                .wrap_err_with(|| {
+                    format!("Invalid template {source}, expected gh:<owner>/<repo>")
+                })?;
+
+            return Ok(Self::GitHub {
+                owner: owner.to_owned(),
+                repo: repo.to_owned(),
+                reference,
+            });
+        }
+
+        if let Some(url) = source.strip_prefix("git+") {
+            let (url, reference) = split_ref(url, '#');
+            return Ok(Self::Git { url, reference });
+        }
+
+        let (url, reference) = split_ref(source, '#');
+
+        if url.ends_with(".git") || url.starts_with("git@") || url.starts_with("ssh://") {
+            return Ok(Self::Git { url, reference });
+        }
+
+        if source.starts_with("http://") || source.starts_with("https://") {
+            let url = source
+                .parse()
+                .wrap_err_with(|| format!("Invalid template url {source}"))?;
+
+            return Ok(Self::Tarball(url));
+        }
+
+        Ok(Self::Local(PathBuf::from(source)))
+    }
+}
+
+impl fmt::Display for TemplateSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GitHub {
+                owner,
+                repo,
+                reference,
+            } => {
+                write!(f, "gh:{owner}/{repo}")?;
+                reference.iter().try_for_each(|r| write!(f, "@{r}"))
+            }
+            Self::Git { url, reference } => {
+                write!(f, "{url}")?;
+                reference.iter().try_for_each(|r| write!(f, "#{r}"))
+            }
+            Self::Tarball(url) => write!(f, "{url}"),
+            Self::Local(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// A template fetched into a temporary directory
+pub struct Template {
+    _dir: TempDir,
+    root: PathBuf,
+}
+
+impl Template {
+    /// Downloads a template
+    ///
+    /// GitHub repositories are fetched as tarball, private ones are accessible
+    /// if `GITHUB_TOKEN` is set. Git repositories are cloned with the `git`
+    /// executable, using its configured credentials. Archives are downloaded
+    /// with `client`.
+    pub async fn fetch(source: &TemplateSource, client: &reqwest::Client) -> eyre::Result<Self> {
+        let dir = tempfile::tempdir().wrap_err("Failed to create temporary directory")?;
+
+        let root = match source {
+            TemplateSource::GitHub {
+                owner,
+                repo,
+                reference,
+            } => {
+                let url = match reference {
+                    Some(reference) => {
+                        format!("https://api.github.com/repos/{owner}/{repo}/tarball/{reference}")
+                    }
+                    None => format!("https://api.github.com/repos/{owner}/{repo}/tarball"),
+                };
+
+                download(client, &url, dir.path()).await?
+            }
+            TemplateSource::Tarball(url) => download(client, url.as_str(), dir.path()).await?,
+            TemplateSource::Git { url, reference } => {
+                let mut git = tokio::process::Command::new("git");
+
+                git.args(["clone", "--quiet", "--depth", "1"]);
+
+                if let Some(reference) = reference {
+                    git.args(["--branch", reference]);
+                }
+
+                let status = git
+                    .arg(url)
+                    .arg(dir.path())
+                    .status()
+                    .await
+                    .wrap_err("Failed to run git, is it installed?")?;
+
+                ensure!(status.success(), "Failed to clone template {source}");
+
+                tokio::fs::remove_dir_all(dir.path().join(".git"))
+                    .await
+                    .ok();
+
+                dir.path().to_owned()
+            }
+            TemplateSource::Local(path) => {
+                ensure!(path.is_dir(), "Template {} does not exist", path.display());
+
+                copy_dir(path, dir.path())?;
+
+                dir.path().to_owned()
+            }
+        };
+
+        Ok(Self { _dir: dir, root })
+    }
+
+    /// Renders the template into `target`, returning the created files
+    ///
+    /// The placeholders `{{name}}`, `{{version}}` and `{{package_dir}}` (the
+    /// name with underscores, as used in `proto/dep`) are replaced in paths
+    /// and in the contents of text files. Existing files are never
+    /// overwritten.
+    pub async fn render(
+        &self,
+        target: &Path,
+        name: &PackageId,
+        version: &str,
+    ) -> eyre::Result<Vec<PathBuf>> {
+        let variables = [
+            ("{{name}}", name.to_string()),
+            ("{{version}}", version.to_owned()),
+            ("{{package_dir}}", name.replace('-', "_")),
+        ];
+
+        let substitute = |text: &str| {
+            variables
+                .iter()
+                .fold(text.to_owned(), |text, (placeholder, value)| {
+                    text.replace(placeholder, value)
+                })
+        };
+
+        let mut files = vec![];
+
+        for entry in WalkDir::new(&self.root).sort_by_file_name() {
+            let entry = entry.wrap_err("Failed to read template")?;
+
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let relative = entry.path().strip_prefix(&self.root)?;
+
+            if relative.starts_with(".git") {
+                continue;
+            }
+
+            let relative = substitute(&relative.to_string_lossy());
+
+            crate::package::validate_path(Path::new(&relative))
+                .wrap_err("Template contains an invalid path")?;
+
+            files.push((entry.path().to_owned(), target.join(relative)));
+        }
+
+        ensure!(!files.is_empty(), "Template is empty");
+
+        if let Some((_, existing)) = files.iter().find(|(_, to)| to.exists()) {
+            eyre::bail!(
+                "Cant initialize project, {} already exists",
+                existing.display()
+            );
+        }
+
+        for (from, to) in &files {
+            let contents = tokio::fs::read(from)
+                .await
+                .wrap_err_with(|| format!("Failed to read {}", from.display()))?;
+
+            let contents = match String::from_utf8(contents) {
+                Ok(text) => substitute(&text).into_bytes(),
+                Err(binary) => binary.into_bytes(),
+            };
+
+            if let Some(parent) = to.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .wrap_err_with(|| format!("Failed to create {}", parent.display()))?;
+            }
+
+            tokio::fs::write(to, contents)
+                .await
+                .wrap_err_with(|| format!("Failed to write {}", to.display()))?;
+        }
+
+        Ok(files.into_iter().map(|(_, to)| to).collect())
+    }
+}
+
+/// Downloads a `.tar.gz` archive and unpacks it into `dir`, returning the root
+/// of its contents
+///
+/// Archives of repositories usually wrap their contents in a single
+/// directory, which is skipped.
+async fn download(client: &reqwest::Client, url: &str, dir: &Path) -> eyre::Result<PathBuf> {
+    let mut request = client
+        .get(url)
+        .header("user-agent", concat!("buffrs/", env!("CARGO_PKG_VERSION")));
+
+    if url.starts_with("https://api.github.com/") {
+        if let Ok(token) = std::env::var("GITHUB_TOKEN") {
+            request = request.bearer_auth(token);
+        }
+    }
+
+    let response = request
+        .send()
+        .await
+        .wrap_err_with(|| format!("Failed to download template {url}"))?;
+
+    ensure!(
+        response.status().is_success(),
+        "Failed to download template {url}: {}",
+        response.status()
+    );
+
+    let bytes = response
+        .bytes()
+        .await
+        .wrap_err_with(|| format!("Failed to download template {url}"))?;
+
+    let dir = dir.to_owned();
+
+    tokio::task::spawn_blocking(move || {
+        tar::Archive::new(flate2::read::GzDecoder::new(&bytes[..]))
+            .unpack(&dir)
+            .wrap_err("Failed to unpack template")?;
+
+        Ok(single_subdirectory(&dir)?.unwrap_or(dir))
+    })
+    .await
+    .wrap_err("Failed to join unpacking task")?
+}
+
+/// Returns the only entry of `dir` if it is a directory
+fn single_subdirectory(dir: &Path) -> eyre::Result<Option<PathBuf>> {
+    let entries = std::fs::read_dir(dir)
+        .wrap_err("Failed to read template")?
+        .collect::<Result<Vec<_>, _>>()
+        .wrap_err("Failed to read template")?;
+
+    Ok(match entries.as_slice() {
+        [entry] if entry.path().is_dir() => Some(entry.path()),
+        _ => None,
+    })
+}
+
+/// Recursively copies the contents of `from` into `to`
+fn copy_dir(from: &Path, to: &Path) -> eyre::Result<()> {
+    for entry in WalkDir::new(from) {
+        let entry = entry.wrap_err("Failed to read template")?;
+        let target = to.join(entry.path().strip_prefix(from)?);
+
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&target)
+        } else {
+            std::fs::copy(entry.path(), &target).map(|_| ())
+        }
+        .wrap_err_with(|| format!("Failed to copy {}", entry.path().display()))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,247 @@
+// (c) Copyright 2023 Helsing GmbH. All rights reserved.
+
+use std::path::{Path, PathBuf};
+
+use eyre::{ensure, Context};
+use tokio::fs;
+use walkdir::WalkDir;
+
+use crate::{
+    error::ErrorCode,
+    manifest::{Manifest, MANIFEST_FILE},
+    package::{self, PackageId, PackageStore},
+};
+
+/// Several projects of a repository sharing a single lockfile
+///
+/// A workspace is declared by a `[workspace]` section in the manifest at its
+/// root, which lists the directories of its members. The root itself only
+/// groups the members and declares neither an api nor dependencies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workspace {
+    root: PathBuf,
+    members: Vec<PathBuf>,
+}
+
+impl Workspace {
+    /// Reads the workspace rooted at `root`, if its manifest declares one
+    pub async fn read(root: &Path) -> eyre::Result<Option<Self>> {
+        if !Manifest::exists(root).await? {
+            return Ok(None);
+        }
+
+        let manifest = Manifest::read(root).await?;
+
+        let Some(workspace) = manifest.workspace else {
+            return Ok(None);
+        };
+
+        ensure!(
+            manifest.api.is_none() && manifest.dependencies.is_empty(),
+            "The workspace root {} can not declare an api or dependencies, move them into a member",
+            root.display()
+        );
+
+        let mut members = vec![];
+
+        for member in &workspace.members {
+            members.extend(expand(root, member).await?);
+        }
+
+        members.sort();
+        members.dedup();
+
+        Ok(Some(Self {
+            root: root.to_owned(),
+            members,
+        }))
+    }
+
+    /// Finds the workspace containing the project at `dir`
+    ///
+    /// This is either a workspace rooted at `dir` or the closest workspace
+    /// above it that lists `dir` as member.
+    pub async fn discover(dir: &Path) -> eyre::Result<Option<Self>> {
+        for root in dir.ancestors() {
+            let Some(workspace) = Self::read(root).await? else {
+                continue;
+            };
+
+            if root == dir || workspace.members.iter().any(|m| m == dir) {
+                return Ok(Some(workspace));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Root directory of the workspace, which contains the lockfile
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Directories of all members, ordered by path
+    pub fn members(&self) -> &[PathBuf] {
+        &self.members
+    }
+
+    /// Path of a member relative to the workspace root, for display
+    pub fn relative<'a>(&self, member: &'a Path) -> &'a Path {
+        member.strip_prefix(&self.root).unwrap_or(member)
+    }
+
+    /// Finds the member publishing `package`, as api package or
+    /// `[[package]]` entry
+    pub async fn member(&self, package: &PackageId) -> eyre::Result<&Path> {
+        for member in &self.members {
+            let manifest = Manifest::read(member).await?;
+
+            let mut published = manifest
+                .api
+                .iter()
+                .chain(manifest.packages.iter().map(|p| &p.api));
+
+            if published.any(|api| &api.name == package) {
+                return Ok(member);
+            }
+        }
+
+        eyre::bail!(
+            "No member of the workspace {} publishes {package}",
+            self.root.display()
+        )
+    }
+
+    /// Checks that every member only declares proto packages within its own
+    /// namespace
+    ///
+    /// A proto package belongs to the member with the longest namespace it
+    /// falls within, so members can neither declare packages outside their
+    /// namespace nor inject types into the namespace of another member.
+    /// Members without a namespace are only checked for the latter.
+    pub async fn check_namespaces(&self) -> eyre::Result<()> {
+        let mut namespaces = vec![];
+
+        for member in &self.members {
+            let namespace = Manifest::read(member).await?.api.and_then(|a| a.namespace);
+
+            if let Some(ref namespace) = namespace {
+                ensure!(
+                    namespace.split('.').all(is_identifier),
+                    "Invalid namespace {namespace} of workspace member {}",
+                    self.relative(member).display()
+                );
+            }
+
+            namespaces.push((member.as_path(), namespace));
+        }
+
+        let mut violations = vec![];
+
+        for (member, namespace) in &namespaces {
+            let api = PackageStore::new(member).api_path();
+
+            let protos = WalkDir::new(&api)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().extension().is_some_and(|e| e == "proto"));
+
+            for proto in protos {
+                let contents = fs::read_to_string(proto.path())
+                    .await
+                    .wrap_err_with(|| format!("Failed to read {}", proto.path().display()))?;
+
+                let path = self.relative(proto.path()).display();
+                let package = package::declared_package(&contents).unwrap_or_default();
+
+                let owner = namespaces
+                    .iter()
+                    .filter_map(|(m, n)| Some(m).zip(n.as_ref()))
+                    .filter(|(_, n)| within(&package, n))
+                    .max_by_key(|(_, n)| n.len());
+
+                match (namespace, owner) {
+                    (Some(namespace), _) if !within(&package, namespace) => {
+                        violations.push(format!(
+                            "{path} declares package `{package}` outside of namespace {namespace}"
+                        ))
+                    }
+                    (_, Some((owner, namespace))) if owner != member => violations.push(format!(
+                        "{path} declares package `{package}` within namespace {namespace} of {}",
+                        self.relative(owner).display()
+                    )),
+                    _ => {}
+                }
+            }
+        }
+
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        Err(eyre::eyre!(
+            "Workspace members declare protos outside of their namespace:{}",
+            violations
+                .iter()
+                .map(|violation| format!("\n  - {violation}"))
+                .collect::<String>()
+        ))
+        .wrap_err(ErrorCode::NamespaceViolation)
+    }
+}
+
+/// Whether a proto package is `namespace` or nested within it
+fn within(package: &str, namespace: &str) -> bool {
+    package
+        .strip_prefix(namespace)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Resolves a member entry of the workspace manifest to project directories
+async fn expand(root: &Path, member: &str) -> eyre::Result<Vec<PathBuf>> {
+    let Some(parent) = member.strip_suffix("/*") else {
+        // Normalizes `./` so members can be compared with other paths
+        let dir: PathBuf = root.join(member).components().collect();
+
+        if !Manifest::exists(&dir).await? {
+            return Err(eyre::eyre!(
+                "Workspace member {member} has no {MANIFEST_FILE}"
+            ))
+            .wrap_err(ErrorCode::InvalidManifest);
+        }
+
+        return Ok(vec![dir]);
+    };
+
+    let mut entries = fs::read_dir(root.join(parent).components().collect::<PathBuf>())
+        .await
+        .wrap_err_with(|| format!("Failed to read workspace members {member}"))?;
+
+    let mut members = vec![];
+
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .wrap_err_with(|| format!("Failed to read workspace members {member}"))?
+    {
+        if Manifest::exists(&entry.path()).await? {
+            members.push(entry.path());
+        }
+    }
+
+    if members.is_empty() {
+        return Err(eyre::eyre!(
+            "Workspace members {member} matched no projects"
+        ))
+        .wrap_err(ErrorCode::InvalidManifest);
+    }
+
+    Ok(members)
+}